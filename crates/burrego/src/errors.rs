@@ -32,4 +32,13 @@ pub enum BurregoError {
     /// Wasmtime execution deadline exceeded
     #[error("guest code interrupted, execution deadline exceeded")]
     ExecutionDeadlineExceeded,
+
+    /// Wasmtime fuel exhausted
+    #[error("guest code interrupted, fuel exhausted")]
+    OutOfFuel,
+
+    /// The guest tried to allocate more resources than the ones allowed
+    /// by the `ResourceLimits`
+    #[error("resource limit exceeded: {0}")]
+    ResourceLimitExceeded(String),
 }
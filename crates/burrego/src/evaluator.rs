@@ -3,6 +3,7 @@ use crate::errors::{BurregoError, Result};
 use crate::host_callbacks::HostCallbacks;
 use crate::opa_host_functions;
 use crate::policy::Policy;
+use crate::resource_limits::ResourceLimits;
 use crate::stack_helper::StackHelper;
 
 use itertools::Itertools;
@@ -19,8 +20,25 @@ macro_rules! set_epoch_deadline_and_call_guest {
     }};
 }
 
+/// The data stored inside of the `wasmtime::Store` used by the evaluator
+pub(crate) struct StoreData {
+    /// Set once the module has been instantiated
+    pub(crate) stack_helper: Option<StackHelper>,
+    limits: ResourceLimits,
+}
+
+/// Deterministic execution budget, expressed in units of wasmtime
+/// [fuel](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.consume_fuel)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FuelBudget {
+    /// Fuel available to the initialization of the policy
+    pub init: u64,
+    /// Fuel available to each evaluation
+    pub call: u64,
+}
+
 struct EvaluatorStack {
-    store: Store<Option<StackHelper>>,
+    store: Store<StoreData>,
     instance: Instance,
     memory: Memory,
     policy: Policy,
@@ -29,7 +47,7 @@ struct EvaluatorStack {
 pub struct Evaluator {
     engine: Engine,
     module: Module,
-    store: Store<Option<StackHelper>>,
+    store: Store<StoreData>,
     instance: Instance,
     memory: Memory,
     policy: Policy,
@@ -38,6 +56,12 @@ pub struct Evaluator {
     /// interruption](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
     /// feature of wasmtime
     epoch_deadline: Option<u64>,
+    /// limits enforced on the resources allocated by the policy
+    resource_limits: Option<ResourceLimits>,
+    /// fuel budgets enforced on the policy
+    fuel: Option<FuelBudget>,
+    /// fuel consumed by the last evaluation
+    fuel_consumed: Option<u64>,
     entrypoints: HashMap<String, i32>,
     used_builtins: HashSet<String>,
 }
@@ -48,12 +72,16 @@ impl Evaluator {
         module: Module,
         host_callbacks: HostCallbacks,
         epoch_deadline: Option<u64>,
+        resource_limits: Option<ResourceLimits>,
+        fuel: Option<FuelBudget>,
     ) -> Result<Evaluator> {
         let stack = Self::setup(
             engine.clone(),
             module.clone(),
             host_callbacks.clone(),
             epoch_deadline,
+            resource_limits,
+            fuel,
         )?;
         let mut store = stack.store;
         let instance = stack.instance;
@@ -87,6 +115,9 @@ impl Evaluator {
             policy,
             host_callbacks,
             epoch_deadline,
+            resource_limits,
+            fuel,
+            fuel_consumed: None,
             entrypoints,
             used_builtins,
         };
@@ -106,15 +137,34 @@ impl Evaluator {
         module: Module,
         host_callbacks: HostCallbacks,
         epoch_deadline: Option<u64>,
+        resource_limits: Option<ResourceLimits>,
+        fuel: Option<FuelBudget>,
     ) -> Result<EvaluatorStack> {
-        let mut linker = Linker::<Option<StackHelper>>::new(&engine);
+        let mut linker = Linker::<StoreData>::new(&engine);
 
-        let opa_data_helper: Option<StackHelper> = None;
-        let mut store = Store::new(&engine, opa_data_helper);
+        let store_data = StoreData {
+            stack_helper: None,
+            limits: resource_limits.unwrap_or_default(),
+        };
+        let mut store = Store::new(&engine, store_data);
+        if resource_limits.is_some() {
+            store.limiter(|data| &mut data.limits);
+        }
+        // The initialization fuel is shared by the instantiation of the module
+        // and by the lookup of its builtins and entrypoints
+        if let Some(fuel) = fuel {
+            store
+                .set_fuel(fuel.init)
+                .map_err(|e| BurregoError::WasmEngineError(format!("cannot set fuel: {e}")))?;
+        }
 
         let memory_ty = MemoryType::new(5, None);
-        let memory = Memory::new(&mut store, memory_ty)
-            .map_err(|e| BurregoError::WasmEngineError(format!("cannot create memory: {e}")))?;
+        let memory =
+            Memory::new(&mut store, memory_ty).map_err(|e| match e.downcast::<BurregoError>() {
+                Ok(err @ BurregoError::ResourceLimitExceeded(_)) => err,
+                Ok(err) => BurregoError::WasmEngineError(format!("cannot create memory: {err}")),
+                Err(e) => BurregoError::WasmEngineError(format!("cannot create memory: {e}")),
+            })?;
         linker
             .define(&mut store, "env", "memory", memory)
             .map_err(|e| {
@@ -143,7 +193,7 @@ impl Evaluator {
             host_callbacks.opa_println,
         )?;
         let policy = Policy::new(&instance, &mut store, &memory)?;
-        _ = store.data_mut().insert(stack_helper);
+        _ = store.data_mut().stack_helper.insert(stack_helper);
 
         Ok(EvaluatorStack {
             memory,
@@ -159,6 +209,8 @@ impl Evaluator {
             self.module.clone(),
             self.host_callbacks.clone(),
             self.epoch_deadline,
+            self.resource_limits,
+            self.fuel,
         )?;
         self.store = stack.store;
         self.instance = stack.instance;
@@ -227,11 +279,41 @@ impl Evaluator {
         self.entrypoints.iter().any(|(_k, &v)| v == entrypoint_id)
     }
 
+    /// Amount of fuel consumed by the last evaluation. Returns `None` when
+    /// fuel consumption is not enabled
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        self.fuel_consumed
+    }
+
     pub fn evaluate(
         &mut self,
         entrypoint_id: i32,
         input: &serde_json::Value,
         data: &[u8],
+    ) -> Result<serde_json::Value> {
+        if let Some(fuel) = self.fuel {
+            self.store
+                .set_fuel(fuel.call)
+                .map_err(|e| BurregoError::WasmEngineError(format!("cannot set fuel: {e}")))?;
+        }
+
+        let evaluation = self.evaluate_guest(entrypoint_id, input, data);
+
+        self.fuel_consumed = self.fuel.and_then(|fuel| {
+            self.store
+                .get_fuel()
+                .ok()
+                .map(|remaining| fuel.call.saturating_sub(remaining))
+        });
+
+        evaluation
+    }
+
+    fn evaluate_guest(
+        &mut self,
+        entrypoint_id: i32,
+        input: &serde_json::Value,
+        data: &[u8],
     ) -> Result<serde_json::Value> {
         set_epoch_deadline_and_call_guest!(self.epoch_deadline, self.store, {
             if !self.has_entrypoint(entrypoint_id) {
@@ -2,7 +2,8 @@ use crate::errors::{BurregoError, Result};
 use std::path::{Path, PathBuf};
 use wasmtime::{Engine, Module};
 
-use crate::{host_callbacks::HostCallbacks, Evaluator};
+use crate::evaluator::FuelBudget;
+use crate::{host_callbacks::HostCallbacks, Evaluator, ResourceLimits};
 
 #[derive(Default)]
 pub struct EvaluatorBuilder {
@@ -11,6 +12,8 @@ pub struct EvaluatorBuilder {
     engine: Option<Engine>,
     epoch_deadline: Option<u64>,
     host_callbacks: Option<HostCallbacks>,
+    resource_limits: Option<ResourceLimits>,
+    fuel: Option<FuelBudget>,
 }
 
 impl EvaluatorBuilder {
@@ -44,6 +47,35 @@ impl EvaluatorBuilder {
         self
     }
 
+    /// Limit the resources the policy can allocate during its evaluation.
+    /// Exceeding any of them causes the evaluation to fail with a
+    /// `BurregoError::ResourceLimitExceeded` error
+    #[must_use]
+    pub fn resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.resource_limits = Some(resource_limits);
+        self
+    }
+
+    /// Enable wasmtime fuel consumption and set the deterministic execution
+    /// budgets to be enforced
+    ///
+    /// * `init_fuel`: fuel available to the initialization of the policy
+    /// * `call_fuel`: fuel available to each evaluation
+    ///
+    /// Exhausting the fuel causes the execution to fail with a
+    /// `BurregoError::OutOfFuel` error.
+    ///
+    /// **Warning:** when providing an instance of `wasmtime::Engine`, ensure it has
+    /// been created with the `consume_fuel` feature enabled
+    #[must_use]
+    pub fn enable_fuel(mut self, init_fuel: u64, call_fuel: u64) -> Self {
+        self.fuel = Some(FuelBudget {
+            init: init_fuel,
+            call: call_fuel,
+        });
+        self
+    }
+
     fn validate(&self) -> Result<()> {
         if self.policy_path.is_some() && self.module.is_some() {
             return Err(BurregoError::EvaluatorBuilderError(
@@ -75,6 +107,9 @@ impl EvaluatorBuilder {
                 if self.epoch_deadline.is_some() {
                     config.epoch_interruption(true);
                 }
+                if self.fuel.is_some() {
+                    config.consume_fuel(true);
+                }
                 Engine::new(&config).map_err(|e| {
                     BurregoError::WasmEngineError(format!("cannot create wasmtime Engine: {e:?}"))
                 })?
@@ -97,6 +132,13 @@ impl EvaluatorBuilder {
             .clone()
             .expect("host callbacks should be set");
 
-        Evaluator::from_engine_and_module(engine, module, host_callbacks, self.epoch_deadline)
+        Evaluator::from_engine_and_module(
+            engine,
+            module,
+            host_callbacks,
+            self.epoch_deadline,
+            self.resource_limits,
+            self.fuel,
+        )
     }
 }
@@ -5,9 +5,11 @@ mod evaluator_builder;
 pub mod host_callbacks;
 mod opa_host_functions;
 mod policy;
+mod resource_limits;
 mod stack_helper;
 
 pub use builtins::get_builtins;
-pub use evaluator::Evaluator;
+pub use evaluator::{Evaluator, FuelBudget};
 pub use evaluator_builder::EvaluatorBuilder;
 pub use host_callbacks::HostCallbacks;
+pub use resource_limits::ResourceLimits;
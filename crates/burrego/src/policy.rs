@@ -7,12 +7,19 @@ use wasmtime::{AsContextMut, Instance, Memory, TypedFunc};
 
 /// Handle errors returned when calling a wasmtime function
 /// The macro looks into the error type and, when an epoch interruption
-/// happens, maps the error to BurregoError::ExecutionDeadlineExceeded
+/// happens, maps the error to BurregoError::ExecutionDeadlineExceeded.
+/// When the fuel is exhausted, the error is mapped to BurregoError::OutOfFuel.
+/// Errors raised by the `ResourceLimits` are returned as they are
 macro_rules! map_call_error {
     ($err:expr, $msg:expr) => {{
-        if let Some(trap) = $err.downcast_ref::<wasmtime::Trap>() {
+        if let Some(BurregoError::ResourceLimitExceeded(msg)) = $err.downcast_ref::<BurregoError>()
+        {
+            BurregoError::ResourceLimitExceeded(msg.clone())
+        } else if let Some(trap) = $err.downcast_ref::<wasmtime::Trap>() {
             if matches!(trap, wasmtime::Trap::Interrupt) {
                 BurregoError::ExecutionDeadlineExceeded
+            } else if matches!(trap, wasmtime::Trap::OutOfFuel) {
+                BurregoError::OutOfFuel
             } else {
                 BurregoError::WasmEngineError(format!("{}: {:?}", $msg, $err))
             }
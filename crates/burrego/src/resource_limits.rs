@@ -0,0 +1,54 @@
+use crate::errors::BurregoError;
+
+/// Limits enforced on the resources a policy can allocate during its evaluation.
+///
+/// The limits are enforced through a [`wasmtime::ResourceLimiter`]. A value set
+/// to `None` means no limit is enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Maximum size of a linear memory, expressed in bytes
+    pub max_memory_bytes: Option<usize>,
+
+    /// Maximum number of elements of a table
+    pub max_table_elements: Option<u32>,
+
+    /// Maximum number of instances that can be created inside of a store
+    pub max_instances: Option<usize>,
+}
+
+impl wasmtime::ResourceLimiter for ResourceLimits {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.max_memory_bytes {
+            Some(limit) if desired > limit => Err(BurregoError::ResourceLimitExceeded(format!(
+                "cannot grow memory to {desired} bytes, the limit is {limit} bytes"
+            ))
+            .into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        desired: u32,
+        _maximum: Option<u32>,
+    ) -> wasmtime::Result<bool> {
+        match self.max_table_elements {
+            Some(limit) if desired > limit => Err(BurregoError::ResourceLimitExceeded(format!(
+                "cannot grow table to {desired} elements, the limit is {limit} elements"
+            ))
+            .into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+            .unwrap_or(wasmtime::DEFAULT_INSTANCE_LIMIT)
+    }
+}
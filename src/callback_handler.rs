@@ -1,20 +1,33 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use kubewarden_policy_sdk::host_capabilities::net::LookupResponse;
 use tokio::sync::{mpsc, oneshot};
-use tracing::{debug, warn};
+use tracing::{debug, info_span, warn, Instrument};
 
 use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
+use crate::errors::HostCallbackError;
+use crate::evaluation_context::EvaluationContext;
 
 mod builder;
 mod crypto;
+mod fixture;
 mod kubernetes;
 mod oci;
+mod rate_limit;
+mod recording;
+mod replay;
 mod sigstore_verification;
 
 pub use builder::CallbackHandlerBuilder;
 pub(crate) use crypto::verify_certificate;
+pub use fixture::FixtureCallbackHandler;
+pub use rate_limit::{CapabilityGroup, RateLimit, RateLimiter};
+pub use replay::ReplayCallbackHandler;
+pub(crate) use sigstore_verification::Client as SigstoreClient;
 
 use sigstore_verification::{
     get_sigstore_certificate_verification_cached, get_sigstore_github_actions_verification_cached,
@@ -22,6 +35,8 @@ use sigstore_verification::{
     get_sigstore_pub_key_verification_cached,
 };
 
+const DEFAULT_CHANNEL_BUFF_SIZE: usize = 100;
+
 /// Struct that computes request coming from a Wasm guest.
 /// This should be used only to handle the requests that need some async
 /// code in order to be fulfilled.
@@ -29,30 +44,29 @@ pub struct CallbackHandler {
     oci_client: Arc<oci::Client>,
     sigstore_client: sigstore_verification::Client,
     kubernetes_client: Option<kubernetes::Client>,
+    recorder: Option<Arc<recording::CallbackRecorder>>,
+    rate_limiter: RateLimiter,
+    timeouts: HashMap<CapabilityGroup, Duration>,
     rx: mpsc::Receiver<CallbackRequest>,
     tx: mpsc::Sender<CallbackRequest>,
     shutdown_channel: oneshot::Receiver<()>,
 }
 
 macro_rules! handle_callback {
-    ($req:expr, $log_value: expr, $log_msg: expr, $code:block) => {{
-        let response = { $code }
-            .await
-            .map(|response| {
-                debug!(
-                    value = ?$log_value,
-                    cached = response.was_cached,
-                    $log_msg,
-                );
-                let payload = serde_json::to_vec(&response.value)
-                    .map_err(|e| anyhow!("error serializing payload: {e:?}"))?;
-                Ok(CallbackResponse { payload })
+    ($log_value: expr, $log_msg: expr, $code:block) => {{
+        { $code }.await.and_then(|response| {
+            debug!(
+                value = ?$log_value,
+                cached = response.was_cached,
+                $log_msg,
+            );
+            let payload = serde_json::to_vec(&response.value)
+                .map_err(|e| anyhow!("error serializing payload: {e:?}"))?;
+            Ok(CallbackResponse {
+                payload,
+                was_cached: response.was_cached,
             })
-            .and_then(|r| r);
-
-        if let Err(e) = $req.response_channel.send(response) {
-            warn!("callback handler: cannot send response back: {:?}", e);
-        }
+        })
     }};
 }
 
@@ -66,6 +80,46 @@ impl CallbackHandler {
         self.tx.clone()
     }
 
+    /// Returns an [`EvaluationContext`] for the given policy, wired to this
+    /// `CallbackHandler`: it holds the channel returned by
+    /// [`CallbackHandler::sender_channel`], together with the rate limiter and
+    /// the timeouts of the handler.
+    ///
+    /// This is the recommended way to create the evaluation context of the
+    /// policies making use of host callbacks.
+    pub fn evaluation_context(&self, policy_id: &str) -> EvaluationContext {
+        EvaluationContext::new(policy_id)
+            .callback_channel(self.sender_channel())
+            .callback_rate_limiter(self.rate_limiter())
+            .callback_timeouts(self.timeouts())
+    }
+
+    /// Returns the rate limiter enforcing the limits set via
+    /// [`CallbackHandlerBuilder::rate_limit`].
+    ///
+    /// The limiter must be set inside of the [`EvaluationContext`] of the
+    /// policies using the channel returned by
+    /// [`CallbackHandler::sender_channel`], which is done by
+    /// [`CallbackHandler::evaluation_context`]: the limits are enforced
+    /// before a request takes a slot of the channel, hence a policy exceeding
+    /// them cannot starve the other ones.
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.rate_limiter.clone()
+    }
+
+    /// Returns the timeouts set via [`CallbackHandlerBuilder::timeout`].
+    ///
+    /// The timeouts must be set inside of the [`EvaluationContext`] of the
+    /// policies using the channel returned by
+    /// [`CallbackHandler::sender_channel`], which is done by
+    /// [`CallbackHandler::evaluation_context`]: the policies stop waiting for a
+    /// response once its timeout is reached, including the time the request
+    /// spent inside of the channel. The requests abandoned by the policies
+    /// are cancelled.
+    pub fn timeouts(&self) -> HashMap<CapabilityGroup, Duration> {
+        self.timeouts.clone()
+    }
+
     /// Enter an endless loop that:
     ///    1. Waits for requests to be evaluated
     ///    2. Evaluate the request
@@ -91,240 +145,366 @@ impl CallbackHandler {
     }
 
     async fn handle_request(&mut self, req: CallbackRequest) {
+        let group = CapabilityGroup::from(&req.request);
+        if req.response_channel.is_closed() {
+            debug!(%group, "callback handler: request abandoned by the policy, skipping it");
+            return;
+        }
+        let record = self
+            .recorder
+            .as_ref()
+            .and_then(|recorder| recorder.start(&req));
+        let span = info_span!(
+            parent: &req.span,
+            "callback_request",
+            policy_id = req.origin.as_ref().map(|origin| origin.policy_id.as_str()),
+            request_uid = req
+                .origin
+                .as_ref()
+                .and_then(|origin| origin.request_uid.as_deref()),
+            %group,
+        );
+        let timeout = self.timeouts.get(&group).copied();
         let oci_client = self.oci_client.clone();
-        let mut sigstore_client = self.sigstore_client.clone();
-        let mut kubernetes_client = self.kubernetes_client.clone();
+        let sigstore_client = self.sigstore_client.clone();
+        let kubernetes_client = self.kubernetes_client.clone();
 
-        tokio::spawn(async move {
-            match req.request {
-                CallbackRequestType::OciManifestDigest { image } => {
-                    handle_callback!(req, image, "Image digest computed", {
-                        oci::get_oci_digest_cached(&oci_client, &image)
-                    });
-                }
-                CallbackRequestType::OciManifest { image } => {
-                    handle_callback!(req, image, "Image manifest computed", {
-                        oci::get_oci_manifest_cached(&oci_client, &image)
-                    });
-                }
-                CallbackRequestType::OciManifestAndConfig { image } => {
-                    handle_callback!(req, image, "Image manifest computed", {
-                        oci::get_oci_manifest_and_config_cached(&oci_client, &image)
-                    });
-                }
-                CallbackRequestType::SigstorePubKeyVerify {
-                    image,
+        tokio::spawn(
+            async move {
+                let CallbackRequest {
+                    request,
+                    response_channel,
+                    ..
+                } = req;
+                let evaluation =
+                    evaluate_request(request, oci_client, sigstore_client, kubernetes_client);
+                let evaluation = async move {
+                    match timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, evaluation)
+                            .await
+                            .unwrap_or_else(|_| {
+                                warn!(%group, ?timeout, "callback handler: request timed out");
+                                Err(HostCallbackError::Timeout { group, timeout }.into())
+                            }),
+                        None => evaluation.await,
+                    }
+                };
+
+                respond(evaluation, response_channel, group, record).await;
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Wait for the evaluation of a request, then record its response and send it
+/// back. Dropping the evaluation cancels it, this happens when the sender of
+/// the request stops waiting for the response
+async fn respond(
+    evaluation: impl Future<Output = anyhow::Result<CallbackResponse>>,
+    mut response_channel: oneshot::Sender<anyhow::Result<CallbackResponse>>,
+    group: CapabilityGroup,
+    record: Option<recording::PendingRecord>,
+) {
+    let response = tokio::select! {
+        response = evaluation => response,
+        _ = response_channel.closed() => {
+            debug!(%group, "callback handler: request abandoned by the policy");
+            return;
+        }
+    };
+
+    if let Some(record) = record {
+        record.finish(&response);
+    }
+    if let Err(e) = response_channel.send(response) {
+        warn!("callback handler: cannot send response back: {:?}", e);
+    }
+}
+
+/// Evaluate the request, returning the response to be given back to the guest
+async fn evaluate_request(
+    request: CallbackRequestType,
+    oci_client: Arc<oci::Client>,
+    mut sigstore_client: sigstore_verification::Client,
+    mut kubernetes_client: Option<kubernetes::Client>,
+) -> anyhow::Result<CallbackResponse> {
+    match request {
+        CallbackRequestType::OciManifestDigest { image } => {
+            handle_callback!(image, "Image digest computed", {
+                oci::get_oci_digest_cached(&oci_client, &image)
+            })
+        }
+        CallbackRequestType::OciManifest { image } => {
+            handle_callback!(image, "Image manifest computed", {
+                oci::get_oci_manifest_cached(&oci_client, &image)
+            })
+        }
+        CallbackRequestType::OciManifestAndConfig { image } => {
+            handle_callback!(image, "Image manifest computed", {
+                oci::get_oci_manifest_and_config_cached(&oci_client, &image)
+            })
+        }
+        CallbackRequestType::SigstorePubKeyVerify {
+            image,
+            pub_keys,
+            annotations,
+        } => {
+            handle_callback!(image, "Sigstore pub key verification done", {
+                get_sigstore_pub_key_verification_cached(
+                    &mut sigstore_client,
+                    image.clone(),
                     pub_keys,
                     annotations,
-                } => {
-                    handle_callback!(req, image, "Sigstore pub key verification done", {
-                        get_sigstore_pub_key_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            pub_keys,
-                            annotations,
-                        )
-                    });
-                }
-                CallbackRequestType::SigstoreKeylessVerify {
-                    image,
+                )
+            })
+        }
+        CallbackRequestType::SigstoreKeylessVerify {
+            image,
+            keyless,
+            annotations,
+        } => {
+            handle_callback!(image, "Sigstore keyless verification done", {
+                get_sigstore_keyless_verification_cached(
+                    &mut sigstore_client,
+                    image.clone(),
                     keyless,
                     annotations,
-                } => {
-                    handle_callback!(req, image, "Sigstore keyless verification done", {
-                        get_sigstore_keyless_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            keyless,
-                            annotations,
-                        )
-                    });
-                }
-                CallbackRequestType::SigstoreKeylessPrefixVerify {
-                    image,
+                )
+            })
+        }
+        CallbackRequestType::SigstoreKeylessPrefixVerify {
+            image,
+            keyless_prefix,
+            annotations,
+        } => {
+            handle_callback!(image, "Sigstore keyless prefix verification done", {
+                get_sigstore_keyless_prefix_verification_cached(
+                    &mut sigstore_client,
+                    image.clone(),
                     keyless_prefix,
                     annotations,
-                } => {
-                    handle_callback!(req, image, "Sigstore keyless prefix verification done", {
-                        get_sigstore_keyless_prefix_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            keyless_prefix,
-                            annotations,
-                        )
-                    });
-                }
-                CallbackRequestType::SigstoreGithubActionsVerify {
-                    image,
+                )
+            })
+        }
+        CallbackRequestType::SigstoreGithubActionsVerify {
+            image,
+            owner,
+            repo,
+            annotations,
+        } => {
+            handle_callback!(image, "Sigstore GitHub Action verification done", {
+                get_sigstore_github_actions_verification_cached(
+                    &mut sigstore_client,
+                    image.clone(),
                     owner,
                     repo,
                     annotations,
-                } => {
-                    handle_callback!(req, image, "Sigstore GitHub Action verification done", {
-                        get_sigstore_github_actions_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            owner,
-                            repo,
-                            annotations,
-                        )
-                    });
-                }
-                CallbackRequestType::SigstoreCertificateVerify {
-                    image,
-                    certificate,
-                    certificate_chain,
+                )
+            })
+        }
+        CallbackRequestType::SigstoreCertificateVerify {
+            image,
+            certificate,
+            certificate_chain,
+            require_rekor_bundle,
+            annotations,
+        } => {
+            handle_callback!(image, "Sigstore GitHub Action verification done", {
+                get_sigstore_certificate_verification_cached(
+                    &mut sigstore_client,
+                    &image,
+                    &certificate,
+                    certificate_chain.as_deref(),
                     require_rekor_bundle,
                     annotations,
-                } => {
-                    handle_callback!(req, image, "Sigstore GitHub Action verification done", {
-                        get_sigstore_certificate_verification_cached(
-                            &mut sigstore_client,
-                            &image,
-                            &certificate,
-                            certificate_chain.as_deref(),
-                            require_rekor_bundle,
-                            annotations,
-                        )
-                    })
-                }
-                CallbackRequestType::DNSLookupHost { host } => {
-                    let response = dns_lookup::lookup_host(&host)
-                        .map(|ips| {
-                            let res = LookupResponse {
-                                ips: ips.iter().map(|ip| ip.to_string()).collect(),
-                            };
-                            CallbackResponse {
-                                payload: serde_json::to_vec(&res).unwrap(),
-                            }
-                        })
-                        .map_err(anyhow::Error::new);
-
-                    if let Err(e) = req.response_channel.send(response) {
-                        warn!("callback handler: cannot send response back: {:?}", e);
-                    }
-                }
-                CallbackRequestType::KubernetesListResourceNamespace {
-                    api_version,
-                    kind,
-                    namespace,
-                    label_selector,
-                    field_selector,
-                } => {
-                    handle_callback!(
-                        req,
-                        format!("[{namespace}] {api_version}/{kind}"),
-                        "List namespaced Kubernetes resource",
-                        {
-                            kubernetes::list_resources_by_namespace(
-                                kubernetes_client.as_mut(),
-                                &api_version,
-                                &kind,
-                                &namespace,
-                                label_selector,
-                                field_selector,
-                            )
-                        }
+                )
+            })
+        }
+        CallbackRequestType::DNSLookupHost { host } => {
+            // the lookup is blocking, run it on a dedicated thread to not
+            // prevent the timeout from firing
+            let ips = tokio::task::spawn_blocking(move || dns_lookup::lookup_host(&host))
+                .await
+                .map_err(|e| anyhow!("cannot perform DNS lookup: {e}"))??;
+            let res = LookupResponse {
+                ips: ips.iter().map(|ip| ip.to_string()).collect(),
+            };
+            Ok(CallbackResponse {
+                payload: serde_json::to_vec(&res)?,
+                was_cached: false,
+            })
+        }
+        CallbackRequestType::KubernetesListResourceNamespace {
+            api_version,
+            kind,
+            namespace,
+            label_selector,
+            field_selector,
+        } => {
+            handle_callback!(
+                format!("[{namespace}] {api_version}/{kind}"),
+                "List namespaced Kubernetes resource",
+                {
+                    kubernetes::list_resources_by_namespace(
+                        kubernetes_client.as_mut(),
+                        &api_version,
+                        &kind,
+                        &namespace,
+                        label_selector,
+                        field_selector,
                     )
                 }
-                CallbackRequestType::KubernetesListResourceAll {
-                    api_version,
-                    kind,
-                    label_selector,
-                    field_selector,
-                } => {
-                    handle_callback!(
-                        req,
-                        format!("{api_version}/{kind}"),
-                        "List Kubernetes resource",
-                        {
-                            kubernetes::list_resources_all(
-                                kubernetes_client.as_mut(),
-                                &api_version,
-                                &kind,
-                                label_selector,
-                                field_selector,
-                            )
-                        }
+            )
+        }
+        CallbackRequestType::KubernetesListResourceAll {
+            api_version,
+            kind,
+            label_selector,
+            field_selector,
+        } => {
+            handle_callback!(
+                format!("{api_version}/{kind}"),
+                "List Kubernetes resource",
+                {
+                    kubernetes::list_resources_all(
+                        kubernetes_client.as_mut(),
+                        &api_version,
+                        &kind,
+                        label_selector,
+                        field_selector,
                     )
                 }
-                CallbackRequestType::KubernetesGetResource {
-                    api_version,
-                    kind,
-                    name,
-                    namespace,
-                    disable_cache,
-                } => {
-                    if disable_cache {
-                        handle_callback!(
-                            req,
-                            format!("{api_version}/{kind}"),
-                            "Get Kubernetes resource - no cache",
-                            {
-                                kubernetes::get_resource(
-                                    kubernetes_client.as_mut(),
-                                    &api_version,
-                                    &kind,
-                                    &name,
-                                    namespace.as_deref(),
-                                )
-                            }
+            )
+        }
+        CallbackRequestType::KubernetesGetResource {
+            api_version,
+            kind,
+            name,
+            namespace,
+            disable_cache,
+        } => {
+            if disable_cache {
+                handle_callback!(
+                    format!("{api_version}/{kind}"),
+                    "Get Kubernetes resource - no cache",
+                    {
+                        kubernetes::get_resource(
+                            kubernetes_client.as_mut(),
+                            &api_version,
+                            &kind,
+                            &name,
+                            namespace.as_deref(),
                         )
-                    } else {
-                        handle_callback!(
-                            req,
-                            format!("{api_version}/{kind}"),
-                            "Get Kubernetes resource",
-                            {
-                                kubernetes::get_resource_cached(
-                                    kubernetes_client.as_mut(),
-                                    &api_version,
-                                    &kind,
-                                    &name,
-                                    namespace.as_deref(),
-                                )
-                            }
+                    }
+                )
+            } else {
+                handle_callback!(
+                    format!("{api_version}/{kind}"),
+                    "Get Kubernetes resource",
+                    {
+                        kubernetes::get_resource_cached(
+                            kubernetes_client.as_mut(),
+                            &api_version,
+                            &kind,
+                            &name,
+                            namespace.as_deref(),
                         )
                     }
-                }
-                CallbackRequestType::KubernetesGetResourcePluralName { api_version, kind } => {
-                    handle_callback!(
-                        req,
-                        format!("{api_version}/{kind}"),
-                        "Get Kubernetes resource plural name",
-                        {
-                            kubernetes::get_resource_plural_name(
-                                kubernetes_client.as_mut(),
-                                &api_version,
-                                &kind,
-                            )
-                        }
+                )
+            }
+        }
+        CallbackRequestType::KubernetesGetResourcePluralName { api_version, kind } => {
+            handle_callback!(
+                format!("{api_version}/{kind}"),
+                "Get Kubernetes resource plural name",
+                {
+                    kubernetes::get_resource_plural_name(
+                        kubernetes_client.as_mut(),
+                        &api_version,
+                        &kind,
                     )
                 }
-                CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
-                    api_version,
-                    kind,
-                    label_selector,
-                    field_selector,
-                    since,
-                } => {
-                    handle_callback!(
-                        req,
-                        format!("{api_version}/{kind}"),
-                        "Has the result of 'Kubernetes list all resources' changed since a given instant",
-                        {
-                            kubernetes::has_list_resources_all_result_changed_since_instant(
-                                kubernetes_client.as_mut(),
-                                &api_version,
-                                &kind,
-                                label_selector,
-                                field_selector,
-                                since,
-                            )
-                        }
+            )
+        }
+        CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+            api_version,
+            kind,
+            label_selector,
+            field_selector,
+            since,
+        } => {
+            handle_callback!(
+                format!("{api_version}/{kind}"),
+                "Has the result of 'Kubernetes list all resources' changed since a given instant",
+                {
+                    kubernetes::has_list_resources_all_result_changed_since_instant(
+                        kubernetes_client.as_mut(),
+                        &api_version,
+                        &kind,
+                        label_selector,
+                        field_selector,
+                        since,
                     )
                 }
-            }
-        });
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn abandoned_recorded_request_is_cancelled() {
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let recorder = Arc::new(recording::CallbackRecorder::new(recording.path()).unwrap());
+
+        let (tx, rx) = oneshot::channel();
+        let req = CallbackRequest {
+            request: CallbackRequestType::DNSLookupHost {
+                host: "localhost".to_string(),
+            },
+            origin: None,
+            span: tracing::Span::current(),
+            response_channel: tx,
+        };
+        let record = recorder.start(&req);
+        drop(rx);
+
+        // the evaluation never completes, it must be cancelled because the
+        // policy is no longer waiting for its response
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            respond(
+                std::future::pending(),
+                req.response_channel,
+                CapabilityGroup::Net,
+                record,
+            ),
+        )
+        .await
+        .expect("the abandoned request has not been cancelled");
+
+        assert!(std::fs::read_to_string(recording.path())
+            .unwrap()
+            .is_empty());
+    }
+    #[tokio::test]
+    async fn evaluation_context_is_wired_to_the_handler() {
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        let callback_handler = CallbackHandlerBuilder::new(shutdown_rx)
+            .timeout(CapabilityGroup::Oci, Duration::from_secs(1))
+            .build()
+            .await
+            .expect("cannot build callback handler");
+
+        let eval_ctx = callback_handler.evaluation_context("test");
+
+        assert_eq!("test", eval_ctx.policy_id);
+        assert!(eval_ctx.callback_channel.is_some());
+        assert!(eval_ctx.callback_rate_limiter.is_some());
+        assert_eq!(callback_handler.timeouts(), eval_ctx.callback_timeouts);
     }
 }
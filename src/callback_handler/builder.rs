@@ -1,15 +1,17 @@
 use anyhow::Result;
 use policy_fetcher::sigstore::trust::ManualTrustRoot;
 use policy_fetcher::sources::Sources;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
 
-use super::CallbackHandler;
-use super::{oci, sigstore_verification};
+use super::{oci, rate_limit, recording, sigstore_verification};
+use super::{CallbackHandler, DEFAULT_CHANNEL_BUFF_SIZE};
+use super::{CapabilityGroup, RateLimit};
 use crate::callback_requests::CallbackRequest;
 
-const DEFAULT_CHANNEL_BUFF_SIZE: usize = 100;
-
 /// Helper struct that creates CallbackHandler objects
 pub struct CallbackHandlerBuilder {
     oci_sources: Option<Sources>,
@@ -17,6 +19,9 @@ pub struct CallbackHandlerBuilder {
     shutdown_channel: oneshot::Receiver<()>,
     trust_root: Option<Arc<ManualTrustRoot<'static>>>,
     kube_client: Option<kube::Client>,
+    recording_path: Option<PathBuf>,
+    rate_limits: HashMap<CapabilityGroup, RateLimit>,
+    timeouts: HashMap<CapabilityGroup, Duration>,
 }
 
 impl CallbackHandlerBuilder {
@@ -27,6 +32,9 @@ impl CallbackHandlerBuilder {
             channel_buffer_size: DEFAULT_CHANNEL_BUFF_SIZE,
             trust_root: None,
             kube_client: None,
+            recording_path: None,
+            rate_limits: HashMap::new(),
+            timeouts: HashMap::new(),
         }
     }
 
@@ -56,6 +64,46 @@ impl CallbackHandlerBuilder {
         self
     }
 
+    /// Record all the requests served by the CallbackHandler, together with
+    /// their responses, to the given file. The file is overwritten if it
+    /// already exists.
+    ///
+    /// The recording can be served by a
+    /// [`ReplayCallbackHandler`](crate::callback_handler::ReplayCallbackHandler)
+    /// to reproduce the evaluation of policies offline. Optional
+    pub fn record_to(mut self, path: PathBuf) -> Self {
+        self.recording_path = Some(path);
+        self
+    }
+
+    /// Limit the rate of the requests made by each policy to the given group
+    /// of host capabilities. Each policy id has its own token bucket, the
+    /// requests exceeding the limit fail straight away. Optional, by default
+    /// no limit is applied.
+    ///
+    /// The limits are enforced by the evaluators whose
+    /// [`EvaluationContext::callback_rate_limiter`](crate::evaluation_context::EvaluationContext::callback_rate_limiter)
+    /// is set to [`CallbackHandler::rate_limiter`], see
+    /// [`CallbackHandler::evaluation_context`]
+    pub fn rate_limit(mut self, group: CapabilityGroup, limit: RateLimit) -> Self {
+        self.rate_limits.insert(group, limit);
+        self
+    }
+
+    /// Set the maximum amount of time the requests made to the given group of
+    /// host capabilities can take. Once the deadline is reached the request is
+    /// cancelled and the host callback fails with a
+    /// [`HostCallbackError::Timeout`](crate::errors::HostCallbackError::Timeout)
+    /// error. Optional, by default requests can take an unbounded amount of time.
+    ///
+    /// The timeouts must also be given to the policies, see
+    /// [`CallbackHandler::evaluation_context`], otherwise the time spent by the requests
+    /// inside of the channel is not taken into account
+    pub fn timeout(mut self, group: CapabilityGroup, timeout: Duration) -> Self {
+        self.timeouts.insert(group, timeout);
+        self
+    }
+
     /// Create a CallbackHandler object
     pub async fn build(self) -> Result<CallbackHandler> {
         let (tx, rx) = mpsc::channel::<CallbackRequest>(self.channel_buffer_size);
@@ -66,11 +114,20 @@ impl CallbackHandlerBuilder {
                 .to_owned();
 
         let kubernetes_client = self.kube_client.map(super::kubernetes::Client::new);
+        let recorder = self
+            .recording_path
+            .as_deref()
+            .map(recording::CallbackRecorder::new)
+            .transpose()?
+            .map(Arc::new);
 
         Ok(CallbackHandler {
             oci_client,
             sigstore_client,
             kubernetes_client,
+            recorder,
+            rate_limiter: rate_limit::RateLimiter::new(self.rate_limits),
+            timeouts: self.timeouts,
             tx,
             rx,
             shutdown_channel: self.shutdown_channel,
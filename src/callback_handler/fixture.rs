@@ -0,0 +1,347 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::path::Path;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+use super::recording::CallbackRecordResponse;
+use super::DEFAULT_CHANNEL_BUFF_SIZE;
+use crate::callback_requests::{CallbackRequest, CallbackResponse};
+
+/// A drop-in replacement of the `CallbackHandler` that answers the requests
+/// using a set of declarative fixtures. Useful to test context aware policies,
+/// or policies using Sigstore, without any external infrastructure.
+///
+/// Fixtures are loaded from all the `.yaml`, `.yml` and `.json` files of a
+/// directory, sorted by name. Each file contains a list of fixtures, like:
+///
+/// ```yaml
+/// - request:
+///     OciManifestDigest:
+///       image: ghcr.io/kubewarden/policies/pod-privileged:v0.2.1
+///   payload: "sha256:0b1d5f8c9e..."
+/// - request:
+///     KubernetesGetResource:
+///       api_version: v1
+///       kind: Namespace
+///       name: default
+///   payload:
+///     apiVersion: v1
+///     kind: Namespace
+///     metadata:
+///       name: default
+/// - request:
+///     DNSLookupHost:
+///       host: example.com
+///   error: "lookup failed"
+/// ```
+///
+/// The `request` uses the serialized form of
+/// [`CallbackRequestType`](crate::callback_requests::CallbackRequestType). A
+/// request matches a fixture when it has the same type and all the attributes
+/// specified by the fixture have the same value, the attributes that are not
+/// specified match any value. When many fixtures match, the first one is used.
+///
+/// The `payload` is given back to the policy, serialized as JSON. It must
+/// have the same format of the payload returned by the real host capability.
+/// Payloads that are not JSON documents are given via `raw_payload`, base64
+/// encoded. Alternatively, `error` makes the request fail with the given
+/// message.
+pub struct FixtureCallbackHandler {
+    fixtures: Vec<Fixture>,
+    missing_fixture_error: Option<String>,
+    rx: mpsc::Receiver<CallbackRequest>,
+    tx: mpsc::Sender<CallbackRequest>,
+    shutdown_channel: oneshot::Receiver<()>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Fixture {
+    request: serde_json::Value,
+    #[serde(flatten)]
+    response: CallbackRecordResponse,
+}
+
+impl Fixture {
+    /// Returns true when the serialized `CallbackRequestType` matches the fixture
+    fn matches(&self, request: &serde_json::Value) -> bool {
+        let (Some(fixture), Some(request)) = (self.request.as_object(), request.as_object()) else {
+            return false;
+        };
+
+        fixture.iter().all(|(kind, fixture_params)| {
+            let Some(request_params) = request.get(kind) else {
+                return false;
+            };
+            match fixture_params {
+                serde_json::Value::Object(fixture_params) => fixture_params
+                    .iter()
+                    .all(|(attribute, value)| request_params.get(attribute) == Some(value)),
+                serde_json::Value::Null => true,
+                _ => fixture_params == request_params,
+            }
+        })
+    }
+}
+
+impl FixtureCallbackHandler {
+    /// Create a new `FixtureCallbackHandler` that uses the fixtures defined
+    /// inside of the given directory
+    pub fn new(dir: &Path, shutdown_channel: oneshot::Receiver<()>) -> Result<Self> {
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(|e| anyhow!("cannot read fixtures directory {}: {e}", dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(|e| anyhow!("cannot read fixtures directory {}: {e}", dir.display()))?;
+        paths.sort();
+
+        let mut fixtures = Vec::new();
+        for path in paths {
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("yaml") | Some("yml") | Some("json") => {
+                    fixtures.extend(load_fixtures(&path)?);
+                }
+                _ => debug!(path = ?path, "ignoring file without fixtures"),
+            }
+        }
+
+        let (tx, rx) = mpsc::channel::<CallbackRequest>(DEFAULT_CHANNEL_BUFF_SIZE);
+
+        Ok(Self {
+            fixtures,
+            missing_fixture_error: None,
+            rx,
+            tx,
+            shutdown_channel,
+        })
+    }
+
+    /// Set the error message returned when no fixture matches a request.
+    /// By default the error message contains the request.
+    pub fn missing_fixture_error(mut self, message: &str) -> Self {
+        self.missing_fixture_error = Some(message.to_owned());
+        self
+    }
+
+    /// Returns the sender side of the channel that can be used by the sync code
+    /// to request the computation of async code.
+    ///
+    /// Can be invoked as many times as wanted.
+    pub fn sender_channel(&self) -> mpsc::Sender<CallbackRequest> {
+        self.tx.clone()
+    }
+
+    /// Enter an endless loop that answers the requests using the fixtures.
+    ///
+    /// The loop is interrupted only when a message is sent over the
+    /// `shutdown_channel`.
+    pub async fn loop_eval(&mut self) {
+        loop {
+            tokio::select! {
+                _ = &mut self.shutdown_channel => {
+                    return;
+                },
+                req = self.rx.recv() => {
+                    if let Some(req) = req {
+                        self.handle_request(req);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_request(&self, req: CallbackRequest) {
+        let response = self.response(&req);
+        if let Err(e) = req.response_channel.send(response) {
+            warn!("callback handler: cannot send response back: {:?}", e);
+        }
+    }
+
+    fn response(&self, req: &CallbackRequest) -> Result<CallbackResponse> {
+        let request = serde_json::to_value(&req.request)?;
+
+        match self.fixtures.iter().find(|f| f.matches(&request)) {
+            Some(fixture) => {
+                debug!(request = ?req.request, "callback request answered by fixture");
+                fixture.response.clone().into_response()
+            }
+            None => Err(match &self.missing_fixture_error {
+                Some(message) => anyhow!(message.clone()),
+                None => anyhow!("no fixture found for callback request {:?}", req.request),
+            }),
+        }
+    }
+}
+
+fn load_fixtures(path: &Path) -> Result<Vec<Fixture>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("cannot read fixtures file {}: {e}", path.display()))?;
+    // YAML is a superset of JSON
+    let fixtures: Vec<Fixture> = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow!("cannot parse fixtures file {}: {e}", path.display()))?;
+
+    for fixture in &fixtures {
+        match fixture.request.as_object() {
+            Some(request) if request.len() == 1 => {}
+            _ => {
+                return Err(anyhow!(
+                    "invalid fixture inside of {}: the request must have exactly one type",
+                    path.display()
+                ))
+            }
+        }
+    }
+
+    Ok(fixtures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::callback_requests::CallbackRequestType;
+
+    const FIXTURES_YAML: &str = r#"
+- request:
+    KubernetesGetResource:
+      api_version: v1
+      kind: Namespace
+      name: default
+  payload:
+    apiVersion: v1
+    kind: Namespace
+    metadata:
+      name: default
+- request:
+    DNSLookupHost:
+      host: example.com
+  error: lookup failed
+"#;
+
+    const FIXTURES_JSON: &str = r#"[
+  {
+    "request": {"OciManifestDigest": {"image": "ghcr.io/kubewarden/policy:v1"}},
+    "payload": "sha256:1"
+  },
+  {
+    "request": {"OciManifestDigest": {}},
+    "payload": "sha256:any"
+  }
+]"#;
+
+    fn handler(missing_fixture_error: Option<&str>) -> FixtureCallbackHandler {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), FIXTURES_YAML).unwrap();
+        std::fs::write(dir.path().join("b.json"), FIXTURES_JSON).unwrap();
+        std::fs::write(dir.path().join("README.md"), "not a fixture").unwrap();
+
+        let (_, shutdown_rx) = oneshot::channel();
+        let mut handler = FixtureCallbackHandler::new(dir.path(), shutdown_rx).unwrap();
+        if let Some(message) = missing_fixture_error {
+            handler = handler.missing_fixture_error(message);
+        }
+
+        handler
+    }
+
+    fn response(
+        handler: &FixtureCallbackHandler,
+        request: CallbackRequestType,
+    ) -> Result<serde_json::Value> {
+        let (tx, _) = oneshot::channel();
+        let req = CallbackRequest {
+            request,
+            origin: None,
+            span: tracing::Span::current(),
+            response_channel: tx,
+        };
+
+        handler
+            .response(&req)
+            .map(|r| serde_json::from_slice(&r.payload).unwrap())
+    }
+
+    #[rstest]
+    #[case::all_attributes(
+        CallbackRequestType::OciManifestDigest { image: "ghcr.io/kubewarden/policy:v1".to_string() },
+        json!("sha256:1")
+    )]
+    #[case::any_attribute(
+        CallbackRequestType::OciManifestDigest { image: "ghcr.io/kubewarden/policy:v2".to_string() },
+        json!("sha256:any")
+    )]
+    #[case::unspecified_attributes(
+        CallbackRequestType::KubernetesGetResource {
+            api_version: "v1".to_string(),
+            kind: "Namespace".to_string(),
+            name: "default".to_string(),
+            namespace: None,
+            disable_cache: true,
+        },
+        json!({"apiVersion": "v1", "kind": "Namespace", "metadata": {"name": "default"}})
+    )]
+    fn request_matching_fixture(
+        #[case] request: CallbackRequestType,
+        #[case] expected: serde_json::Value,
+    ) {
+        let handler = handler(None);
+
+        assert_eq!(expected, response(&handler, request).unwrap());
+    }
+
+    #[test]
+    fn fixture_with_error() {
+        let handler = handler(None);
+
+        let response = response(
+            &handler,
+            CallbackRequestType::DNSLookupHost {
+                host: "example.com".to_string(),
+            },
+        );
+
+        assert_eq!("lookup failed", response.unwrap_err().to_string());
+    }
+
+    #[rstest]
+    #[case::default_error(None, "no fixture found for callback request")]
+    #[case::custom_error(Some("not allowed"), "not allowed")]
+    fn request_without_fixture(
+        #[case] missing_fixture_error: Option<&str>,
+        #[case] expected_error: &str,
+    ) {
+        let handler = handler(missing_fixture_error);
+
+        let response = response(
+            &handler,
+            CallbackRequestType::KubernetesGetResource {
+                api_version: "v1".to_string(),
+                kind: "Namespace".to_string(),
+                name: "kube-system".to_string(),
+                namespace: None,
+                disable_cache: false,
+            },
+        );
+
+        assert!(response
+            .unwrap_err()
+            .to_string()
+            .starts_with(expected_error));
+    }
+
+    #[test]
+    fn invalid_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("fixtures.yaml"),
+            "- request: OciManifestDigest\n  payload: sha256:1\n",
+        )
+        .unwrap();
+
+        let (_, shutdown_rx) = oneshot::channel();
+        assert!(FixtureCallbackHandler::new(dir.path(), shutdown_rx).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::callback_requests::CallbackRequestType;
+use crate::errors::HostCallbackError;
+
+/// Group of host capabilities sharing the same rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CapabilityGroup {
+    /// Access to OCI registries (manifests, digests and configs)
+    Oci,
+    /// Verification of Sigstore signatures
+    Sigstore,
+    /// Access to the Kubernetes API server
+    Kubernetes,
+    /// Network operations, like DNS lookups
+    Net,
+}
+
+impl From<&CallbackRequestType> for CapabilityGroup {
+    fn from(request: &CallbackRequestType) -> Self {
+        match request {
+            CallbackRequestType::OciManifestDigest { .. }
+            | CallbackRequestType::OciManifest { .. }
+            | CallbackRequestType::OciManifestAndConfig { .. } => CapabilityGroup::Oci,
+            CallbackRequestType::SigstorePubKeyVerify { .. }
+            | CallbackRequestType::SigstoreKeylessVerify { .. }
+            | CallbackRequestType::SigstoreKeylessPrefixVerify { .. }
+            | CallbackRequestType::SigstoreGithubActionsVerify { .. }
+            | CallbackRequestType::SigstoreCertificateVerify { .. } => CapabilityGroup::Sigstore,
+            CallbackRequestType::KubernetesListResourceNamespace { .. }
+            | CallbackRequestType::KubernetesListResourceAll { .. }
+            | CallbackRequestType::KubernetesGetResource { .. }
+            | CallbackRequestType::KubernetesGetResourcePluralName { .. }
+            | CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                ..
+            } => CapabilityGroup::Kubernetes,
+            CallbackRequestType::DNSLookupHost { .. } => CapabilityGroup::Net,
+        }
+    }
+}
+
+impl fmt::Display for CapabilityGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapabilityGroup::Oci => write!(f, "oci"),
+            CapabilityGroup::Sigstore => write!(f, "sigstore"),
+            CapabilityGroup::Kubernetes => write!(f, "kubernetes"),
+            CapabilityGroup::Net => write!(f, "net"),
+        }
+    }
+}
+
+/// Token bucket limit applied to each policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Number of requests that can be made in a burst
+    pub burst: u32,
+    /// Number of requests per second that are added back to the bucket
+    pub requests_per_second: f64,
+}
+
+impl RateLimit {
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        Self {
+            burst,
+            requests_per_second,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Keeps track of the token buckets of each policy, one per capability group.
+///
+/// The limiter is obtained via
+/// [`CallbackHandler::rate_limiter`](crate::callback_handler::CallbackHandler::rate_limiter)
+/// and is enforced by the policy evaluators before sending a request to the
+/// `CallbackHandler`, see
+/// [`EvaluationContext::callback_rate_limiter`](crate::evaluation_context::EvaluationContext::callback_rate_limiter).
+/// All the clones of a limiter share the same token buckets.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    limits: Arc<HashMap<CapabilityGroup, RateLimit>>,
+    buckets: Arc<Mutex<HashMap<(String, CapabilityGroup), TokenBucket>>>,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: HashMap<CapabilityGroup, RateLimit>) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Consume a token from the bucket of the policy. An error is returned
+    /// when the bucket is empty. Capability groups without a limit are never
+    /// rate limited
+    pub(crate) fn acquire(
+        &self,
+        policy_id: &str,
+        group: CapabilityGroup,
+        now: Instant,
+    ) -> Result<(), HostCallbackError> {
+        let Some(limit) = self.limits.get(&group) else {
+            return Ok(());
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry((policy_id.to_owned(), group))
+            .or_insert_with(|| TokenBucket {
+                tokens: f64::from(limit.burst),
+                last_refill: now,
+            });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * limit.requests_per_second)
+            .min(f64::from(limit.burst));
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(HostCallbackError::RateLimited {
+                policy_id: policy_id.to_owned(),
+                group,
+            });
+        }
+        bucket.tokens -= 1.0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn token_bucket_is_refilled() {
+        let limiter = RateLimiter::new(HashMap::from([(
+            CapabilityGroup::Oci,
+            RateLimit::new(2.0, 2),
+        )]));
+        let now = Instant::now();
+
+        assert!(limiter.acquire("policy", CapabilityGroup::Oci, now).is_ok());
+        assert!(limiter.acquire("policy", CapabilityGroup::Oci, now).is_ok());
+        assert!(matches!(
+            limiter.acquire("policy", CapabilityGroup::Oci, now),
+            Err(HostCallbackError::RateLimited {
+                group: CapabilityGroup::Oci,
+                ..
+            })
+        ));
+
+        // other policies have their own bucket
+        assert!(limiter.acquire("other", CapabilityGroup::Oci, now).is_ok());
+
+        // one token is added every 500 milliseconds
+        let later = now + Duration::from_millis(500);
+        assert!(limiter
+            .acquire("policy", CapabilityGroup::Oci, later)
+            .is_ok());
+        assert!(limiter
+            .acquire("policy", CapabilityGroup::Oci, later)
+            .is_err());
+    }
+
+    #[test]
+    fn groups_without_limit_are_not_rate_limited() {
+        let limiter = RateLimiter::new(HashMap::from([(
+            CapabilityGroup::Oci,
+            RateLimit::new(1.0, 1),
+        )]));
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert!(limiter
+                .acquire("policy", CapabilityGroup::Kubernetes, now)
+                .is_ok());
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::callback_requests::{CallbackRequest, CallbackRequestOrigin, CallbackResponse};
+
+/// A request served by the `CallbackHandler`, together with its response.
+///
+/// Recordings are stored using the JSON Lines format, one record per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CallbackRecord {
+    /// The evaluation that made the request
+    pub origin: Option<CallbackRequestOrigin>,
+    /// The serialized `CallbackRequestType`
+    pub request: serde_json::Value,
+    pub response: CallbackRecordResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CallbackRecordResponse {
+    /// The payload given back to the policy
+    Payload(serde_json::Value),
+    /// The payload given back to the policy, when it is not a JSON document.
+    /// The payload is base64 encoded
+    #[serde(rename = "raw_payload")]
+    RawPayload(String),
+    /// The request could not be fulfilled
+    Error(String),
+}
+
+impl CallbackRecordResponse {
+    fn new(response: &Result<CallbackResponse>) -> Self {
+        match response {
+            Ok(response) => serde_json::from_slice(&response.payload)
+                .map(CallbackRecordResponse::Payload)
+                .unwrap_or_else(|_| {
+                    CallbackRecordResponse::RawPayload(
+                        general_purpose::STANDARD.encode(&response.payload),
+                    )
+                }),
+            Err(e) => CallbackRecordResponse::Error(e.to_string()),
+        }
+    }
+
+    pub(crate) fn into_response(self) -> Result<CallbackResponse> {
+        match self {
+            CallbackRecordResponse::Payload(payload) => Ok(CallbackResponse {
+                payload: serde_json::to_vec(&payload)?,
+                was_cached: false,
+            }),
+            CallbackRecordResponse::RawPayload(payload) => Ok(CallbackResponse {
+                payload: general_purpose::STANDARD
+                    .decode(payload)
+                    .map_err(|e| anyhow!("raw payload is not base64 encoded: {e}"))?,
+                was_cached: false,
+            }),
+            CallbackRecordResponse::Error(e) => Err(anyhow!(e)),
+        }
+    }
+}
+
+/// Writes all the requests served by the `CallbackHandler`, and their
+/// responses, to a file
+pub(crate) struct CallbackRecorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl CallbackRecorder {
+    /// Create a new recorder, the file is truncated if it already exists
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("cannot create recording file {}: {e}", path.display()))?;
+
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Start recording the given request. Its response must be given to
+    /// [`PendingRecord::finish`] before being sent back.
+    ///
+    /// The response channel of the request is left untouched, hence the
+    /// requests abandoned by their senders can still be detected and
+    /// cancelled. These requests are not recorded
+    pub(crate) fn start(self: &Arc<Self>, req: &CallbackRequest) -> Option<PendingRecord> {
+        match serde_json::to_value(&req.request) {
+            Ok(request) => Some(PendingRecord {
+                recorder: self.clone(),
+                origin: req.origin.clone(),
+                request,
+            }),
+            Err(e) => {
+                warn!(error = ?e, "callback handler: cannot record request");
+                None
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        origin: Option<CallbackRequestOrigin>,
+        request: serde_json::Value,
+        response: &Result<CallbackResponse>,
+    ) -> Result<()> {
+        let record = CallbackRecord {
+            origin,
+            request,
+            response: CallbackRecordResponse::new(response),
+        };
+        let line = serde_json::to_string(&record)?;
+
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{line}")?;
+        // flush every record, recordings must be usable even when the process
+        // is abruptly terminated
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// A request that is being recorded, waiting for its response
+pub(crate) struct PendingRecord {
+    recorder: Arc<CallbackRecorder>,
+    origin: Option<CallbackRequestOrigin>,
+    request: serde_json::Value,
+}
+
+impl PendingRecord {
+    /// Record the response given to the request
+    pub(crate) fn finish(self, response: &Result<CallbackResponse>) {
+        if let Err(e) = self.recorder.write(self.origin, self.request, response) {
+            warn!(error = ?e, "callback handler: cannot record response");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::callback_requests::CallbackRequestType;
+
+    #[test]
+    fn record_request_and_response() {
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let recorder = Arc::new(CallbackRecorder::new(recording.path()).unwrap());
+        let origin = Some(CallbackRequestOrigin {
+            policy_id: "policy".to_string(),
+            request_uid: Some("uid".to_string()),
+        });
+
+        let (tx, _rx) = tokio::sync::oneshot::channel::<Result<CallbackResponse>>();
+        let req = CallbackRequest {
+            request: CallbackRequestType::DNSLookupHost {
+                host: "localhost".to_string(),
+            },
+            origin: origin.clone(),
+            span: tracing::Span::current(),
+            response_channel: tx,
+        };
+        recorder.start(&req).unwrap().finish(&Ok(CallbackResponse {
+            payload: serde_json::to_vec(&json!({"ips": ["127.0.0.1"]})).unwrap(),
+            was_cached: false,
+        }));
+
+        let recorded = std::fs::read_to_string(recording.path()).unwrap();
+        let record: CallbackRecord = serde_json::from_str(recorded.trim_end()).unwrap();
+        assert_eq!(
+            CallbackRecord {
+                origin,
+                request: json!({"DNSLookupHost": {"host": "localhost"}}),
+                response: CallbackRecordResponse::Payload(json!({"ips": ["127.0.0.1"]})),
+            },
+            record
+        );
+    }
+
+    #[test]
+    fn record_non_json_payload() {
+        let payload = b"\x00not a JSON document".to_vec();
+        let response = CallbackRecordResponse::new(&Ok(CallbackResponse {
+            payload: payload.clone(),
+            was_cached: false,
+        }));
+
+        assert_eq!(
+            CallbackRecordResponse::RawPayload(general_purpose::STANDARD.encode(&payload)),
+            response
+        );
+        // the recorded payload is replayed as it was received
+        assert_eq!(payload, response.into_response().unwrap().payload);
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+use super::recording::{CallbackRecord, CallbackRecordResponse};
+use super::DEFAULT_CHANNEL_BUFF_SIZE;
+use crate::callback_requests::{CallbackRequest, CallbackRequestOrigin};
+
+/// A drop-in replacement of the `CallbackHandler` that serves the requests
+/// using a recording produced via
+/// [`CallbackHandlerBuilder::record_to`](crate::callback_handler::CallbackHandlerBuilder::record_to).
+///
+/// No OCI registry, Sigstore infrastructure or Kubernetes cluster is
+/// contacted, which allows to reproduce the evaluation of a policy offline.
+///
+/// Requests are matched against the recording by looking at their type and
+/// their parameters. The responses are served in the same order they have been
+/// recorded, first by looking at the ones made by the same evaluation (policy id
+/// and request uid), then by looking at all the recorded ones. Once all the
+/// matching responses have been served, the last one is served again.
+pub struct ReplayCallbackHandler {
+    by_origin: HashMap<(CallbackRequestOrigin, String), ReplayQueue>,
+    by_request: HashMap<String, ReplayQueue>,
+    rx: mpsc::Receiver<CallbackRequest>,
+    tx: mpsc::Sender<CallbackRequest>,
+    shutdown_channel: oneshot::Receiver<()>,
+}
+
+/// The recorded responses of a given request, in recording order
+#[derive(Default)]
+struct ReplayQueue(VecDeque<CallbackRecordResponse>);
+
+impl ReplayQueue {
+    fn next(&mut self) -> Option<CallbackRecordResponse> {
+        if self.0.len() > 1 {
+            self.0.pop_front()
+        } else {
+            self.0.front().cloned()
+        }
+    }
+}
+
+impl ReplayCallbackHandler {
+    /// Create a new `ReplayCallbackHandler` that serves the recording stored at
+    /// the given path
+    pub fn new(path: &Path, shutdown_channel: oneshot::Receiver<()>) -> Result<Self> {
+        let recording = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read recording file {}: {e}", path.display()))?;
+
+        let mut by_origin: HashMap<(CallbackRequestOrigin, String), ReplayQueue> = HashMap::new();
+        let mut by_request: HashMap<String, ReplayQueue> = HashMap::new();
+        for (line_number, line) in recording.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: CallbackRecord = serde_json::from_str(line).map_err(|e| {
+                anyhow!(
+                    "cannot parse line {} of recording file {}: {e}",
+                    line_number + 1,
+                    path.display()
+                )
+            })?;
+
+            let key = replay_key(record.request);
+            if let Some(origin) = record.origin {
+                by_origin
+                    .entry((origin, key.clone()))
+                    .or_default()
+                    .0
+                    .push_back(record.response.clone());
+            }
+            by_request
+                .entry(key)
+                .or_default()
+                .0
+                .push_back(record.response);
+        }
+
+        let (tx, rx) = mpsc::channel::<CallbackRequest>(DEFAULT_CHANNEL_BUFF_SIZE);
+
+        Ok(Self {
+            by_origin,
+            by_request,
+            rx,
+            tx,
+            shutdown_channel,
+        })
+    }
+
+    /// Returns the sender side of the channel that can be used by the sync code
+    /// to request the computation of async code.
+    ///
+    /// Can be invoked as many times as wanted.
+    pub fn sender_channel(&self) -> mpsc::Sender<CallbackRequest> {
+        self.tx.clone()
+    }
+
+    /// Enter an endless loop that serves the requests using the recording.
+    ///
+    /// The loop is interrupted only when a message is sent over the
+    /// `shutdown_channel`.
+    pub async fn loop_eval(&mut self) {
+        loop {
+            tokio::select! {
+                _ = &mut self.shutdown_channel => {
+                    return;
+                },
+                req = self.rx.recv() => {
+                    if let Some(req) = req {
+                        self.handle_request(req);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_request(&mut self, req: CallbackRequest) {
+        let response = self.replay(&req).ok_or_else(|| {
+            anyhow!(
+                "no recorded response for callback request {:?}",
+                req.request
+            )
+        });
+
+        let response = response.and_then(|r| {
+            debug!(request = ?req.request, "replaying recorded callback response");
+            r.into_response()
+        });
+        if let Err(e) = req.response_channel.send(response) {
+            warn!("callback handler: cannot send response back: {:?}", e);
+        }
+    }
+
+    fn replay(&mut self, req: &CallbackRequest) -> Option<CallbackRecordResponse> {
+        let key = replay_key(serde_json::to_value(&req.request).ok()?);
+
+        if let Some(origin) = &req.origin {
+            if let Some(queue) = self.by_origin.get_mut(&(origin.clone(), key.clone())) {
+                return queue.next();
+            }
+        }
+
+        self.by_request.get_mut(&key).and_then(ReplayQueue::next)
+    }
+}
+
+/// Compute the key used to match a serialized `CallbackRequestType` against
+/// the recorded ones.
+///
+/// The `since` attribute holds an instant relative to the moment the request
+/// has been made, hence it is not taken into account.
+fn replay_key(mut request: serde_json::Value) -> String {
+    if let Some(variant) = request.as_object_mut() {
+        for params in variant.values_mut() {
+            if let Some(params) = params.as_object_mut() {
+                params.remove("since");
+            }
+        }
+    }
+
+    request.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use std::io::Write;
+    use tokio::time::Instant;
+
+    use crate::callback_requests::{CallbackRequestType, CallbackResponse};
+
+    fn record(
+        origin: Option<CallbackRequestOrigin>,
+        request: CallbackRequestType,
+        response: CallbackRecordResponse,
+    ) -> String {
+        serde_json::to_string(&CallbackRecord {
+            origin,
+            request: serde_json::to_value(request).unwrap(),
+            response,
+        })
+        .unwrap()
+    }
+
+    fn origin(request_uid: &str) -> Option<CallbackRequestOrigin> {
+        Some(CallbackRequestOrigin {
+            policy_id: "policy".to_string(),
+            request_uid: Some(request_uid.to_string()),
+        })
+    }
+
+    fn digest_request() -> CallbackRequestType {
+        CallbackRequestType::OciManifestDigest {
+            image: "ghcr.io/kubewarden/policy:v1".to_string(),
+        }
+    }
+
+    async fn send(
+        tx: &mpsc::Sender<CallbackRequest>,
+        origin: Option<CallbackRequestOrigin>,
+        request: CallbackRequestType,
+    ) -> Result<CallbackResponse> {
+        let (response_tx, response_rx) = oneshot::channel();
+        tx.send(CallbackRequest {
+            request,
+            origin,
+            span: tracing::Span::current(),
+            response_channel: response_tx,
+        })
+        .await
+        .unwrap();
+
+        response_rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn replay_recorded_responses() {
+        let mut recording = tempfile::NamedTempFile::new().unwrap();
+        for line in [
+            record(
+                origin("uid-1"),
+                digest_request(),
+                CallbackRecordResponse::Payload(json!("sha256:1")),
+            ),
+            record(
+                origin("uid-2"),
+                digest_request(),
+                CallbackRecordResponse::Payload(json!("sha256:2")),
+            ),
+            record(
+                origin("uid-2"),
+                digest_request(),
+                CallbackRecordResponse::Payload(json!("sha256:3")),
+            ),
+            record(
+                None,
+                CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                    api_version: "v1".to_string(),
+                    kind: "Pod".to_string(),
+                    label_selector: None,
+                    field_selector: None,
+                    since: Instant::now(),
+                },
+                CallbackRecordResponse::Error("boom".to_string()),
+            ),
+        ] {
+            writeln!(recording, "{line}").unwrap();
+        }
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let mut handler = ReplayCallbackHandler::new(recording.path(), shutdown_rx).unwrap();
+        let tx = handler.sender_channel();
+        let handle = tokio::spawn(async move { handler.loop_eval().await });
+
+        // responses of the same evaluation are served in order, the last one
+        // is served again
+        for expected in ["sha256:2", "sha256:3", "sha256:3"] {
+            let response = send(&tx, origin("uid-2"), digest_request()).await;
+            assert_eq!(
+                serde_json::to_vec(&json!(expected)).unwrap(),
+                response.unwrap().payload
+            );
+        }
+
+        // unknown evaluation, use all the recorded responses
+        let response = send(&tx, origin("uid-3"), digest_request()).await;
+        assert_eq!(
+            serde_json::to_vec(&json!("sha256:1")).unwrap(),
+            response.unwrap().payload
+        );
+
+        // the instant is ignored when matching the request
+        let response = send(
+            &tx,
+            None,
+            CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                api_version: "v1".to_string(),
+                kind: "Pod".to_string(),
+                label_selector: None,
+                field_selector: None,
+                since: Instant::now(),
+            },
+        )
+        .await;
+        assert_eq!("boom", response.unwrap_err().to_string());
+
+        let response = send(
+            &tx,
+            None,
+            CallbackRequestType::DNSLookupHost {
+                host: "localhost".to_string(),
+            },
+        )
+        .await;
+        assert!(response.is_err());
+
+        shutdown_tx.send(()).unwrap();
+        handle.await.unwrap();
+    }
+}
@@ -4,6 +4,7 @@ use itertools::Itertools;
 use kubewarden_policy_sdk::host_capabilities::verification::{
     KeylessInfo, KeylessPrefixInfo, VerificationResponse,
 };
+use policy_fetcher::policy::Policy;
 use policy_fetcher::sigstore;
 use policy_fetcher::sigstore::trust::ManualTrustRoot;
 use policy_fetcher::sources::Sources;
@@ -67,6 +68,25 @@ impl Client {
         Ok(cosign_client)
     }
 
+    /// Verify the signatures of a policy fetched from an OCI registry, then
+    /// ensure the local copy of the policy is the one referenced by the
+    /// verified manifest. Returns the digest of the verified manifest
+    pub async fn verify_policy(
+        &mut self,
+        policy: &Policy,
+        verification_config: &LatestVerificationConfig,
+    ) -> Result<String> {
+        let digest = self
+            .verifier
+            .verify(&policy.uri, verification_config)
+            .await?;
+        self.verifier
+            .verify_local_file_checksum(policy, &digest)
+            .await?;
+
+        Ok(digest)
+    }
+
     pub async fn verify_public_key(
         &mut self,
         image: String,
@@ -12,6 +12,8 @@ use tokio::{sync::oneshot, time::Instant};
 pub struct CallbackResponse {
     /// The data to be given back to the waPC guest
     pub payload: Vec<u8>,
+    /// True when the response has been served from the cache
+    pub was_cached: bool,
 }
 
 /// A request sent by some synchronous code (usually waPC's host_callback)
@@ -20,10 +22,26 @@ pub struct CallbackResponse {
 pub struct CallbackRequest {
     /// The actual request to be evaluated
     pub request: CallbackRequestType,
+    /// The evaluation that originated the request, when known
+    pub origin: Option<CallbackRequestOrigin>,
+    /// The span that was active when the request has been made. The request
+    /// is evaluated inside of it, allowing to follow an admission request
+    /// across the evaluator and the `CallbackHandler`
+    pub span: tracing::Span,
     /// A tokio oneshot channel over which the evaluation response has to be sent
     pub response_channel: oneshot::Sender<Result<CallbackResponse>>,
 }
 
+/// Identifies the policy evaluation that made a `CallbackRequest`
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CallbackRequestOrigin {
+    /// The id of the policy
+    pub policy_id: String,
+    /// The uid of the request being evaluated, `None` when the policy is
+    /// validating its settings
+    pub request_uid: Option<String>,
+}
+
 /// Describes the different kinds of request a waPC guest can make to
 /// our host.
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
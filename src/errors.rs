@@ -34,11 +34,34 @@ pub enum ArtifactHubError {
 
 #[derive(Error, Debug)]
 pub enum PolicyEvaluatorError {
+    #[deprecated(
+        since = "0.19.0",
+        note = "the protocol version of all the policies is known, this error is never returned"
+    )]
     #[error("protocol_version is only applicable to a Kubewarden policy")]
     InvalidProtocolVersion(),
 
     #[error("protocol_version is only applicable to a Kubewarden policy")]
     InvokeWapcProtocolVersion(#[source] crate::runtimes::wapc::errors::WapcRuntimeError),
+
+    #[error("cannot run 'protocol-version' command: {0}")]
+    InvokeWasiProtocolVersion(#[source] crate::runtimes::wasi_cli::errors::WasiRuntimeError),
+
+    #[error("policy protocol version {found:?} is not among the supported ones: {supported:?}")]
+    UnsupportedProtocolVersion {
+        found: kubewarden_policy_sdk::metadata::ProtocolVersion,
+        supported: Vec<kubewarden_policy_sdk::metadata::ProtocolVersion>,
+    },
+
+    #[error("cannot invoke 'protocol-version' export: {0}")]
+    InvokeWasiComponentProtocolVersion(
+        #[source] crate::runtimes::wasi_component::errors::WasiComponentRuntimeError,
+    ),
+
+    #[error(
+        "the {0} policy cannot be evaluated asynchronously, it would block the current thread"
+    )]
+    AsyncEvaluationNotSupported(String),
 }
 
 #[derive(Error, Debug)]
@@ -64,8 +87,37 @@ pub enum PolicyEvaluatorBuilderError {
     #[error("error when building wasi precompiled stack: {0}")]
     NewWasiStackPre(#[source] crate::runtimes::wasi_cli::errors::WasiRuntimeError),
 
+    #[error("error when building wasi component precompiled stack: {0}")]
+    NewWasiComponentStackPre(
+        #[source] crate::runtimes::wasi_component::errors::WasiComponentRuntimeError,
+    ),
+
     #[error("error when building rego precompiled stack")]
     NewRegoStackPre(#[source] wasmtime::Error),
+
+    #[error("error when configuring rego settings validation: {0}")]
+    RegoSettingsValidation(#[source] crate::runtimes::rego::errors::RegoRuntimeError),
+
+    #[error("cannot select the rego entrypoints: {0}")]
+    RegoEntrypoints(#[source] crate::runtimes::rego::errors::RegoRuntimeError),
+
+    #[error("cannot fetch policy {uri}: {error}")]
+    FetchPolicy { uri: String, error: String },
+
+    #[error("cannot add policy to the local store: {0}")]
+    PolicyStore(#[source] std::io::Error),
+
+    #[error("cannot read policy from the local store: {0}")]
+    ReadStoredPolicy(#[source] std::io::Error),
+
+    #[error("cannot read policy metadata: {0}")]
+    Metadata(#[source] MetadataError),
+
+    #[error("policy signature verification failed: {0}")]
+    PolicyVerification(String),
+
+    #[error("the policy is not the one that has been verified")]
+    UnverifiedPolicy,
 }
 
 #[derive(Error, Debug)]
@@ -77,6 +129,76 @@ pub enum PolicyEvaluatorPreError {
     RehydrateRego(#[source] crate::runtimes::rego::errors::RegoRuntimeError),
 }
 
+#[derive(Error, Debug)]
+pub enum PolicyEvaluatorPoolError {
+    #[error("policy evaluator pool exhausted: all the {0} instances are in use")]
+    Exhausted(usize),
+
+    #[error("cannot create policy evaluator: {0}")]
+    Rehydrate(#[source] PolicyEvaluatorPreError),
+}
+
+#[derive(Error, Debug)]
+pub enum PolicyGroupError {
+    #[error("invalid policy group expression: {0}")]
+    InvalidExpression(String),
+
+    #[error("policy group expression references unknown policy '{0}'")]
+    UnknownMember(String),
+}
+
+#[derive(Error, Debug)]
+pub enum MutationChainError {
+    #[error("policies '{first}' and '{second}' are both changing '{path}'")]
+    Conflict {
+        path: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("mutation chain did not reach a fixed point after {0} rounds")]
+    NoFixedPoint(usize),
+
+    #[error("invalid patch produced by policy '{member}': {error}")]
+    InvalidPatch { member: String, error: String },
+
+    #[error("cannot serialize patch: {0}")]
+    SerializePatch(#[source] serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum LabelSelectorError {
+    #[error("invalid label selector requirement for key '{key}': {reason}")]
+    InvalidRequirement { key: String, reason: String },
+
+    #[error("cannot send request over callback channel: {0}")]
+    CallbackSend(String),
+
+    #[error("cannot obtain namespace '{namespace}': {error}")]
+    Namespace { namespace: String, error: String },
+
+    #[error(transparent)]
+    HostCallback(#[from] HostCallbackError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HostCallbackError {
+    #[error("policy '{policy_id}' exceeded the rate limit of the {group} host capabilities")]
+    RateLimited {
+        policy_id: String,
+        group: crate::callback_handler::CapabilityGroup,
+    },
+
+    #[error("policy '{policy_id}' exceeded the maximum number of host callbacks per evaluation ({limit})")]
+    TooManyCallbacks { policy_id: String, limit: u32 },
+
+    #[error("request to the {group} host capabilities timed out after {timeout:?}")]
+    Timeout {
+        group: crate::callback_handler::CapabilityGroup,
+        timeout: std::time::Duration,
+    },
+}
+
 #[derive(Error, Debug)]
 pub enum MetadataError {
     #[error("cannot read metadata from path: {0}")]
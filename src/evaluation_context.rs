@@ -1,13 +1,22 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
-use crate::callback_requests::CallbackRequest;
-use crate::policy_metadata::ContextAwareResource;
+use crate::callback_handler::{CapabilityGroup, RateLimiter};
+use crate::callback_requests::{CallbackRequest, CallbackRequestType};
+use crate::errors::HostCallbackError;
+use crate::policy_metadata::{ContextAwareResource, HostCapability};
 
 /// A struct that holds metadata and other data that are needed when a policy
-/// is being evaluated
+/// is being evaluated.
+///
+/// Create it via [`EvaluationContext::new`], or via
+/// [`CallbackHandler::evaluation_context`](crate::callback_handler::CallbackHandler::evaluation_context)
+/// for the policies that make use of host callbacks, then customize it with
+/// the builder methods
 #[derive(Clone, Default)]
+#[non_exhaustive]
 pub struct EvaluationContext {
     /// The policy identifier. This is mostly relevant for Policy Server,
     /// which uses the identifier provided by the user inside of the `policy.yml`
@@ -20,11 +29,113 @@ pub struct EvaluationContext {
     /// asynchronous block
     pub callback_channel: Option<mpsc::Sender<CallbackRequest>>,
 
+    /// Rate limiter of the `CallbackHandler` serving `callback_channel`, see
+    /// [`CallbackHandler::rate_limiter`](crate::callback_handler::CallbackHandler::rate_limiter).
+    /// The requests exceeding the limits of the policy are rejected before
+    /// being sent over the channel. No limit is applied when `None`.
+    pub callback_rate_limiter: Option<RateLimiter>,
+
+    /// Timeouts of the `CallbackHandler` serving `callback_channel`, see
+    /// [`CallbackHandler::timeouts`](crate::callback_handler::CallbackHandler::timeouts).
+    /// The policy stops waiting for the response once the timeout of the
+    /// request is reached, including the time the request spent inside of
+    /// the channel. Requests can take an unbounded amount of time when the
+    /// timeout of their group is not set.
+    pub callback_timeouts: HashMap<CapabilityGroup, Duration>,
+
     /// List of ContextAwareResource the policy is granted access to.
     pub ctx_aware_resources_allow_list: BTreeSet<ContextAwareResource>,
+
+    /// List of host capabilities the policy is granted access to. When `None`,
+    /// the policy can use all the host capabilities.
+    ///
+    /// Logging is always allowed, while the access to Kubernetes resources is
+    /// also restricted by `ctx_aware_resources_allow_list`.
+    pub host_capabilities_allow_list: Option<BTreeSet<HostCapability>>,
+
+    /// Maximum number of host callbacks the policy can make during a single
+    /// evaluation, logging excluded. No limit is applied when `None`.
+    pub max_host_callbacks_per_evaluation: Option<u32>,
+
+    /// Capture the log entries produced by the policy during each evaluation,
+    /// see [`PolicyEvaluator::policy_logs`](crate::policy_evaluator::PolicyEvaluator::policy_logs).
+    /// The entries are always forwarded to `tracing`, regardless of this setting.
+    pub policy_log_capture: Option<PolicyLogCapture>,
+}
+
+/// Limits applied when capturing the log entries produced by a policy during
+/// a single evaluation. The entries exceeding them are discarded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PolicyLogCapture {
+    /// Maximum number of entries
+    pub max_entries: usize,
+    /// Maximum size of all the entries, expressed in bytes
+    pub max_bytes: usize,
 }
 
 impl EvaluationContext {
+    /// Create a new `EvaluationContext` for the given policy: no host
+    /// callback can be made and no limit is applied
+    pub fn new(policy_id: &str) -> Self {
+        Self {
+            policy_id: policy_id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the channel used to send the host callbacks to the `CallbackHandler`
+    #[must_use]
+    pub fn callback_channel(mut self, callback_channel: mpsc::Sender<CallbackRequest>) -> Self {
+        self.callback_channel = Some(callback_channel);
+        self
+    }
+
+    /// Set the rate limiter of the `CallbackHandler` serving the callback channel
+    #[must_use]
+    pub fn callback_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.callback_rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Set the timeouts of the `CallbackHandler` serving the callback channel
+    #[must_use]
+    pub fn callback_timeouts(mut self, timeouts: HashMap<CapabilityGroup, Duration>) -> Self {
+        self.callback_timeouts = timeouts;
+        self
+    }
+
+    /// Set the Kubernetes resources the policy is granted access to
+    #[must_use]
+    pub fn ctx_aware_resources_allow_list(
+        mut self,
+        allow_list: BTreeSet<ContextAwareResource>,
+    ) -> Self {
+        self.ctx_aware_resources_allow_list = allow_list;
+        self
+    }
+
+    /// Restrict the host capabilities the policy can use to the given ones
+    #[must_use]
+    pub fn host_capabilities_allow_list(mut self, allow_list: BTreeSet<HostCapability>) -> Self {
+        self.host_capabilities_allow_list = Some(allow_list);
+        self
+    }
+
+    /// Limit the number of host callbacks the policy can make during a
+    /// single evaluation
+    #[must_use]
+    pub fn max_host_callbacks_per_evaluation(mut self, max: u32) -> Self {
+        self.max_host_callbacks_per_evaluation = Some(max);
+        self
+    }
+
+    /// Capture the log entries produced by the policy during each evaluation
+    #[must_use]
+    pub fn policy_log_capture(mut self, policy_log_capture: PolicyLogCapture) -> Self {
+        self.policy_log_capture = Some(policy_log_capture);
+        self
+    }
+
     /// Checks if a policy has access to a Kubernetes resource, based on the privileges
     /// that have been granted by the user
     pub(crate) fn can_access_kubernetes_resource(&self, api_version: &str, kind: &str) -> bool {
@@ -36,6 +147,50 @@ impl EvaluationContext {
         self.ctx_aware_resources_allow_list
             .contains(&wanted_resource)
     }
+
+    /// Consume a token from the rate limit bucket of the policy, before sending
+    /// the given request over the callback channel
+    pub(crate) fn acquire_callback_rate_limit(
+        &self,
+        request: &CallbackRequestType,
+    ) -> Result<(), HostCallbackError> {
+        match &self.callback_rate_limiter {
+            Some(rate_limiter) => rate_limiter.acquire(
+                &self.policy_id,
+                CapabilityGroup::from(request),
+                Instant::now(),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// The group of the request and the maximum amount of time the policy
+    /// waits for its response, `None` when the wait is unbounded
+    pub(crate) fn callback_timeout(
+        &self,
+        request: &CallbackRequestType,
+    ) -> Option<(CapabilityGroup, Duration)> {
+        let group = CapabilityGroup::from(request);
+        self.callback_timeouts
+            .get(&group)
+            .map(|timeout| (group, *timeout))
+    }
+
+    /// Checks if a policy can use a host capability, based on the privileges
+    /// that have been granted by the user
+    pub(crate) fn can_use_host_capability(&self, namespace: &str, operation: &str) -> bool {
+        if namespace == "tracing" {
+            return true;
+        }
+
+        self.host_capabilities_allow_list
+            .as_ref()
+            .map_or(true, |allow_list| {
+                allow_list
+                    .iter()
+                    .any(|capability| capability.matches(namespace, operation))
+            })
+    }
 }
 
 impl fmt::Debug for EvaluationContext {
@@ -47,8 +202,15 @@ impl fmt::Debug for EvaluationContext {
 
         write!(
             f,
-            r#"EvaluationContext {{ policy_id: "{}", callback_channel: {}, allowed_kubernetes_resources: {:?} }}"#,
-            self.policy_id, callback_channel, self.ctx_aware_resources_allow_list,
+            r#"EvaluationContext {{ policy_id: "{}", callback_channel: {}, callback_rate_limiter: {:?}, callback_timeouts: {:?}, allowed_kubernetes_resources: {:?}, allowed_host_capabilities: {:?}, max_host_callbacks_per_evaluation: {:?}, policy_log_capture: {:?} }}"#,
+            self.policy_id,
+            callback_channel,
+            self.callback_rate_limiter,
+            self.callback_timeouts,
+            self.ctx_aware_resources_allow_list,
+            self.host_capabilities_allow_list,
+            self.max_host_callbacks_per_evaluation,
+            self.policy_log_capture,
         )
     }
 }
@@ -90,11 +252,7 @@ mod tests {
         #[case] kind: &str,
         #[case] allowed: bool,
     ) {
-        let ctx = EvaluationContext {
-            policy_id: name.to_string(),
-            callback_channel: None,
-            ctx_aware_resources_allow_list: allowed_resources,
-        };
+        let ctx = EvaluationContext::new(name).ctx_aware_resources_allow_list(allowed_resources);
 
         let requested_resource = ContextAwareResource {
             api_version: api_version.to_string(),
@@ -109,4 +267,79 @@ mod tests {
             )
         );
     }
+
+    #[rstest]
+    #[case::no_grant(None, "oci", "v2/verify", true)]
+    #[case::nothing_granted(Some(BTreeSet::new()), "oci", "v2/verify", false)]
+    #[case::logging_always_allowed(Some(BTreeSet::new()), "tracing", "log", true)]
+    #[case::granted_operation(
+        Some(BTreeSet::from([HostCapability {
+            namespace: "oci".to_string(),
+            operation: "v2/verify".to_string(),
+        }])),
+        "oci",
+        "v2/verify",
+        true,
+    )]
+    #[case::operation_not_granted(
+        Some(BTreeSet::from([HostCapability {
+            namespace: "oci".to_string(),
+            operation: "v2/verify".to_string(),
+        }])),
+        "oci",
+        "v1/manifest_digest",
+        false,
+    )]
+    fn can_use_host_capability(
+        #[case] allowed_capabilities: Option<BTreeSet<HostCapability>>,
+        #[case] namespace: &str,
+        #[case] operation: &str,
+        #[case] allowed: bool,
+    ) {
+        let ctx = EvaluationContext {
+            host_capabilities_allow_list: allowed_capabilities,
+            ..Default::default()
+        };
+
+        assert_eq!(allowed, ctx.can_use_host_capability(namespace, operation));
+    }
+
+    #[test]
+    fn new_evaluation_context_applies_no_limit() {
+        let ctx = EvaluationContext::new("test");
+
+        assert_eq!("test", ctx.policy_id);
+        assert!(ctx.callback_channel.is_none());
+        assert!(ctx.callback_rate_limiter.is_none());
+        assert!(ctx.callback_timeouts.is_empty());
+        assert!(ctx.ctx_aware_resources_allow_list.is_empty());
+        assert!(ctx.host_capabilities_allow_list.is_none());
+        assert!(ctx.max_host_callbacks_per_evaluation.is_none());
+        assert!(ctx.policy_log_capture.is_none());
+    }
+
+    #[test]
+    fn builder_methods_set_the_limits() {
+        let (tx, _rx) = mpsc::channel(1);
+        let timeouts = HashMap::from([(CapabilityGroup::Oci, Duration::from_secs(1))]);
+        let policy_log_capture = PolicyLogCapture {
+            max_entries: 10,
+            max_bytes: 1024,
+        };
+
+        let ctx = EvaluationContext::new("test")
+            .callback_channel(tx)
+            .callback_rate_limiter(RateLimiter::default())
+            .callback_timeouts(timeouts.clone())
+            .host_capabilities_allow_list(BTreeSet::new())
+            .max_host_callbacks_per_evaluation(5)
+            .policy_log_capture(policy_log_capture);
+
+        assert!(ctx.callback_channel.is_some());
+        assert!(ctx.callback_rate_limiter.is_some());
+        assert_eq!(timeouts, ctx.callback_timeouts);
+        assert_eq!(Some(BTreeSet::new()), ctx.host_capabilities_allow_list);
+        assert_eq!(Some(5), ctx.max_host_callbacks_per_evaluation);
+        assert_eq!(Some(policy_log_capture), ctx.policy_log_capture);
+    }
 }
@@ -0,0 +1,388 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector as KubeLabelSelector;
+use k8s_openapi::apimachinery::pkg::runtime::RawExtension;
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::sync::oneshot;
+
+use crate::admission_request::AdmissionRequest;
+use crate::callback_requests::{CallbackRequest, CallbackRequestOrigin, CallbackRequestType};
+use crate::errors::LabelSelectorError;
+use crate::evaluation_context::EvaluationContext;
+
+/// A Kubernetes label selector, like the `namespaceSelector` and the
+/// `objectSelector` of admission webhooks.
+///
+/// The selector is made of a list of requirements, all of them must be
+/// satisfied by the labels. An empty selector matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Requirement {
+    key: String,
+    operator: Operator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operator {
+    In(BTreeSet<String>),
+    NotIn(BTreeSet<String>),
+    Exists,
+    DoesNotExist,
+}
+
+impl Requirement {
+    fn new(key: &str, operator: &str, values: &[String]) -> Result<Self, LabelSelectorError> {
+        let invalid = |reason: &str| LabelSelectorError::InvalidRequirement {
+            key: key.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        if key.is_empty() {
+            return Err(invalid("the key cannot be empty"));
+        }
+
+        let operator = match operator {
+            "In" | "NotIn" if values.is_empty() => {
+                return Err(invalid(&format!(
+                    "values must be specified when the operator is '{operator}'"
+                )))
+            }
+            "Exists" | "DoesNotExist" if !values.is_empty() => {
+                return Err(invalid(&format!(
+                    "values cannot be specified when the operator is '{operator}'"
+                )))
+            }
+            "In" => Operator::In(values.iter().cloned().collect()),
+            "NotIn" => Operator::NotIn(values.iter().cloned().collect()),
+            "Exists" => Operator::Exists,
+            "DoesNotExist" => Operator::DoesNotExist,
+            _ => return Err(invalid(&format!("unknown operator '{operator}'"))),
+        };
+
+        Ok(Requirement {
+            key: key.to_owned(),
+            operator,
+        })
+    }
+
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        let value = labels.get(&self.key);
+        match &self.operator {
+            Operator::In(values) => value.is_some_and(|v| values.contains(v)),
+            Operator::NotIn(values) => value.map_or(true, |v| !values.contains(v)),
+            Operator::Exists => value.is_some(),
+            Operator::DoesNotExist => value.is_none(),
+        }
+    }
+}
+
+impl TryFrom<&KubeLabelSelector> for LabelSelector {
+    type Error = LabelSelectorError;
+
+    fn try_from(selector: &KubeLabelSelector) -> Result<Self, Self::Error> {
+        let mut requirements = Vec::new();
+
+        for (key, value) in selector.match_labels.iter().flatten() {
+            requirements.push(Requirement::new(key, "In", &[value.to_owned()])?);
+        }
+        for expression in selector.match_expressions.iter().flatten() {
+            requirements.push(Requirement::new(
+                &expression.key,
+                &expression.operator,
+                expression.values.as_deref().unwrap_or_default(),
+            )?);
+        }
+
+        Ok(LabelSelector { requirements })
+    }
+}
+
+impl LabelSelector {
+    /// Returns true when the selector has no requirements, hence it matches
+    /// everything
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    /// Returns true when the given labels satisfy all the requirements of the
+    /// selector
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|r| r.matches(labels))
+    }
+
+    /// Evaluate the selector as the `objectSelector` of an admission webhook:
+    /// the request is matched when either its `object` or its `old_object`
+    /// has matching labels.
+    ///
+    /// A missing object is treated as an object without labels.
+    pub fn matches_object(&self, request: &AdmissionRequest) -> bool {
+        self.matches(&object_labels(request.object.as_ref()))
+            || self.matches(&object_labels(request.old_object.as_ref()))
+    }
+
+    /// Evaluate the selector as the `namespaceSelector` of an admission webhook.
+    ///
+    /// The labels of the namespace of the request are obtained via the
+    /// `KubernetesGetResource` host capability, hence the `CallbackHandler`
+    /// must be able to reach the Kubernetes cluster. The lookup is made over
+    /// the callback channel of `eval_ctx`, on behalf of the policy the selector
+    /// belongs to: it is subject to the rate limit of the policy.
+    ///
+    /// Following the Kubernetes semantics, requests about a `Namespace` are
+    /// matched against the labels of the namespace itself, while requests about
+    /// other cluster-wide resources are always matched.
+    pub async fn matches_namespace(
+        &self,
+        request: &AdmissionRequest,
+        eval_ctx: &EvaluationContext,
+    ) -> Result<bool, LabelSelectorError> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+        if request.resource.group.is_empty() && request.resource.resource == "namespaces" {
+            return Ok(self.matches_object(request));
+        }
+
+        match request.namespace.as_deref() {
+            Some(namespace) if !namespace.is_empty() => {
+                let labels = namespace_labels(namespace, &request.uid, eval_ctx).await?;
+                Ok(self.matches(&labels))
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+/// Extract the labels from a Kubernetes object
+fn object_labels(object: Option<&RawExtension>) -> BTreeMap<String, String> {
+    object
+        .and_then(|object| object.0.pointer("/metadata/labels"))
+        .and_then(serde_json::Value::as_object)
+        .map(|labels| {
+            labels
+                .iter()
+                .filter_map(|(k, v)| v.as_str().map(|v| (k.to_owned(), v.to_owned())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Fetch the labels of a namespace via the callback channel, on behalf of the
+/// policy evaluating the request with the given uid
+async fn namespace_labels(
+    namespace: &str,
+    request_uid: &str,
+    eval_ctx: &EvaluationContext,
+) -> Result<BTreeMap<String, String>, LabelSelectorError> {
+    let namespace_error = |error: String| LabelSelectorError::Namespace {
+        namespace: namespace.to_owned(),
+        error,
+    };
+
+    let callback_channel = eval_ctx
+        .callback_channel
+        .as_ref()
+        .ok_or_else(|| LabelSelectorError::CallbackSend("callback channel not set".to_owned()))?;
+    let request = CallbackRequestType::KubernetesGetResource {
+        api_version: "v1".to_string(),
+        kind: "Namespace".to_string(),
+        name: namespace.to_owned(),
+        namespace: None,
+        disable_cache: false,
+    };
+    eval_ctx.acquire_callback_rate_limit(&request)?;
+
+    let (tx, rx) = oneshot::channel();
+    let req = CallbackRequest {
+        request,
+        origin: Some(CallbackRequestOrigin {
+            policy_id: eval_ctx.policy_id.clone(),
+            request_uid: Some(request_uid.to_owned()),
+        }),
+        span: tracing::Span::current(),
+        response_channel: tx,
+    };
+    callback_channel
+        .send(req)
+        .await
+        .map_err(|e| LabelSelectorError::CallbackSend(e.to_string()))?;
+
+    let response = rx
+        .await
+        .map_err(|e| namespace_error(e.to_string()))?
+        .map_err(|e| namespace_error(e.to_string()))?;
+    let namespace_object: serde_json::Value =
+        serde_json::from_slice(&response.payload).map_err(|e| namespace_error(e.to_string()))?;
+
+    Ok(object_labels(Some(&RawExtension(namespace_object))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelectorRequirement;
+    use rstest::rstest;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use crate::callback_requests::CallbackResponse;
+
+    fn selector(
+        match_labels: &[(&str, &str)],
+        expressions: &[(&str, &str, &[&str])],
+    ) -> KubeLabelSelector {
+        KubeLabelSelector {
+            match_labels: Some(
+                match_labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            match_expressions: Some(
+                expressions
+                    .iter()
+                    .map(|(key, operator, values)| LabelSelectorRequirement {
+                        key: key.to_string(),
+                        operator: operator.to_string(),
+                        values: Some(values.iter().map(|v| v.to_string()).collect()),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn admission_request(
+        resource: &str,
+        namespace: Option<&str>,
+        object: Option<serde_json::Value>,
+        old_object: Option<serde_json::Value>,
+    ) -> AdmissionRequest {
+        serde_json::from_value(json!({
+            "uid": "uid",
+            "kind": {"group": "", "version": "v1", "kind": "Kind"},
+            "resource": {"group": "", "version": "v1", "resource": resource},
+            "namespace": namespace,
+            "operation": "UPDATE",
+            "userInfo": {},
+            "object": object,
+            "oldObject": old_object,
+        }))
+        .unwrap()
+    }
+
+    fn object_with_labels(labels: serde_json::Value) -> Option<serde_json::Value> {
+        Some(json!({"metadata": {"name": "test", "labels": labels}}))
+    }
+
+    #[rstest]
+    #[case::empty_selector(&[], &[], true)]
+    #[case::match_labels(&[("env", "prod")], &[], true)]
+    #[case::match_labels_other_value(&[("env", "dev")], &[], false)]
+    #[case::in_operator(&[], &[("env", "In", &["dev", "prod"])], true)]
+    #[case::in_operator_missing_key(&[], &[("tier", "In", &["web"])], false)]
+    #[case::not_in_operator(&[], &[("env", "NotIn", &["prod"])], false)]
+    #[case::not_in_operator_missing_key(&[], &[("tier", "NotIn", &["web"])], true)]
+    #[case::exists(&[], &[("env", "Exists", &[])], true)]
+    #[case::does_not_exist(&[], &[("env", "DoesNotExist", &[])], false)]
+    #[case::all_requirements(&[("env", "prod")], &[("team", "Exists", &[])], false)]
+    fn match_labels(
+        #[case] match_labels: &[(&str, &str)],
+        #[case] expressions: &[(&str, &str, &[&str])],
+        #[case] expected: bool,
+    ) {
+        let labels = BTreeMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("app".to_string(), "nginx".to_string()),
+        ]);
+        let selector = LabelSelector::try_from(&selector(match_labels, expressions)).unwrap();
+
+        assert_eq!(expected, selector.matches(&labels));
+    }
+
+    #[rstest]
+    #[case::unknown_operator(("env", "Equals", &["prod"]))]
+    #[case::in_without_values(("env", "In", &[]))]
+    #[case::exists_with_values(("env", "Exists", &["prod"]))]
+    #[case::empty_key(("", "Exists", &[]))]
+    fn invalid_selector(#[case] expression: (&str, &str, &[&str])) {
+        assert!(matches!(
+            LabelSelector::try_from(&selector(&[], &[expression])),
+            Err(LabelSelectorError::InvalidRequirement { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case::object(object_with_labels(json!({"env": "prod"})), None, true)]
+    #[case::old_object(None, object_with_labels(json!({"env": "prod"})), true)]
+    #[case::no_match(
+        object_with_labels(json!({"env": "dev"})),
+        object_with_labels(json!({"env": "dev"})),
+        false
+    )]
+    #[case::no_labels(Some(json!({"metadata": {"name": "test"}})), None, false)]
+    fn match_object(
+        #[case] object: Option<serde_json::Value>,
+        #[case] old_object: Option<serde_json::Value>,
+        #[case] expected: bool,
+    ) {
+        let selector = LabelSelector::try_from(&selector(&[("env", "prod")], &[])).unwrap();
+        let request = admission_request("pods", Some("default"), object, old_object);
+
+        assert_eq!(expected, selector.matches_object(&request));
+    }
+
+    #[rstest]
+    #[case::namespace_labels("pods", Some("prod-namespace"), None, true)]
+    #[case::other_namespace_labels("pods", Some("dev-namespace"), None, false)]
+    #[case::cluster_wide_resource("clusterroles", None, None, true)]
+    #[case::namespace_resource(
+        "namespaces",
+        None,
+        object_with_labels(json!({"env": "dev"})),
+        false
+    )]
+    #[tokio::test]
+    async fn match_namespace(
+        #[case] resource: &str,
+        #[case] namespace: Option<&str>,
+        #[case] object: Option<serde_json::Value>,
+        #[case] expected: bool,
+    ) {
+        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        tokio::spawn(async move {
+            while let Some(req) = callback_rx.recv().await {
+                let env = match req.request {
+                    CallbackRequestType::KubernetesGetResource { name, .. } => {
+                        name.trim_end_matches("-namespace").to_owned()
+                    }
+                    _ => panic!("unexpected request"),
+                };
+                let namespace = json!({"metadata": {"labels": {"env": env}}});
+                req.response_channel
+                    .send(Ok(CallbackResponse {
+                        payload: serde_json::to_vec(&namespace).unwrap(),
+                        was_cached: false,
+                    }))
+                    .unwrap();
+            }
+        });
+
+        let selector = LabelSelector::try_from(&selector(&[("env", "prod")], &[])).unwrap();
+        let request = admission_request(resource, namespace, object, None);
+        let eval_ctx = EvaluationContext {
+            callback_channel: Some(callback_tx),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            expected,
+            selector
+                .matches_namespace(&request, &eval_ctx)
+                .await
+                .unwrap()
+        );
+    }
+}
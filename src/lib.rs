@@ -8,8 +8,11 @@ pub mod callback_requests;
 pub mod constants;
 pub mod errors;
 pub mod evaluation_context;
+pub mod label_selector;
+pub mod mutation_chain;
 pub mod policy_artifacthub;
 pub mod policy_evaluator;
+pub mod policy_group_evaluator;
 pub mod policy_metadata;
 mod policy_tracing;
 pub mod runtimes;
@@ -0,0 +1,448 @@
+use base64::{engine::general_purpose, Engine as _};
+use k8s_openapi::apimachinery::pkg::runtime::RawExtension;
+use std::collections::{BTreeMap, HashMap};
+use tracing::debug;
+
+use crate::admission_response::AdmissionResponse;
+use crate::errors::MutationChainError;
+use crate::policy_evaluator::{PolicyEvaluator, PolicySettings, ValidateRequest};
+
+/// A mutating policy that is part of a [`MutationChain`]
+pub struct MutationChainMember {
+    /// Name of the member, used when reporting errors
+    pub name: String,
+
+    /// The evaluator of the policy
+    pub policy_evaluator: PolicyEvaluator,
+
+    /// The settings of the policy
+    pub settings: PolicySettings,
+}
+
+/// Runs an ordered list of mutating policies against the same request.
+///
+/// Each policy receives the object as already patched by the previous ones. The
+/// final response contains a single JSONPatch against the original object.
+///
+/// The evaluation fails when a policy changes a value written by a different
+/// policy of the chain.
+pub struct MutationChain {
+    members: Vec<MutationChainMember>,
+    max_rounds: usize,
+}
+
+impl MutationChain {
+    /// Create a new chain, the members are evaluated following the order of
+    /// the given list
+    pub fn new(members: Vec<MutationChainMember>) -> Self {
+        Self {
+            members,
+            max_rounds: 1,
+        }
+    }
+
+    /// Enable Kubernetes-style reinvocation: the whole chain is evaluated again
+    /// as long as one of its members changes the object, until a fixed point
+    /// is reached.
+    ///
+    /// `max_rounds` is the maximum number of times the chain is evaluated. An
+    /// error is returned when the object is still being changed after that.
+    /// The chain is always evaluated at least once, `0` is treated as `1`.
+    #[must_use]
+    pub fn enable_reinvocation(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    /// Evaluate the request against the chain.
+    ///
+    /// The evaluation stops at the first member that rejects the request,
+    /// its response is returned.
+    #[tracing::instrument(skip(self, request))]
+    pub fn validate(
+        &mut self,
+        request: ValidateRequest,
+    ) -> Result<AdmissionResponse, MutationChainError> {
+        let uid = request.uid().to_string();
+        let original = match &request {
+            ValidateRequest::Raw(obj) => obj.clone(),
+            ValidateRequest::AdmissionRequest(adm_req) => adm_req
+                .object
+                .as_ref()
+                .map(|obj| obj.0.clone())
+                .unwrap_or_default(),
+        };
+
+        let names: Vec<String> = self.members.iter().map(|m| m.name.clone()).collect();
+        let members = &mut self.members;
+
+        let outcome = run_chain(&names, &original, self.max_rounds, |index, obj| {
+            let member = &mut members[index];
+            let member_request = match &request {
+                ValidateRequest::Raw(_) => ValidateRequest::Raw(obj.clone()),
+                ValidateRequest::AdmissionRequest(adm_req) => {
+                    let mut adm_req = adm_req.clone();
+                    if adm_req.object.is_some() {
+                        adm_req.object = Some(RawExtension(obj.clone()));
+                    }
+                    ValidateRequest::AdmissionRequest(adm_req)
+                }
+            };
+            member
+                .policy_evaluator
+                .validate(member_request, &member.settings)
+        })?;
+
+        match outcome {
+            ChainOutcome::Rejected(response) => Ok(response),
+            ChainOutcome::Mutated {
+                object,
+                warnings,
+                audit_annotations,
+            } => {
+                let patch = json_patch::diff(&original, &object);
+                let patch = if patch.0.is_empty() {
+                    None
+                } else {
+                    let patch_str = serde_json::to_string(&patch)
+                        .map(|s| general_purpose::STANDARD.encode(s))
+                        .map_err(MutationChainError::SerializePatch)?;
+                    Some(patch_str)
+                };
+
+                Ok(AdmissionResponse {
+                    uid,
+                    allowed: true,
+                    patch_type: patch.as_ref().map(|_| "JSONPatch".to_string()),
+                    patch,
+                    status: None,
+                    audit_annotations: (!audit_annotations.is_empty()).then_some(audit_annotations),
+                    warnings: (!warnings.is_empty()).then_some(warnings),
+                })
+            }
+        }
+    }
+}
+
+enum ChainOutcome {
+    Rejected(AdmissionResponse),
+    Mutated {
+        object: serde_json::Value,
+        warnings: Vec<String>,
+        audit_annotations: HashMap<String, String>,
+    },
+}
+
+/// Evaluate the members of the chain until a fixed point is reached, or
+/// `max_rounds` evaluations of the chain have been done. The chain is
+/// evaluated at least once, regardless of `max_rounds`.
+///
+/// `eval_member` evaluates the member at the given index against the given object.
+fn run_chain<F>(
+    names: &[String],
+    original: &serde_json::Value,
+    max_rounds: usize,
+    mut eval_member: F,
+) -> Result<ChainOutcome, MutationChainError>
+where
+    F: FnMut(usize, &serde_json::Value) -> AdmissionResponse,
+{
+    // never let a request through without evaluating the chain
+    let max_rounds = max_rounds.max(1);
+    let mut object = original.clone();
+    let mut warnings: Vec<String> = Vec::new();
+    let mut audit_annotations: HashMap<String, String> = HashMap::new();
+    // the paths changed so far, together with the name of the member that changed them
+    let mut changed_paths: BTreeMap<String, String> = BTreeMap::new();
+
+    for round in 1..=max_rounds {
+        let mut object_changed = false;
+
+        for (index, name) in names.iter().enumerate() {
+            let response = eval_member(index, &object);
+            if !response.allowed {
+                debug!(
+                    member = name,
+                    round, "request rejected by mutation chain member"
+                );
+                return Ok(ChainOutcome::Rejected(response));
+            }
+
+            // warnings and audit annotations are collected only once, reinvocations
+            // usually produce the same ones
+            if round == 1 {
+                warnings.extend(response.warnings.unwrap_or_default());
+                audit_annotations.extend(response.audit_annotations.unwrap_or_default());
+            }
+
+            let patch = match response.patch {
+                Some(patch) => decode_patch(name, &patch)?,
+                None => continue,
+            };
+
+            let mut patched = object.clone();
+            json_patch::patch(&mut patched, &patch).map_err(|e| {
+                MutationChainError::InvalidPatch {
+                    member: name.to_owned(),
+                    error: e.to_string(),
+                }
+            })?;
+
+            for (path, owner) in &changed_paths {
+                if owner != name && object.pointer(path) != patched.pointer(path) {
+                    return Err(MutationChainError::Conflict {
+                        path: path.to_owned(),
+                        first: owner.to_owned(),
+                        second: name.to_owned(),
+                    });
+                }
+            }
+            for path in written_paths(&patch) {
+                changed_paths.insert(path, name.to_owned());
+            }
+
+            if patched != object {
+                object_changed = true;
+                object = patched;
+            }
+        }
+
+        if !object_changed {
+            return Ok(ChainOutcome::Mutated {
+                object,
+                warnings,
+                audit_annotations,
+            });
+        }
+    }
+
+    if max_rounds > 1 {
+        return Err(MutationChainError::NoFixedPoint(max_rounds));
+    }
+
+    Ok(ChainOutcome::Mutated {
+        object,
+        warnings,
+        audit_annotations,
+    })
+}
+
+fn decode_patch(member: &str, patch: &str) -> Result<json_patch::Patch, MutationChainError> {
+    general_purpose::STANDARD
+        .decode(patch)
+        .map_err(|e| e.to_string())
+        .and_then(|raw| serde_json::from_slice(&raw).map_err(|e| e.to_string()))
+        .map_err(|error| MutationChainError::InvalidPatch {
+            member: member.to_owned(),
+            error,
+        })
+}
+
+/// The paths written by the operations of the patch. When an object or an
+/// array is written, the paths of all its leaves are returned. This allows
+/// a member to add new keys to an object created by another member.
+fn written_paths(patch: &json_patch::Patch) -> Vec<String> {
+    let mut paths = Vec::new();
+    for op in &patch.0 {
+        match op {
+            json_patch::PatchOperation::Add(op) => leaf_paths(&op.path, &op.value, &mut paths),
+            json_patch::PatchOperation::Replace(op) => leaf_paths(&op.path, &op.value, &mut paths),
+            json_patch::PatchOperation::Remove(op) => paths.push(op.path.clone()),
+            json_patch::PatchOperation::Move(op) => {
+                paths.push(op.from.clone());
+                paths.push(op.path.clone());
+            }
+            json_patch::PatchOperation::Copy(op) => paths.push(op.path.clone()),
+            json_patch::PatchOperation::Test(_) => {}
+        }
+    }
+    paths
+}
+
+fn leaf_paths(path: &str, value: &serde_json::Value, paths: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(obj) if !obj.is_empty() => {
+            for (key, value) in obj {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                leaf_paths(&format!("{path}/{key}"), value, paths);
+            }
+        }
+        serde_json::Value::Array(items) if !items.is_empty() => {
+            for (index, value) in items.iter().enumerate() {
+                leaf_paths(&format!("{path}/{index}"), value, paths);
+            }
+        }
+        _ => paths.push(path.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Build the response of a policy that changes the given object into `mutated`
+    fn mutate(obj: &serde_json::Value, mutated: serde_json::Value) -> AdmissionResponse {
+        let patch = json_patch::diff(obj, &mutated);
+        AdmissionResponse {
+            uid: "uid".to_string(),
+            allowed: true,
+            patch_type: Some("JSONPatch".to_string()),
+            patch: Some(general_purpose::STANDARD.encode(serde_json::to_string(&patch).unwrap())),
+            ..Default::default()
+        }
+    }
+
+    fn set_label(obj: &serde_json::Value, key: &str, value: &str) -> AdmissionResponse {
+        let mut mutated = obj.clone();
+        mutated["metadata"]["labels"][key] = json!(value);
+        mutate(obj, mutated)
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn members_see_previous_mutations() {
+        let original = json!({"metadata": {"labels": {}}});
+
+        let outcome = run_chain(&names(&["a", "b"]), &original, 1, |index, obj| {
+            match index {
+                0 => set_label(obj, "a", "true"),
+                _ => {
+                    // the second member sees the label set by the first one
+                    assert_eq!(json!("true"), obj["metadata"]["labels"]["a"]);
+                    set_label(obj, "b", "true")
+                }
+            }
+        })
+        .unwrap();
+
+        let ChainOutcome::Mutated { object, .. } = outcome else {
+            panic!("request should have been mutated")
+        };
+        assert_eq!(
+            json!({"metadata": {"labels": {"a": "true", "b": "true"}}}),
+            object
+        );
+    }
+
+    #[test]
+    fn zero_rounds_still_evaluate_the_chain() {
+        let original = json!({});
+        let mut evaluated = Vec::new();
+
+        let outcome = run_chain(&names(&["a", "b"]), &original, 0, |index, _| {
+            evaluated.push(index);
+            AdmissionResponse::reject("uid".to_string(), "nope".to_string(), 400)
+        })
+        .unwrap();
+
+        assert!(matches!(outcome, ChainOutcome::Rejected(_)));
+        assert_eq!(vec![0], evaluated);
+    }
+
+    #[test]
+    fn rejection_stops_the_chain() {
+        let original = json!({});
+        let mut evaluated = Vec::new();
+
+        let outcome = run_chain(&names(&["a", "b"]), &original, 1, |index, _| {
+            evaluated.push(index);
+            AdmissionResponse::reject("uid".to_string(), "nope".to_string(), 400)
+        })
+        .unwrap();
+
+        assert!(matches!(outcome, ChainOutcome::Rejected(_)));
+        assert_eq!(vec![0], evaluated);
+    }
+
+    #[test]
+    fn conflicting_members() {
+        let original = json!({"metadata": {"labels": {}}});
+
+        let result = run_chain(
+            &names(&["a", "b"]),
+            &original,
+            1,
+            |index, obj| match index {
+                0 => set_label(obj, "owner", "a"),
+                _ => set_label(obj, "owner", "b"),
+            },
+        );
+
+        assert!(matches!(
+            result,
+            Err(MutationChainError::Conflict { path, first, second })
+                if path == "/metadata/labels/owner" && first == "a" && second == "b"
+        ));
+    }
+
+    #[test]
+    fn members_can_extend_objects_created_by_others() {
+        let original = json!({"metadata": {}});
+
+        let outcome = run_chain(
+            &names(&["a", "b"]),
+            &original,
+            1,
+            |index, obj| match index {
+                0 => set_label(obj, "a", "true"),
+                _ => set_label(obj, "b", "true"),
+            },
+        )
+        .unwrap();
+
+        let ChainOutcome::Mutated { object, .. } = outcome else {
+            panic!("request should have been mutated")
+        };
+        assert_eq!(
+            json!({"metadata": {"labels": {"a": "true", "b": "true"}}}),
+            object
+        );
+    }
+
+    #[test]
+    fn reinvocation_reaches_fixed_point() {
+        let original = json!({"metadata": {"labels": {}}});
+
+        // `a` copies the label set by `b`, hence it has to be invoked again
+        let outcome = run_chain(
+            &names(&["a", "b"]),
+            &original,
+            3,
+            |index, obj| match index {
+                0 => match obj["metadata"]["labels"].get("b") {
+                    Some(value) => set_label(obj, "a", value.as_str().unwrap()),
+                    None => AdmissionResponse {
+                        allowed: true,
+                        ..Default::default()
+                    },
+                },
+                _ => set_label(obj, "b", "value"),
+            },
+        )
+        .unwrap();
+
+        let ChainOutcome::Mutated { object, .. } = outcome else {
+            panic!("request should have been mutated")
+        };
+        assert_eq!(
+            json!({"metadata": {"labels": {"a": "value", "b": "value"}}}),
+            object
+        );
+    }
+
+    #[test]
+    fn reinvocation_without_fixed_point() {
+        let original = json!({"counter": 0});
+
+        let result = run_chain(&names(&["a"]), &original, 3, |_, obj| {
+            let counter = obj["counter"].as_i64().unwrap();
+            mutate(obj, json!({"counter": counter + 1}))
+        });
+
+        assert!(matches!(result, Err(MutationChainError::NoFixedPoint(3))));
+    }
+}
@@ -508,9 +508,12 @@ mod tests {
             mutating: false,
             background_audit: true,
             context_aware_resources: BTreeSet::new(),
+            host_capabilities: BTreeSet::new(),
             execution_mode: Default::default(),
             policy_type: PolicyType::Kubernetes,
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            rego_entrypoints: vec![],
         }
     }
 
@@ -573,9 +576,12 @@ mod tests {
             mutating: false,
             background_audit: true,
             context_aware_resources,
+            host_capabilities: BTreeSet::new(),
             execution_mode: Default::default(),
             minimum_kubewarden_version: None,
             policy_type: Default::default(),
+            settings_schema: None,
+            rego_entrypoints: vec![],
         }
     }
 
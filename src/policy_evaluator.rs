@@ -1,12 +1,23 @@
 pub mod errors;
+mod evaluation_report;
 mod evaluator;
 pub mod policy_evaluator_builder;
+mod policy_evaluator_pool;
 mod policy_evaluator_pre;
+mod policy_store;
+mod precompiled_cache;
 mod stack_pre;
+mod trusted_dir;
 
+pub use evaluation_report::{EvaluationReport, HostCallbackReport};
 pub use evaluator::PolicyEvaluator;
+pub use policy_evaluator_pool::{
+    PolicyEvaluatorPool, PolicyEvaluatorPoolStats, PooledPolicyEvaluator,
+};
 pub use policy_evaluator_pre::PolicyEvaluatorPre;
 
+pub use crate::policy_tracing::{PolicyLogEntry, PolicyLogEntryLevel, PolicyLogs};
+
 use anyhow::{anyhow, Result};
 use serde::Serialize;
 use serde_json::value;
@@ -25,6 +36,9 @@ pub enum PolicyExecutionMode {
     OpaGatekeeper,
     #[serde(rename = "wasi")]
     Wasi,
+    /// Wasm component implementing the `policy` WIT world
+    #[serde(rename = "wasi-component")]
+    WasiComponent,
 }
 
 impl fmt::Display for PolicyExecutionMode {
@@ -68,7 +82,9 @@ impl TryFrom<PolicyExecutionMode> for RegoPolicyExecutionMode {
         match execution_mode {
             PolicyExecutionMode::Opa => Ok(RegoPolicyExecutionMode::Opa),
             PolicyExecutionMode::OpaGatekeeper => Ok(RegoPolicyExecutionMode::Gatekeeper),
-            PolicyExecutionMode::KubewardenWapc | PolicyExecutionMode::Wasi => Err(anyhow!(
+            PolicyExecutionMode::KubewardenWapc
+            | PolicyExecutionMode::Wasi
+            | PolicyExecutionMode::WasiComponent => Err(anyhow!(
                 "execution mode not convertible to a Rego based executon mode"
             )),
         }
@@ -100,6 +116,10 @@ mod tests {
             serde_json::to_string(&json!("gatekeeper")).unwrap(),
             PolicyExecutionMode::OpaGatekeeper,
         );
+        test_data.insert(
+            serde_json::to_string(&json!("wasi-component")).unwrap(),
+            PolicyExecutionMode::WasiComponent,
+        );
 
         for (expected, mode) in &test_data {
             let actual = serde_json::to_string(&mode);
@@ -123,6 +143,10 @@ mod tests {
             serde_json::to_string(&json!("gatekeeper")).unwrap(),
             PolicyExecutionMode::OpaGatekeeper,
         );
+        test_data.insert(
+            serde_json::to_string(&json!("wasi-component")).unwrap(),
+            PolicyExecutionMode::WasiComponent,
+        );
 
         for (mode_str, expected) in &test_data {
             let actual: std::result::Result<PolicyExecutionMode, serde_json::Error> =
@@ -21,4 +21,21 @@ pub enum InvalidUserInputError {
 
     #[error("must specify execution mode")]
     ExecutionMode,
+
+    #[error(
+        "directories, environment variables, clocks and output limits can be configured only for WASI policies"
+    )]
+    WasiConfigNotSupported,
+
+    #[error("async support can be enabled only for WASI and Rego policies")]
+    AsyncSupportNotAvailable,
+
+    #[error("entrypoints can be selected only for Rego policies")]
+    RegoEntrypointsNotSupported,
+
+    #[error("a pre-built `policy_module` cannot be used by Wasm component policies")]
+    ModuleForComponent,
+
+    #[error("policy verification requires the policy to be fetched via `policy_uri`")]
+    VerificationWithoutPolicyUri,
 }
@@ -0,0 +1,64 @@
+use serde::Serialize;
+use std::time::Duration;
+
+/// Describes where the time of an evaluation has been spent.
+///
+/// Returned by
+/// [`PolicyEvaluator::validate_with_report`](crate::policy_evaluator::PolicyEvaluator::validate_with_report).
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EvaluationReport {
+    /// Time spent instantiating the policy.
+    ///
+    /// WASI policies are instantiated at each evaluation, hence this is the time
+    /// spent during the evaluation. waPC and Rego policies are instantiated once,
+    /// when the `PolicyEvaluator` is created via
+    /// [`PolicyEvaluatorPre::rehydrate`](crate::policy_evaluator::PolicyEvaluatorPre::rehydrate).
+    pub instantiation_time: Duration,
+
+    /// Time spent evaluating the request, including the time spent
+    /// performing host callbacks
+    pub execution_time: Duration,
+
+    /// The host callbacks performed by the policy, in the order they
+    /// have been made. The callbacks that failed or have been denied are
+    /// included. For Rego policies these are the callbacks made to build
+    /// their Kubernetes context
+    pub host_callbacks: Vec<HostCallbackReport>,
+
+    /// True when the policy had to be reset after the evaluation, for example
+    /// because its execution has been interrupted
+    pub reset: bool,
+}
+
+impl EvaluationReport {
+    /// Total number of host callbacks performed by the policy
+    pub fn host_callbacks_count(&self) -> usize {
+        self.host_callbacks.len()
+    }
+
+    /// Total time spent performing host callbacks
+    pub fn host_callbacks_time(&self) -> Duration {
+        self.host_callbacks.iter().map(|cb| cb.duration).sum()
+    }
+}
+
+/// A host callback performed by the policy during an evaluation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HostCallbackReport {
+    pub binding: String,
+    pub namespace: String,
+    pub operation: String,
+
+    /// Time spent by the host to fulfill the request
+    pub duration: Duration,
+
+    /// Whether the response has been served from the cache of the
+    /// `CallbackHandler`. This is `None` for the requests that are fulfilled
+    /// without involving the `CallbackHandler`
+    pub was_cached: Option<bool>,
+
+    /// The error returned to the policy, set when the host callback failed or
+    /// has been denied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
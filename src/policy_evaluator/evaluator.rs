@@ -1,44 +1,55 @@
 use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
 use std::fmt;
+use std::time::{Duration, Instant};
 
 use crate::admission_response::AdmissionResponse;
 use crate::errors::PolicyEvaluatorError;
 use crate::evaluation_context::EvaluationContext;
-use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::policy_evaluator::{EvaluationReport, PolicyLogs, PolicySettings, ValidateRequest};
 use crate::runtimes::rego::Runtime as BurregoRuntime;
 use crate::runtimes::wapc::Runtime as WapcRuntime;
 use crate::runtimes::wasi_cli::Runtime as WasiRuntime;
+use crate::runtimes::wasi_component::Runtime as WasiComponentRuntime;
 use crate::runtimes::Runtime;
 
 pub struct PolicyEvaluator {
     runtime: Runtime,
     eval_ctx: EvaluationContext,
+    /// Time spent creating the runtime of the policy
+    instantiation_time: Duration,
 }
 
 impl PolicyEvaluator {
-    pub(crate) fn new(runtime: Runtime, eval_ctx: &EvaluationContext) -> Self {
+    pub(crate) fn new(
+        runtime: Runtime,
+        eval_ctx: &EvaluationContext,
+        instantiation_time: Duration,
+    ) -> Self {
         Self {
             runtime,
             eval_ctx: eval_ctx.to_owned(),
+            instantiation_time,
         }
     }
 
-    #[tracing::instrument(skip(request))]
+    #[tracing::instrument(
+        skip(request),
+        fields(policy_id = %self.eval_ctx.policy_id, request_uid = %request.uid())
+    )]
     pub fn validate(
         &mut self,
         request: ValidateRequest,
         settings: &PolicySettings,
     ) -> AdmissionResponse {
+        self.start_evaluation(Some(request.uid()));
+
         match self.runtime {
             Runtime::Wapc(ref mut wapc_stack) => {
                 WapcRuntime(wapc_stack).validate(settings, &request)
             }
             Runtime::Rego(ref mut burrego_evaluator) => {
-                let kube_ctx = burrego_evaluator.build_kubernetes_context(
-                    self.eval_ctx.callback_channel.as_ref(),
-                    &self.eval_ctx.ctx_aware_resources_allow_list,
-                );
+                let kube_ctx = burrego_evaluator.build_kubernetes_context(&self.eval_ctx);
                 match kube_ctx {
                     Ok(ctx) => BurregoRuntime(burrego_evaluator).validate(settings, &request, &ctx),
                     Err(e) => {
@@ -47,21 +58,139 @@ impl PolicyEvaluator {
                 }
             }
             Runtime::Cli(ref mut cli_stack) => WasiRuntime(cli_stack).validate(settings, &request),
+            Runtime::Component(ref mut component_stack) => {
+                WasiComponentRuntime(component_stack).validate(settings, &request)
+            }
         }
     }
 
-    #[tracing::instrument]
-    pub fn validate_settings(&mut self, settings: &PolicySettings) -> SettingsValidationResponse {
-        let settings_str = match serde_json::to_string(settings) {
-            Ok(settings) => settings,
-            Err(err) => {
-                return SettingsValidationResponse {
-                    valid: false,
-                    message: Some(format!("could not marshal settings: {err}")),
+    /// Same as [`PolicyEvaluator::validate`], but an [`EvaluationReport`]
+    /// describing where the time of the evaluation has been spent is returned
+    /// alongside the `AdmissionResponse`
+    #[tracing::instrument(
+        skip(request),
+        fields(policy_id = %self.eval_ctx.policy_id, request_uid = %request.uid())
+    )]
+    pub fn validate_with_report(
+        &mut self,
+        request: ValidateRequest,
+        settings: &PolicySettings,
+    ) -> (AdmissionResponse, EvaluationReport) {
+        if let Some(recorder) = self.runtime.callback_recorder() {
+            recorder.start();
+        }
+
+        let started_at = Instant::now();
+        let response = self.validate(request, settings);
+        let elapsed = started_at.elapsed();
+
+        let host_callbacks = self
+            .runtime
+            .callback_recorder()
+            .map(|recorder| recorder.finish())
+            .unwrap_or_default();
+        let (instantiation_time, execution_time) = match self.runtime.instantiation_time() {
+            // the policy has been instantiated as part of the evaluation
+            Some(instantiation_time) => (
+                instantiation_time,
+                elapsed.saturating_sub(instantiation_time),
+            ),
+            None => (self.instantiation_time, elapsed),
+        };
+
+        let report = EvaluationReport {
+            instantiation_time,
+            execution_time,
+            host_callbacks,
+            reset: self.runtime.was_reset(),
+        };
+
+        (response, report)
+    }
+
+    /// Asynchronous version of [`PolicyEvaluator::validate`], for the
+    /// policies that can wait for the host without blocking the current
+    /// thread, see [`PolicyEvaluator::supports_async`]:
+    ///
+    /// * WASI policies built with
+    ///   [`enable_async_support`](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::enable_async_support)
+    ///   yield while waiting for the outcome of their host callbacks.
+    /// * Rego policies yield while their Kubernetes context is fetched, which
+    ///   is the only moment they wait for the host. The evaluation itself is
+    ///   CPU bound: inside of a tokio multi-threaded runtime it's done using
+    ///   [`tokio::task::block_in_place`], otherwise it's done in place.
+    ///
+    /// waPC policies, Wasm components and WASI policies built without async
+    /// support block the current thread while waiting for their host
+    /// callbacks, which would stall the `CallbackHandler` serving them when
+    /// they share the same runtime. These policies are refused with
+    /// [`PolicyEvaluatorError::AsyncEvaluationNotSupported`], they must be
+    /// evaluated with [`PolicyEvaluator::validate`] from a thread that can
+    /// block, like the ones of [`tokio::task::spawn_blocking`].
+    #[tracing::instrument(
+        skip(request),
+        fields(policy_id = %self.eval_ctx.policy_id, request_uid = %request.uid())
+    )]
+    pub async fn validate_async(
+        &mut self,
+        request: ValidateRequest,
+        settings: &PolicySettings,
+    ) -> Result<AdmissionResponse, PolicyEvaluatorError> {
+        if !self.supports_async() {
+            return Err(PolicyEvaluatorError::AsyncEvaluationNotSupported(
+                self.runtime.to_string(),
+            ));
+        }
+        self.start_evaluation(Some(request.uid()));
+
+        let response = match self.runtime {
+            Runtime::Cli(ref mut cli_stack) => {
+                WasiRuntime(cli_stack)
+                    .validate_async(settings, &request)
+                    .await
+            }
+            Runtime::Rego(ref mut burrego_evaluator) => {
+                let kube_ctx = burrego_evaluator
+                    .build_kubernetes_context_async(&self.eval_ctx)
+                    .await;
+                match kube_ctx {
+                    Ok(ctx) => evaluate_cpu_bound(|| {
+                        BurregoRuntime(burrego_evaluator).validate(settings, &request, &ctx)
+                    }),
+                    Err(e) => {
+                        AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500)
+                    }
                 }
             }
+            Runtime::Wapc(_) | Runtime::Component(_) => {
+                return Err(PolicyEvaluatorError::AsyncEvaluationNotSupported(
+                    self.runtime.to_string(),
+                ))
+            }
         };
 
+        Ok(response)
+    }
+
+    /// Returns true when the policy can be evaluated via
+    /// [`PolicyEvaluator::validate_async`] and
+    /// [`PolicyEvaluator::validate_settings_async`]
+    pub fn supports_async(&self) -> bool {
+        match &self.runtime {
+            Runtime::Cli(cli_stack) => cli_stack.async_support(),
+            Runtime::Rego(_) => true,
+            Runtime::Wapc(_) | Runtime::Component(_) => false,
+        }
+    }
+
+    #[tracing::instrument(fields(policy_id = %self.eval_ctx.policy_id))]
+    pub fn validate_settings(&mut self, settings: &PolicySettings) -> SettingsValidationResponse {
+        let settings_str = match serialize_settings(settings) {
+            Ok(settings) => settings,
+            Err(response) => return response,
+        };
+        self.start_evaluation(None);
+
         match self.runtime {
             Runtime::Wapc(ref mut wapc_stack) => {
                 WapcRuntime(wapc_stack).validate_settings(settings_str)
@@ -72,19 +201,147 @@ impl PolicyEvaluator {
             Runtime::Cli(ref mut cli_stack) => {
                 WasiRuntime(cli_stack).validate_settings(settings_str)
             }
+            Runtime::Component(ref mut component_stack) => {
+                WasiComponentRuntime(component_stack).validate_settings(settings_str)
+            }
+        }
+    }
+
+    /// Asynchronous version of [`PolicyEvaluator::validate_settings`].
+    ///
+    /// Only the policies supporting [`PolicyEvaluator::validate_async`] can
+    /// be used, the other ones are refused with
+    /// [`PolicyEvaluatorError::AsyncEvaluationNotSupported`].
+    #[tracing::instrument(fields(policy_id = %self.eval_ctx.policy_id))]
+    pub async fn validate_settings_async(
+        &mut self,
+        settings: &PolicySettings,
+    ) -> Result<SettingsValidationResponse, PolicyEvaluatorError> {
+        if !self.supports_async() {
+            return Err(PolicyEvaluatorError::AsyncEvaluationNotSupported(
+                self.runtime.to_string(),
+            ));
+        }
+
+        let response = match self.runtime {
+            Runtime::Cli(ref mut cli_stack) => {
+                let settings_str = match serialize_settings(settings) {
+                    Ok(settings) => settings,
+                    Err(response) => return Ok(response),
+                };
+                self.start_evaluation(None);
+                WasiRuntime(cli_stack)
+                    .validate_settings_async(settings_str)
+                    .await
+            }
+            // the validation of the settings of Rego policies never waits
+            // for the host
+            _ => evaluate_cpu_bound(|| self.validate_settings(settings)),
+        };
+
+        Ok(response)
+    }
+
+    /// Returns true if the execution of the policy has been aborted during the
+    /// last evaluation. This happens when the guest traps, for example because
+    /// an epoch deadline has been reached. The errors returned by the policy
+    /// are not traps.
+    pub fn has_trapped(&self) -> bool {
+        self.runtime.trapped()
+    }
+
+    /// Returns the amount of fuel consumed by the last evaluation, or `None`
+    /// when fuel consumption has not been enabled via
+    /// [`PolicyEvaluatorBuilder::enable_fuel`](crate::policy_evaluator_builder::PolicyEvaluatorBuilder::enable_fuel)
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        self.runtime.fuel_consumed()
+    }
+
+    /// Returns the log entries produced by the policy during the last
+    /// evaluation. Entries are captured only when
+    /// [`EvaluationContext::policy_log_capture`] is set. Rego policies do not
+    /// produce log entries
+    pub fn policy_logs(&self) -> PolicyLogs {
+        self.runtime
+            .callback_recorder()
+            .map(|recorder| recorder.policy_logs())
+            .unwrap_or_default()
+    }
+
+    /// Tag the host callbacks made by the policy with the uid of the request
+    /// being evaluated, and reset the count of the host callbacks
+    fn start_evaluation(&self, request_uid: Option<&str>) {
+        if let Some(recorder) = self.runtime.callback_recorder() {
+            recorder.start_evaluation(request_uid);
         }
     }
 
+    /// Returns the version of the protocol spoken by the policy.
+    ///
+    /// waPC and Wasm component policies export a dedicated function, while
+    /// WASI policies implement a `protocol-version` command. The protocol
+    /// version of Rego policies is read from their metadata, `v1` is assumed
+    /// when the metadata does not specify it
     pub fn protocol_version(&mut self) -> Result<ProtocolVersion, PolicyEvaluatorError> {
         match &mut self.runtime {
             Runtime::Wapc(ref mut wapc_stack) => Ok(WapcRuntime(wapc_stack)
                 .protocol_version()
                 .map_err(PolicyEvaluatorError::InvokeWapcProtocolVersion)?),
-            _ => Err(PolicyEvaluatorError::InvalidProtocolVersion()),
+            Runtime::Rego(ref mut burrego_evaluator) => {
+                Ok(BurregoRuntime(burrego_evaluator).protocol_version())
+            }
+            Runtime::Cli(ref mut cli_stack) => Ok(WasiRuntime(cli_stack)
+                .protocol_version()
+                .map_err(PolicyEvaluatorError::InvokeWasiProtocolVersion)?),
+            Runtime::Component(ref mut component_stack) => {
+                Ok(WasiComponentRuntime(component_stack)
+                    .protocol_version()
+                    .map_err(PolicyEvaluatorError::InvokeWasiComponentProtocolVersion)?)
+            }
+        }
+    }
+
+    /// Ensure the policy speaks one of the protocol versions supported by the
+    /// embedder, regardless of its execution mode. This is meant to be done
+    /// once, before the policy starts serving requests.
+    ///
+    /// Returns the protocol version of the policy on success.
+    pub fn negotiate_protocol_version(
+        &mut self,
+        supported: &[ProtocolVersion],
+    ) -> Result<ProtocolVersion, PolicyEvaluatorError> {
+        let found = self.protocol_version()?;
+        if !supported.contains(&found) {
+            return Err(PolicyEvaluatorError::UnsupportedProtocolVersion {
+                found,
+                supported: supported.to_vec(),
+            });
         }
+
+        Ok(found)
     }
 }
 
+/// Run a CPU bound evaluation, which never waits for the host, from an async
+/// function. Inside of a tokio multi-threaded runtime the evaluation is done
+/// using [`tokio::task::block_in_place`], to let the other tasks of the
+/// current worker move to a different thread. Otherwise it's done in place
+fn evaluate_cpu_bound<R>(f: impl FnOnce() -> R) -> R {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+fn serialize_settings(settings: &PolicySettings) -> Result<String, SettingsValidationResponse> {
+    serde_json::to_string(settings).map_err(|err| SettingsValidationResponse {
+        valid: false,
+        message: Some(format!("could not marshal settings: {err}")),
+    })
+}
+
 impl fmt::Debug for PolicyEvaluator {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let runtime = self.runtime.to_string();
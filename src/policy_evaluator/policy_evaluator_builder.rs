@@ -1,12 +1,30 @@
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::result::Result;
+use std::sync::Arc;
+use std::time::SystemTime;
 
+use burrego::{FuelBudget, ResourceLimits};
+use policy_fetcher::sigstore::trust::ManualTrustRoot;
+use policy_fetcher::sources::Sources;
+use policy_fetcher::verify::config::LatestVerificationConfig;
+use policy_fetcher::PullDestination;
+use sha2::{Digest, Sha256};
+use tracing::debug;
 use wasmtime_provider::wasmtime;
 
+use crate::callback_handler::SigstoreClient;
 use crate::errors::PolicyEvaluatorBuilderError;
 use crate::policy_evaluator::errors::InvalidUserInputError;
+use crate::policy_evaluator::policy_store::{self, PolicyStore};
+use crate::policy_evaluator::precompiled_cache::{self, PrecompiledCache};
 use crate::policy_evaluator::{stack_pre::StackPre, PolicyEvaluatorPre, PolicyExecutionMode};
-use crate::runtimes::{rego, wapc, wasi_cli};
+use crate::policy_metadata::Metadata;
+use crate::runtimes::{rego, wapc, wasi_cli, wasi_component};
+
+/// The preamble of the Wasm binary format
+const WASM_MAGIC: &[u8] = b"\0asm";
 
 /// Configure behavior of wasmtime [epoch-based interruptions](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
 ///
@@ -24,6 +42,65 @@ pub(crate) struct EpochDeadlines {
     pub wapc_func: u64,
 }
 
+/// Clocks exposed to WASI policies
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WasiClock {
+    /// The clocks of the host
+    #[default]
+    Host,
+
+    /// The wall clock is frozen at the given time and the monotonic clock
+    /// never advances. Makes the evaluation of time-dependent policies
+    /// reproducible
+    Fixed(SystemTime),
+}
+
+/// Host resources exposed to WASI policies
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct WasiConfig {
+    /// Host directories preopened read-only, keyed by their path inside of
+    /// the guest
+    pub preopened_dirs: BTreeMap<String, PathBuf>,
+
+    /// Environment variables of the policy
+    pub env: BTreeMap<String, String>,
+
+    pub clock: WasiClock,
+
+    /// Maximum number of bytes the policy can write to stdout
+    pub max_stdout_bytes: Option<usize>,
+
+    /// Maximum number of bytes the policy can write to stderr
+    pub max_stderr_bytes: Option<usize>,
+}
+
+/// Signatures the policy must have, see [`PolicyEvaluatorBuilder::verify_policy`]
+struct PolicyVerification {
+    config: LatestVerificationConfig,
+    trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    /// sha256 digest of the policy that has been verified
+    verified_digest: Option<String>,
+}
+
+/// Name of the directory used by the policy store when no location is given
+const DEFAULT_POLICY_STORE_DIR: &str = "policy-evaluator-store";
+
+/// Directory used by the policy store when no location is given. The
+/// temporary directory of the system is shared by all the users, hence each
+/// user gets its own store
+fn default_policy_store_dir() -> PathBuf {
+    #[cfg(unix)]
+    {
+        // SAFETY: `geteuid` has no preconditions and cannot fail
+        let uid = unsafe { libc::geteuid() };
+        std::env::temp_dir().join(format!("{DEFAULT_POLICY_STORE_DIR}-{uid}"))
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::temp_dir().join(DEFAULT_POLICY_STORE_DIR)
+    }
+}
+
 /// Helper Struct that creates a `PolicyEvaluator` object
 #[derive(Default)]
 pub struct PolicyEvaluatorBuilder {
@@ -31,9 +108,22 @@ pub struct PolicyEvaluatorBuilder {
     policy_file: Option<String>,
     policy_contents: Option<Vec<u8>>,
     policy_module: Option<wasmtime::Module>,
+    policy_store_dir: Option<PathBuf>,
+    /// sha256 digest of the `policy_file` saved by `policy_uri` inside of the
+    /// policy store
+    stored_policy_digest: Option<String>,
+    policy_verification: Option<PolicyVerification>,
     execution_mode: Option<PolicyExecutionMode>,
     wasmtime_cache: bool,
+    precompiled_cache_dir: Option<PathBuf>,
+    precompiled_cache_max_bytes: Option<u64>,
     epoch_deadlines: Option<EpochDeadlines>,
+    async_support: bool,
+    resource_limits: Option<ResourceLimits>,
+    fuel_limits: Option<FuelBudget>,
+    wasi_config: Option<WasiConfig>,
+    rego_entrypoints: Vec<String>,
+    rego_settings_entrypoint: Option<String>,
 }
 
 impl PolicyEvaluatorBuilder {
@@ -65,6 +155,7 @@ impl PolicyEvaluatorBuilder {
             .map(|s| s.to_string())
             .ok_or_else(|| PolicyEvaluatorBuilderError::ConvertPath)?;
         self.policy_file = Some(filename);
+        self.stored_policy_digest = None;
         Ok(self)
     }
 
@@ -76,6 +167,143 @@ impl PolicyEvaluatorBuilder {
         self
     }
 
+    /// Build the policy by fetching it from the given URI, using
+    /// [`policy_fetcher`]. Both `registry://` and `https://` URIs are supported,
+    /// `sources` provides the information needed to access the remote
+    /// registries and servers.
+    ///
+    /// The policy is saved inside of a local content-addressed store, see
+    /// [`PolicyEvaluatorBuilder::policy_store_dir`], then it's used as if it had
+    /// been given via `policy_file`.
+    ///
+    /// The execution mode is read from the metadata of the policy, unless it
+    /// has already been set via [`PolicyEvaluatorBuilder::execution_mode`].
+    pub async fn policy_uri(
+        mut self,
+        uri: &str,
+        sources: Option<&Sources>,
+    ) -> Result<PolicyEvaluatorBuilder, PolicyEvaluatorBuilderError> {
+        let store_dir = self
+            .policy_store_dir
+            .clone()
+            .unwrap_or_else(default_policy_store_dir);
+        let store =
+            PolicyStore::open(&store_dir).map_err(PolicyEvaluatorBuilderError::PolicyStore)?;
+
+        let download_dir = store
+            .download_dir()
+            .map_err(PolicyEvaluatorBuilderError::PolicyStore)?;
+        let contents = self.fetch_and_verify(uri, sources, &download_dir).await;
+        let _ = std::fs::remove_dir_all(&download_dir);
+        let contents = contents?;
+
+        let digest = policy_store::digest(&contents);
+        if let Some(verification) = self.policy_verification.as_mut() {
+            verification.verified_digest = Some(digest.clone());
+        }
+
+        if self.execution_mode.is_none() {
+            self.execution_mode = Metadata::from_contents(&contents)
+                .map_err(PolicyEvaluatorBuilderError::Metadata)?
+                .map(|metadata| metadata.execution_mode);
+        }
+
+        let path = store
+            .add(&contents)
+            .map_err(PolicyEvaluatorBuilderError::PolicyStore)?;
+        self.policy_file = Some(
+            path.to_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| PolicyEvaluatorBuilderError::ConvertPath)?,
+        );
+        self.stored_policy_digest = Some(digest);
+
+        Ok(self)
+    }
+
+    /// Fetch the policy inside of the given directory, verify its signatures
+    /// when requested and return its contents
+    async fn fetch_and_verify(
+        &self,
+        uri: &str,
+        sources: Option<&Sources>,
+        download_dir: &Path,
+    ) -> Result<Vec<u8>, PolicyEvaluatorBuilderError> {
+        let fetch_error = |error: String| PolicyEvaluatorBuilderError::FetchPolicy {
+            uri: uri.to_owned(),
+            error,
+        };
+
+        let policy = policy_fetcher::fetch_policy(
+            uri,
+            PullDestination::LocalFile(download_dir.to_path_buf()),
+            sources,
+        )
+        .await
+        .map_err(|e| fetch_error(e.to_string()))?;
+
+        if let Some(verification) = &self.policy_verification {
+            if !uri.starts_with("registry://") {
+                return Err(PolicyEvaluatorBuilderError::PolicyVerification(
+                    "only policies fetched from an OCI registry can be verified".to_string(),
+                ));
+            }
+
+            let mut sigstore_client =
+                SigstoreClient::new(sources.cloned(), verification.trust_root.clone())
+                    .await
+                    .map_err(|e| PolicyEvaluatorBuilderError::PolicyVerification(e.to_string()))?;
+            let digest = sigstore_client
+                .verify_policy(&policy, &verification.config)
+                .await
+                .map_err(|e| PolicyEvaluatorBuilderError::PolicyVerification(e.to_string()))?;
+            debug!(uri, digest, "policy signatures verified");
+        }
+
+        std::fs::read(&policy.local_path).map_err(|e| fetch_error(e.to_string()))
+    }
+
+    /// Refuse to build the policy unless it's signed according to the given
+    /// verification config. The config can require signatures made with
+    /// public keys, keyless signatures with a given issuer and subject, or
+    /// signatures made with a certificate issued by a given chain.
+    ///
+    /// The signatures are verified when the policy is fetched, hence the
+    /// policy must be provided via [`PolicyEvaluatorBuilder::policy_uri`],
+    /// using a `registry://` URI. This option must be set before invoking
+    /// `policy_uri`.
+    ///
+    /// The `trust_root` provides the Fulcio and Rekor data required to verify
+    /// keyless signatures and transparency log entries. A [`ManualTrustRoot`]
+    /// allows to perform the verification offline.
+    #[must_use]
+    pub fn verify_policy(
+        mut self,
+        config: LatestVerificationConfig,
+        trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    ) -> Self {
+        self.policy_verification = Some(PolicyVerification {
+            config,
+            trust_root,
+            verified_digest: None,
+        });
+        self
+    }
+
+    /// Directory of the local content-addressed store used by
+    /// [`PolicyEvaluatorBuilder::policy_uri`]. Must be set before invoking
+    /// `policy_uri`.
+    ///
+    /// The directory must be changed only by the current user, because the
+    /// stored policies are not verified again when they are loaded. Defaults
+    /// to a directory, owned by the current user, inside of the temporary
+    /// directory of the system.
+    #[must_use]
+    pub fn policy_store_dir(mut self, dir: &Path) -> Self {
+        self.policy_store_dir = Some(dir.to_path_buf());
+        self
+    }
+
     /// Use a pre-built [`wasmtime::Module`] instance.
     /// **Warning:** you must provide also the [`wasmtime::Engine`] used
     /// to allocate the `Module`, otherwise the code will panic at runtime
@@ -99,6 +327,43 @@ impl PolicyEvaluatorBuilder {
         self
     }
 
+    /// Store the precompiled policies inside of the given directory, to
+    /// reduce the time required to build them the next time.
+    ///
+    /// Unlike the [Wasmtime cache](PolicyEvaluatorBuilder::enable_wasmtime_cache),
+    /// the artifacts are stored inside of the given directory and are keyed
+    /// by the sha256 digest of the policy and by the configuration of the
+    /// [`wasmtime::Engine`]. The integrity of each artifact is verified before
+    /// it's loaded: corrupted artifacts are discarded and the policy is
+    /// compiled again. The artifacts produced by a different engine
+    /// configuration for the same policy are evicted, as are the least
+    /// recently used ones once the size of the cache exceeds its limit, see
+    /// [`PolicyEvaluatorBuilder::precompiled_cache_max_bytes`].
+    ///
+    /// Loading an artifact runs native code, hence the directory must be
+    /// writable only by the current user. The directory is created with
+    /// `0700` permissions when missing. The cache is not used, and the policy
+    /// is compiled from scratch, when other users can change the contents of
+    /// the directory.
+    ///
+    /// This setting has no effect when a pre-built [`wasmtime::Module`] is
+    /// provided via [`PolicyEvaluatorBuilder::policy_module`], nor when the
+    /// policy is a Wasm component.
+    #[must_use]
+    pub fn precompiled_cache_dir(mut self, dir: &Path) -> Self {
+        self.precompiled_cache_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Maximum size, in bytes, of the artifacts stored inside of the
+    /// [precompiled cache](PolicyEvaluatorBuilder::precompiled_cache_dir).
+    /// Defaults to 1 GiB.
+    #[must_use]
+    pub fn precompiled_cache_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.precompiled_cache_max_bytes = Some(max_bytes);
+        self
+    }
+
     /// Enable Wasmtime [epoch-based interruptions](wasmtime::Config::epoch_interruption) and set
     /// the deadlines to be enforced
     ///
@@ -131,6 +396,171 @@ impl PolicyEvaluatorBuilder {
         self
     }
 
+    /// Enable Wasmtime [async support](wasmtime::Config::async_support)
+    ///
+    /// When enabled, the WASI policies evaluated via
+    /// [`PolicyEvaluator::validate_async`](crate::policy_evaluator::PolicyEvaluator::validate_async)
+    /// yield while waiting for the outcome of their host callbacks, instead of
+    /// blocking the current thread.
+    ///
+    /// Rego policies can always be evaluated asynchronously, this setting has
+    /// no effect on them. waPC and Wasm component policies cannot be evaluated
+    /// asynchronously, enabling async support for them is an error.
+    ///
+    /// **Warning:** when providing an instance of `wasmtime::Engine` to be used
+    /// with a WASI policy, ensure the `wasmtime::Engine` has been created with
+    /// the `async_support` feature enabled
+    #[must_use]
+    pub fn enable_async_support(mut self) -> Self {
+        self.async_support = true;
+        self
+    }
+
+    /// Enable Wasmtime [fuel consumption](wasmtime::Config::consume_fuel) and set the
+    /// deterministic execution budgets to be enforced
+    ///
+    /// Unlike [epoch-based interruptions](PolicyEvaluatorBuilder::enable_epoch_interruptions),
+    /// fuel does not depend on a ticker driven by the embedder: the same evaluation
+    /// always consumes the same amount of fuel, regardless of the load of the host.
+    ///
+    /// Two kind of budgets have to be set:
+    ///
+    /// * `init_fuel`: the fuel available to the initialization code of the policy
+    /// * `call_fuel`: the fuel available to each evaluation of the policy
+    ///
+    /// The amount of fuel consumed by the last evaluation is reported by
+    /// [`PolicyEvaluator::fuel_consumed`](crate::policy_evaluator::PolicyEvaluator::fuel_consumed).
+    ///
+    /// **Warning:** when providing an instance of `wasmtime::Engine`, ensure the
+    /// `wasmtime::Engine` has been created with the `consume_fuel` feature enabled
+    #[must_use]
+    pub fn enable_fuel(mut self, init_fuel: u64, call_fuel: u64) -> Self {
+        self.fuel_limits = Some(FuelBudget {
+            init: init_fuel,
+            call: call_fuel,
+        });
+        self
+    }
+
+    /// Limit the size of the linear memory of the policy, expressed in bytes.
+    ///
+    /// Growing the memory past this limit aborts the evaluation, which is
+    /// rejected with an error explaining the limit that has been exceeded.
+    #[must_use]
+    pub fn max_memory_bytes(mut self, max_memory_bytes: usize) -> Self {
+        self.resource_limits
+            .get_or_insert_with(ResourceLimits::default)
+            .max_memory_bytes = Some(max_memory_bytes);
+        self
+    }
+
+    /// Limit the number of elements of the tables of the policy.
+    ///
+    /// Growing a table past this limit aborts the evaluation, which is
+    /// rejected with an error explaining the limit that has been exceeded.
+    #[must_use]
+    pub fn max_table_elements(mut self, max_table_elements: u32) -> Self {
+        self.resource_limits
+            .get_or_insert_with(ResourceLimits::default)
+            .max_table_elements = Some(max_table_elements);
+        self
+    }
+
+    /// Limit the number of Wasm instances that can be created during an
+    /// evaluation.
+    #[must_use]
+    pub fn max_instances(mut self, max_instances: usize) -> Self {
+        self.resource_limits
+            .get_or_insert_with(ResourceLimits::default)
+            .max_instances = Some(max_instances);
+        self
+    }
+
+    /// Give the policy read-only access to the `host_path` directory, which is
+    /// available at `guest_path` inside of the guest.
+    ///
+    /// The directory must exist when the `PolicyEvaluatorPre` is built.
+    ///
+    /// Only WASI policies can access host directories.
+    #[must_use]
+    pub fn wasi_preopened_dir(mut self, guest_path: &str, host_path: &Path) -> Self {
+        self.wasi_config
+            .get_or_insert_with(WasiConfig::default)
+            .preopened_dirs
+            .insert(guest_path.to_owned(), host_path.to_path_buf());
+        self
+    }
+
+    /// Set an environment variable of the policy.
+    ///
+    /// Only WASI policies have access to environment variables.
+    #[must_use]
+    pub fn wasi_env(mut self, key: &str, value: &str) -> Self {
+        self.wasi_config
+            .get_or_insert_with(WasiConfig::default)
+            .env
+            .insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Set the clocks used by the policy. The clocks of the host are used by
+    /// default.
+    ///
+    /// Only WASI policies can use a different clock.
+    #[must_use]
+    pub fn wasi_clock(mut self, clock: WasiClock) -> Self {
+        self.wasi_config
+            .get_or_insert_with(WasiConfig::default)
+            .clock = clock;
+        self
+    }
+
+    /// Limit the number of bytes a WASI policy can write to stdout during
+    /// an evaluation.
+    ///
+    /// Writes exceeding the limit fail and the evaluation is rejected with
+    /// an error explaining the limit that has been exceeded.
+    #[must_use]
+    pub fn max_wasi_stdout_bytes(mut self, max_bytes: usize) -> Self {
+        self.wasi_config
+            .get_or_insert_with(WasiConfig::default)
+            .max_stdout_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Limit the number of bytes a WASI policy can write to stderr during
+    /// an evaluation. Same as [`PolicyEvaluatorBuilder::max_wasi_stdout_bytes`].
+    #[must_use]
+    pub fn max_wasi_stderr_bytes(mut self, max_bytes: usize) -> Self {
+        self.wasi_config
+            .get_or_insert_with(WasiConfig::default)
+            .max_stderr_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Name of a Rego entrypoint to be evaluated. Can be called multiple
+    /// times to evaluate several entrypoints: the request is accepted only
+    /// when all of them accept it, and their messages, warnings and audit
+    /// annotations are merged.
+    ///
+    /// Overrides the entrypoints listed by the policy metadata. The first
+    /// entrypoint of the policy is evaluated when none is given.
+    #[must_use]
+    pub fn rego_entrypoint(mut self, name: &str) -> Self {
+        self.rego_entrypoints.push(name.to_owned());
+        self
+    }
+
+    /// Name of the Rego entrypoint validating the settings of the policy.
+    ///
+    /// The settings are validated only against the schema provided by the
+    /// policy metadata, if any, when none is given.
+    #[must_use]
+    pub fn rego_settings_entrypoint(mut self, name: &str) -> Self {
+        self.rego_settings_entrypoint = Some(name.to_owned());
+        self
+    }
+
     /// Ensure the configuration provided to the build is correct
     fn validate_user_input(&self) -> Result<(), InvalidUserInputError> {
         if self.policy_file.is_some() && self.policy_contents.is_some() {
@@ -158,6 +588,39 @@ impl PolicyEvaluatorBuilder {
             return Err(InvalidUserInputError::ExecutionMode);
         }
 
+        if self.policy_verification.is_some() && self.policy_module.is_some() {
+            return Err(InvalidUserInputError::VerificationWithoutPolicyUri);
+        }
+
+        // A `wasmtime::Module` cannot be turned into a `wasmtime::component::Component`
+        if self.policy_module.is_some()
+            && self.execution_mode == Some(PolicyExecutionMode::WasiComponent)
+        {
+            return Err(InvalidUserInputError::ModuleForComponent);
+        }
+
+        if self.async_support
+            && matches!(
+                self.execution_mode,
+                Some(PolicyExecutionMode::KubewardenWapc | PolicyExecutionMode::WasiComponent)
+            )
+        {
+            return Err(InvalidUserInputError::AsyncSupportNotAvailable);
+        }
+
+        if self.wasi_config.is_some() && self.execution_mode != Some(PolicyExecutionMode::Wasi) {
+            return Err(InvalidUserInputError::WasiConfigNotSupported);
+        }
+
+        if (!self.rego_entrypoints.is_empty() || self.rego_settings_entrypoint.is_some())
+            && !matches!(
+                self.execution_mode,
+                Some(PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper)
+            )
+        {
+            return Err(InvalidUserInputError::RegoEntrypointsNotSupported);
+        }
+
         Ok(())
     }
 
@@ -166,32 +629,86 @@ impl PolicyEvaluatorBuilder {
         self.validate_user_input()
             .map_err(PolicyEvaluatorBuilderError::InvalidUserInput)?;
 
+        // The policy is read only once, the bytes that have been checked
+        // against the digest of the stored and of the verified policy are the
+        // ones compiled
+        let wasm = match &self.policy_module {
+            Some(_) => Cow::Borrowed(&[][..]),
+            None => self.policy_bytes()?,
+        };
+
+        self.ensure_policy_verified(&wasm)?;
+
         let engine = self.build_engine()?;
-        let module = self.build_module(&engine)?;
 
         let execution_mode = self.execution_mode.unwrap();
 
         let stack_pre = match execution_mode {
             PolicyExecutionMode::KubewardenWapc => {
-                let wapc_stack_pre = wapc::StackPre::new(engine, module, self.epoch_deadlines)
-                    .map_err(PolicyEvaluatorBuilderError::NewWapcStackPre)?;
+                let module = self.build_module(&engine, &wasm)?;
+                let wapc_stack_pre = wapc::StackPre::new(
+                    engine,
+                    module,
+                    self.epoch_deadlines,
+                    self.resource_limits,
+                    self.fuel_limits,
+                )
+                .map_err(PolicyEvaluatorBuilderError::NewWapcStackPre)?;
                 StackPre::from(wapc_stack_pre)
             }
             PolicyExecutionMode::Wasi => {
-                let wasi_stack_pre = wasi_cli::StackPre::new(engine, module, self.epoch_deadlines)
-                    .map_err(PolicyEvaluatorBuilderError::NewWasiStackPre)?;
+                let module = self.build_module(&engine, &wasm)?;
+                let wasi_stack_pre = wasi_cli::StackPre::new(
+                    engine,
+                    module,
+                    self.epoch_deadlines,
+                    self.async_support,
+                    self.resource_limits,
+                    self.fuel_limits,
+                    self.wasi_config.clone().unwrap_or_default(),
+                )
+                .map_err(PolicyEvaluatorBuilderError::NewWasiStackPre)?;
                 StackPre::from(wasi_stack_pre)
             }
+            PolicyExecutionMode::WasiComponent => {
+                let component = self.build_component(&engine, &wasm)?;
+                let component_stack_pre = wasi_component::StackPre::new(
+                    engine,
+                    component,
+                    self.epoch_deadlines,
+                    self.resource_limits,
+                    self.fuel_limits,
+                )
+                .map_err(PolicyEvaluatorBuilderError::NewWasiComponentStackPre)?;
+                StackPre::from(component_stack_pre)
+            }
             PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper => {
+                let module = self.build_module(&engine, &wasm)?;
+                let metadata = self.metadata(&wasm)?;
+                let entrypoints = if self.rego_entrypoints.is_empty() {
+                    metadata
+                        .as_ref()
+                        .map(|metadata| metadata.rego_entrypoints.clone())
+                        .unwrap_or_default()
+                } else {
+                    self.rego_entrypoints.clone()
+                };
                 let rego_stack_pre = rego::StackPre::new(
                     engine,
                     module,
                     self.epoch_deadlines,
-                    0, // currently the entrypoint is hard coded to this value
+                    self.resource_limits,
+                    self.fuel_limits,
                     execution_mode
                         .try_into()
                         .map_err(PolicyEvaluatorBuilderError::NewRegoStackPre)?,
-                );
+                )
+                .with_metadata(metadata)
+                .map_err(PolicyEvaluatorBuilderError::RegoSettingsValidation)?
+                .with_settings_entrypoint(self.rego_settings_entrypoint.as_deref())
+                .map_err(PolicyEvaluatorBuilderError::RegoSettingsValidation)?
+                .with_entrypoints(&entrypoints)
+                .map_err(PolicyEvaluatorBuilderError::RegoEntrypoints)?;
                 StackPre::from(rego_stack_pre)
             }
         };
@@ -199,6 +716,59 @@ impl PolicyEvaluatorBuilder {
         Ok(PolicyEvaluatorPre::new(stack_pre))
     }
 
+    /// Ensure the policy that is going to be built is the one that has been
+    /// verified by `policy_uri`
+    fn ensure_policy_verified(&self, wasm: &[u8]) -> Result<(), PolicyEvaluatorBuilderError> {
+        let Some(verification) = &self.policy_verification else {
+            return Ok(());
+        };
+        let Some(verified_digest) = &verification.verified_digest else {
+            return Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::VerificationWithoutPolicyUri,
+            ));
+        };
+
+        if &format!("{:x}", Sha256::digest(wasm)) != verified_digest {
+            return Err(PolicyEvaluatorBuilderError::UnverifiedPolicy);
+        }
+
+        Ok(())
+    }
+
+    /// The contents of the policy given via `policy_file` or `policy_contents`.
+    /// The policies saved inside of the policy store by `policy_uri` must
+    /// still match their digest
+    fn policy_bytes(&self) -> Result<Cow<'_, [u8]>, PolicyEvaluatorBuilderError> {
+        match &self.policy_file {
+            Some(file) if self.stored_policy_digest.is_some() => {
+                let digest = self.stored_policy_digest.as_deref().unwrap_or_default();
+                Ok(Cow::Owned(
+                    policy_store::read_policy(Path::new(file), digest)
+                        .map_err(PolicyEvaluatorBuilderError::ReadStoredPolicy)?,
+                ))
+            }
+            Some(file) => {
+                Ok(Cow::Owned(std::fs::read(file).map_err(|e| {
+                    PolicyEvaluatorBuilderError::WasmModuleBuild(e.into())
+                })?))
+            }
+            None => Ok(Cow::Borrowed(
+                self.policy_contents.as_deref().unwrap_or_default(),
+            )),
+        }
+    }
+
+    /// The metadata embedded into the policy. The metadata of a pre-built
+    /// `policy_module` cannot be read, while a policy given using the Wasm
+    /// text format cannot embed any. Metadata that cannot be parsed is an
+    /// error: its settings schema and entrypoints must never be ignored
+    fn metadata(&self, wasm: &[u8]) -> Result<Option<Metadata>, PolicyEvaluatorBuilderError> {
+        if self.policy_module.is_some() || !wasm.starts_with(WASM_MAGIC) {
+            return Ok(None);
+        }
+        Metadata::from_contents(wasm).map_err(PolicyEvaluatorBuilderError::Metadata)
+    }
+
     fn build_engine(&self) -> Result<wasmtime::Engine, PolicyEvaluatorBuilderError> {
         self.engine
             .as_ref()
@@ -211,6 +781,18 @@ impl PolicyEvaluatorBuilder {
                     if self.epoch_deadlines.is_some() {
                         wasmtime_config.epoch_interruption(true);
                     }
+                    if self.fuel_limits.is_some() {
+                        wasmtime_config.consume_fuel(true);
+                    }
+                    // Only the WASI runtime can leverage async support, the
+                    // other runtimes require a synchronous engine
+                    if self.async_support && self.execution_mode == Some(PolicyExecutionMode::Wasi)
+                    {
+                        wasmtime_config.async_support(true);
+                    }
+                    if self.execution_mode == Some(PolicyExecutionMode::WasiComponent) {
+                        wasmtime_config.wasm_component_model(true);
+                    }
 
                     wasmtime::Engine::new(&wasmtime_config)
                 },
@@ -219,21 +801,35 @@ impl PolicyEvaluatorBuilder {
             .map_err(PolicyEvaluatorBuilderError::WasmtimeEngineBuild)
     }
 
+    fn build_component(
+        &self,
+        engine: &wasmtime::Engine,
+        wasm: &[u8],
+    ) -> Result<wasmtime::component::Component, PolicyEvaluatorBuilderError> {
+        wasmtime::component::Component::new(engine, wasm)
+            .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild)
+    }
+
     fn build_module(
         &self,
         engine: &wasmtime::Engine,
+        wasm: &[u8],
     ) -> Result<wasmtime::Module, PolicyEvaluatorBuilderError> {
         if let Some(m) = &self.policy_module {
             // it's fine to clone a Module, this is a cheap operation that just
             // copies its internal reference. See wasmtime docs
             Ok(m.clone())
+        } else if let Some(dir) = &self.precompiled_cache_dir {
+            PrecompiledCache::new(dir)
+                .max_bytes(
+                    self.precompiled_cache_max_bytes
+                        .unwrap_or(precompiled_cache::DEFAULT_MAX_BYTES),
+                )
+                .load_or_compile(engine, wasm)
+                .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild)
         } else {
-            match &self.policy_file {
-                Some(file) => wasmtime::Module::from_file(engine, file)
-                    .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild),
-                None => wasmtime::Module::new(engine, self.policy_contents.as_ref().unwrap())
-                    .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild),
-            }
+            wasmtime::Module::new(engine, wasm)
+                .map_err(PolicyEvaluatorBuilderError::WasmModuleBuild)
         }
     }
 }
@@ -241,6 +837,19 @@ impl PolicyEvaluatorBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use kubewarden_policy_sdk::metadata::ProtocolVersion;
+    use policy_fetcher::verify::config::Signature;
+
+    use crate::runtimes::wasi_cli::errors::WasiRuntimeError;
+
+    use crate::errors::PolicyEvaluatorError;
+    use crate::evaluation_context::{EvaluationContext, PolicyLogCapture};
+    use crate::policy_evaluator::{
+        PolicyEvaluator, PolicyLogEntryLevel, PolicySettings, ValidateRequest,
+    };
 
     #[test]
     fn build_policy_evaluator_pre() {
@@ -257,4 +866,568 @@ mod tests {
 
         _ = policy_evaluator_builder.build_pre().unwrap();
     }
+
+    #[test]
+    fn build_wasi_policy_evaluator_pre_with_async_support() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat)
+            .enable_async_support();
+
+        _ = policy_evaluator_builder.build_pre().unwrap();
+    }
+
+    #[rstest]
+    #[case::wapc(PolicyExecutionMode::KubewardenWapc)]
+    #[case::wasi_component(PolicyExecutionMode::WasiComponent)]
+    fn async_support_is_rejected_by_policies_that_cannot_yield(
+        #[case] execution_mode: PolicyExecutionMode,
+    ) {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(execution_mode)
+            .policy_contents(wat)
+            .enable_async_support();
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::AsyncSupportNotAvailable
+            ))
+        ));
+    }
+
+    #[test]
+    fn wasi_policy_exceeding_memory_limit_is_rejected() {
+        // grow the memory by 10 pages (640 KiB)
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "_start")
+                (drop (memory.grow (i32.const 10))))
+            )
+        "#;
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat.as_bytes())
+            .max_memory_bytes(2 * 64 * 1024)
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(json!({"uid": "test"})),
+            &PolicySettings::default(),
+        );
+
+        assert!(!response.allowed);
+        assert_eq!(
+            Some(format!(
+                "resource limit exceeded: cannot grow memory to {} bytes, the limit is {} bytes",
+                11 * 64 * 1024,
+                2 * 64 * 1024
+            )),
+            response.status.and_then(|status| status.message)
+        );
+        assert!(policy_evaluator.has_trapped());
+    }
+
+    #[test]
+    fn wasi_policy_exhausting_fuel_is_rejected() {
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "_start")
+                (loop $endless (br $endless)))
+            )
+        "#;
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat.as_bytes())
+            .enable_fuel(1_000, 10_000)
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(json!({"uid": "test"})),
+            &PolicySettings::default(),
+        );
+
+        assert!(!response.allowed);
+        assert_eq!(
+            Some("guest code interrupted, fuel exhausted".to_string()),
+            response.status.and_then(|status| status.message)
+        );
+        assert!(policy_evaluator.has_trapped());
+        assert_eq!(Some(10_000), policy_evaluator.fuel_consumed());
+    }
+
+    #[test]
+    fn wapc_policy_exhausting_fuel_is_rejected() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_contents(wat)
+            .enable_fuel(1_000, 10_000)
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(json!({"uid": "test"})),
+            &PolicySettings::default(),
+        );
+
+        assert!(!response.allowed);
+        let message = response.status.and_then(|status| status.message).unwrap();
+        assert!(
+            message.contains("guest code interrupted, fuel exhausted"),
+            "{message}"
+        );
+        assert!(policy_evaluator.has_trapped());
+        assert_eq!(policy_evaluator.fuel_consumed(), Some(10_000));
+    }
+
+    #[test]
+    fn wapc_policy_exceeding_memory_limit_is_rejected() {
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "wapc_init"))
+              (func (export "__guest_call") (param i32 i32) (result i32)
+                (drop (memory.grow (i32.const 10)))
+                (i32.const 1))
+            )
+        "#;
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_contents(wat.as_bytes())
+            .max_memory_bytes(2 * 64 * 1024)
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(json!({"uid": "test"})),
+            &PolicySettings::default(),
+        );
+
+        assert!(!response.allowed);
+        let message = response.status.and_then(|status| status.message).unwrap();
+        assert!(
+            message.contains(&format!(
+                "resource limit exceeded: cannot grow memory to {} bytes, the limit is {} bytes",
+                11 * 64 * 1024,
+                2 * 64 * 1024
+            )),
+            "{message}"
+        );
+        assert!(policy_evaluator.has_trapped());
+    }
+
+    fn verification_config() -> LatestVerificationConfig {
+        LatestVerificationConfig {
+            all_of: Some(vec![Signature::PubKey {
+                owner: None,
+                key: include_str!("../../tests/data/signed_policy/cosign.pub").to_string(),
+                annotations: None,
+            }]),
+            any_of: None,
+        }
+    }
+
+    #[test]
+    fn policy_verification_requires_policy_uri() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat)
+            .verify_policy(verification_config(), None);
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::VerificationWithoutPolicyUri
+            ))
+        ));
+    }
+
+    #[test]
+    fn policy_different_from_the_verified_one_is_rejected() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+
+        let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .verify_policy(verification_config(), None)
+            .policy_contents(wat);
+        policy_evaluator_builder
+            .policy_verification
+            .as_mut()
+            .unwrap()
+            .verified_digest = Some(format!("{:x}", Sha256::digest(b"verified policy")));
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::UnverifiedPolicy)
+        ));
+    }
+
+    #[test]
+    fn wasi_component_policy_cannot_use_policy_module() {
+        let engine = wasmtime::Engine::default();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::WasiComponent)
+            .policy_module(module)
+            .engine(engine);
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::ModuleForComponent
+            ))
+        ));
+    }
+
+    #[test]
+    fn wasi_component_without_policy_world_is_rejected() {
+        let wat = r#"(component)"#;
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::WasiComponent)
+            .policy_contents(wat.as_bytes())
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        let response = policy_evaluator.validate_settings(&PolicySettings::default());
+
+        assert!(!response.valid);
+        assert!(response
+            .message
+            .unwrap()
+            .starts_with("component does not implement the policy world"));
+    }
+
+    #[test]
+    fn wasi_config_is_rejected_by_non_wasi_policies() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_contents(wat)
+            .wasi_env("KEY", "value");
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::WasiConfigNotSupported
+            ))
+        ));
+    }
+
+    #[test]
+    fn rego_entrypoints_are_rejected_by_non_rego_policies() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat)
+            .rego_entrypoint("policy/main");
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::RegoEntrypointsNotSupported
+            ))
+        ));
+    }
+
+    #[test]
+    fn rego_settings_entrypoint_is_rejected_by_non_rego_policies() {
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat)
+            .rego_settings_entrypoint("policy/validate_settings");
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::RegoEntrypointsNotSupported
+            ))
+        ));
+    }
+
+    #[rstest]
+    #[case::not_selected(None, true)]
+    #[case::selected(Some("opa/validate_settings"), false)]
+    fn rego_settings_entrypoint_is_opt_in(
+        #[case] settings_entrypoint: Option<&str>,
+        #[case] valid: bool,
+    ) {
+        let wat = include_bytes!("../../tests/data/rego_settings/settings_validation.wat");
+        let engine = wasmtime::Engine::default();
+        let module = wasmtime::Module::new(&engine, wat).unwrap();
+
+        let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Opa)
+            .engine(engine)
+            .policy_module(module);
+        if let Some(settings_entrypoint) = settings_entrypoint {
+            policy_evaluator_builder =
+                policy_evaluator_builder.rego_settings_entrypoint(settings_entrypoint);
+        }
+        let mut policy_evaluator = policy_evaluator_builder
+            .build_pre()
+            .unwrap()
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        // the settings are rejected by the `opa/validate_settings` entrypoint
+        let settings = PolicySettings::from_iter([("mode".to_string(), json!("forbidden"))]);
+        let response = policy_evaluator.validate_settings(&settings);
+
+        assert_eq!(valid, response.valid);
+    }
+
+    #[test]
+    fn rego_policy_in_text_format_is_built_without_metadata() {
+        let wat = include_bytes!("../../tests/data/rego_settings/settings_validation.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Opa)
+            .policy_contents(wat)
+            .rego_settings_entrypoint("opa/validate_settings");
+
+        assert!(policy_evaluator_builder.build_pre().is_ok());
+    }
+
+    #[test]
+    fn rego_policy_with_malformed_metadata_is_rejected() {
+        let name = crate::constants::KUBEWARDEN_CUSTOM_SECTION_METADATA.as_bytes();
+        let data = b"not a JSON document";
+        // an empty Wasm module with a single custom section
+        let mut wasm = b"\0asm\x01\0\0\0\0".to_vec();
+        wasm.push((1 + name.len() + data.len()) as u8);
+        wasm.push(name.len() as u8);
+        wasm.extend_from_slice(name);
+        wasm.extend_from_slice(data);
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Opa)
+            .policy_contents(&wasm);
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::Metadata(_))
+        ));
+    }
+
+    #[test]
+    fn wasi_missing_preopened_dir_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wasm_endless_loop.wat");
+
+        let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat)
+            .wasi_preopened_dir("/data", &root.path().join("does-not-exist"));
+
+        assert!(matches!(
+            policy_evaluator_builder.build_pre(),
+            Err(PolicyEvaluatorBuilderError::NewWasiStackPre(
+                WasiRuntimeError::PreopenedDir { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn wasi_policy_exceeding_output_limit_is_rejected() {
+        // write 16 bytes to stdout over and over, ignoring the outcome of the
+        // writes: the program is trapped once the limit is exceeded
+        let wat = r#"
+            (module
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\10\00\00\00\10\00\00\00")
+              (data (i32.const 16) "0123456789abcdef")
+              (func (export "_start")
+                (loop $write
+                  (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 32)))
+                  (br $write)))
+            )
+        "#;
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat.as_bytes())
+            .max_wasi_stdout_bytes(8)
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(json!({"uid": "test"})),
+            &PolicySettings::default(),
+        );
+
+        assert!(!response.allowed);
+        assert_eq!(
+            Some("stdout exceeded the limit of 8 bytes".to_string()),
+            response.status.and_then(|status| status.message)
+        );
+        assert!(policy_evaluator.has_trapped());
+    }
+
+    #[test]
+    fn wasi_policy_protocol_version_is_negotiated() {
+        // print "v1" when invoked, regardless of the command
+        let wat = r#"
+            (module
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\10\00\00\00\03\00\00\00")
+              (data (i32.const 16) "v1\n")
+              (func (export "_start")
+                (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 32))))
+            )
+        "#;
+
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(wat.as_bytes())
+            .build_pre()
+            .unwrap();
+        let mut policy_evaluator = policy_evaluator_pre
+            .rehydrate(&EvaluationContext::default())
+            .unwrap();
+
+        assert_eq!(
+            ProtocolVersion::V1,
+            policy_evaluator
+                .negotiate_protocol_version(&[ProtocolVersion::V1])
+                .unwrap()
+        );
+        assert!(matches!(
+            policy_evaluator.negotiate_protocol_version(&[]),
+            Err(PolicyEvaluatorError::UnsupportedProtocolVersion {
+                found: ProtocolVersion::V1,
+                ..
+            })
+        ));
+    }
+
+    fn wasi_component_policy_evaluator(eval_ctx: &EvaluationContext) -> PolicyEvaluator {
+        let wat = include_bytes!("../../tests/data/wasi_component/policy.wat");
+
+        PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::WasiComponent)
+            .policy_contents(wat)
+            .build_pre()
+            .unwrap()
+            .rehydrate(eval_ctx)
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case::accepted(json!({}), true, None, None)]
+    #[case::rejected(
+        json!({"reject": true}),
+        false,
+        Some("rejected by the settings"),
+        Some(403)
+    )]
+    fn wasi_component_policy_validates_requests(
+        #[case] settings: serde_json::Value,
+        #[case] allowed: bool,
+        #[case] message: Option<&str>,
+        #[case] code: Option<u16>,
+    ) {
+        let eval_ctx = EvaluationContext {
+            policy_log_capture: Some(PolicyLogCapture {
+                max_entries: 10,
+                max_bytes: 1024,
+            }),
+            ..Default::default()
+        };
+        let mut policy_evaluator = wasi_component_policy_evaluator(&eval_ctx);
+
+        let response = policy_evaluator.validate(
+            ValidateRequest::Raw(json!({"uid": "test"})),
+            settings.as_object().unwrap(),
+        );
+
+        assert_eq!(allowed, response.allowed);
+        assert_eq!(
+            message,
+            response
+                .status
+                .as_ref()
+                .and_then(|status| status.message.as_deref())
+        );
+        assert_eq!(code, response.status.and_then(|status| status.code));
+        assert!(!policy_evaluator.has_trapped());
+
+        // the entry has been logged via the typed `log` host import
+        let logs = policy_evaluator.policy_logs();
+        assert_eq!(1, logs.entries.len());
+        assert_eq!(PolicyLogEntryLevel::Info, logs.entries[0].level);
+        assert_eq!(
+            Some("validating request"),
+            logs.entries[0].message.as_deref()
+        );
+    }
+
+    #[rstest]
+    #[case::valid(json!({}), true, None)]
+    #[case::invalid(json!({"invalid": true}), false, Some("the settings are invalid"))]
+    fn wasi_component_policy_validates_settings(
+        #[case] settings: serde_json::Value,
+        #[case] valid: bool,
+        #[case] message: Option<&str>,
+    ) {
+        let mut policy_evaluator = wasi_component_policy_evaluator(&EvaluationContext::default());
+
+        let response = policy_evaluator.validate_settings(settings.as_object().unwrap());
+
+        assert_eq!(valid, response.valid);
+        assert_eq!(message, response.message.as_deref());
+    }
+
+    #[test]
+    fn wasi_component_policy_protocol_version() {
+        let mut policy_evaluator = wasi_component_policy_evaluator(&EvaluationContext::default());
+
+        assert_eq!(
+            ProtocolVersion::V1,
+            policy_evaluator.protocol_version().unwrap()
+        );
+    }
 }
@@ -0,0 +1,422 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use crate::errors::PolicyEvaluatorPoolError;
+use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre};
+
+/// Snapshot of the counters kept by a [`PolicyEvaluatorPool`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PolicyEvaluatorPoolStats {
+    /// Number of checkouts served by an idle instance
+    pub hits: u64,
+    /// Number of checkouts that required a new instance to be created
+    pub misses: u64,
+    /// Number of instances that have been replaced by a fresh one because
+    /// their execution trapped
+    pub recycles: u64,
+}
+
+struct PoolState {
+    idle: Vec<PolicyEvaluator>,
+    /// Number of instances currently owned by the pool, both idle and checked out
+    size: usize,
+}
+
+/// A bounded pool of reusable [`PolicyEvaluator`] instances, all created from the
+/// same [`PolicyEvaluatorPre`].
+///
+/// Instances are obtained via [`checkout`](PolicyEvaluatorPool::checkout) and are
+/// given back to the pool when the returned [`PooledPolicyEvaluator`] is dropped.
+pub struct PolicyEvaluatorPool {
+    policy_evaluator_pre: PolicyEvaluatorPre,
+    eval_ctx: EvaluationContext,
+    max_size: usize,
+    recycle_on_trap: bool,
+    checkout_timeout: Duration,
+    state: Mutex<PoolState>,
+    /// Notified when an instance is checked in, or when a slot is released
+    available: Condvar,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    recycles: AtomicU64,
+}
+
+impl PolicyEvaluatorPool {
+    /// Create a new pool that holds at most `max_size` instances of `PolicyEvaluator`.
+    /// The instances are created lazily, using the given `EvaluationContext`
+    pub fn new(
+        policy_evaluator_pre: PolicyEvaluatorPre,
+        eval_ctx: &EvaluationContext,
+        max_size: usize,
+    ) -> Self {
+        Self {
+            policy_evaluator_pre,
+            eval_ctx: eval_ctx.to_owned(),
+            max_size,
+            recycle_on_trap: false,
+            checkout_timeout: Duration::ZERO,
+            state: Mutex::new(PoolState {
+                idle: Vec::with_capacity(max_size),
+                size: 0,
+            }),
+            available: Condvar::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            recycles: AtomicU64::new(0),
+        }
+    }
+
+    /// Replace an instance with a fresh one when it's checked in after its
+    /// execution trapped.
+    ///
+    /// By default, only waPC and Rego instances interrupted by an epoch deadline
+    /// are reset.
+    #[must_use]
+    pub fn enable_recycle_on_trap(mut self) -> Self {
+        self.recycle_on_trap = true;
+        self
+    }
+
+    /// Wait up to `timeout` for an instance to be checked in when all the
+    /// instances of the pool are in use.
+    ///
+    /// By default, [`checkout`](PolicyEvaluatorPool::checkout) doesn't wait.
+    #[must_use]
+    pub fn checkout_timeout(mut self, timeout: Duration) -> Self {
+        self.checkout_timeout = timeout;
+        self
+    }
+
+    /// Obtain a `PolicyEvaluator` from the pool. An idle instance is reused when
+    /// available, otherwise a new one is created.
+    ///
+    /// When all the instances of the pool are in use, the calling thread is
+    /// blocked until one of them is checked in, see
+    /// [`checkout_timeout`](PolicyEvaluatorPool::checkout_timeout). An error is
+    /// returned once the timeout is reached.
+    pub fn checkout(&self) -> Result<PooledPolicyEvaluator<'_>, PolicyEvaluatorPoolError> {
+        let deadline = Instant::now() + self.checkout_timeout;
+        {
+            let mut state = self.state.lock().unwrap();
+            loop {
+                if let Some(policy_evaluator) = state.idle.pop() {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(PooledPolicyEvaluator::new(self, policy_evaluator));
+                }
+                if state.size < self.max_size {
+                    break;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(PolicyEvaluatorPoolError::Exhausted(self.max_size));
+                }
+                state = self
+                    .available
+                    .wait_timeout(state, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+            // reserve the slot, the instance is created without holding the lock
+            state.size += 1;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        match self.policy_evaluator_pre.rehydrate(&self.eval_ctx) {
+            Ok(policy_evaluator) => Ok(PooledPolicyEvaluator::new(self, policy_evaluator)),
+            Err(e) => {
+                self.release_slot();
+                Err(PolicyEvaluatorPoolError::Rehydrate(e))
+            }
+        }
+    }
+
+    /// Maximum number of instances held by the pool
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns a snapshot of the pool counters
+    pub fn stats(&self) -> PolicyEvaluatorPoolStats {
+        PolicyEvaluatorPoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            recycles: self.recycles.load(Ordering::Relaxed),
+        }
+    }
+
+    fn checkin(&self, policy_evaluator: PolicyEvaluator) {
+        let policy_evaluator = if self.recycle_on_trap && policy_evaluator.has_trapped() {
+            debug!(
+                policy_id = self.eval_ctx.policy_id,
+                "recycling policy evaluator after trap"
+            );
+            self.recycles.fetch_add(1, Ordering::Relaxed);
+            drop(policy_evaluator);
+
+            match self.policy_evaluator_pre.rehydrate(&self.eval_ctx) {
+                Ok(policy_evaluator) => policy_evaluator,
+                Err(e) => {
+                    error!(
+                        policy_id = self.eval_ctx.policy_id,
+                        error = e.to_string().as_str(),
+                        "cannot recycle policy evaluator"
+                    );
+                    self.release_slot();
+                    return;
+                }
+            }
+        } else {
+            policy_evaluator
+        };
+
+        self.state.lock().unwrap().idle.push(policy_evaluator);
+        self.available.notify_one();
+    }
+
+    fn release_slot(&self) {
+        self.state.lock().unwrap().size -= 1;
+        self.available.notify_one();
+    }
+}
+
+/// A `PolicyEvaluator` checked out from a [`PolicyEvaluatorPool`].
+///
+/// The instance is checked in when this object is dropped.
+pub struct PooledPolicyEvaluator<'a> {
+    pool: &'a PolicyEvaluatorPool,
+    policy_evaluator: Option<PolicyEvaluator>,
+}
+
+impl<'a> PooledPolicyEvaluator<'a> {
+    fn new(pool: &'a PolicyEvaluatorPool, policy_evaluator: PolicyEvaluator) -> Self {
+        Self {
+            pool,
+            policy_evaluator: Some(policy_evaluator),
+        }
+    }
+}
+
+impl<'a> Deref for PooledPolicyEvaluator<'a> {
+    type Target = PolicyEvaluator;
+
+    fn deref(&self) -> &Self::Target {
+        self.policy_evaluator
+            .as_ref()
+            .expect("policy evaluator already checked in")
+    }
+}
+
+impl<'a> DerefMut for PooledPolicyEvaluator<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.policy_evaluator
+            .as_mut()
+            .expect("policy evaluator already checked in")
+    }
+}
+
+impl<'a> Drop for PooledPolicyEvaluator<'a> {
+    fn drop(&mut self) {
+        if let Some(policy_evaluator) = self.policy_evaluator.take() {
+            self.pool.checkin(policy_evaluator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+    use std::thread;
+
+    use crate::policy_evaluator::{PolicyExecutionMode, PolicySettings, ValidateRequest};
+    use crate::policy_evaluator_builder::PolicyEvaluatorBuilder;
+
+    /// A WASI module whose `_start` function always traps
+    const WASI_TRAP_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start") unreachable)
+        )
+    "#;
+
+    /// A waPC module whose functions always trap
+    const WAPC_TRAP_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "wapc_init"))
+          (func (export "__guest_call") (param i32 i32) (result i32) unreachable)
+        )
+    "#;
+
+    /// A waPC module whose functions always return an error
+    const WAPC_ERROR_WAT: &str = r#"
+        (module
+          (import "wapc" "__guest_error" (func $guest_error (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "rejected")
+          (func (export "wapc_init"))
+          (func (export "__guest_call") (param i32 i32) (result i32)
+            (call $guest_error (i32.const 0) (i32.const 8))
+            (i32.const 0))
+        )
+    "#;
+
+    const REGO_WAT: &[u8] =
+        include_bytes!("../../tests/data/rego_settings/settings_validation.wat");
+
+    fn build_pool(
+        execution_mode: PolicyExecutionMode,
+        policy: &[u8],
+        max_size: usize,
+    ) -> PolicyEvaluatorPool {
+        let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(execution_mode)
+            .policy_contents(policy)
+            .build_pre()
+            .expect("cannot build policy evaluator pre");
+
+        PolicyEvaluatorPool::new(
+            policy_evaluator_pre,
+            &EvaluationContext::default(),
+            max_size,
+        )
+    }
+
+    #[test]
+    fn checkout_reuses_idle_instances() {
+        let pool = build_pool(PolicyExecutionMode::Wasi, WASI_TRAP_WAT.as_bytes(), 2);
+
+        {
+            let _first = pool.checkout().unwrap();
+            let _second = pool.checkout().unwrap();
+        }
+        let _third = pool.checkout().unwrap();
+
+        assert_eq!(
+            PolicyEvaluatorPoolStats {
+                hits: 1,
+                misses: 2,
+                recycles: 0,
+            },
+            pool.stats()
+        );
+    }
+
+    #[test]
+    fn checkout_fails_when_pool_is_exhausted() {
+        let pool = build_pool(PolicyExecutionMode::Wasi, WASI_TRAP_WAT.as_bytes(), 1);
+
+        let first = pool.checkout().unwrap();
+        assert!(matches!(
+            pool.checkout(),
+            Err(PolicyEvaluatorPoolError::Exhausted(1))
+        ));
+
+        drop(first);
+        assert!(pool.checkout().is_ok());
+    }
+
+    #[rstest]
+    #[case::wasi(PolicyExecutionMode::Wasi, WASI_TRAP_WAT.as_bytes())]
+    #[case::wapc(PolicyExecutionMode::KubewardenWapc, WAPC_TRAP_WAT.as_bytes())]
+    #[case::rego(PolicyExecutionMode::Opa, REGO_WAT)]
+    fn checkout_waits_for_an_instance_to_be_checked_in(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] policy: &[u8],
+    ) {
+        let pool = build_pool(execution_mode, policy, 1).checkout_timeout(Duration::from_secs(10));
+
+        let first = pool.checkout().unwrap();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                drop(first);
+            });
+            assert!(pool.checkout().is_ok());
+        });
+
+        assert_eq!(
+            PolicyEvaluatorPoolStats {
+                hits: 1,
+                misses: 1,
+                recycles: 0,
+            },
+            pool.stats()
+        );
+    }
+
+    #[rstest]
+    #[case::wasi(PolicyExecutionMode::Wasi, WASI_TRAP_WAT.as_bytes())]
+    #[case::wapc(PolicyExecutionMode::KubewardenWapc, WAPC_TRAP_WAT.as_bytes())]
+    #[case::rego(PolicyExecutionMode::Opa, REGO_WAT)]
+    fn checkout_wait_is_bounded(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] policy: &[u8],
+    ) {
+        let timeout = Duration::from_millis(50);
+        let pool = build_pool(execution_mode, policy, 1).checkout_timeout(timeout);
+
+        let _first = pool.checkout().unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            pool.checkout(),
+            Err(PolicyEvaluatorPoolError::Exhausted(1))
+        ));
+        assert!(started.elapsed() >= timeout);
+    }
+
+    #[rstest]
+    #[case::wasi(PolicyExecutionMode::Wasi, WASI_TRAP_WAT.as_bytes())]
+    #[case::wapc(PolicyExecutionMode::KubewardenWapc, WAPC_TRAP_WAT.as_bytes())]
+    fn recycle_on_trap(#[case] execution_mode: PolicyExecutionMode, #[case] policy: &[u8]) {
+        let pool = build_pool(execution_mode, policy, 1).enable_recycle_on_trap();
+
+        {
+            let mut policy_evaluator = pool.checkout().unwrap();
+            let response = policy_evaluator.validate(
+                ValidateRequest::Raw(json!({"uid": "test"})),
+                &PolicySettings::default(),
+            );
+            assert!(!response.allowed);
+            assert!(policy_evaluator.has_trapped());
+        }
+
+        let policy_evaluator = pool.checkout().unwrap();
+        assert!(!policy_evaluator.has_trapped());
+        assert_eq!(
+            PolicyEvaluatorPoolStats {
+                hits: 1,
+                misses: 1,
+                recycles: 1,
+            },
+            pool.stats()
+        );
+    }
+
+    #[test]
+    fn wapc_guest_error_is_not_a_trap() {
+        let pool = build_pool(
+            PolicyExecutionMode::KubewardenWapc,
+            WAPC_ERROR_WAT.as_bytes(),
+            1,
+        )
+        .enable_recycle_on_trap();
+
+        {
+            let mut policy_evaluator = pool.checkout().unwrap();
+            let response = policy_evaluator.validate(
+                ValidateRequest::Raw(json!({"uid": "test"})),
+                &PolicySettings::default(),
+            );
+            assert!(!response.allowed);
+            assert!(!policy_evaluator.has_trapped());
+        }
+
+        assert_eq!(0, pool.stats().recycles);
+    }
+}
@@ -1,9 +1,10 @@
 use std::result::Result;
+use std::time::Instant;
 
 use crate::errors::PolicyEvaluatorPreError;
 use crate::evaluation_context::EvaluationContext;
 use crate::policy_evaluator::{stack_pre::StackPre, PolicyEvaluator};
-use crate::runtimes::{rego, wapc, wasi_cli, Runtime};
+use crate::runtimes::{rego, wapc, wasi_cli, wasi_component, Runtime};
 
 /// This struct provides a way to quickly allocate a `PolicyEvaluator`
 /// object.
@@ -29,6 +30,7 @@ impl PolicyEvaluatorPre {
         &self,
         eval_ctx: &EvaluationContext,
     ) -> Result<PolicyEvaluator, PolicyEvaluatorPreError> {
+        let started_at = Instant::now();
         let runtime = match &self.stack_pre {
             StackPre::Wapc(stack_pre) => {
                 let wapc_stack = wapc::WapcStack::new_from_pre(stack_pre, eval_ctx)
@@ -39,6 +41,10 @@ impl PolicyEvaluatorPre {
                 let wasi_stack = wasi_cli::Stack::new_from_pre(stack_pre, eval_ctx);
                 Runtime::Cli(wasi_stack)
             }
+            StackPre::WasiComponent(stack_pre) => {
+                let component_stack = wasi_component::Stack::new_from_pre(stack_pre, eval_ctx);
+                Runtime::Component(component_stack)
+            }
             StackPre::Rego(stack_pre) => {
                 let rego_stack = rego::Stack::new_from_pre(stack_pre)
                     .map_err(PolicyEvaluatorPreError::RehydrateRego)?;
@@ -46,6 +52,10 @@ impl PolicyEvaluatorPre {
             }
         };
 
-        Ok(PolicyEvaluator::new(runtime, eval_ctx))
+        Ok(PolicyEvaluator::new(
+            runtime,
+            eval_ctx,
+            started_at.elapsed(),
+        ))
     }
 }
@@ -0,0 +1,141 @@
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::policy_evaluator::trusted_dir::{ensure_trusted_dir, write_atomically};
+
+/// Local content-addressed store of policies.
+///
+/// Each policy is stored at `<root>/sha256/<digest>.wasm`, where `digest` is
+/// the sha256 digest of the Wasm module. Policies fetched from different URIs
+/// that have the same contents share the same file.
+///
+/// The policies are loaded from the store without being verified again,
+/// hence the store must be a directory that can be changed by the current
+/// user alone, see [`ensure_trusted_dir`].
+pub(crate) struct PolicyStore<'a> {
+    root: &'a Path,
+}
+
+impl<'a> PolicyStore<'a> {
+    /// Open the store, creating its root directory when it does not exist
+    /// yet. Fails when the directory can be changed by other users
+    pub(crate) fn open(root: &'a Path) -> io::Result<Self> {
+        ensure_trusted_dir(root)?;
+        Ok(Self { root })
+    }
+
+    /// Create a new directory, inside of the store, where a policy can be
+    /// downloaded before being added to the store. The directory must be
+    /// removed by the caller
+    pub(crate) fn download_dir(&self) -> io::Result<PathBuf> {
+        let downloads = self.root.join("downloads");
+        ensure_trusted_dir(&downloads)?;
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = downloads.join(format!("{}-{nanos}", std::process::id()));
+        // fails when the directory already exists, the directory is never
+        // shared with another download
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&dir)?;
+
+        Ok(dir)
+    }
+
+    /// Add the policy to the store, returning the path where it has been saved.
+    ///
+    /// The file is written atomically, existing files are replaced to ensure
+    /// their contents matches the digest.
+    pub(crate) fn add(&self, policy: &[u8]) -> io::Result<PathBuf> {
+        let dir = self.root.join("sha256");
+        ensure_trusted_dir(&dir)?;
+
+        let path = dir.join(format!("{}.wasm", digest(policy)));
+        write_atomically(&path, policy)?;
+
+        Ok(path)
+    }
+}
+
+/// The sha256 digest of the policy, as stored by [`PolicyStore`]
+pub(crate) fn digest(policy: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(policy))
+}
+
+/// Read a policy saved inside of a [`PolicyStore`], ensuring its contents
+/// still match the given digest
+pub(crate) fn read_policy(path: &Path, expected_digest: &str) -> io::Result<Vec<u8>> {
+    let policy = std::fs::read(path)?;
+    let actual_digest = digest(&policy);
+    if actual_digest != expected_digest {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the sha256 digest of {} is {actual_digest}, expected {expected_digest}",
+                path.display()
+            ),
+        ));
+    }
+
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_policy() {
+        let root = tempfile::tempdir().unwrap();
+        let store = PolicyStore::open(root.path()).unwrap();
+
+        let path = store.add(b"policy").unwrap();
+        assert_eq!(
+            root.path()
+                .join("sha256")
+                .join(format!("{:x}.wasm", Sha256::digest(b"policy".as_slice()))),
+            path
+        );
+        assert_eq!(b"policy".as_slice(), std::fs::read(&path).unwrap());
+
+        // same contents, same file
+        assert_eq!(path, store.add(b"policy").unwrap());
+        assert_ne!(path, store.add(b"other policy").unwrap());
+    }
+
+    #[test]
+    fn read_policy_checks_digest() {
+        let root = tempfile::tempdir().unwrap();
+        let store = PolicyStore::open(root.path()).unwrap();
+
+        let path = store.add(b"policy").unwrap();
+        assert_eq!(
+            b"policy".as_slice(),
+            read_policy(&path, &digest(b"policy")).unwrap()
+        );
+
+        std::fs::write(&path, b"tampered policy").unwrap();
+        let error = read_policy(&path, &digest(b"policy")).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn store_writable_by_others_is_rejected() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(root.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        let error = PolicyStore::open(root.path()).err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+    }
+}
@@ -0,0 +1,372 @@
+use sha2::{Digest, Sha256};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{debug, warn};
+use wasmtime_provider::wasmtime;
+
+use crate::policy_evaluator::trusted_dir::{ensure_trusted_dir, write_atomically};
+
+/// Identifies the files written by the cache
+const MAGIC: &[u8] = b"KWPRECOMPILED\0";
+
+/// Extension of the cached artifacts
+const EXTENSION: &str = "cwasm";
+
+/// Default maximum size of the cache, see [`PrecompiledCache::max_bytes`]
+pub(crate) const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// On-disk cache of precompiled Wasm modules.
+///
+/// Each artifact is keyed by the sha256 digest of the policy and by the
+/// fingerprint of the configuration of the [`wasmtime::Engine`], hence policies
+/// compiled with different engine settings do not collide.
+///
+/// Artifacts are stored together with the sha256 digest of the output of
+/// [`wasmtime::Module::serialize`]. The digest is verified before
+/// deserializing the module: corrupted or truncated artifacts are discarded and
+/// the policy is compiled again. The digest does not authenticate the
+/// artifact, anybody able to write it could make the host run arbitrary
+/// native code. Because of that, the cache is used only when the directory
+/// can be changed by the current user alone, see [`ensure_trusted_dir`].
+///
+/// The least recently used artifacts are evicted when the size of the cache
+/// exceeds its limit.
+pub(crate) struct PrecompiledCache<'a> {
+    dir: &'a Path,
+    max_bytes: u64,
+}
+
+impl<'a> PrecompiledCache<'a> {
+    pub(crate) fn new(dir: &'a Path) -> Self {
+        Self {
+            dir,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    /// Set the maximum size, in bytes, of the artifacts stored inside of the
+    /// cache. The artifact of the policy being loaded is never evicted
+    pub(crate) fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Returns the module built from the given Wasm bytes, using the cached
+    /// artifact when available. Otherwise the module is compiled and stored
+    /// inside of the cache.
+    ///
+    /// Failures of the cache are not fatal, the module is compiled from
+    /// scratch when something goes wrong.
+    pub(crate) fn load_or_compile(
+        &self,
+        engine: &wasmtime::Engine,
+        wasm: &[u8],
+    ) -> wasmtime::Result<wasmtime::Module> {
+        if let Err(e) = ensure_trusted_dir(self.dir) {
+            warn!(dir = ?self.dir, error = ?e, "cannot use precompiled cache, compiling the module");
+            return wasmtime::Module::new(engine, wasm);
+        }
+
+        let policy_digest = format!("{:x}", Sha256::digest(wasm));
+        let artifact = self.artifact_path(&policy_digest, &engine_fingerprint(engine));
+
+        if let Some(module) = self.load(engine, &artifact) {
+            debug!(artifact = ?artifact, "precompiled module loaded from cache");
+            // keep track of the usage of the artifact, for the eviction
+            if let Err(e) = std::fs::File::options()
+                .write(true)
+                .open(&artifact)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+            {
+                debug!(artifact = ?artifact, error = ?e, "cannot update modification time");
+            }
+            return Ok(module);
+        }
+
+        let module = wasmtime::Module::new(engine, wasm)?;
+        if let Err(e) = self.store(&module, &artifact) {
+            warn!(artifact = ?artifact, error = ?e, "cannot store precompiled module");
+        }
+        self.evict_stale(&policy_digest, &artifact);
+        self.evict_least_recently_used(&artifact);
+
+        Ok(module)
+    }
+
+    fn artifact_path(&self, policy_digest: &str, engine_fingerprint: &str) -> PathBuf {
+        self.dir
+            .join(format!("{policy_digest}-{engine_fingerprint}.{EXTENSION}"))
+    }
+
+    /// Load the artifact, ensuring its integrity. Invalid artifacts are removed
+    fn load(&self, engine: &wasmtime::Engine, artifact: &Path) -> Option<wasmtime::Module> {
+        let contents = std::fs::read(artifact).ok()?;
+
+        let serialized = match verify_artifact(&contents) {
+            Some(serialized)
+                if wasmtime::Engine::detect_precompiled(serialized)
+                    == Some(wasmtime::Precompiled::Module) =>
+            {
+                serialized
+            }
+            _ => {
+                warn!(artifact = ?artifact, "corrupted precompiled module, removing it");
+                let _ = std::fs::remove_file(artifact);
+                return None;
+            }
+        };
+
+        // SAFETY: the cache directory can be changed only by the current
+        // user, see `ensure_trusted_dir`, hence the artifact has been written
+        // by `store` with the bytes produced by `Module::serialize`. The
+        // digest ensures the artifact has not been corrupted since then.
+        // Moreover, wasmtime refuses to load artifacts produced by an
+        // incompatible engine.
+        match unsafe { wasmtime::Module::deserialize(engine, serialized) } {
+            Ok(module) => Some(module),
+            Err(e) => {
+                warn!(artifact = ?artifact, error = ?e, "cannot deserialize precompiled module, removing it");
+                let _ = std::fs::remove_file(artifact);
+                None
+            }
+        }
+    }
+
+    /// Store the artifact. The file is written atomically, concurrent readers
+    /// never see a partially written artifact
+    fn store(&self, module: &wasmtime::Module, artifact: &Path) -> wasmtime::Result<()> {
+        let serialized = module.serialize()?;
+
+        let mut contents = Vec::with_capacity(MAGIC.len() + 32 + serialized.len());
+        contents.extend_from_slice(MAGIC);
+        contents.extend_from_slice(&Sha256::digest(&serialized));
+        contents.extend_from_slice(&serialized);
+
+        write_atomically(artifact, &contents)?;
+
+        Ok(())
+    }
+
+    /// Remove the artifacts of the same policy that have been produced by an
+    /// engine with a different configuration, or by a different version of
+    /// wasmtime
+    fn evict_stale(&self, policy_digest: &str, current: &Path) {
+        let Ok(entries) = std::fs::read_dir(self.dir) else {
+            return;
+        };
+
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            let is_stale = path != current
+                && path.extension().and_then(|ext| ext.to_str()) == Some(EXTENSION)
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&format!("{policy_digest}-")));
+            if is_stale {
+                debug!(artifact = ?path, "evicting stale precompiled module");
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!(artifact = ?path, error = ?e, "cannot evict stale precompiled module");
+                }
+            }
+        }
+    }
+
+    /// Remove the least recently used artifacts, of any policy, until the
+    /// size of the cache is within its limit. The current artifact is kept
+    fn evict_least_recently_used(&self, current: &Path) {
+        let Ok(entries) = std::fs::read_dir(self.dir) else {
+            return;
+        };
+
+        let mut artifacts: Vec<(PathBuf, u64, SystemTime)> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                    return None;
+                }
+                let metadata = std::fs::metadata(&path).ok()?;
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((path, metadata.len(), used))
+            })
+            .collect();
+        let mut size: u64 = artifacts.iter().map(|(_, len, _)| len).sum();
+
+        // oldest first
+        artifacts.sort_by_key(|(_, _, used)| *used);
+        for (path, len, _) in artifacts {
+            if size <= self.max_bytes {
+                break;
+            }
+            if path == current {
+                continue;
+            }
+            debug!(artifact = ?path, "evicting least recently used precompiled module");
+            match std::fs::remove_file(&path) {
+                Ok(()) => size = size.saturating_sub(len),
+                Err(e) => {
+                    warn!(artifact = ?path, error = ?e, "cannot evict precompiled module")
+                }
+            }
+        }
+    }
+}
+
+/// Returns the output of `Module::serialize` stored inside of the artifact,
+/// provided its digest matches
+fn verify_artifact(contents: &[u8]) -> Option<&[u8]> {
+    let contents = contents.strip_prefix(MAGIC)?;
+    if contents.len() < 32 {
+        return None;
+    }
+    let (digest, serialized) = contents.split_at(32);
+
+    (Sha256::digest(serialized).as_slice() == digest).then_some(serialized)
+}
+
+/// Fingerprint of the engine configuration, including the version of wasmtime.
+///
+/// The hash is computed with sha256, unlike `DefaultHasher` its output does not
+/// change between Rust releases
+fn engine_fingerprint(engine: &wasmtime::Engine) -> String {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!("{:x}", hasher.0.finalize())
+}
+
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes long"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &str = r#"(module (func (export "_start")))"#;
+
+    fn artifacts(dir: &Path) -> Vec<PathBuf> {
+        let mut artifacts: Vec<PathBuf> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        artifacts.sort();
+        artifacts
+    }
+
+    #[test]
+    fn store_and_load_artifact() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = wasmtime::Engine::default();
+        let cache = PrecompiledCache::new(dir.path());
+
+        cache.load_or_compile(&engine, WAT.as_bytes()).unwrap();
+        let stored = artifacts(dir.path());
+        assert_eq!(1, stored.len());
+        let contents = std::fs::read(&stored[0]).unwrap();
+        assert!(verify_artifact(&contents).is_some());
+
+        assert!(cache.load(&engine, &stored[0]).is_some());
+        cache.load_or_compile(&engine, WAT.as_bytes()).unwrap();
+        assert_eq!(stored, artifacts(dir.path()));
+    }
+
+    #[test]
+    fn corrupted_artifact_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = wasmtime::Engine::default();
+        let cache = PrecompiledCache::new(dir.path());
+
+        cache.load_or_compile(&engine, WAT.as_bytes()).unwrap();
+        let artifact = artifacts(dir.path()).pop().unwrap();
+        let mut contents = std::fs::read(&artifact).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        std::fs::write(&artifact, &contents).unwrap();
+
+        assert!(cache.load(&engine, &artifact).is_none());
+        assert!(!artifact.exists());
+
+        cache.load_or_compile(&engine, WAT.as_bytes()).unwrap();
+        assert!(verify_artifact(&std::fs::read(&artifact).unwrap()).is_some());
+    }
+
+    #[test]
+    fn stale_artifacts_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = wasmtime::Engine::default();
+        let cache = PrecompiledCache::new(dir.path());
+
+        let policy_digest = format!("{:x}", Sha256::digest(WAT.as_bytes()));
+        let stale = cache.artifact_path(&policy_digest, "0000000000000000");
+        let other_policy = cache.artifact_path("other", "0000000000000000");
+        std::fs::write(&stale, b"stale").unwrap();
+        std::fs::write(&other_policy, b"other").unwrap();
+
+        cache.load_or_compile(&engine, WAT.as_bytes()).unwrap();
+
+        assert!(!stale.exists());
+        assert!(other_policy.exists());
+        assert!(cache
+            .artifact_path(&policy_digest, &engine_fingerprint(&engine))
+            .exists());
+    }
+
+    #[test]
+    fn least_recently_used_artifacts_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = wasmtime::Engine::default();
+
+        let old = dir.path().join(format!("old-policy.{EXTENSION}"));
+        std::fs::write(&old, vec![0; 1024]).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let recent = dir.path().join(format!("recent-policy.{EXTENSION}"));
+        std::fs::write(&recent, vec![0; 1024]).unwrap();
+
+        // room for the new artifact and one of the others
+        let artifact_len = wasmtime::Module::new(&engine, WAT)
+            .unwrap()
+            .serialize()
+            .unwrap()
+            .len()
+            + MAGIC.len()
+            + 32;
+        PrecompiledCache::new(dir.path())
+            .max_bytes(artifact_len as u64 + 1024)
+            .load_or_compile(&engine, WAT.as_bytes())
+            .unwrap();
+
+        assert!(!old.exists());
+        assert!(recent.exists());
+        assert_eq!(2, artifacts(dir.path()).len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn untrusted_dir_is_not_used() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+        let engine = wasmtime::Engine::default();
+
+        PrecompiledCache::new(dir.path())
+            .load_or_compile(&engine, WAT.as_bytes())
+            .unwrap();
+
+        assert!(artifacts(dir.path()).is_empty());
+    }
+}
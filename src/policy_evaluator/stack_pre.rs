@@ -1,4 +1,4 @@
-use crate::runtimes::{rego, wapc, wasi_cli};
+use crate::runtimes::{rego, wapc, wasi_cli, wasi_component};
 
 /// Holds pre-initialized stacks for all the types of policies we run
 ///
@@ -10,6 +10,7 @@ pub(crate) enum StackPre {
     Wapc(crate::runtimes::wapc::StackPre),
     Wasi(crate::runtimes::wasi_cli::StackPre),
     Rego(crate::runtimes::rego::StackPre),
+    WasiComponent(crate::runtimes::wasi_component::StackPre),
 }
 
 impl From<wapc::StackPre> for StackPre {
@@ -29,3 +30,9 @@ impl From<rego::StackPre> for StackPre {
         StackPre::Rego(rego_stack_pre)
     }
 }
+
+impl From<wasi_component::StackPre> for StackPre {
+    fn from(wasi_component_stack_pre: wasi_component::StackPre) -> Self {
+        StackPre::WasiComponent(wasi_component_stack_pre)
+    }
+}
@@ -0,0 +1,162 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter used to give a unique name to the temporary files written by this
+/// process, regardless of the thread writing them
+static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Create the directory, when it does not exist yet, and ensure only the
+/// current user can change its contents.
+///
+/// On Unix the directory is created with `0700` permissions. The directory
+/// must be owned by the current user and must not be writable by the group
+/// or by others. None of its ancestors can be changed by other users either:
+/// they must be owned by the current user or by root, and they can be
+/// writable by others only when the sticky bit is set, like `/tmp`.
+///
+/// No check is done on other platforms.
+pub(crate) fn ensure_trusted_dir(dir: &Path) -> io::Result<()> {
+    create_private_dir(dir)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // SAFETY: `geteuid` has no preconditions and cannot fail
+        let uid = unsafe { libc::geteuid() };
+
+        let metadata = std::fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() {
+            return Err(untrusted(dir, "not a directory"));
+        }
+        if metadata.uid() != uid {
+            return Err(untrusted(dir, "not owned by the current user"));
+        }
+        if metadata.mode() & 0o022 != 0 {
+            return Err(untrusted(dir, "writable by other users"));
+        }
+
+        let dir = dir.canonicalize()?;
+        for ancestor in dir.ancestors().skip(1) {
+            let metadata = std::fs::metadata(ancestor)?;
+            if metadata.uid() != uid && metadata.uid() != 0 {
+                return Err(untrusted(ancestor, "owned by another user"));
+            }
+            if metadata.mode() & 0o022 != 0 && metadata.mode() & 0o1000 == 0 {
+                return Err(untrusted(ancestor, "writable by other users"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn untrusted(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("untrusted directory {}: {reason}", path.display()),
+    )
+}
+
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+/// Write the file atomically: concurrent readers never see a partially
+/// written file. The file can be read only by the current user
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))?;
+    let tmp = path.with_file_name(format!(
+        ".{file_name}.tmp-{}-{}",
+        std::process::id(),
+        TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let result = options
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusted_dir_is_created() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("nested").join("dir");
+
+        ensure_trusted_dir(&dir).unwrap();
+        assert!(dir.is_dir());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(0o700, mode & 0o777);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_writable_by_others_is_not_trusted() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(root.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        let error = ensure_trusted_dir(root.path()).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+
+        // the same applies to the directories created inside of it
+        let error = ensure_trusted_dir(&root.path().join("dir")).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+    }
+
+    #[test]
+    fn concurrent_atomic_writes() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("file");
+
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let path = &path;
+                scope.spawn(move || write_atomically(path, &[i; 1024]).unwrap());
+            }
+        });
+
+        let contents = std::fs::read(&path).unwrap();
+        assert_eq!(1024, contents.len());
+        assert!(contents.iter().all(|b| *b == contents[0]));
+        // no temporary file is left behind
+        assert_eq!(1, std::fs::read_dir(root.path()).unwrap().count());
+    }
+}
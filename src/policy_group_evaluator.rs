@@ -0,0 +1,456 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::admission_response::{AdmissionResponse, AdmissionResponseStatus};
+use crate::errors::PolicyGroupError;
+use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::{
+    PolicyEvaluatorPool, PolicyEvaluatorPre, PolicySettings, ValidateRequest,
+};
+use crate::policy_metadata::{ContextAwareResource, HostCapability};
+
+mod expression;
+
+use expression::Expression;
+
+/// Time waited by an evaluation for an instance of a member to be available,
+/// it matches the default timeout of the Kubernetes admission webhooks
+const DEFAULT_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A policy that is part of a [`PolicyGroupEvaluator`]
+pub struct PolicyGroupMember {
+    /// Used to create the `PolicyEvaluator` instances of the member
+    pub policy_evaluator_pre: PolicyEvaluatorPre,
+
+    /// The settings of the member
+    pub settings: PolicySettings,
+
+    /// List of ContextAwareResource the member is granted access to
+    pub ctx_aware_resources_allow_list: BTreeSet<ContextAwareResource>,
+
+    /// List of host capabilities the member is granted access to, `None`
+    /// grants access to all of them
+    pub host_capabilities_allow_list: Option<BTreeSet<HostCapability>>,
+}
+
+struct Member {
+    pool: PolicyEvaluatorPool,
+    settings: PolicySettings,
+}
+
+/// Evaluates a group of policies and combines their results into a single
+/// `AdmissionResponse`.
+///
+/// The results of the members are combined using a boolean expression, like
+/// `policy_a && (policy_b || !policy_c)`. Each member is evaluated at most once,
+/// and only when its outcome is needed to compute the result of the expression.
+///
+/// The messages of the rejecting members are merged into the final response,
+/// together with the warnings and the audit annotations of all the evaluated
+/// members.
+///
+/// The request is rejected with an internal server error, regardless of the
+/// expression, when a member cannot be evaluated: its instance cannot be
+/// created, its execution traps or it rejects the request with the `500`
+/// status code, which is used for internal errors. The same happens when
+/// the members set an audit annotation to different values.
+///
+/// The members are evaluated by pools of reusable `PolicyEvaluator`
+/// instances, an instance is replaced once its execution traps.
+///
+/// **Note:** policy groups can only validate requests, the patches produced by
+/// the members are ignored.
+pub struct PolicyGroupEvaluator {
+    expression: Expression,
+    members: HashMap<String, Member>,
+}
+
+impl PolicyGroupEvaluator {
+    /// Create a new `PolicyGroupEvaluator`, each member holds at most
+    /// `max_instances_per_member` instances of `PolicyEvaluator`.
+    ///
+    /// The members are evaluated using a copy of the given `EvaluationContext`,
+    /// where the policy id is set to `<policy_id>/<member name>` and the lists of
+    /// the Kubernetes resources and of the host capabilities are replaced by the
+    /// ones of the member.
+    ///
+    /// An error is returned when the expression is not valid, or when it
+    /// references a policy that is not part of `members`.
+    pub fn new(
+        expression: &str,
+        members: HashMap<String, PolicyGroupMember>,
+        eval_ctx: &EvaluationContext,
+        max_instances_per_member: usize,
+    ) -> Result<Self, PolicyGroupError> {
+        let expression = Expression::parse(expression)?;
+
+        if let Some(unknown) = expression
+            .members()
+            .into_iter()
+            .find(|name| !members.contains_key(*name))
+        {
+            return Err(PolicyGroupError::UnknownMember(unknown.to_string()));
+        }
+
+        let members = members
+            .into_iter()
+            .map(|(name, member)| {
+                let member_eval_ctx = EvaluationContext {
+                    policy_id: format!("{}/{}", eval_ctx.policy_id, name),
+                    ctx_aware_resources_allow_list: member.ctx_aware_resources_allow_list,
+                    host_capabilities_allow_list: member.host_capabilities_allow_list,
+                    ..eval_ctx.clone()
+                };
+                let pool = PolicyEvaluatorPool::new(
+                    member.policy_evaluator_pre,
+                    &member_eval_ctx,
+                    max_instances_per_member,
+                )
+                .enable_recycle_on_trap()
+                .checkout_timeout(DEFAULT_CHECKOUT_TIMEOUT);
+                (
+                    name,
+                    Member {
+                        pool,
+                        settings: member.settings,
+                    },
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            expression,
+            members,
+        })
+    }
+
+    /// Evaluate the request against the group
+    #[tracing::instrument(skip(self, request))]
+    pub fn validate(&self, request: &ValidateRequest) -> AdmissionResponse {
+        let mut responses: Vec<(String, AdmissionResponse)> = Vec::new();
+
+        let allowed = self.expression.evaluate(&mut |name: &str| {
+            let response = self.validate_member(name, request)?;
+            let allowed = response.allowed;
+            responses.push((name.to_string(), response));
+            Ok(allowed)
+        });
+
+        match allowed {
+            Ok(allowed) => merge_responses(request.uid(), allowed, responses),
+            Err(internal_error) => internal_error,
+        }
+    }
+
+    /// Evaluate a single member. Internal errors are returned as `Err`, they
+    /// must not be mistaken for rejections: the expression could negate them
+    fn validate_member(
+        &self,
+        name: &str,
+        request: &ValidateRequest,
+    ) -> Result<AdmissionResponse, AdmissionResponse> {
+        // the expression has been checked at creation time, all the members exist
+        let member = &self.members[name];
+
+        let mut policy_evaluator = member.pool.checkout().map_err(|e| {
+            AdmissionResponse::reject_internal_server_error(
+                request.uid().to_string(),
+                format!("cannot evaluate policy group member {name}: {e}"),
+            )
+        })?;
+        debug!(member = name, "evaluating policy group member");
+        let response = policy_evaluator.validate(request.to_owned(), &member.settings);
+
+        if policy_evaluator.has_trapped() || is_internal_error(&response) {
+            let message = response
+                .status
+                .and_then(|status| status.message)
+                .unwrap_or_default();
+            return Err(AdmissionResponse::reject(
+                request.uid().to_string(),
+                format!("{name}: {message}"),
+                500,
+            ));
+        }
+        Ok(response)
+    }
+}
+
+/// Internal errors are reported with the `500` status code, see
+/// [`AdmissionResponse::reject_internal_server_error`]
+fn is_internal_error(response: &AdmissionResponse) -> bool {
+    !response.allowed
+        && response
+            .status
+            .as_ref()
+            .is_some_and(|status| status.code == Some(500))
+}
+
+/// Merge the responses of the evaluated members into a single `AdmissionResponse`.
+/// The members cannot set the same audit annotation to different values
+fn merge_responses(
+    uid: &str,
+    allowed: bool,
+    responses: Vec<(String, AdmissionResponse)>,
+) -> AdmissionResponse {
+    let mut warnings: Vec<String> = Vec::new();
+    let mut audit_annotations: HashMap<String, String> = HashMap::new();
+    let mut messages: Vec<String> = Vec::new();
+    let mut code: Option<u16> = None;
+
+    for (name, response) in responses {
+        warnings.extend(response.warnings.unwrap_or_default());
+
+        for (key, value) in response.audit_annotations.unwrap_or_default() {
+            match audit_annotations.get(&key) {
+                Some(previous) if previous != &value => {
+                    return AdmissionResponse::reject_internal_server_error(
+                        uid.to_string(),
+                        format!("policy group member {name} sets the audit annotation {key:?} to a conflicting value"),
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    audit_annotations.insert(key, value);
+                }
+            }
+        }
+
+        if !response.allowed {
+            let status = response.status.unwrap_or_default();
+            if let Some(message) = status.message {
+                messages.push(format!("{name}: {message}"));
+            }
+            code = code.or(status.code);
+        }
+    }
+
+    let status = if allowed {
+        None
+    } else {
+        Some(AdmissionResponseStatus {
+            message: Some(if messages.is_empty() {
+                "the request has been rejected by the policy group".to_string()
+            } else {
+                messages.join(", ")
+            }),
+            code,
+        })
+    };
+
+    AdmissionResponse {
+        uid: uid.to_string(),
+        allowed,
+        status,
+        audit_annotations: (!audit_annotations.is_empty()).then_some(audit_annotations),
+        warnings: (!warnings.is_empty()).then_some(warnings),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::policy_evaluator::{PolicyEvaluatorPoolStats, PolicyExecutionMode};
+    use crate::policy_evaluator_builder::PolicyEvaluatorBuilder;
+
+    /// A waPC module accepting all the requests
+    const ACCEPT_WAT: &str = r#"
+        (module
+          (import "wapc" "__guest_response" (func $guest_response (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"accepted\":true}")
+          (func (export "wapc_init"))
+          (func (export "__guest_call") (param i32 i32) (result i32)
+            (call $guest_response (i32.const 0) (i32.const 17))
+            (i32.const 1))
+        )
+    "#;
+
+    /// A waPC module whose functions always trap
+    const TRAP_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "wapc_init"))
+          (func (export "__guest_call") (param i32 i32) (result i32) unreachable)
+        )
+    "#;
+
+    fn policy_group(expression: &str) -> PolicyGroupEvaluator {
+        let members = [("accept", ACCEPT_WAT), ("trap", TRAP_WAT)]
+            .into_iter()
+            .map(|(name, wat)| {
+                let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+                    .execution_mode(PolicyExecutionMode::KubewardenWapc)
+                    .policy_contents(wat.as_bytes())
+                    .build_pre()
+                    .expect("cannot build policy evaluator pre");
+                (
+                    name.to_string(),
+                    PolicyGroupMember {
+                        policy_evaluator_pre,
+                        settings: PolicySettings::default(),
+                        ctx_aware_resources_allow_list: BTreeSet::new(),
+                        host_capabilities_allow_list: None,
+                    },
+                )
+            })
+            .collect();
+
+        PolicyGroupEvaluator::new(expression, members, &EvaluationContext::default(), 1)
+            .expect("cannot build policy group evaluator")
+    }
+
+    #[test]
+    fn members_instances_are_reused() {
+        let policy_group = policy_group("accept");
+        let request = ValidateRequest::Raw(json!({"uid": "uid"}));
+
+        assert!(policy_group.validate(&request).allowed);
+        assert!(policy_group.validate(&request).allowed);
+
+        assert_eq!(
+            PolicyEvaluatorPoolStats {
+                hits: 1,
+                misses: 1,
+                recycles: 0,
+            },
+            policy_group.members["accept"].pool.stats()
+        );
+    }
+
+    #[rstest]
+    #[case::member("trap")]
+    #[case::negated_member("!trap")]
+    #[case::evaluated_alternative("!trap || accept")]
+    fn member_internal_error_rejects_the_group(#[case] expression: &str) {
+        let policy_group = policy_group(expression);
+
+        let response = policy_group.validate(&ValidateRequest::Raw(json!({"uid": "uid"})));
+
+        assert!(!response.allowed);
+        let status = response.status.unwrap();
+        assert_eq!(Some(500), status.code);
+        let message = status.message.unwrap();
+        assert!(message.starts_with("trap: "), "{message}");
+    }
+
+    #[test]
+    fn member_not_evaluated_cannot_fail() {
+        let policy_group = policy_group("accept || !trap");
+
+        let response = policy_group.validate(&ValidateRequest::Raw(json!({"uid": "uid"})));
+
+        assert!(response.allowed);
+    }
+
+    #[test]
+    fn merge_rejected_responses() {
+        let responses = vec![
+            (
+                "a".to_string(),
+                response(
+                    false,
+                    Some("not allowed"),
+                    Some(vec!["warning a"]),
+                    Some(vec![("key", "a")]),
+                ),
+            ),
+            (
+                "b".to_string(),
+                response(
+                    true,
+                    None,
+                    Some(vec!["warning b"]),
+                    Some(vec![("key", "a"), ("other", "b")]),
+                ),
+            ),
+            ("c".to_string(), response(false, Some("bad"), None, None)),
+        ];
+
+        let merged = merge_responses("uid", false, responses);
+
+        assert!(!merged.allowed);
+        assert_eq!(
+            Some(AdmissionResponseStatus {
+                message: Some("a: not allowed, c: bad".to_string()),
+                code: None,
+            }),
+            merged.status
+        );
+        assert_eq!(
+            Some(vec!["warning a".to_string(), "warning b".to_string()]),
+            merged.warnings
+        );
+        assert_eq!(
+            Some(HashMap::from([
+                ("key".to_string(), "a".to_string()),
+                ("other".to_string(), "b".to_string()),
+            ])),
+            merged.audit_annotations
+        );
+    }
+
+    #[test]
+    fn merge_conflicting_audit_annotations() {
+        let responses = vec![
+            (
+                "a".to_string(),
+                response(true, None, None, Some(vec![("key", "a")])),
+            ),
+            (
+                "b".to_string(),
+                response(true, None, None, Some(vec![("key", "b")])),
+            ),
+        ];
+
+        let merged = merge_responses("uid", true, responses);
+
+        assert!(!merged.allowed);
+        let status = merged.status.unwrap();
+        assert_eq!(Some(500), status.code);
+        assert_eq!(
+            Some(
+                "internal server error: policy group member b sets the audit annotation \"key\" to a conflicting value"
+                    .to_string()
+            ),
+            status.message
+        );
+    }
+
+    #[test]
+    fn merge_allowed_responses() {
+        let responses = vec![
+            (
+                "a".to_string(),
+                response(false, Some("not allowed"), None, None),
+            ),
+            ("b".to_string(), response(true, None, None, None)),
+        ];
+
+        let merged = merge_responses("uid", true, responses);
+
+        assert!(merged.allowed);
+        assert!(merged.status.is_none());
+        assert!(merged.warnings.is_none());
+        assert!(merged.audit_annotations.is_none());
+    }
+
+    #[test]
+    fn rejected_without_messages() {
+        let responses = vec![("a".to_string(), response(true, None, None, None))];
+
+        // this happens with expressions like `!a`
+        let merged = merge_responses("uid", false, responses);
+
+        assert!(!merged.allowed);
+        assert_eq!(
+            Some("the request has been rejected by the policy group".to_string()),
+            merged.status.and_then(|s| s.message)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::errors::PolicyGroupError;
+
+/// A boolean expression over the members of a policy group.
+///
+/// The grammar is the following one, from the lowest to the highest precedence:
+///
+/// ```text
+/// expression := or
+/// or         := and ( "||" and )*
+/// and        := not ( "&&" not )*
+/// not        := "!" not | primary
+/// primary    := member | "(" expression ")"
+/// member     := [a-zA-Z0-9_-]+
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expression {
+    Member(String),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Parse the given expression
+    pub(crate) fn parse(expression: &str) -> Result<Self, PolicyGroupError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+        };
+        let parsed = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(PolicyGroupError::InvalidExpression(format!(
+                "unexpected token `{token}`"
+            )));
+        }
+
+        Ok(parsed)
+    }
+
+    /// Names of all the members referenced by the expression
+    pub(crate) fn members(&self) -> BTreeSet<&str> {
+        let mut members = BTreeSet::new();
+        self.collect_members(&mut members);
+        members
+    }
+
+    fn collect_members<'a>(&'a self, members: &mut BTreeSet<&'a str>) {
+        match self {
+            Expression::Member(name) => {
+                members.insert(name.as_str());
+            }
+            Expression::Not(expr) => expr.collect_members(members),
+            Expression::And(lhs, rhs) | Expression::Or(lhs, rhs) => {
+                lhs.collect_members(members);
+                rhs.collect_members(members);
+            }
+        }
+    }
+
+    /// Evaluate the expression. The outcome of each member is obtained by
+    /// invoking `eval_member`.
+    ///
+    /// The evaluation short-circuits: the right-hand side of `&&` and `||` is
+    /// evaluated only when needed. Members referenced multiple times are
+    /// evaluated only once. The evaluation stops at the first member that
+    /// cannot be evaluated, its error is returned.
+    pub(crate) fn evaluate<F, E>(&self, eval_member: &mut F) -> Result<bool, E>
+    where
+        F: FnMut(&str) -> Result<bool, E>,
+    {
+        let mut outcomes: HashMap<String, bool> = HashMap::new();
+        self.evaluate_with_cache(eval_member, &mut outcomes)
+    }
+
+    fn evaluate_with_cache<F, E>(
+        &self,
+        eval_member: &mut F,
+        outcomes: &mut HashMap<String, bool>,
+    ) -> Result<bool, E>
+    where
+        F: FnMut(&str) -> Result<bool, E>,
+    {
+        match self {
+            Expression::Member(name) => {
+                if let Some(outcome) = outcomes.get(name) {
+                    return Ok(*outcome);
+                }
+                let outcome = eval_member(name)?;
+                outcomes.insert(name.to_owned(), outcome);
+                Ok(outcome)
+            }
+            Expression::Not(expr) => Ok(!expr.evaluate_with_cache(eval_member, outcomes)?),
+            Expression::And(lhs, rhs) => Ok(lhs.evaluate_with_cache(eval_member, outcomes)?
+                && rhs.evaluate_with_cache(eval_member, outcomes)?),
+            Expression::Or(lhs, rhs) => Ok(lhs.evaluate_with_cache(eval_member, outcomes)?
+                || rhs.evaluate_with_cache(eval_member, outcomes)?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Member(String),
+    Not,
+    And,
+    Or,
+    LeftParen,
+    RightParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Member(name) => write!(f, "{name}"),
+            Token::Not => write!(f, "!"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
+fn is_member_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, PolicyGroupError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::LeftParen),
+            ')' => tokens.push(Token::RightParen),
+            '&' | '|' => {
+                if chars.next_if(|(_, next)| *next == c).is_none() {
+                    return Err(PolicyGroupError::InvalidExpression(format!(
+                        "unexpected character `{c}` at position {pos}, did you mean `{c}{c}`?"
+                    )));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            c if is_member_char(c) => {
+                let mut name = c.to_string();
+                while let Some((_, next)) = chars.next_if(|(_, next)| is_member_char(*next)) {
+                    name.push(next);
+                }
+                tokens.push(Token::Member(name));
+            }
+            _ => {
+                return Err(PolicyGroupError::InvalidExpression(format!(
+                    "unexpected character `{c}` at position {pos}"
+                )))
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, PolicyGroupError> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            expr = Expression::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, PolicyGroupError> {
+        let mut expr = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            expr = Expression::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, PolicyGroupError> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(Expression::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, PolicyGroupError> {
+        match self.advance() {
+            Some(Token::Member(name)) => Ok(Expression::Member(name.to_owned())),
+            Some(Token::LeftParen) => {
+                let expr = self.parse_or()?;
+                match self.advance() {
+                    Some(Token::RightParen) => Ok(expr),
+                    Some(token) => Err(PolicyGroupError::InvalidExpression(format!(
+                        "expected `)`, found `{token}`"
+                    ))),
+                    None => Err(PolicyGroupError::InvalidExpression(
+                        "expected `)`, found end of expression".to_string(),
+                    )),
+                }
+            }
+            Some(token) => Err(PolicyGroupError::InvalidExpression(format!(
+                "expected policy name or `(`, found `{token}`"
+            ))),
+            None => Err(PolicyGroupError::InvalidExpression(
+                "expected policy name or `(`, found end of expression".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn member(name: &str) -> Box<Expression> {
+        Box::new(Expression::Member(name.to_string()))
+    }
+
+    #[rstest]
+    #[case::single("a", Expression::Member("a".to_string()))]
+    #[case::and_binds_tighter_than_or(
+        "a || b && c",
+        Expression::Or(member("a"), Box::new(Expression::And(member("b"), member("c"))))
+    )]
+    #[case::parens(
+        "a && (b || c)",
+        Expression::And(member("a"), Box::new(Expression::Or(member("b"), member("c"))))
+    )]
+    #[case::not(
+        "!a && !!b",
+        Expression::And(
+            Box::new(Expression::Not(member("a"))),
+            Box::new(Expression::Not(Box::new(Expression::Not(member("b")))))
+        )
+    )]
+    #[case::names_with_dashes(
+        "pod-privileged||psp_capabilities",
+        Expression::Or(member("pod-privileged"), member("psp_capabilities"))
+    )]
+    fn parse_valid_expression(#[case] input: &str, #[case] expected: Expression) {
+        assert_eq!(expected, Expression::parse(input).unwrap());
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::single_ampersand("a & b")]
+    #[case::unbalanced_parens("(a && b")]
+    #[case::extra_paren("a && b)")]
+    #[case::missing_operand("a ||")]
+    #[case::missing_operator("a b")]
+    #[case::invalid_char("a && b$")]
+    fn parse_invalid_expression(#[case] input: &str) {
+        assert!(matches!(
+            Expression::parse(input),
+            Err(PolicyGroupError::InvalidExpression(_))
+        ));
+    }
+
+    #[rstest]
+    #[case::and_short_circuit("a && b", &[("a", false), ("b", true)], false, &["a"])]
+    #[case::or_short_circuit("a || b", &[("a", true), ("b", false)], true, &["a"])]
+    #[case::full_evaluation("a && (b || c)", &[("a", true), ("b", false), ("c", true)], true, &["a", "b", "c"])]
+    #[case::not("!a || b", &[("a", true), ("b", false)], false, &["a", "b"])]
+    #[case::member_evaluated_once("a && (a || b)", &[("a", true), ("b", true)], true, &["a"])]
+    fn evaluate_expression(
+        #[case] input: &str,
+        #[case] outcomes: &[(&str, bool)],
+        #[case] expected: bool,
+        #[case] expected_evaluated: &[&str],
+    ) {
+        let outcomes: HashMap<&str, bool> = outcomes.iter().copied().collect();
+        let mut evaluated = Vec::new();
+
+        let expression = Expression::parse(input).unwrap();
+        let result = expression.evaluate(&mut |name: &str| -> Result<bool, ()> {
+            evaluated.push(name.to_string());
+            Ok(outcomes[name])
+        });
+
+        assert_eq!(Ok(expected), result);
+        assert_eq!(expected_evaluated, evaluated.as_slice());
+    }
+
+    #[test]
+    fn evaluation_stops_at_the_first_error() {
+        let mut evaluated = Vec::new();
+
+        let expression = Expression::parse("!a || b").unwrap();
+        let result = expression.evaluate(&mut |name: &str| {
+            evaluated.push(name.to_string());
+            match name {
+                "a" => Err(format!("{name} failed")),
+                _ => Ok(true),
+            }
+        });
+
+        assert_eq!(Err("a failed".to_string()), result);
+        assert_eq!(vec!["a".to_string()], evaluated);
+    }
+}
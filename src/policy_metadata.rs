@@ -8,6 +8,7 @@ use std::path::Path;
 use validator::{Validate, ValidationError};
 use wasmparser::{Parser, Payload};
 
+use crate::admission_request::AdmissionRequest;
 use crate::errors::MetadataError;
 use crate::policy_evaluator::PolicyExecutionMode;
 
@@ -25,6 +26,17 @@ pub enum Operation {
     All,
 }
 
+impl Operation {
+    /// Returns true when the operation of an `AdmissionRequest` (e.g. `CREATE`)
+    /// is covered by this one
+    pub fn matches(&self, operation: &str) -> bool {
+        match self {
+            Operation::All => true,
+            _ => Operation::try_from(operation).is_ok_and(|op| &op == self),
+        }
+    }
+}
+
 impl TryFrom<&str> for Operation {
     type Error = &'static str;
 
@@ -56,6 +68,45 @@ pub struct Rule {
     pub operations: Vec<Operation>,
 }
 
+impl Rule {
+    /// Returns true when the given request is in the scope of the rule.
+    ///
+    /// The matching follows the semantics of Kubernetes admission webhooks:
+    /// `*` matches all the API groups, API versions and operations, while the
+    /// resources are matched together with the subresource of the request:
+    ///
+    /// * `pods` matches the `pods` resource, but none of its subresources
+    /// * `pods/exec` matches only the `exec` subresource of `pods`
+    /// * `pods/*` matches `pods` and all its subresources
+    /// * `*/exec` matches the `exec` subresource of all the resources
+    /// * `*` matches all the resources, but none of their subresources
+    /// * `*/*` matches all the resources and all their subresources
+    pub fn matches(&self, request: &AdmissionRequest) -> bool {
+        // see https://github.com/kubernetes/kubernetes/blob/09268c16853b233ebaedcd6a877eac23690b5190/staging/src/k8s.io/apiserver/pkg/admission/plugin/webhook/predicates/rules/rules.go
+        let matches_any =
+            |values: &[String], value: &str| values.iter().any(|v| v == "*" || v == value);
+
+        self.operations
+            .iter()
+            .any(|op| op.matches(&request.operation))
+            && matches_any(&self.api_groups, &request.resource.group)
+            && matches_any(&self.api_versions, &request.resource.version)
+            && self.matches_resource(
+                &request.resource.resource,
+                request.sub_resource.as_deref().unwrap_or_default(),
+            )
+    }
+
+    fn matches_resource(&self, resource: &str, sub_resource: &str) -> bool {
+        self.resources.iter().any(|rule_resource| {
+            let (res, sub) = rule_resource
+                .split_once('/')
+                .unwrap_or((rule_resource.as_str(), ""));
+            (res == "*" || res == resource) && (sub == "*" || sub == sub_resource)
+        })
+    }
+}
+
 fn validate_asterisk_usage(data: &[String]) -> Result<(), ValidationError> {
     if data.contains(&String::from("*")) && data.len() > 1 {
         return Err(ValidationError::new(
@@ -166,6 +217,26 @@ pub struct ContextAwareResource {
     pub kind: String,
 }
 
+/// A host capability used by the policy, identified by its namespace (e.g. `oci`)
+/// and by its operation (e.g. `v1/verify`). The `*` operation covers all the
+/// operations of the namespace.
+#[derive(Deserialize, Serialize, Debug, Clone, Validate, PartialEq, Hash, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub struct HostCapability {
+    #[validate(length(min = 1))]
+    pub namespace: String,
+    #[validate(length(min = 1))]
+    pub operation: String,
+}
+
+impl HostCapability {
+    /// Returns true when the given operation of the namespace is covered by
+    /// this capability
+    pub fn matches(&self, namespace: &str, operation: &str) -> bool {
+        self.namespace == namespace && (self.operation == "*" || self.operation == operation)
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
 pub enum PolicyType {
     #[default]
@@ -202,8 +273,21 @@ pub struct Metadata {
     #[serde(default)]
     #[validate(nested)]
     pub context_aware_resources: BTreeSet<ContextAwareResource>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    #[validate(nested)]
+    pub host_capabilities: BTreeSet<HostCapability>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub minimum_kubewarden_version: Option<Version>,
+    /// JSON Schema the settings of the policy must comply with. This is
+    /// currently enforced only for Rego policies, which cannot validate
+    /// their settings otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings_schema: Option<serde_json::Value>,
+    /// Names of the entrypoints of a Rego policy to be evaluated, their
+    /// results are merged. The first entrypoint of the policy is evaluated
+    /// when none is given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rego_entrypoints: Vec<String>,
 }
 
 const fn _default_true() -> bool {
@@ -221,12 +305,21 @@ impl Default for Metadata {
             execution_mode: PolicyExecutionMode::KubewardenWapc,
             policy_type: PolicyType::Kubernetes,
             context_aware_resources: BTreeSet::new(),
+            host_capabilities: BTreeSet::new(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            rego_entrypoints: vec![],
         }
     }
 }
 
 impl Metadata {
+    /// Returns true when the given request is in the scope of at least one of
+    /// the rules of the policy. See [`Rule::matches`] for the matching semantics.
+    pub fn matches(&self, request: &AdmissionRequest) -> bool {
+        self.rules.iter().any(|rule| rule.matches(request))
+    }
+
     pub fn from_path(path: &Path) -> std::result::Result<Option<Metadata>, MetadataError> {
         Metadata::from_contents(&std::fs::read(path).map_err(MetadataError::Path)?)
     }
@@ -256,6 +349,13 @@ fn validate_metadata(metadata: &Metadata) -> Result<(), ValidationError> {
             "Must specify a valid protocol version",
         ));
     }
+    if let Some(schema) = &metadata.settings_schema {
+        if jsonschema::JSONSchema::compile(schema).is_err() {
+            return Err(ValidationError::new(
+                "Settings schema must be a valid JSON Schema",
+            ));
+        }
+    }
     Ok(())
 }
 
@@ -263,6 +363,7 @@ fn validate_metadata(metadata: &Metadata) -> Result<(), ValidationError> {
 mod tests {
     use super::*;
     use assert_json_diff::assert_json_eq;
+    use rstest::rstest;
     use serde_json::json;
 
     #[test]
@@ -283,6 +384,21 @@ mod tests {
         Ok(())
     }
 
+    #[rstest]
+    #[case::valid_schema(json!({"type": "object", "required": ["name"]}), true)]
+    #[case::invalid_schema(json!({"type": "not-a-type"}), false)]
+    fn metadata_validation_of_settings_schema(
+        #[case] settings_schema: serde_json::Value,
+        #[case] valid: bool,
+    ) {
+        let metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            settings_schema: Some(settings_schema),
+            ..Default::default()
+        };
+        assert_eq!(valid, metadata.validate().is_ok());
+    }
+
     #[test]
     fn metadata_validation_failure() -> Result<(), ()> {
         // fail because api_groups has both '*' and another value
@@ -700,4 +816,178 @@ mod tests {
 
         assert!(metadata.validate().is_err());
     }
+
+    #[test]
+    fn validate_host_capability_without_operation() {
+        let metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            host_capabilities: BTreeSet::from([HostCapability {
+                namespace: "oci".to_string(),
+                operation: "".to_string(),
+            }]),
+            ..Default::default()
+        };
+
+        assert!(metadata.validate().is_err());
+    }
+
+    #[test]
+    fn metadata_with_host_capabilities() {
+        let json_metadata = json!({
+            "protocolVersion": "v1",
+            "rules": [ ],
+            "mutating": false,
+            "hostCapabilities": [
+                {"namespace": "oci", "operation": "v2/verify"},
+                {"namespace": "net", "operation": "*"},
+            ],
+        });
+
+        let metadata: Metadata =
+            serde_json::from_value(json_metadata).expect("cannot deserialize Metadata");
+
+        assert!(metadata.validate().is_ok());
+        assert_eq!(
+            BTreeSet::from([
+                HostCapability {
+                    namespace: "net".to_string(),
+                    operation: "*".to_string(),
+                },
+                HostCapability {
+                    namespace: "oci".to_string(),
+                    operation: "v2/verify".to_string(),
+                },
+            ]),
+            metadata.host_capabilities
+        );
+    }
+
+    #[rstest]
+    #[case::same_operation("oci", "v2/verify", "oci", "v2/verify", true)]
+    #[case::other_operation("oci", "v2/verify", "oci", "v1/verify", false)]
+    #[case::any_operation("oci", "*", "oci", "v1/manifest_digest", true)]
+    #[case::other_namespace("oci", "*", "net", "v1/dns_lookup_host", false)]
+    fn host_capability_matches(
+        #[case] capability_namespace: &str,
+        #[case] capability_operation: &str,
+        #[case] namespace: &str,
+        #[case] operation: &str,
+        #[case] expected: bool,
+    ) {
+        let capability = HostCapability {
+            namespace: capability_namespace.to_string(),
+            operation: capability_operation.to_string(),
+        };
+
+        assert_eq!(expected, capability.matches(namespace, operation));
+    }
+
+    fn admission_request(
+        group: &str,
+        version: &str,
+        resource: &str,
+        sub_resource: Option<&str>,
+        operation: &str,
+    ) -> AdmissionRequest {
+        serde_json::from_value(json!({
+            "uid": "uid",
+            "kind": {"group": group, "version": version, "kind": "Kind"},
+            "resource": {"group": group, "version": version, "resource": resource},
+            "subResource": sub_resource,
+            "operation": operation,
+            "userInfo": {},
+        }))
+        .unwrap()
+    }
+
+    fn rule(resources: &[&str], operations: Vec<Operation>) -> Rule {
+        Rule {
+            api_groups: vec![String::from("")],
+            api_versions: vec![String::from("v1")],
+            resources: resources.iter().map(|r| r.to_string()).collect(),
+            operations,
+        }
+    }
+
+    #[rstest]
+    #[case::resource(&["pods"], None, true)]
+    #[case::resource_does_not_match_subresource(&["pods"], Some("exec"), false)]
+    #[case::other_resource(&["deployments"], None, false)]
+    #[case::subresource(&["pods/exec"], Some("exec"), true)]
+    #[case::subresource_does_not_match_resource(&["pods/exec"], None, false)]
+    #[case::other_subresource(&["pods/exec"], Some("log"), false)]
+    #[case::resource_wildcard_subresource(&["pods/*"], Some("exec"), true)]
+    #[case::resource_wildcard_subresource_without_subresource(&["pods/*"], None, true)]
+    #[case::wildcard_resource_subresource(&["*/exec"], Some("exec"), true)]
+    #[case::wildcard_resource_other_subresource(&["*/exec"], Some("log"), false)]
+    #[case::wildcard(&["*"], None, true)]
+    #[case::wildcard_does_not_match_subresource(&["*"], Some("exec"), false)]
+    #[case::double_wildcard(&["*/*"], Some("exec"), true)]
+    #[case::many_resources(&["deployments", "pods/exec"], Some("exec"), true)]
+    fn rule_matches_resource(
+        #[case] resources: &[&str],
+        #[case] sub_resource: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let request = admission_request("", "v1", "pods", sub_resource, "CREATE");
+
+        assert_eq!(
+            expected,
+            rule(resources, vec![Operation::Create]).matches(&request)
+        );
+    }
+
+    #[rstest]
+    #[case::same_operation(vec![Operation::Create], "CREATE", true)]
+    #[case::other_operation(vec![Operation::Create, Operation::Delete], "UPDATE", false)]
+    #[case::wildcard(vec![Operation::All], "CONNECT", true)]
+    #[case::unknown_operation(vec![Operation::Create], "PATCH", false)]
+    fn rule_matches_operation(
+        #[case] operations: Vec<Operation>,
+        #[case] operation: &str,
+        #[case] expected: bool,
+    ) {
+        let request = admission_request("", "v1", "pods", None, operation);
+
+        assert_eq!(expected, rule(&["pods"], operations).matches(&request));
+    }
+
+    #[rstest]
+    #[case::same_group_version(&[""], &["v1"], true)]
+    #[case::other_group(&["apps"], &["v1"], false)]
+    #[case::other_version(&[""], &["v2"], false)]
+    #[case::wildcards(&["*"], &["*"], true)]
+    fn rule_matches_group_version(
+        #[case] api_groups: &[&str],
+        #[case] api_versions: &[&str],
+        #[case] expected: bool,
+    ) {
+        let request = admission_request("", "v1", "pods", None, "CREATE");
+        let rule = Rule {
+            api_groups: api_groups.iter().map(|g| g.to_string()).collect(),
+            api_versions: api_versions.iter().map(|v| v.to_string()).collect(),
+            ..rule(&["pods"], vec![Operation::Create])
+        };
+
+        assert_eq!(expected, rule.matches(&request));
+    }
+
+    #[test]
+    fn metadata_matches_any_rule() {
+        let request = admission_request("apps", "v1", "deployments", None, "UPDATE");
+        let mut metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            rules: vec![rule(&["pods"], vec![Operation::All])],
+            ..Default::default()
+        };
+        assert!(!metadata.matches(&request));
+
+        metadata.rules.push(Rule {
+            api_groups: vec![String::from("apps")],
+            api_versions: vec![String::from("v1")],
+            resources: vec![String::from("deployments")],
+            operations: vec![Operation::Update],
+        });
+        assert!(metadata.matches(&request));
+    }
 }
@@ -2,10 +2,11 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use tracing::{event, Level};
 
-use crate::evaluation_context::EvaluationContext;
+use crate::evaluation_context::{EvaluationContext, PolicyLogCapture};
 
-#[derive(Debug, Serialize)]
-enum PolicyLogEntryLevel {
+/// The level of a log entry produced by a policy
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum PolicyLogEntryLevel {
     Trace,
     Debug,
     Info,
@@ -30,23 +31,71 @@ impl<'de> Deserialize<'de> for PolicyLogEntryLevel {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct PolicyLogEntry {
-    level: PolicyLogEntryLevel,
-    message: Option<String>,
+/// A log entry produced by a policy
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PolicyLogEntry {
+    pub level: PolicyLogEntryLevel,
+    pub message: Option<String>,
+    /// The structured data attached to the entry
     #[serde(flatten)]
-    data: Option<serde_json::Map<String, serde_json::Value>>,
+    pub data: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// The log entries produced by a policy during an evaluation, captured when
+/// [`EvaluationContext::policy_log_capture`] is set.
+///
+/// Returned by
+/// [`PolicyEvaluator::policy_logs`](crate::policy_evaluator::PolicyEvaluator::policy_logs).
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PolicyLogs {
+    /// The captured entries, in the order they have been produced
+    pub entries: Vec<PolicyLogEntry>,
+
+    /// Number of entries that have been discarded because the limits of the
+    /// capture have been reached
+    pub dropped: usize,
+
+    /// Size of the captured entries, expressed in bytes
+    #[serde(skip)]
+    size: usize,
+}
+
+impl PolicyLogs {
+    /// Capture the entry, unless one of the limits would be exceeded. The
+    /// size of the entry is the one of its serialized form, as produced by
+    /// the policy
+    pub(crate) fn capture(
+        &mut self,
+        entry: PolicyLogEntry,
+        size: usize,
+        limits: &PolicyLogCapture,
+    ) {
+        if self.entries.len() >= limits.max_entries || self.size + size > limits.max_bytes {
+            self.dropped += 1;
+            return;
+        }
+
+        self.entries.push(entry);
+        self.size += size;
+    }
 }
 
 impl EvaluationContext {
+    /// Forward a log entry produced by the policy to `tracing`. The event
+    /// carries the policy id and the uid of the request being evaluated
+    /// (`None` when validating the settings).
+    ///
+    /// The decoded entry is returned.
     #[tracing::instrument(name = "policy_log", skip(contents))]
-    pub(crate) fn log(&self, contents: &[u8]) -> Result<()> {
+    pub(crate) fn log(&self, contents: &[u8], request_uid: Option<&str>) -> Result<PolicyLogEntry> {
         let log_entry: PolicyLogEntry = serde_json::from_slice(contents)?;
         macro_rules! log {
             ($level:path) => {
                 event!(
                     target: "policy_log",
                     $level,
+                    policy_id = self.policy_id.as_str(),
+                    request_uid,
                     data = %&serde_json::to_string(&log_entry.data.clone().unwrap())?.as_str(),
                     "{}",
                     log_entry.message.clone().unwrap_or_default(),
@@ -72,6 +121,44 @@ impl EvaluationContext {
             }
         };
 
-        Ok(())
+        Ok(log_entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(message: &str) -> PolicyLogEntry {
+        PolicyLogEntry {
+            level: PolicyLogEntryLevel::Info,
+            message: Some(message.to_string()),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn capture_policy_logs_within_limits() {
+        let limits = PolicyLogCapture {
+            max_entries: 2,
+            max_bytes: 10,
+        };
+        let mut logs = PolicyLogs::default();
+
+        logs.capture(entry("first"), 4, &limits);
+        // too big
+        logs.capture(entry("second"), 7, &limits);
+        logs.capture(entry("third"), 6, &limits);
+        // too many entries
+        logs.capture(entry("fourth"), 1, &limits);
+
+        assert_eq!(
+            vec![Some("first"), Some("third")],
+            logs.entries
+                .iter()
+                .map(|e| e.message.as_deref())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, logs.dropped);
     }
 }
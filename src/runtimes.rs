@@ -1,22 +1,82 @@
 use std::fmt::Display;
+use std::time::Duration;
 
 use crate::policy_evaluator::RegoPolicyExecutionMode;
+use crate::runtimes::callback::HostCallbackRecorder;
 
 pub(crate) mod callback;
 pub(crate) mod rego;
 pub(crate) mod wapc;
 pub(crate) mod wasi_cli;
+pub(crate) mod wasi_component;
 
 pub(crate) enum Runtime {
     Wapc(wapc::WapcStack),
     Rego(rego::Stack),
     Cli(wasi_cli::Stack),
+    Component(wasi_component::Stack),
+}
+
+impl Runtime {
+    /// Returns true if the execution of the guest has been aborted during
+    /// the last evaluation
+    pub(crate) fn trapped(&self) -> bool {
+        match self {
+            Runtime::Wapc(stack) => stack.trapped(),
+            Runtime::Rego(stack) => stack.trapped,
+            Runtime::Cli(stack) => stack.trapped(),
+            Runtime::Component(stack) => stack.trapped(),
+        }
+    }
+
+    /// Returns true if the runtime has been reset during the last evaluation
+    pub(crate) fn was_reset(&self) -> bool {
+        match self {
+            Runtime::Wapc(stack) => stack.was_reset(),
+            Runtime::Rego(stack) => stack.was_reset,
+            // a fresh store is used for each evaluation, a reset is never needed
+            Runtime::Cli(_) | Runtime::Component(_) => false,
+        }
+    }
+
+    /// The recorder tracking the host callbacks. Rego policies make host
+    /// callbacks only to build their Kubernetes context, before being evaluated
+    pub(crate) fn callback_recorder(&self) -> Option<&HostCallbackRecorder> {
+        match self {
+            Runtime::Wapc(stack) => Some(stack.callback_recorder()),
+            Runtime::Rego(stack) => Some(&stack.callback_recorder),
+            Runtime::Cli(stack) => Some(stack.callback_recorder()),
+            Runtime::Component(stack) => Some(stack.callback_recorder()),
+        }
+    }
+
+    /// Time spent instantiating the policy during the last evaluation. This is
+    /// `None` for the runtimes that instantiate the policy only once
+    pub(crate) fn instantiation_time(&self) -> Option<Duration> {
+        match self {
+            Runtime::Cli(stack) => Some(stack.instantiation_time()),
+            Runtime::Component(stack) => Some(stack.instantiation_time()),
+            Runtime::Wapc(_) | Runtime::Rego(_) => None,
+        }
+    }
+
+    /// Amount of fuel consumed by the last evaluation, `None` when fuel
+    /// consumption is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        match self {
+            Runtime::Wapc(stack) => stack.fuel_consumed(),
+            Runtime::Rego(stack) => stack.evaluator.fuel_consumed(),
+            Runtime::Cli(stack) => stack.fuel_consumed(),
+            Runtime::Component(stack) => stack.fuel_consumed(),
+        }
+    }
 }
 
 impl Display for Runtime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Runtime::Cli(_) => write!(f, "wasi"),
+            Runtime::Component(_) => write!(f, "wasi-component"),
             Runtime::Wapc(_) => write!(f, "wapc"),
             Runtime::Rego(stack) => match stack.policy_execution_mode {
                 RegoPolicyExecutionMode::Opa => {
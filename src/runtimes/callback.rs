@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use kubewarden_policy_sdk::host_capabilities::{
@@ -9,31 +12,310 @@ use kubewarden_policy_sdk::host_capabilities::{
 use tokio::sync::{mpsc, oneshot, oneshot::Receiver};
 use tracing::{debug, error, warn};
 
-use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
+use crate::callback_handler::CapabilityGroup;
+use crate::callback_requests::{
+    CallbackRequest, CallbackRequestOrigin, CallbackRequestType, CallbackResponse,
+};
+use crate::errors::HostCallbackError;
+use crate::evaluation_context::PolicyLogCapture;
+use crate::policy_evaluator::HostCallbackReport;
+use crate::policy_tracing::{PolicyLogEntry, PolicyLogs};
 use crate::{callback_handler::verify_certificate, evaluation_context::EvaluationContext};
 
+/// Keeps track of the host callbacks performed by a policy during an evaluation.
+///
+/// Nothing is recorded until [`HostCallbackRecorder::start`] is invoked. All the
+/// clones of a recorder share the same records.
+///
+/// The recorder also knows the uid of the request being evaluated, which is
+/// used to tag the requests sent to the `CallbackHandler`, counts the host
+/// callbacks made during the evaluation and captures the log entries of the
+/// policy.
+#[derive(Clone, Default)]
+pub(crate) struct HostCallbackRecorder {
+    reports: Arc<Mutex<Option<Vec<HostCallbackReport>>>>,
+    request_uid: Arc<Mutex<Option<String>>>,
+    callbacks: Arc<AtomicU32>,
+    policy_logs: Arc<Mutex<PolicyLogs>>,
+}
+
+impl HostCallbackRecorder {
+    /// Start recording the host callbacks, previous records are discarded
+    pub(crate) fn start(&self) {
+        *self.reports.lock().unwrap() = Some(Vec::new());
+    }
+
+    /// Stop recording and return the host callbacks recorded so far
+    pub(crate) fn finish(&self) -> Vec<HostCallbackReport> {
+        self.reports.lock().unwrap().take().unwrap_or_default()
+    }
+
+    /// Signal the beginning of a new evaluation, setting the uid of the request
+    /// being evaluated. `None` must be used when the policy is validating its
+    /// settings
+    pub(crate) fn start_evaluation(&self, request_uid: Option<&str>) {
+        *self.request_uid.lock().unwrap() = request_uid.map(str::to_owned);
+        self.callbacks.store(0, Ordering::Relaxed);
+        *self.policy_logs.lock().unwrap() = PolicyLogs::default();
+    }
+
+    /// The log entries captured during the last evaluation
+    pub(crate) fn policy_logs(&self) -> PolicyLogs {
+        self.policy_logs.lock().unwrap().clone()
+    }
+
+    fn capture_log(&self, entry: PolicyLogEntry, size: usize, limits: &PolicyLogCapture) {
+        self.policy_logs
+            .lock()
+            .unwrap()
+            .capture(entry, size, limits);
+    }
+
+    /// Count a new host callback, ensuring the maximum number of callbacks
+    /// per evaluation is not exceeded
+    pub(crate) fn count_callback(
+        &self,
+        eval_ctx: &EvaluationContext,
+    ) -> Result<(), HostCallbackError> {
+        let callbacks = self.callbacks.fetch_add(1, Ordering::Relaxed) + 1;
+
+        match eval_ctx.max_host_callbacks_per_evaluation {
+            Some(limit) if callbacks > limit => {
+                error!(
+                    policy = eval_ctx.policy_id,
+                    limit, "Policy exceeded the maximum number of host callbacks per evaluation"
+                );
+                Err(HostCallbackError::TooManyCallbacks {
+                    policy_id: eval_ctx.policy_id.clone(),
+                    limit,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The uid of the request being evaluated
+    fn request_uid(&self) -> Option<String> {
+        self.request_uid.lock().unwrap().clone()
+    }
+
+    pub(crate) fn origin(&self, policy_id: &str) -> CallbackRequestOrigin {
+        CallbackRequestOrigin {
+            policy_id: policy_id.to_owned(),
+            request_uid: self.request_uid(),
+        }
+    }
+
+    /// Record a host callback, regardless of its outcome. `outcome` holds
+    /// whether the response has been cached by the `CallbackHandler`, or the
+    /// error returned to the policy
+    pub(crate) fn record(
+        &self,
+        binding: &str,
+        namespace: &str,
+        operation: &str,
+        started_at: Instant,
+        outcome: std::result::Result<Option<bool>, String>,
+    ) {
+        if let Some(records) = self.reports.lock().unwrap().as_mut() {
+            let (was_cached, error) = match outcome {
+                Ok(was_cached) => (was_cached, None),
+                Err(error) => (None, Some(error)),
+            };
+            records.push(HostCallbackReport {
+                binding: binding.to_owned(),
+                namespace: namespace.to_owned(),
+                operation: operation.to_owned(),
+                duration: started_at.elapsed(),
+                was_cached,
+                error,
+            });
+        }
+    }
+}
+
+/// The response given back to the Wasm guest, together with whether it has
+/// been served from the cache of the `CallbackHandler`
+type HostCallbackResult = Result<(Vec<u8>, Option<bool>), Box<dyn std::error::Error + Send + Sync>>;
+
+/// The result of processing a request made by a Wasm guest
+enum HostCallbackOutcome {
+    /// The request has been fulfilled by the host, the payload must be
+    /// given back to the guest
+    Done(Vec<u8>),
+    /// The request must be evaluated by the `CallbackHandler`, which runs
+    /// inside of an asynchronous block
+    Pending(CallbackRequestType),
+}
+
 /// The callback function used by waPC and Wasi policies to use host capabilities
+///
+/// Requests that must be evaluated by the `CallbackHandler` block the current
+/// thread until the response is received.
+///
+/// The callback is tracked by the given `HostCallbackRecorder`, including
+/// when it fails or it is denied.
 pub(crate) fn host_callback(
     binding: &str,
     namespace: &str,
     operation: &str,
     payload: &[u8],
     eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let started_at = Instant::now();
+    let result = perform_host_callback(binding, namespace, operation, payload, eval_ctx, recorder);
+    record_host_callback(binding, namespace, operation, started_at, recorder, result)
+}
+
+/// Asynchronous version of [`host_callback`]. Requests that must be evaluated by
+/// the `CallbackHandler` do not block the current thread while waiting for
+/// the response.
+pub(crate) async fn host_callback_async(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+    eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let started_at = Instant::now();
+    let result =
+        perform_host_callback_async(binding, namespace, operation, payload, eval_ctx, recorder)
+            .await;
+    record_host_callback(binding, namespace, operation, started_at, recorder, result)
+}
+
+fn perform_host_callback(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+    eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
+) -> HostCallbackResult {
+    if namespace != "tracing" {
+        recorder.count_callback(eval_ctx)?;
+    }
+
+    match process_host_callback(binding, namespace, operation, payload, eval_ctx, recorder)? {
+        HostCallbackOutcome::Done(response) => Ok((response, None)),
+        HostCallbackOutcome::Pending(request) => {
+            let timeout = eval_ctx.callback_timeout(&request);
+            let rx = send_request(
+                &eval_ctx.policy_id,
+                binding,
+                operation,
+                request,
+                recorder.origin(&eval_ctx.policy_id),
+                eval_ctx,
+            )?;
+            // wait for the response
+            let response = handle_response(
+                &eval_ctx.policy_id,
+                binding,
+                operation,
+                wait_for_response(rx, timeout),
+            )?;
+            Ok((response.payload, Some(response.was_cached)))
+        }
+    }
+}
+
+async fn perform_host_callback_async(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+    eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
+) -> HostCallbackResult {
+    if namespace != "tracing" {
+        recorder.count_callback(eval_ctx)?;
+    }
+
+    match process_host_callback(binding, namespace, operation, payload, eval_ctx, recorder)? {
+        HostCallbackOutcome::Done(response) => Ok((response, None)),
+        HostCallbackOutcome::Pending(request) => {
+            let timeout = eval_ctx.callback_timeout(&request);
+            let rx = send_request(
+                &eval_ctx.policy_id,
+                binding,
+                operation,
+                request,
+                recorder.origin(&eval_ctx.policy_id),
+                eval_ctx,
+            )?;
+            let response = handle_response(
+                &eval_ctx.policy_id,
+                binding,
+                operation,
+                wait_for_response_async(rx, timeout).await,
+            )?;
+            Ok((response.payload, Some(response.was_cached)))
+        }
+    }
+}
+
+fn record_host_callback(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    started_at: Instant,
+    recorder: &HostCallbackRecorder,
+    result: HostCallbackResult,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let outcome = match &result {
+        Ok((_, was_cached)) => Ok(*was_cached),
+        Err(e) => Err(e.to_string()),
+    };
+    recorder.record(binding, namespace, operation, started_at, outcome);
+
+    result.map(|(response, _)| response)
+}
+
+/// Forward the JSON encoded log entry produced by the policy to `tracing`,
+/// capturing it when [`EvaluationContext::policy_log_capture`] is set
+pub(crate) fn log_policy_entry(
+    payload: &[u8],
+    eval_ctx: &EvaluationContext,
+    recorder: &HostCallbackRecorder,
+) {
+    match eval_ctx.log(payload, recorder.request_uid().as_deref()) {
+        Ok(entry) => {
+            if let Some(limits) = &eval_ctx.policy_log_capture {
+                recorder.capture_log(entry, payload.len(), limits);
+            }
+        }
+        Err(e) => {
+            let p = String::from_utf8(payload.to_vec()).unwrap_or_else(|e| e.to_string());
+            error!(
+                payload = p.as_str(),
+                error = e.to_string().as_str(),
+                "Cannot log event"
+            );
+        }
+    }
+}
+
+/// Decode the request made by the Wasm guest. Requests that can be fulfilled
+/// by synchronous code are evaluated straight away.
+fn process_host_callback(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+    eval_ctx: &Arc<EvaluationContext>,
+    recorder: &HostCallbackRecorder,
+) -> Result<HostCallbackOutcome, Box<dyn std::error::Error + Send + Sync>> {
+    check_host_capability(binding, namespace, operation, eval_ctx)?;
+
     match binding {
         "kubewarden" => match namespace {
             "tracing" => match operation {
                 "log" => {
-                    if let Err(e) = eval_ctx.log(payload) {
-                        let p =
-                            String::from_utf8(payload.to_vec()).unwrap_or_else(|e| e.to_string());
-                        error!(
-                            payload = p.as_str(),
-                            error = e.to_string().as_str(),
-                            "Cannot log event"
-                        );
-                    }
-                    Ok(Vec::new())
+                    log_policy_entry(payload, eval_ctx, recorder);
+                    Ok(HostCallbackOutcome::Done(Vec::new()))
                 }
                 _ => {
                     error!(namespace, operation, "unknown operation");
@@ -45,39 +327,13 @@ pub(crate) fn host_callback(
                     let req: SigstoreVerificationInputV1 =
                         serde_json::from_slice(payload.to_vec().as_ref())?;
                     let req_type: CallbackRequestType = req.into();
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: req_type,
-                        response_channel: tx,
-                    };
-
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(req_type))
                 }
                 "v2/verify" => {
                     let req: SigstoreVerificationInputV2 =
                         serde_json::from_slice(payload.to_vec().as_ref())?;
                     let req_type: CallbackRequestType = req.into();
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: req_type,
-                        response_channel: tx,
-                    };
-
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(req_type))
                 }
                 "v1/manifest_digest" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
@@ -88,19 +344,9 @@ pub(crate) fn host_callback(
                         image = image.as_str(),
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::OciManifestDigest { image },
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(
+                        CallbackRequestType::OciManifestDigest { image },
+                    ))
                 }
                 "v1/oci_manifest" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
@@ -111,19 +357,9 @@ pub(crate) fn host_callback(
                         image = image.as_str(),
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::OciManifest { image },
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(
+                        CallbackRequestType::OciManifest { image },
+                    ))
                 }
                 "v1/oci_manifest_config" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
@@ -134,19 +370,9 @@ pub(crate) fn host_callback(
                         image = image.as_str(),
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::OciManifestAndConfig { image },
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(
+                        CallbackRequestType::OciManifestAndConfig { image },
+                    ))
                 }
                 _ => {
                     error!("unknown operation: {}", operation);
@@ -163,19 +389,9 @@ pub(crate) fn host_callback(
                         ?host,
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::DNSLookupHost { host },
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(
+                        CallbackRequestType::DNSLookupHost { host },
+                    ))
                 }
                 _ => {
                     error!("unknown operation: {}", operation);
@@ -192,7 +408,7 @@ pub(crate) fn host_callback(
                             return Err(format!("Error when verifying certificate: {e}").into())
                         }
                     };
-                    Ok(serde_json::to_vec(&response)?)
+                    Ok(HostCallbackOutcome::Done(serde_json::to_vec(&response)?))
                 }
                 _ => {
                     error!(namespace, operation, "unknown operation");
@@ -223,19 +439,7 @@ pub(crate) fn host_callback(
                         ?req,
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::from(req),
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(CallbackRequestType::from(req)))
                 }
                 "list_resources_all" => {
                     let req: ListAllResourcesRequest =
@@ -259,19 +463,7 @@ pub(crate) fn host_callback(
                         ?req,
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::from(req),
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(CallbackRequestType::from(req)))
                 }
                 "get_resource" => {
                     let req: GetResourceRequest =
@@ -295,19 +487,7 @@ pub(crate) fn host_callback(
                         ?req,
                         "Sending request via callback channel"
                     );
-                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                    let req = CallbackRequest {
-                        request: CallbackRequestType::from(req),
-                        response_channel: tx,
-                    };
-                    send_request_and_wait_for_response(
-                        &eval_ctx.policy_id,
-                        binding,
-                        operation,
-                        req,
-                        rx,
-                        eval_ctx,
-                    )
+                    Ok(HostCallbackOutcome::Pending(CallbackRequestType::from(req)))
                 }
                 _ => {
                     error!(namespace, operation, "unknown operation");
@@ -333,19 +513,7 @@ pub(crate) fn host_callback(
                     ?req,
                     "Usage of deprecated `ClusterContext`"
                 );
-                let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                let req = CallbackRequest {
-                    request: req,
-                    response_channel: tx,
-                };
-                send_request_and_wait_for_response(
-                    &eval_ctx.policy_id,
-                    binding,
-                    operation,
-                    req,
-                    rx,
-                    eval_ctx,
-                )
+                Ok(HostCallbackOutcome::Pending(req))
             }
             "namespaces" => {
                 let req = CallbackRequestType::KubernetesListResourceAll {
@@ -360,19 +528,7 @@ pub(crate) fn host_callback(
                     ?req,
                     "Usage of deprecated `ClusterContext`"
                 );
-                let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                let req = CallbackRequest {
-                    request: req,
-                    response_channel: tx,
-                };
-                send_request_and_wait_for_response(
-                    &eval_ctx.policy_id,
-                    binding,
-                    operation,
-                    req,
-                    rx,
-                    eval_ctx,
-                )
+                Ok(HostCallbackOutcome::Pending(req))
             }
             "services" => {
                 let req = CallbackRequestType::KubernetesListResourceAll {
@@ -387,19 +543,7 @@ pub(crate) fn host_callback(
                     ?req,
                     "Usage of deprecated `ClusterContext`"
                 );
-                let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
-                let req = CallbackRequest {
-                    request: req,
-                    response_channel: tx,
-                };
-                send_request_and_wait_for_response(
-                    &eval_ctx.policy_id,
-                    binding,
-                    operation,
-                    req,
-                    rx,
-                    eval_ctx,
-                )
+                Ok(HostCallbackOutcome::Pending(req))
             }
             _ => {
                 error!("unknown namespace: {}", namespace);
@@ -413,14 +557,45 @@ pub(crate) fn host_callback(
     }
 }
 
-fn send_request_and_wait_for_response(
+/// Ensure the policy has been granted access to the host capability
+fn check_host_capability(
+    binding: &str,
+    namespace: &str,
+    operation: &str,
+    eval_ctx: &EvaluationContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // The deprecated `ClusterContext` lists all the resources of a given kind
+    let (namespace, operation) = match binding {
+        "kubernetes" => ("kubernetes", "list_resources_all"),
+        _ => (namespace, operation),
+    };
+
+    if !eval_ctx.can_use_host_capability(namespace, operation) {
+        error!(
+            policy = eval_ctx.policy_id,
+            capability_requested = format!("{namespace}/{operation}"),
+            capabilities_allowed = ?eval_ctx.host_capabilities_allow_list,
+            "Policy tried to use a host capability it doesn't have access to");
+        return Err(format!(
+                "Policy has not been granted access to the {namespace} {operation} host capability. The violation has been reported.").into());
+    }
+
+    Ok(())
+}
+
+/// Send the request to the `CallbackHandler`, the returned channel must be used
+/// to obtain the response.
+///
+/// The rate limit of the policy is enforced before the request takes a slot
+/// of the callback channel
+fn send_request(
     policy_id: &str,
     binding: &str,
     operation: &str,
-    req: CallbackRequest,
-    rx: Receiver<Result<CallbackResponse>>,
+    request: CallbackRequestType,
+    origin: CallbackRequestOrigin,
     eval_ctx: &EvaluationContext,
-) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Receiver<Result<CallbackResponse>>, Box<dyn std::error::Error + Send + Sync>> {
     let cb_channel: mpsc::Sender<CallbackRequest> = if let Some(c) =
         eval_ctx.callback_channel.clone()
     {
@@ -435,15 +610,121 @@ fn send_request_and_wait_for_response(
         ))
     }?;
 
+    if let Err(e) = eval_ctx.acquire_callback_rate_limit(&request) {
+        warn!(
+            policy_id,
+            binding, operation, "Cannot process Wasm guest request: rate limit exceeded"
+        );
+        return Err(e.into());
+    }
+
+    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
+    let req = CallbackRequest {
+        request,
+        origin: Some(origin),
+        span: tracing::Span::current(),
+        response_channel: tx,
+    };
+
     let send_result = cb_channel.try_send(req);
     if let Err(e) = send_result {
         return Err(format!("Error sending request over callback channel: {e:?}").into());
     }
 
-    // wait for the response
-    match rx.blocking_recv() {
-        Ok(msg) => match msg {
-            Ok(resp) => Ok(resp.payload),
+    Ok(rx)
+}
+
+/// The outcome of the wait for the response of the `CallbackHandler`
+enum WaitOutcome {
+    Received(Result<CallbackResponse>),
+    ChannelClosed(oneshot::error::RecvError),
+    TimedOut(CapabilityGroup, Duration),
+}
+
+/// Block the current thread until the response is received, or until the
+/// timeout of the request is reached. Dropping the receiver makes the
+/// `CallbackHandler` cancel the evaluation of the request
+fn wait_for_response(
+    rx: Receiver<Result<CallbackResponse>>,
+    timeout: Option<(CapabilityGroup, Duration)>,
+) -> WaitOutcome {
+    let Some((group, timeout)) = timeout else {
+        return rx.blocking_recv().into();
+    };
+
+    let wait = async move {
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(response) => response.into(),
+            Err(_) => WaitOutcome::TimedOut(group, timeout),
+        }
+    };
+    block_on(wait).unwrap_or_else(|e| {
+        WaitOutcome::Received(Err(anyhow!("cannot wait for the response: {e}")))
+    })
+}
+
+/// Block the current thread until the given future is completed.
+///
+/// The future is driven by the tokio runtime of the current thread or, outside
+/// of a runtime, by a dedicated one: the timeouts of the host callbacks require
+/// the tokio timer. The worker threads of a runtime can invoke this function
+/// only from within [`tokio::task::block_in_place`]
+pub(crate) fn block_on<F: Future>(future: F) -> std::io::Result<F::Output> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => Ok(handle.block_on(future)),
+        Err(_) => tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .map(|runtime| runtime.block_on(future)),
+    }
+}
+
+/// Asynchronous version of [`wait_for_response`]
+async fn wait_for_response_async(
+    rx: Receiver<Result<CallbackResponse>>,
+    timeout: Option<(CapabilityGroup, Duration)>,
+) -> WaitOutcome {
+    match timeout {
+        Some((group, timeout)) => match tokio::time::timeout(timeout, rx).await {
+            Ok(response) => response.into(),
+            Err(_) => WaitOutcome::TimedOut(group, timeout),
+        },
+        None => rx.await.into(),
+    }
+}
+
+impl From<std::result::Result<Result<CallbackResponse>, oneshot::error::RecvError>>
+    for WaitOutcome
+{
+    fn from(
+        response: std::result::Result<Result<CallbackResponse>, oneshot::error::RecvError>,
+    ) -> Self {
+        match response {
+            Ok(response) => WaitOutcome::Received(response),
+            Err(e) => WaitOutcome::ChannelClosed(e),
+        }
+    }
+}
+
+fn handle_response(
+    policy_id: &str,
+    binding: &str,
+    operation: &str,
+    response: WaitOutcome,
+) -> Result<CallbackResponse, Box<dyn std::error::Error + Send + Sync>> {
+    match response {
+        WaitOutcome::TimedOut(group, timeout) => {
+            warn!(
+                policy_id,
+                binding,
+                operation,
+                ?timeout,
+                "Cannot process Wasm guest request: timed out waiting for the response"
+            );
+            Err(HostCallbackError::Timeout { group, timeout }.into())
+        }
+        WaitOutcome::Received(msg) => match msg {
+            Ok(resp) => Ok(resp),
             Err(e) => {
                 error!(
                     policy_id,
@@ -452,10 +733,15 @@ fn send_request_and_wait_for_response(
                     error = e.to_string().as_str(),
                     "callback evaluation failed"
                 );
-                Err(format!("Callback evaluation failure: {e:?}").into())
+                // keep the errors produced by the limits enforced by the
+                // `CallbackHandler`, like timeouts, typed
+                match e.downcast::<HostCallbackError>() {
+                    Ok(e) => Err(e.into()),
+                    Err(e) => Err(format!("Callback evaluation failure: {e:?}").into()),
+                }
             }
         },
-        Err(e) => {
+        WaitOutcome::ChannelClosed(e) => {
             error!(
                 policy_id,
                 binding,
@@ -1,13 +1,19 @@
 use kube::api::ObjectList;
 use std::collections::{BTreeMap, BTreeSet};
-use tokio::sync::{mpsc, oneshot};
+use std::time::Instant;
+use tokio::sync::oneshot;
 
 use crate::{
     callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse},
+    errors::HostCallbackError,
+    evaluation_context::EvaluationContext,
     policy_metadata::ContextAwareResource,
-    runtimes::rego::{
-        errors::{RegoRuntimeError, Result},
-        opa_inventory::OpaInventory,
+    runtimes::{
+        callback::HostCallbackRecorder,
+        rego::{
+            errors::{RegoRuntimeError, Result},
+            opa_inventory::OpaInventory,
+        },
     },
 };
 
@@ -19,28 +25,34 @@ pub(crate) enum KubernetesContext {
     Gatekeeper(Vec<u8>),
 }
 
+/// The policy on whose behalf the host callbacks are made
+pub(crate) struct CallbackContext<'a> {
+    pub eval_ctx: &'a EvaluationContext,
+    pub recorder: &'a HostCallbackRecorder,
+}
+
 /// Uses the callback channel to get all the Kubernetes resources defined inside of
 /// the cluster whose type is mentioned inside of `allowed_resources`.
 ///
 /// The resources are returned based on the actual RBAC privileges of the client
 /// used by the runtime.
-pub(crate) fn get_allowed_resources(
-    callback_channel: &mpsc::Sender<CallbackRequest>,
+pub(crate) async fn get_allowed_resources(
+    callback_ctx: &CallbackContext<'_>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
 ) -> Result<BTreeMap<ContextAwareResource, ObjectList<kube::core::DynamicObject>>> {
     let mut kube_resources: BTreeMap<ContextAwareResource, ObjectList<kube::core::DynamicObject>> =
         BTreeMap::new();
 
     for resource in allowed_resources {
-        let resource_list = get_all_resources_by_type(callback_channel, resource)?;
+        let resource_list = get_all_resources_by_type(callback_ctx, resource).await?;
         kube_resources.insert(resource.to_owned(), resource_list);
     }
 
     Ok(kube_resources)
 }
 
-fn get_all_resources_by_type(
-    callback_channel: &mpsc::Sender<CallbackRequest>,
+async fn get_all_resources_by_type(
+    callback_ctx: &CallbackContext<'_>,
     resource_type: &ContextAwareResource,
 ) -> Result<ObjectList<kube::core::DynamicObject>> {
     let req_type = CallbackRequestType::KubernetesListResourceAll {
@@ -50,19 +62,20 @@ fn get_all_resources_by_type(
         field_selector: None,
     };
 
-    let response = make_request_via_callback_channel(req_type, callback_channel)?;
+    let response =
+        make_request_via_callback_channel("list_resources_all", req_type, callback_ctx).await?;
     serde_json::from_slice::<ObjectList<kube::core::DynamicObject>>(&response.payload)
         .map_err(RegoRuntimeError::CallbackConvertList)
 }
 
 /// For each allowed resource, check if the "list all resources" result changed since the given instant
-pub(crate) fn have_allowed_resources_changed_since_instant(
-    callback_channel: &mpsc::Sender<CallbackRequest>,
+pub(crate) async fn have_allowed_resources_changed_since_instant(
+    callback_ctx: &CallbackContext<'_>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
     since: tokio::time::Instant,
 ) -> Result<bool> {
     for resource in allowed_resources {
-        if has_resource_changed_since(callback_channel, resource, since)? {
+        if has_resource_changed_since(callback_ctx, resource, since).await? {
             return Ok(true);
         }
     }
@@ -73,8 +86,8 @@ pub(crate) fn have_allowed_resources_changed_since_instant(
 /// Check if the "list all resources" result changed since the given instant
 /// Note: this function doesn't take label_selector and field_selector into account because
 /// it's used only by gatekeeper policies, which don't use these selectors.
-fn has_resource_changed_since(
-    callback_channel: &mpsc::Sender<CallbackRequest>,
+async fn has_resource_changed_since(
+    callback_ctx: &CallbackContext<'_>,
     resource_type: &ContextAwareResource,
     since: tokio::time::Instant,
 ) -> Result<bool> {
@@ -86,15 +99,20 @@ fn has_resource_changed_since(
         since,
     };
 
-    let response = make_request_via_callback_channel(req_type, callback_channel)?;
+    let response = make_request_via_callback_channel(
+        "has_list_resources_all_result_changed_since_instant",
+        req_type,
+        callback_ctx,
+    )
+    .await?;
     serde_json::from_slice::<bool>(&response.payload).map_err(RegoRuntimeError::CallbackConvertBool)
 }
 
 /// Creates a map that has ContextAwareResource as key, and its plural name as value.
 /// For example, the key for {`apps/v1`, `Deployment`} will have `deployments` as value.
 /// The map is built by making request via the given callback channel.
-pub(crate) fn get_plural_names(
-    callback_channel: &mpsc::Sender<CallbackRequest>,
+pub(crate) async fn get_plural_names(
+    callback_ctx: &CallbackContext<'_>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
 ) -> Result<BTreeMap<ContextAwareResource, String>> {
     let mut plural_names_by_resource: BTreeMap<ContextAwareResource, String> = BTreeMap::new();
@@ -105,7 +123,9 @@ pub(crate) fn get_plural_names(
             kind: resource.kind.to_owned(),
         };
 
-        let response = make_request_via_callback_channel(req_type, callback_channel)?;
+        let response =
+            make_request_via_callback_channel("get_resource_plural_name", req_type, callback_ctx)
+                .await?;
         let plural_name = serde_json::from_slice::<String>(&response.payload)
             .map_err(RegoRuntimeError::CallbackGetPluralName)?;
 
@@ -117,20 +137,65 @@ pub(crate) fn get_plural_names(
 
 /// Internal helper function that sends a request over the callback channel and returns the
 /// response
-fn make_request_via_callback_channel(
+///
+/// The request counts towards the host callbacks of the current evaluation of
+/// the policy, and it is subject to its rate limit. It is recorded as a
+/// `kubewarden/kubernetes/<operation>` host callback, regardless of its outcome
+async fn make_request_via_callback_channel(
+    operation: &str,
+    request_type: CallbackRequestType,
+    callback_ctx: &CallbackContext<'_>,
+) -> Result<CallbackResponse> {
+    let started_at = Instant::now();
+    let result = send_request_and_wait_for_response(request_type, callback_ctx).await;
+    callback_ctx.recorder.record(
+        "kubewarden",
+        "kubernetes",
+        operation,
+        started_at,
+        result
+            .as_ref()
+            .map(|response| Some(response.was_cached))
+            .map_err(ToString::to_string),
+    );
+
+    result
+}
+
+async fn send_request_and_wait_for_response(
     request_type: CallbackRequestType,
-    callback_channel: &mpsc::Sender<CallbackRequest>,
+    callback_ctx: &CallbackContext<'_>,
 ) -> Result<CallbackResponse> {
+    let CallbackContext { eval_ctx, recorder } = callback_ctx;
+    let callback_channel = eval_ctx
+        .callback_channel
+        .as_ref()
+        .ok_or(RegoRuntimeError::CallbackChannelNotSet)?;
+    recorder.count_callback(eval_ctx)?;
+    eval_ctx.acquire_callback_rate_limit(&request_type)?;
+    let timeout = eval_ctx.callback_timeout(&request_type);
+
     let (tx, rx) = oneshot::channel::<std::result::Result<CallbackResponse, wasmtime::Error>>();
     let req = CallbackRequest {
         request: request_type,
+        origin: Some(recorder.origin(&eval_ctx.policy_id)),
+        span: tracing::Span::current(),
         response_channel: tx,
     };
     callback_channel
         .try_send(req)
         .map_err(|e| RegoRuntimeError::CallbackSend(e.to_string()))?;
 
-    match rx.blocking_recv() {
+    // the wait is bounded by the timeout of the request, including the time
+    // spent inside of the channel. Dropping the receiver makes the
+    // `CallbackHandler` cancel the evaluation of the request
+    let response = match timeout {
+        Some((group, timeout)) => tokio::time::timeout(timeout, rx)
+            .await
+            .map_err(|_| HostCallbackError::Timeout { group, timeout })?,
+        None => rx.await,
+    };
+    match response {
         Ok(msg) => msg.map_err(RegoRuntimeError::CallbackRequest),
         Err(e) => Err(RegoRuntimeError::CallbackResponse(e.to_string())),
     }
@@ -144,6 +209,22 @@ pub(crate) mod tests {
     use rstest::rstest;
     use std::collections::HashMap;
     use std::path::Path;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    use crate::callback_handler::{CapabilityGroup, RateLimit, RateLimiter};
+    use crate::callback_requests::CallbackRequestOrigin;
+    use crate::runtimes::callback::block_on;
+
+    pub fn eval_ctx_with_callback_channel(
+        callback_tx: mpsc::Sender<CallbackRequest>,
+    ) -> EvaluationContext {
+        EvaluationContext {
+            policy_id: "test".to_string(),
+            callback_channel: Some(callback_tx),
+            ..Default::default()
+        }
+    }
 
     pub fn dynamic_object_from_fixture(
         resource_type: &str,
@@ -174,6 +255,12 @@ pub(crate) mod tests {
     #[tokio::test(flavor = "multi_thread")]
     async fn get_all_resources_success() {
         let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = eval_ctx_with_callback_channel(callback_tx);
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
@@ -210,24 +297,29 @@ pub(crate) mod tests {
             let services_list = object_list_from_dynamic_objects(&services).unwrap();
             let callback_response = CallbackResponse {
                 payload: serde_json::to_vec(&services_list).unwrap(),
+                was_cached: false,
             };
 
             req.response_channel.send(Ok(callback_response)).unwrap();
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = get_all_resources_by_type(&callback_tx, &resource).unwrap();
-            let actual_json = serde_json::to_value(actual).unwrap();
-            let expected_json = serde_json::to_value(services_list).unwrap();
-            assert_json_eq!(actual_json, expected_json);
-        })
-        .await
-        .unwrap();
+        let actual = get_all_resources_by_type(&callback_ctx, &resource)
+            .await
+            .unwrap();
+        let actual_json = serde_json::to_value(actual).unwrap();
+        let expected_json = serde_json::to_value(services_list).unwrap();
+        assert_json_eq!(actual_json, expected_json);
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn get_resource_plural_name_success() {
         let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = eval_ctx_with_callback_channel(callback_tx);
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
@@ -259,17 +351,14 @@ pub(crate) mod tests {
 
             let callback_response = CallbackResponse {
                 payload: serde_json::to_vec(&plural_name).unwrap(),
+                was_cached: false,
             };
 
             req.response_channel.send(Ok(callback_response)).unwrap();
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = get_plural_names(&callback_tx, &resources).unwrap();
-            assert_eq!(actual, expected_names);
-        })
-        .await
-        .unwrap();
+        let actual = get_plural_names(&callback_ctx, &resources).await.unwrap();
+        assert_eq!(actual, expected_names);
     }
     #[rstest]
     #[case(
@@ -300,6 +389,12 @@ pub(crate) mod tests {
         #[case] expected: bool,
     ) {
         let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = eval_ctx_with_callback_channel(callback_tx);
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
         let since = tokio::time::Instant::now();
         let expected_resources_with_change_status = resources_with_change_status.clone();
 
@@ -335,20 +430,178 @@ pub(crate) mod tests {
 
                 let callback_response = CallbackResponse {
                     payload: serde_json::to_vec(&changed).unwrap(),
+                    was_cached: false,
                 };
 
                 req.response_channel.send(Ok(callback_response)).unwrap();
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            let resources = resources_with_change_status.keys().cloned().collect();
-            let actual =
-                have_allowed_resources_changed_since_instant(&callback_tx, &resources, since)
+        let resources = resources_with_change_status.keys().cloned().collect();
+        let actual = have_allowed_resources_changed_since_instant(&callback_ctx, &resources, since)
+            .await
+            .unwrap();
+        assert_json_eq!(expected, actual);
+    }
+
+    /// Serve the `HasKubernetesListResourceAllResultChangedSinceInstant` requests,
+    /// returning the origins of the requests received once the channel is closed
+    fn serve_has_changed_requests(
+        mut callback_rx: mpsc::Receiver<CallbackRequest>,
+    ) -> tokio::task::JoinHandle<Vec<Option<CallbackRequestOrigin>>> {
+        tokio::spawn(async move {
+            let mut origins = Vec::new();
+            while let Some(req) = callback_rx.recv().await {
+                origins.push(req.origin);
+                req.response_channel
+                    .send(Ok(CallbackResponse {
+                        payload: serde_json::to_vec(&false).unwrap(),
+                        was_cached: false,
+                    }))
                     .unwrap();
-            assert_json_eq!(expected, actual);
+            }
+            origins
+        })
+    }
+
+    #[rstest]
+    #[case::rate_limit(
+        EvaluationContext {
+            callback_rate_limiter: Some(RateLimiter::new(HashMap::from([(
+                CapabilityGroup::Kubernetes,
+                RateLimit::new(1.0, 1),
+            )]))),
+            ..Default::default()
+        }
+    )]
+    #[case::max_host_callbacks(
+        EvaluationContext {
+            max_host_callbacks_per_evaluation: Some(1),
+            ..Default::default()
+        }
+    )]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn limits_are_enforced_before_sending_the_request(#[case] limits: EvaluationContext) {
+        let (callback_tx, callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let server = serve_has_changed_requests(callback_rx);
+        let eval_ctx = EvaluationContext {
+            policy_id: "test".to_string(),
+            callback_channel: Some(callback_tx),
+            ..limits
+        };
+        let recorder = HostCallbackRecorder::default();
+        recorder.start();
+        recorder.start_evaluation(Some("uid"));
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
+        let resource = ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Service".to_string(),
+        };
+        let since = tokio::time::Instant::now();
+
+        assert!(!has_resource_changed_since(&callback_ctx, &resource, since)
+            .await
+            .unwrap());
+        assert!(matches!(
+            has_resource_changed_since(&callback_ctx, &resource, since).await,
+            Err(RegoRuntimeError::HostCallback(_))
+        ));
+
+        // both requests are recorded, including the rejected one
+        let reports = recorder.finish();
+        assert_eq!(2, reports.len());
+        assert!(reports.iter().all(
+            |report| report.operation == "has_list_resources_all_result_changed_since_instant"
+        ));
+        assert_eq!(Some(false), reports[0].was_cached);
+        assert!(reports[0].error.is_none());
+        assert!(reports[1].error.is_some());
+
+        // the rejected request never reached the channel
+        drop(eval_ctx);
+        let origins = server.await.unwrap();
+        assert_eq!(
+            vec![Some(CallbackRequestOrigin {
+                policy_id: "test".to_string(),
+                request_uid: Some("uid".to_string()),
+            })],
+            origins
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_wait_for_the_response_is_bounded_by_the_timeout() {
+        // the request is queued, but it's never served
+        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = EvaluationContext {
+            policy_id: "test".to_string(),
+            callback_channel: Some(callback_tx),
+            callback_timeouts: HashMap::from([(
+                CapabilityGroup::Kubernetes,
+                Duration::from_millis(50),
+            )]),
+            ..Default::default()
+        };
+        let recorder = HostCallbackRecorder::default();
+        recorder.start();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
+        let resource = ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Service".to_string(),
+        };
+
+        assert!(matches!(
+            has_resource_changed_since(&callback_ctx, &resource, tokio::time::Instant::now()).await,
+            Err(RegoRuntimeError::HostCallback(HostCallbackError::Timeout {
+                group: CapabilityGroup::Kubernetes,
+                ..
+            }))
+        ));
+
+        // the request has been abandoned, the `CallbackHandler` skips it
+        let req = callback_rx.recv().await.unwrap();
+        assert!(req.response_channel.is_closed());
+    }
+    #[test]
+    fn the_timeout_is_enforced_outside_of_a_tokio_runtime() {
+        // synchronous evaluations can be run by threads that don't belong to
+        // a tokio runtime, the timer is provided by a dedicated one
+        let (callback_tx, _callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = EvaluationContext {
+            policy_id: "test".to_string(),
+            callback_channel: Some(callback_tx),
+            callback_timeouts: HashMap::from([(
+                CapabilityGroup::Kubernetes,
+                Duration::from_millis(50),
+            )]),
+            ..Default::default()
+        };
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
+        let resource = ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Service".to_string(),
+        };
+
+        let result = block_on(async {
+            has_resource_changed_since(&callback_ctx, &resource, tokio::time::Instant::now()).await
         })
-        .await
-        .unwrap();
+        .expect("cannot build the tokio runtime");
+        assert!(matches!(
+            result,
+            Err(RegoRuntimeError::HostCallback(HostCallbackError::Timeout {
+                group: CapabilityGroup::Kubernetes,
+                ..
+            }))
+        ));
     }
 }
@@ -19,9 +19,15 @@ pub enum RegoRuntimeError {
     #[error("error obtaining response from callback channel: {0}")]
     CallbackResponse(String),
 
+    #[error("cannot build a tokio runtime to wait for the host callbacks: {0}")]
+    AsyncRuntime(#[source] std::io::Error),
+
     #[error("cannot perform a request via callback channel: {0}")]
     CallbackRequest(#[source] wasmtime::Error),
 
+    #[error("{0}")]
+    HostCallback(#[from] crate::errors::HostCallbackError),
+
     #[error("get plural name failure, cannot convert callback response: {0}")]
     CallbackGetPluralName(#[source] serde_json::Error),
 
@@ -60,4 +66,13 @@ pub enum RegoRuntimeError {
 
     #[error("cannot build Rego engine: {0}")]
     RegoEngineBuilder(#[source] burrego::errors::BurregoError),
+
+    #[error("invalid settings schema: {0}")]
+    InvalidSettingsSchema(String),
+
+    #[error("cannot find entrypoint {name:?}, available entrypoints: {available:?}")]
+    UnknownEntrypoint {
+        name: String,
+        available: Vec<String>,
+    },
 }
@@ -4,13 +4,12 @@ use std::{
     collections::{BTreeSet, HashMap},
     sync::{Arc, RwLock},
 };
-use tokio::{sync::mpsc, time::Instant};
+use tokio::time::Instant;
 
 use crate::runtimes::rego::context_aware::{
-    get_allowed_resources, have_allowed_resources_changed_since_instant,
+    get_allowed_resources, have_allowed_resources_changed_since_instant, CallbackContext,
 };
 use crate::{
-    callback_requests::CallbackRequest,
     policy_metadata::ContextAwareResource,
     runtimes::rego::{
         errors::{RegoRuntimeError, Result},
@@ -70,9 +69,9 @@ impl GateKeeperInventoryCache {
     /// The inventory is computed and serialized only if it's not already present in the cache.
     /// The inventory is also recreated if the set of resources has changed since the time
     /// the inventory was computed
-    pub fn get_inventory(
+    pub async fn get_inventory(
         &self,
-        callback_channel: &mpsc::Sender<CallbackRequest>,
+        callback_ctx: &CallbackContext<'_>,
         ctx_aware_resources: &BTreeSet<ContextAwareResource>,
     ) -> Result<Vec<u8>> {
         let inventory = {
@@ -80,14 +79,20 @@ impl GateKeeperInventoryCache {
             inventories.get(ctx_aware_resources).cloned()
         };
         let inventory = match inventory {
-            None => self.create_and_register_inventory(ctx_aware_resources, callback_channel),
+            None => {
+                self.create_and_register_inventory(ctx_aware_resources, callback_ctx)
+                    .await
+            }
             Some(cached_inventory) => {
                 if have_allowed_resources_changed_since_instant(
-                    callback_channel,
+                    callback_ctx,
                     ctx_aware_resources,
                     cached_inventory.cache_time,
-                )? {
-                    self.create_and_register_inventory(ctx_aware_resources, callback_channel)
+                )
+                .await?
+                {
+                    self.create_and_register_inventory(ctx_aware_resources, callback_ctx)
+                        .await
                 } else {
                     Ok(cached_inventory)
                 }
@@ -98,13 +103,13 @@ impl GateKeeperInventoryCache {
 
     /// Create the inventory and register it in the cache. A prior entry of the inventory is
     /// automatically removed from the cache.
-    fn create_and_register_inventory(
+    async fn create_and_register_inventory(
         &self,
         ctx_aware_resources: &BTreeSet<ContextAwareResource>,
-        callback_channel: &mpsc::Sender<CallbackRequest>,
+        callback_ctx: &CallbackContext<'_>,
     ) -> Result<Arc<CachedInventory>> {
         let now = Instant::now();
-        let cluster_resources = get_allowed_resources(callback_channel, ctx_aware_resources)?;
+        let cluster_resources = get_allowed_resources(callback_ctx, ctx_aware_resources).await?;
         let inventory = GatekeeperInput {
             inventory: GatekeeperInventory::new(&cluster_resources)?,
         };
@@ -125,18 +130,27 @@ impl GateKeeperInventoryCache {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use crate::callback_requests::{CallbackRequestType, CallbackResponse};
+    use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
+    use crate::runtimes::callback::HostCallbackRecorder;
     use serial_test::serial;
     use std::collections::BTreeMap;
+    use tokio::sync::mpsc;
 
     use crate::runtimes::rego::context_aware::tests::{
-        dynamic_object_from_fixture, object_list_from_dynamic_objects,
+        dynamic_object_from_fixture, eval_ctx_with_callback_channel,
+        object_list_from_dynamic_objects,
     };
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
     async fn test_create_entry_because_cache_does_not_exist() {
         let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = eval_ctx_with_callback_channel(callback_tx);
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
@@ -169,6 +183,7 @@ pub(crate) mod tests {
                         assert!(field_selector.is_none());
                         CallbackResponse {
                             payload: serde_json::to_vec(&services_list).unwrap(),
+                            was_cached: false,
                         }
                     }
                     _ => {
@@ -180,38 +195,41 @@ pub(crate) mod tests {
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            {
-                // ensure the cache is empty
-                let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
-                inventories.clear();
-            }
+        {
+            // ensure the cache is empty
+            let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
+            inventories.clear();
+        }
 
-            let resources: BTreeSet<ContextAwareResource> = BTreeSet::from([resource]);
-
-            let cached_inventory = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
-                .unwrap();
-            assert!(!cached_inventory.is_empty());
-
-            {
-                let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let cached_input_json = inventories.get(&resources).unwrap();
-                let actual_inventory =
-                    serde_json::from_slice::<GatekeeperInput>(&cached_input_json.data)
-                        .unwrap()
-                        .inventory;
-                assert_eq!(expected_inventory, actual_inventory);
-            }
-        })
-        .await
-        .unwrap();
+        let resources: BTreeSet<ContextAwareResource> = BTreeSet::from([resource]);
+
+        let cached_inventory = GATEKEEPER_INVENTORY_CACHE
+            .get_inventory(&callback_ctx, &resources)
+            .await
+            .unwrap();
+        assert!(!cached_inventory.is_empty());
+
+        {
+            let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
+            let cached_input_json = inventories.get(&resources).unwrap();
+            let actual_inventory =
+                serde_json::from_slice::<GatekeeperInput>(&cached_input_json.data)
+                    .unwrap()
+                    .inventory;
+            assert_eq!(expected_inventory, actual_inventory);
+        }
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
     async fn test_cached_entry_is_still_valid() {
         let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = eval_ctx_with_callback_channel(callback_tx);
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
@@ -255,6 +273,7 @@ pub(crate) mod tests {
 
                         CallbackResponse {
                             payload: serde_json::to_vec(&false).unwrap(),
+                            was_cached: false,
                         }
                     }
                     _ => {
@@ -266,20 +285,23 @@ pub(crate) mod tests {
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
-                .unwrap();
-            assert_eq!(expected_cached_inventory.data, actual);
-        })
-        .await
-        .unwrap();
+        let actual = GATEKEEPER_INVENTORY_CACHE
+            .get_inventory(&callback_ctx, &resources)
+            .await
+            .unwrap();
+        assert_eq!(expected_cached_inventory.data, actual);
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
     async fn test_cached_entry_is_no_longer_valid() {
         let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let eval_ctx = eval_ctx_with_callback_channel(callback_tx);
+        let recorder = HostCallbackRecorder::default();
+        let callback_ctx = CallbackContext {
+            eval_ctx: &eval_ctx,
+            recorder: &recorder,
+        };
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
@@ -328,6 +350,7 @@ pub(crate) mod tests {
                         assert!(field_selector.is_none());
                         CallbackResponse {
                             payload: serde_json::to_vec(&services_list).unwrap(),
+                            was_cached: false,
                         }
                     }
                     CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
@@ -344,6 +367,7 @@ pub(crate) mod tests {
 
                         CallbackResponse {
                             payload: serde_json::to_vec(&true).unwrap(),
+                            was_cached: false,
                         }
                     }
                     _ => {
@@ -355,27 +379,24 @@ pub(crate) mod tests {
             }
         });
 
-        tokio::task::spawn_blocking(move || {
-            let actual = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
-                .unwrap();
-            assert!(actual != stale_cached_inventory.data);
-            let actual_inventory = serde_json::from_slice::<GatekeeperInput>(&actual).unwrap();
-            assert_eq!(expected_inventory, actual_inventory.inventory);
-
-            {
-                let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let actual_inventory = inventories.get(&resources).unwrap();
-                assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
-            }
+        let actual = GATEKEEPER_INVENTORY_CACHE
+            .get_inventory(&callback_ctx, &resources)
+            .await
+            .unwrap();
+        assert!(actual != stale_cached_inventory.data);
+        let actual_inventory = serde_json::from_slice::<GatekeeperInput>(&actual).unwrap();
+        assert_eq!(expected_inventory, actual_inventory.inventory);
 
-            {
-                let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let actual_inventory = inventories.get(&resources).unwrap();
-                assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
-            }
-        })
-        .await
-        .unwrap();
+        {
+            let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
+            let actual_inventory = inventories.get(&resources).unwrap();
+            assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
+        }
+
+        {
+            let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
+            let actual_inventory = inventories.get(&resources).unwrap();
+            assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
+        }
     }
 }
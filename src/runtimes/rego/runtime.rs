@@ -1,7 +1,9 @@
 use burrego::errors::BurregoError;
+use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use tracing::{error, warn};
 
 use crate::runtimes::rego::{
@@ -15,7 +17,45 @@ use crate::{
 
 pub(crate) struct Runtime<'a>(pub(crate) &'a mut Stack);
 
+/// Gatekeeper rules, like the `violations` entrypoint, might evaluate to a
+/// list of violations, each violation with a `msg` string explaining the
+/// violation reason
+#[derive(Debug, Deserialize)]
+struct Violation {
+    msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Violations {
+    result: Vec<Violation>,
+}
+
+impl Violations {
+    /// Fails when the result of the evaluation is not a list of violations:
+    /// a malformed result must never be mistaken for the lack of violations
+    fn from_evaluation(evaluation_result: &serde_json::Value) -> Result<Self, RegoRuntimeError> {
+        evaluation_result
+            .get(0)
+            .ok_or(RegoRuntimeError::InvalidResponse)
+            .and_then(|response| {
+                serde_json::from_value(response.clone())
+                    .map_err(RegoRuntimeError::InvalidResponseWithError)
+            })
+    }
+
+    fn message(&self) -> String {
+        self.result
+            .iter()
+            .filter_map(|violation| violation.msg.clone())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
 impl<'a> Runtime<'a> {
+    /// Evaluate all the selected entrypoints and merge their responses, see
+    /// [`merge_responses`]. Internal errors abort the evaluation of the
+    /// remaining entrypoints
     pub fn validate(
         &mut self,
         settings: &PolicySettings,
@@ -23,22 +63,53 @@ impl<'a> Runtime<'a> {
         ctx_data: &context_aware::KubernetesContext,
     ) -> AdmissionResponse {
         let uid = request.uid();
+        self.0.trapped = false;
+        self.0.was_reset = false;
+
+        let mut responses = Vec::with_capacity(self.0.entrypoint_ids.len());
+        for entrypoint_id in self.0.entrypoint_ids.clone() {
+            match self.validate_entrypoint(entrypoint_id, settings, request, ctx_data) {
+                Ok(response) => responses.push(response),
+                Err(message) => {
+                    return AdmissionResponse::reject_internal_server_error(
+                        uid.to_string(),
+                        message,
+                    )
+                }
+            }
+        }
+
+        merge_responses(uid, responses)
+    }
+
+    /// Evaluate a single entrypoint. Internal errors are returned as `Err`,
+    /// they must not be mistaken for the rejections made by the policy, which
+    /// can use any status code
+    fn validate_entrypoint(
+        &mut self,
+        entrypoint_id: i32,
+        settings: &PolicySettings,
+        request: &ValidateRequest,
+        ctx_data: &context_aware::KubernetesContext,
+    ) -> Result<AdmissionResponse, String> {
+        let uid = request.uid();
 
         // OPA and Gatekeeper expect arguments in different ways
         let burrego_evaluation = match self.0.policy_execution_mode {
-            RegoPolicyExecutionMode::Opa => self.evaluate_opa(settings, request, ctx_data),
+            RegoPolicyExecutionMode::Opa => {
+                self.evaluate_opa(entrypoint_id, settings, request, ctx_data)
+            }
             RegoPolicyExecutionMode::Gatekeeper => {
                 // Gatekeeper policies expect the `AdmissionRequest` variant only.
                 let request = match request {
                     ValidateRequest::AdmissionRequest(adm_req) => adm_req,
                     ValidateRequest::Raw(_) => {
-                        return AdmissionResponse::reject_internal_server_error(
-                            uid.to_string(),
-                            "Gatekeeper does not support raw validation requests".to_string(),
+                        return Err(
+                            "Gatekeeper does not support raw validation requests".to_string()
                         );
                     }
                 };
-                self.evaluate_gatekeeper(settings, request, ctx_data)
+                self.evaluate_gatekeeper(entrypoint_id, settings, request, ctx_data)
             }
         };
 
@@ -57,95 +128,84 @@ impl<'a> Runtime<'a> {
                         match evaluation_result {
                             Some(evaluation_result) => {
                                 match serde_json::from_value(evaluation_result.clone()) {
-                                    Ok(evaluation_result) => AdmissionResponse {
+                                    Ok(evaluation_result) => Ok(AdmissionResponse {
                                         uid: uid.to_string(),
                                         ..evaluation_result
-                                    },
-                                    Err(err) => AdmissionResponse::reject_internal_server_error(
-                                        uid.to_string(),
-                                        err.to_string(),
-                                    ),
+                                    }),
+                                    Err(err) => Err(err.to_string()),
                                 }
                             }
-                            None => AdmissionResponse::reject_internal_server_error(
-                                uid.to_string(),
-                                "cannot interpret OPA policy result".to_string(),
-                            ),
+                            None => Err("cannot interpret OPA policy result".to_string()),
                         }
                     }
                     RegoPolicyExecutionMode::Gatekeeper => {
                         // Gatekeeper entrypoint is usually a
-                        // `violations` rule that might evaluate to a
-                        // list of violations, each violation with a
-                        // `msg` string explaining the violation
-                        // reason. If no violations are reported, the
-                        // request is accepted. Otherwise it is
-                        // rejected.
-                        #[derive(Debug, Deserialize)]
-                        struct Violation {
-                            msg: Option<String>,
-                        }
-                        #[derive(Debug, Default, Deserialize)]
-                        struct Violations {
-                            result: Vec<Violation>,
-                        }
-
-                        let violations: Violations = evaluation_result
-                            .get(0)
-                            .ok_or_else(|| RegoRuntimeError::InvalidResponse)
-                            .and_then(|response| {
-                                serde_json::from_value(response.clone())
-                                    .map_err(RegoRuntimeError::InvalidResponseWithError)
-                            })
-                            .unwrap_or_default();
+                        // `violations` rule. If no violations are
+                        // reported, the request is accepted. Otherwise
+                        // it is rejected.
+                        let violations = match Violations::from_evaluation(&evaluation_result) {
+                            Ok(violations) => violations,
+                            Err(err) => {
+                                return Err(format!(
+                                    "cannot interpret Gatekeeper policy result: {err}"
+                                ))
+                            }
+                        };
 
                         if violations.result.is_empty() {
-                            AdmissionResponse {
+                            Ok(AdmissionResponse {
                                 uid: uid.to_string(),
                                 allowed: true,
                                 ..Default::default()
-                            }
+                            })
                         } else {
-                            AdmissionResponse {
+                            Ok(AdmissionResponse {
                                 uid: uid.to_string(),
                                 allowed: false,
                                 status: Some(AdmissionResponseStatus {
-                                    message: Some(
-                                        violations
-                                            .result
-                                            .iter()
-                                            .filter_map(|violation| violation.msg.clone())
-                                            .collect::<Vec<String>>()
-                                            .join(", "),
-                                    ),
+                                    message: Some(violations.message()),
                                     ..Default::default()
                                 }),
                                 ..Default::default()
-                            }
+                            })
                         }
                     }
                 }
             }
             Err(err) => {
-                error!(
-                    error = ?err,
-                    "error evaluating policy with burrego"
-                );
-                if matches!(
-                    err,
-                    burrego::errors::BurregoError::ExecutionDeadlineExceeded
-                ) {
-                    if let Err(reset_error) = self.0.evaluator.reset() {
-                        error!(?reset_error, "cannot reset burrego evaluator, further invocations might fail or behave not properly");
-                    }
+                self.handle_evaluation_error(&err);
+                Err(err.to_string())
+            }
+        }
+    }
+
+    /// Mark the stack as trapped and reset the evaluator when the guest has
+    /// been interrupted. Other errors, like a malformed input, leave the
+    /// evaluator usable
+    fn handle_evaluation_error(&mut self, err: &BurregoError) {
+        error!(
+            error = ?err,
+            "error evaluating policy with burrego"
+        );
+        if matches!(
+            err,
+            burrego::errors::BurregoError::ExecutionDeadlineExceeded
+                | burrego::errors::BurregoError::OutOfFuel
+                | burrego::errors::BurregoError::ResourceLimitExceeded(_)
+        ) {
+            self.0.trapped = true;
+            match self.0.evaluator.reset() {
+                Ok(_) => self.0.was_reset = true,
+                Err(reset_error) => {
+                    error!(?reset_error, "cannot reset burrego evaluator, further invocations might fail or behave not properly");
                 }
-                AdmissionResponse::reject_internal_server_error(uid.to_string(), err.to_string())
             }
         }
     }
 
     fn evaluate_opa(
         &mut self,
+        entrypoint_id: i32,
         settings: &PolicySettings,
         request: &ValidateRequest,
         ctx_data: &context_aware::KubernetesContext,
@@ -176,13 +236,12 @@ impl<'a> Runtime<'a> {
             source: e,
         })?;
 
-        self.0
-            .evaluator
-            .evaluate(self.0.entrypoint_id, &input, &data_raw)
+        self.0.evaluator.evaluate(entrypoint_id, &input, &data_raw)
     }
 
     fn evaluate_gatekeeper(
         &mut self,
+        entrypoint_id: i32,
         settings: &PolicySettings,
         request: &admission_request::AdmissionRequest,
         ctx_data: &context_aware::KubernetesContext,
@@ -203,18 +262,547 @@ impl<'a> Runtime<'a> {
             KubernetesContext::Opa(_) => unreachable!(),
         };
 
-        self.0
-            .evaluator
-            .evaluate(self.0.entrypoint_id, &input, data_raw)
+        self.0.evaluator.evaluate(entrypoint_id, &input, data_raw)
+    }
+
+    /// Validate the settings against the schema provided by the policy
+    /// metadata, then against the `validate_settings` entrypoint of the
+    /// policy. Both of them are optional: the settings are considered valid
+    /// when the policy provides neither.
+    ///
+    /// The entrypoint receives the settings under `input.settings` for OPA
+    /// policies and under `input.parameters` for Gatekeeper ones. OPA
+    /// policies must evaluate to a `SettingsValidationResponse` object, while
+    /// Gatekeeper policies must evaluate to a list of violations.
+    pub fn validate_settings(&mut self, settings: String) -> SettingsValidationResponse {
+        if self.0.settings_schema.is_none() && self.0.settings_entrypoint_id.is_none() {
+            // The burrego backend is mainly for compatibility with
+            // existing OPA policies. Those policies don't have a generic
+            // way of validating settings. Return true
+            return SettingsValidationResponse {
+                valid: true,
+                message: None,
+            };
+        }
+
+        let settings: serde_json::Value = match serde_json::from_str(&settings) {
+            Ok(settings) => settings,
+            Err(err) => {
+                return SettingsValidationResponse {
+                    valid: false,
+                    message: Some(format!("cannot parse settings: {err}")),
+                }
+            }
+        };
+
+        if let Some(schema) = &self.0.settings_schema {
+            if let Err(errors) = schema.validate(&settings) {
+                let errors = errors
+                    .map(|error| match error.instance_path.to_string().as_str() {
+                        "" => error.to_string(),
+                        path => format!("{path}: {error}"),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                return SettingsValidationResponse {
+                    valid: false,
+                    message: Some(format!("settings do not match the schema: {errors}")),
+                };
+            }
+        }
+
+        let Some(entrypoint_id) = self.0.settings_entrypoint_id else {
+            return SettingsValidationResponse {
+                valid: true,
+                message: None,
+            };
+        };
+
+        self.0.trapped = false;
+        self.0.was_reset = false;
+        let input = match self.0.policy_execution_mode {
+            RegoPolicyExecutionMode::Opa => json!({ "settings": settings }),
+            RegoPolicyExecutionMode::Gatekeeper => json!({ "parameters": settings }),
+        };
+        let evaluation_result = match self.0.evaluator.evaluate(entrypoint_id, &input, b"{}") {
+            Ok(evaluation_result) => evaluation_result,
+            Err(err) => {
+                self.handle_evaluation_error(&err);
+                return SettingsValidationResponse {
+                    valid: false,
+                    message: Some(format!("cannot validate settings: {err}")),
+                };
+            }
+        };
+
+        match self.0.policy_execution_mode {
+            RegoPolicyExecutionMode::Opa => evaluation_result
+                .get(0)
+                .and_then(|r| r.get("result"))
+                .ok_or(RegoRuntimeError::InvalidResponse)
+                .and_then(|r| {
+                    serde_json::from_value(r.clone())
+                        .map_err(RegoRuntimeError::InvalidResponseWithError)
+                })
+                .unwrap_or_else(|err| SettingsValidationResponse {
+                    valid: false,
+                    message: Some(format!(
+                        "cannot interpret settings validation result: {err}"
+                    )),
+                }),
+            RegoPolicyExecutionMode::Gatekeeper => {
+                match Violations::from_evaluation(&evaluation_result) {
+                    Ok(violations) => SettingsValidationResponse {
+                        valid: violations.result.is_empty(),
+                        message: (!violations.result.is_empty()).then(|| violations.message()),
+                    },
+                    Err(err) => SettingsValidationResponse {
+                        valid: false,
+                        message: Some(format!(
+                            "cannot interpret settings validation result: {err}"
+                        )),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Rego policies do not export their protocol version, the value is
+    /// derived from the metadata of the policy
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.0.protocol_version.clone()
     }
+}
 
-    pub fn validate_settings(&mut self, _settings: String) -> SettingsValidationResponse {
-        // The burrego backend is mainly for compatibility with
-        // existing OPA policies. Those policies don't have a generic
-        // way of validating settings. Return true
-        SettingsValidationResponse {
-            valid: true,
-            message: None,
+/// Merge the responses given by several entrypoints to the same request.
+///
+/// The request is accepted only when all the entrypoints accept it. When
+/// rejected, the messages of the rejections are joined and the code of the
+/// first rejection is used. Warnings and audit annotations are always merged,
+/// the entrypoints cannot set the same audit annotation to different values.
+/// At most one entrypoint can mutate the request.
+fn merge_responses(uid: &str, mut responses: Vec<AdmissionResponse>) -> AdmissionResponse {
+    if responses.len() == 1 {
+        return responses.remove(0);
+    }
+
+    let warnings = responses
+        .iter()
+        .flat_map(|response| response.warnings.iter().flatten().cloned())
+        .collect::<Vec<String>>();
+    let mut audit_annotations = HashMap::new();
+    for (key, value) in responses
+        .iter()
+        .flat_map(|response| response.audit_annotations.iter().flatten())
+    {
+        match audit_annotations.insert(key.clone(), value.clone()) {
+            Some(previous) if &previous != value => {
+                return AdmissionResponse::reject_internal_server_error(
+                    uid.to_string(),
+                    format!("entrypoints set conflicting values for the audit annotation {key:?}"),
+                );
+            }
+            _ => {}
         }
     }
+    let mut merged = AdmissionResponse {
+        uid: uid.to_string(),
+        allowed: true,
+        warnings: (!warnings.is_empty()).then_some(warnings),
+        audit_annotations: (!audit_annotations.is_empty()).then_some(audit_annotations),
+        ..Default::default()
+    };
+
+    let rejections = responses
+        .iter()
+        .filter(|response| !response.allowed)
+        .collect::<Vec<_>>();
+    if !rejections.is_empty() {
+        merged.allowed = false;
+        merged.status = Some(AdmissionResponseStatus {
+            message: Some(
+                rejections
+                    .iter()
+                    .filter_map(|response| response.status.as_ref()?.message.clone())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
+            code: rejections
+                .iter()
+                .find_map(|response| response.status.as_ref()?.code),
+        });
+        return merged;
+    }
+
+    let mut mutations = responses
+        .into_iter()
+        .filter(|response| response.patch.is_some());
+    if let Some(mutation) = mutations.next() {
+        if mutations.next().is_some() {
+            return AdmissionResponse::reject_internal_server_error(
+                uid.to_string(),
+                "more than one entrypoint mutated the request".to_string(),
+            );
+        }
+        merged.patch = mutation.patch;
+        merged.patch_type = mutation.patch_type;
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    use crate::policy_metadata::Metadata;
+    use crate::runtimes::rego::StackPre;
+
+    /// A fake Rego policy, see `tests/data/rego_settings/README.md`
+    const SETTINGS_VALIDATION_WAT: &[u8] =
+        include_bytes!("../../../tests/data/rego_settings/settings_validation.wat");
+
+    fn settings_validation_stack_pre(
+        policy_execution_mode: RegoPolicyExecutionMode,
+        settings_schema: Option<serde_json::Value>,
+    ) -> StackPre {
+        let engine = wasmtime::Engine::default();
+        let module = wasmtime::Module::new(&engine, SETTINGS_VALIDATION_WAT).unwrap();
+        let metadata = Metadata {
+            settings_schema,
+            ..Default::default()
+        };
+
+        StackPre::new(engine, module, None, None, None, policy_execution_mode)
+            .with_metadata(Some(metadata))
+            .unwrap()
+    }
+
+    fn settings_validation_stack(
+        policy_execution_mode: RegoPolicyExecutionMode,
+        settings_schema: Option<serde_json::Value>,
+        settings_entrypoint: &str,
+    ) -> Stack {
+        let stack_pre = settings_validation_stack_pre(policy_execution_mode, settings_schema)
+            .with_settings_entrypoint(Some(settings_entrypoint))
+            .unwrap();
+        Stack::new_from_pre(&stack_pre).unwrap()
+    }
+
+    fn settings_schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "replicas": { "type": "integer" }
+            }
+        })
+    }
+
+    #[rstest]
+    #[case::opa_valid(
+        RegoPolicyExecutionMode::Opa,
+        "opa/validate_settings",
+        r#"{"mode": "allowed"}"#,
+        true,
+        None
+    )]
+    #[case::opa_invalid(
+        RegoPolicyExecutionMode::Opa,
+        "opa/validate_settings",
+        r#"{"mode": "forbidden"}"#,
+        false,
+        Some("forbidden setting")
+    )]
+    #[case::gatekeeper_valid(
+        RegoPolicyExecutionMode::Gatekeeper,
+        "gatekeeper/validate_settings",
+        r#"{"mode": "allowed"}"#,
+        true,
+        None
+    )]
+    #[case::gatekeeper_invalid(
+        RegoPolicyExecutionMode::Gatekeeper,
+        "gatekeeper/validate_settings",
+        r#"{"mode": "forbidden"}"#,
+        false,
+        Some("forbidden setting")
+    )]
+    fn settings_are_validated_by_the_entrypoint(
+        #[case] policy_execution_mode: RegoPolicyExecutionMode,
+        #[case] settings_entrypoint: &str,
+        #[case] settings: &str,
+        #[case] valid: bool,
+        #[case] message: Option<&str>,
+    ) {
+        let mut stack = settings_validation_stack(policy_execution_mode, None, settings_entrypoint);
+
+        let response = Runtime(&mut stack).validate_settings(settings.to_string());
+
+        assert_eq!(valid, response.valid);
+        assert_eq!(message.map(str::to_string), response.message);
+    }
+
+    #[rstest]
+    #[case::opa(RegoPolicyExecutionMode::Opa, "opa/validate_settings")]
+    #[case::gatekeeper(RegoPolicyExecutionMode::Gatekeeper, "gatekeeper/validate_settings")]
+    fn settings_are_validated_by_the_schema_and_the_entrypoint(
+        #[case] policy_execution_mode: RegoPolicyExecutionMode,
+        #[case] settings_entrypoint: &str,
+    ) {
+        let mut stack = settings_validation_stack(
+            policy_execution_mode,
+            Some(settings_schema()),
+            settings_entrypoint,
+        );
+        let mut runtime = Runtime(&mut stack);
+
+        let response = runtime.validate_settings(r#"{"replicas": 1}"#.to_string());
+        assert!(response.valid, "{:?}", response.message);
+
+        let response = runtime.validate_settings(r#"{"replicas": "one"}"#.to_string());
+        assert!(!response.valid);
+        let message = response.message.unwrap();
+        assert!(
+            message.starts_with("settings do not match the schema: /replicas"),
+            "{message}"
+        );
+
+        // the settings comply with the schema, but they are rejected by the
+        // entrypoint
+        let response =
+            runtime.validate_settings(r#"{"replicas": 1, "mode": "forbidden"}"#.to_string());
+        assert!(!response.valid);
+        assert_eq!(Some("forbidden setting".to_string()), response.message);
+    }
+
+    #[test]
+    fn malformed_gatekeeper_settings_validation_result_is_rejected() {
+        let mut stack = settings_validation_stack(
+            RegoPolicyExecutionMode::Gatekeeper,
+            None,
+            "gatekeeper/malformed",
+        );
+
+        let response = Runtime(&mut stack).validate_settings(r#"{"mode": "allowed"}"#.to_string());
+
+        assert!(!response.valid);
+        let message = response.message.unwrap();
+        assert!(
+            message.starts_with("cannot interpret settings validation result"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn malformed_gatekeeper_validation_result_is_an_internal_error() {
+        let stack_pre = settings_validation_stack_pre(RegoPolicyExecutionMode::Gatekeeper, None)
+            .with_entrypoints(&["gatekeeper/malformed".to_string()])
+            .unwrap();
+        let mut stack = Stack::new_from_pre(&stack_pre).unwrap();
+        let request = ValidateRequest::AdmissionRequest(admission_request::AdmissionRequest {
+            uid: "uid".to_string(),
+            ..Default::default()
+        });
+
+        let response = Runtime(&mut stack).validate(
+            &PolicySettings::default(),
+            &request,
+            &KubernetesContext::Empty,
+        );
+
+        assert!(!response.allowed);
+        let status = response.status.unwrap();
+        assert_eq!(Some(500), status.code);
+        let message = status.message.unwrap();
+        assert!(
+            message.starts_with("internal server error: cannot interpret Gatekeeper policy result"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn settings_entrypoint_is_not_detected_by_name() {
+        let stack_pre = settings_validation_stack_pre(RegoPolicyExecutionMode::Opa, None)
+            .with_settings_entrypoint(None)
+            .unwrap();
+
+        assert_eq!(None, stack_pre.settings_entrypoint_id);
+    }
+
+    #[test]
+    fn unknown_settings_entrypoint_is_rejected() {
+        let stack_pre = settings_validation_stack_pre(RegoPolicyExecutionMode::Opa, None);
+
+        assert!(matches!(
+            stack_pre.with_settings_entrypoint(Some("policy/validate_settings")),
+            Err(RegoRuntimeError::UnknownEntrypoint { name, .. }) if name == "policy/validate_settings"
+        ));
+    }
+
+    #[rstest]
+    #[case::deadline_exceeded(BurregoError::ExecutionDeadlineExceeded, true)]
+    #[case::out_of_fuel(BurregoError::OutOfFuel, true)]
+    #[case::resource_limit_exceeded(
+        BurregoError::ResourceLimitExceeded("memory".to_string()),
+        true
+    )]
+    #[case::builtin_error(
+        BurregoError::BuiltinError {
+            name: "builtin".to_string(),
+            message: "boom".to_string(),
+        },
+        false
+    )]
+    #[case::rego_wasm_error(BurregoError::RegoWasmError("boom".to_string()), false)]
+    fn only_interrupted_evaluations_trap_the_stack(
+        #[case] err: BurregoError,
+        #[case] trapped: bool,
+    ) {
+        let mut stack =
+            settings_validation_stack(RegoPolicyExecutionMode::Opa, None, "opa/validate_settings");
+
+        Runtime(&mut stack).handle_evaluation_error(&err);
+
+        assert_eq!(trapped, stack.trapped);
+    }
+
+    fn rejection(message: &str, code: Option<u16>) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed: false,
+            status: Some(AdmissionResponseStatus {
+                message: Some(message.to_string()),
+                code,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn acceptance(warning: Option<&str>, patch: Option<&str>) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed: true,
+            warnings: warning.map(|warning| vec![warning.to_string()]),
+            patch: patch.map(str::to_string),
+            patch_type: patch.map(|_| "JSONPatch".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_accepted_responses() {
+        let response = merge_responses(
+            "uid",
+            vec![
+                acceptance(Some("first"), None),
+                acceptance(Some("second"), Some("patch")),
+            ],
+        );
+
+        assert!(response.allowed);
+        assert_eq!("uid", response.uid);
+        assert_eq!(
+            Some(vec!["first".to_string(), "second".to_string()]),
+            response.warnings
+        );
+        assert_eq!(Some("patch".to_string()), response.patch);
+        assert_eq!(Some("JSONPatch".to_string()), response.patch_type);
+    }
+
+    #[test]
+    fn merge_rejected_responses() {
+        let response = merge_responses(
+            "uid",
+            vec![
+                rejection("first", None),
+                acceptance(None, Some("patch")),
+                rejection("second", Some(400)),
+            ],
+        );
+
+        assert!(!response.allowed);
+        assert!(response.patch.is_none());
+        assert_eq!(
+            Some(AdmissionResponseStatus {
+                message: Some("first, second".to_string()),
+                code: Some(400),
+            }),
+            response.status
+        );
+    }
+
+    #[test]
+    fn merge_several_mutations_is_rejected() {
+        let response = merge_responses(
+            "uid",
+            vec![
+                acceptance(None, Some("first")),
+                acceptance(None, Some("second")),
+            ],
+        );
+
+        assert!(!response.allowed);
+        assert_eq!(Some(500), response.status.and_then(|status| status.code));
+    }
+
+    fn annotated(annotations: &[(&str, &str)]) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed: true,
+            audit_annotations: Some(
+                annotations
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_audit_annotations() {
+        let response = merge_responses(
+            "uid",
+            vec![
+                annotated(&[("first", "value"), ("shared", "value")]),
+                annotated(&[("second", "value"), ("shared", "value")]),
+            ],
+        );
+
+        assert!(response.allowed);
+        assert_eq!(
+            Some(HashMap::from([
+                ("first".to_string(), "value".to_string()),
+                ("second".to_string(), "value".to_string()),
+                ("shared".to_string(), "value".to_string()),
+            ])),
+            response.audit_annotations
+        );
+    }
+
+    #[test]
+    fn merge_conflicting_audit_annotations_is_rejected() {
+        let response = merge_responses(
+            "uid",
+            vec![
+                annotated(&[("shared", "first")]),
+                annotated(&[("shared", "second")]),
+            ],
+        );
+
+        assert!(!response.allowed);
+        assert_eq!(Some(500), response.status.and_then(|status| status.code));
+    }
+
+    #[test]
+    fn rejections_with_internal_server_error_code_are_merged() {
+        let response = merge_responses(
+            "uid",
+            vec![rejection("first", Some(500)), rejection("second", None)],
+        );
+
+        assert!(!response.allowed);
+        assert_eq!(
+            Some("first, second".to_string()),
+            response.status.and_then(|status| status.message)
+        );
+    }
 }
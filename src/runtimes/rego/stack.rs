@@ -1,23 +1,40 @@
-use std::collections::BTreeSet;
-use tokio::sync::mpsc;
+use jsonschema::JSONSchema;
+use kubewarden_policy_sdk::metadata::ProtocolVersion;
+use std::sync::Arc;
 
 use crate::{
-    callback_requests::CallbackRequest,
+    evaluation_context::EvaluationContext,
     policy_evaluator::RegoPolicyExecutionMode,
-    policy_metadata::ContextAwareResource,
-    runtimes::rego::{
-        context_aware,
-        errors::{RegoRuntimeError, Result},
-        gatekeeper_inventory_cache::GATEKEEPER_INVENTORY_CACHE,
-        opa_inventory::OpaInventory,
-        stack_pre::StackPre,
+    runtimes::{
+        callback::{block_on, HostCallbackRecorder},
+        rego::{
+            context_aware::{self, CallbackContext},
+            errors::{RegoRuntimeError, Result},
+            gatekeeper_inventory_cache::GATEKEEPER_INVENTORY_CACHE,
+            opa_inventory::OpaInventory,
+            stack_pre::StackPre,
+        },
     },
 };
 
 pub(crate) struct Stack {
     pub evaluator: burrego::Evaluator,
-    pub entrypoint_id: i32,
+    /// Ids of the entrypoints to be evaluated, their results are merged
+    pub entrypoint_ids: Vec<i32>,
     pub policy_execution_mode: RegoPolicyExecutionMode,
+    /// Version of the protocol spoken with the host, see [`StackPre::with_metadata`]
+    pub protocol_version: ProtocolVersion,
+    /// Schema the settings must comply with, see [`StackPre::with_metadata`]
+    pub settings_schema: Option<Arc<JSONSchema>>,
+    /// Id of the entrypoint validating the settings, see
+    /// [`StackPre::with_settings_entrypoint`]
+    pub settings_entrypoint_id: Option<i32>,
+    /// Set when the last evaluation of the policy has been interrupted
+    pub trapped: bool,
+    /// Set when the evaluator has been reset after the last evaluation
+    pub was_reset: bool,
+    /// Tracks the host callbacks made to build the Kubernetes context
+    pub callback_recorder: HostCallbackRecorder,
 }
 
 impl Stack {
@@ -26,42 +43,80 @@ impl Stack {
         let evaluator = stack_pre
             .rehydrate()
             .map_err(|e| RegoRuntimeError::EvaluatorError(e.to_string()))?;
+
         Ok(Self {
             evaluator,
-            entrypoint_id: stack_pre.entrypoint_id,
+            entrypoint_ids: stack_pre.entrypoint_ids.clone(),
             policy_execution_mode: stack_pre.policy_execution_mode.clone(),
+            // the host implements the v1 protocol on behalf of the Rego
+            // policies, unless their metadata states otherwise
+            protocol_version: stack_pre
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.protocol_version.clone())
+                .unwrap_or(ProtocolVersion::V1),
+            settings_schema: stack_pre.settings_schema.clone(),
+            settings_entrypoint_id: stack_pre.settings_entrypoint_id,
+            trapped: false,
+            was_reset: false,
+            callback_recorder: HostCallbackRecorder::default(),
         })
     }
 
+    /// Build the Kubernetes context of the policy, blocking the current thread
+    /// until the host callbacks are done. See
+    /// [`Stack::build_kubernetes_context_async`]
     pub fn build_kubernetes_context(
         &self,
-        callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
-        ctx_aware_resources_allow_list: &BTreeSet<ContextAwareResource>,
+        eval_ctx: &EvaluationContext,
+    ) -> Result<context_aware::KubernetesContext> {
+        block_on(self.build_kubernetes_context_async(eval_ctx))
+            .map_err(RegoRuntimeError::AsyncRuntime)?
+    }
+
+    /// Build the Kubernetes context of the policy, yielding while waiting for
+    /// the outcome of the host callbacks. These are the only host callbacks
+    /// made by Rego policies, the evaluation itself never waits for the host.
+    ///
+    /// The host callbacks are made on behalf of the policy: they count towards
+    /// the limits set inside of its `EvaluationContext`
+    pub async fn build_kubernetes_context_async(
+        &self,
+        eval_ctx: &EvaluationContext,
     ) -> Result<context_aware::KubernetesContext> {
+        let ctx_aware_resources_allow_list = &eval_ctx.ctx_aware_resources_allow_list;
         if ctx_aware_resources_allow_list.is_empty() {
             return Ok(context_aware::KubernetesContext::Empty);
         }
+        if eval_ctx.callback_channel.is_none() {
+            return Err(RegoRuntimeError::CallbackChannelNotSet);
+        }
+        let callback_ctx = CallbackContext {
+            eval_ctx,
+            recorder: &self.callback_recorder,
+        };
 
-        match callback_channel {
-            None => Err(RegoRuntimeError::CallbackChannelNotSet),
-            Some(chan) => match self.policy_execution_mode {
-                RegoPolicyExecutionMode::Opa => {
-                    let cluster_resources =
-                        context_aware::get_allowed_resources(chan, ctx_aware_resources_allow_list)?;
-                    let plural_names_by_resource =
-                        context_aware::get_plural_names(chan, ctx_aware_resources_allow_list)?;
-                    let inventory =
-                        OpaInventory::new(&cluster_resources, &plural_names_by_resource)?;
-                    Ok(context_aware::KubernetesContext::Opa(inventory))
-                }
-                RegoPolicyExecutionMode::Gatekeeper => {
-                    let cached_inventory = GATEKEEPER_INVENTORY_CACHE
-                        .get_inventory(chan, ctx_aware_resources_allow_list)?;
-                    Ok(context_aware::KubernetesContext::Gatekeeper(
-                        cached_inventory,
-                    ))
-                }
-            },
+        match self.policy_execution_mode {
+            RegoPolicyExecutionMode::Opa => {
+                let cluster_resources = context_aware::get_allowed_resources(
+                    &callback_ctx,
+                    ctx_aware_resources_allow_list,
+                )
+                .await?;
+                let plural_names_by_resource =
+                    context_aware::get_plural_names(&callback_ctx, ctx_aware_resources_allow_list)
+                        .await?;
+                let inventory = OpaInventory::new(&cluster_resources, &plural_names_by_resource)?;
+                Ok(context_aware::KubernetesContext::Opa(inventory))
+            }
+            RegoPolicyExecutionMode::Gatekeeper => {
+                let cached_inventory = GATEKEEPER_INVENTORY_CACHE
+                    .get_inventory(&callback_ctx, ctx_aware_resources_allow_list)
+                    .await?;
+                Ok(context_aware::KubernetesContext::Gatekeeper(
+                    cached_inventory,
+                ))
+            }
         }
     }
 }
@@ -1,5 +1,13 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use burrego::{FuelBudget, ResourceLimits};
+use itertools::Itertools;
+use jsonschema::JSONSchema;
+
 use crate::policy_evaluator::RegoPolicyExecutionMode;
 use crate::policy_evaluator_builder::EpochDeadlines;
+use crate::policy_metadata::Metadata;
 use crate::runtimes::rego::errors::{RegoRuntimeError, Result};
 
 /// This struct allows to follow the `StackPre -> Stack`
@@ -18,8 +26,21 @@ pub(crate) struct StackPre {
     engine: wasmtime::Engine,
     module: wasmtime::Module,
     epoch_deadlines: Option<EpochDeadlines>,
-    pub entrypoint_id: i32,
+    resource_limits: Option<ResourceLimits>,
+    fuel_limits: Option<FuelBudget>,
+    /// Ids of the entrypoints to be evaluated, see [`StackPre::with_entrypoints`]
+    pub entrypoint_ids: Vec<i32>,
     pub policy_execution_mode: RegoPolicyExecutionMode,
+    /// The metadata embedded into the policy, if any
+    pub metadata: Option<Metadata>,
+    /// The compiled settings schema provided by the metadata
+    pub settings_schema: Option<Arc<JSONSchema>>,
+    /// Id of the entrypoint validating the settings, see
+    /// [`StackPre::with_settings_entrypoint`]
+    pub settings_entrypoint_id: Option<i32>,
+    /// The entrypoints defined by the module, looked up once, see
+    /// [`StackPre::entrypoints`]
+    entrypoints: Option<HashMap<String, i32>>,
 }
 
 impl StackPre {
@@ -27,16 +48,85 @@ impl StackPre {
         engine: wasmtime::Engine,
         module: wasmtime::Module,
         epoch_deadlines: Option<EpochDeadlines>,
-        entrypoint_id: i32,
+        resource_limits: Option<ResourceLimits>,
+        fuel_limits: Option<FuelBudget>,
         policy_execution_mode: RegoPolicyExecutionMode,
     ) -> Self {
         Self {
             engine,
             module,
             epoch_deadlines,
-            entrypoint_id,
+            resource_limits,
+            fuel_limits,
+            // the first entrypoint of the policy is evaluated by default
+            entrypoint_ids: vec![0],
             policy_execution_mode,
+            metadata: None,
+            settings_schema: None,
+            settings_entrypoint_id: None,
+            entrypoints: None,
+        }
+    }
+
+    /// Set the metadata embedded into the policy. Rego policies cannot
+    /// report information about themselves, hence these details are taken
+    /// from the metadata.
+    ///
+    /// Fails when the settings schema provided by the metadata cannot be
+    /// compiled
+    pub(crate) fn with_metadata(mut self, metadata: Option<Metadata>) -> Result<Self> {
+        self.settings_schema = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.settings_schema.as_ref())
+            .map(|schema| {
+                JSONSchema::compile(schema)
+                    .map(Arc::new)
+                    .map_err(|e| RegoRuntimeError::InvalidSettingsSchema(e.to_string()))
+            })
+            .transpose()?;
+        self.metadata = metadata;
+        Ok(self)
+    }
+
+    /// Select the entrypoints to be evaluated by name. Nothing is changed
+    /// when no name is given.
+    ///
+    /// Fails when the policy does not provide one of the entrypoints
+    pub(crate) fn with_entrypoints(mut self, names: &[String]) -> Result<Self> {
+        if names.is_empty() {
+            return Ok(self);
+        }
+
+        let entrypoints = self.entrypoints()?;
+        self.entrypoint_ids = names
+            .iter()
+            .map(|name| entrypoint_id(&entrypoints, name))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(self)
+    }
+
+    /// Select the entrypoint validating the settings by name. The settings
+    /// are not validated by the policy when no name is given.
+    ///
+    /// Fails when the policy does not provide the entrypoint
+    pub(crate) fn with_settings_entrypoint(mut self, name: Option<&str>) -> Result<Self> {
+        let Some(name) = name else {
+            return Ok(self);
+        };
+
+        let entrypoints = self.entrypoints()?;
+        self.settings_entrypoint_id = Some(entrypoint_id(&entrypoints, name)?);
+        Ok(self)
+    }
+
+    /// The entrypoints defined by the module, mapped to their ids. The ids
+    /// don't depend on the evaluator, hence a single one is created to look
+    /// them up
+    fn entrypoints(&mut self) -> Result<HashMap<String, i32>> {
+        if self.entrypoints.is_none() {
+            self.entrypoints = Some(self.rehydrate()?.entrypoints());
         }
+        Ok(self.entrypoints.clone().unwrap_or_default())
     }
 
     /// Create a fresh `burrego::Evaluator`
@@ -49,9 +139,25 @@ impl StackPre {
         if let Some(deadlines) = self.epoch_deadlines {
             builder = builder.enable_epoch_interruptions(deadlines.wapc_func);
         }
+        if let Some(limits) = self.resource_limits {
+            builder = builder.resource_limits(limits);
+        }
+        if let Some(fuel) = self.fuel_limits {
+            builder = builder.enable_fuel(fuel.init, fuel.call);
+        }
         let evaluator = builder
             .build()
             .map_err(RegoRuntimeError::RegoEngineBuilder)?;
         Ok(evaluator)
     }
 }
+
+fn entrypoint_id(entrypoints: &HashMap<String, i32>, name: &str) -> Result<i32> {
+    entrypoints
+        .get(name)
+        .copied()
+        .ok_or_else(|| RegoRuntimeError::UnknownEntrypoint {
+            name: name.to_owned(),
+            available: entrypoints.keys().sorted().cloned().collect(),
+        })
+}
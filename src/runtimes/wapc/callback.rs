@@ -4,6 +4,7 @@ use anyhow::Result;
 use tracing::debug;
 
 use crate::evaluation_context::EvaluationContext;
+use crate::runtimes::callback::HostCallbackRecorder;
 
 /// A host callback function that can be used by the waPC runtime.
 type HostCallback = Box<
@@ -19,13 +20,17 @@ type HostCallback = Box<
 >;
 
 /// Returns a host callback function that can be used by the waPC runtime.
-/// The callback function will be able to access the `EvaluationContext` instance.
-pub(crate) fn new_host_callback(eval_ctx: Arc<EvaluationContext>) -> HostCallback {
+/// The callback function will be able to access the `EvaluationContext` instance,
+/// the invocations are tracked by the given `HostCallbackRecorder`.
+pub(crate) fn new_host_callback(
+    eval_ctx: Arc<EvaluationContext>,
+    recorder: HostCallbackRecorder,
+) -> HostCallback {
     Box::new({
         move |wapc_id, binding, namespace, operation, payload| {
             debug!(wapc_id, "invoking host_callback");
             crate::runtimes::callback::host_callback(
-                binding, namespace, operation, payload, &eval_ctx,
+                binding, namespace, operation, payload, &eval_ctx, &recorder,
             )
         }
     })
@@ -0,0 +1,431 @@
+use std::sync::{Arc, Mutex};
+
+use burrego::errors::BurregoError;
+use burrego::{FuelBudget, ResourceLimits};
+use wapc::{ModuleState, WebAssemblyEngineProvider};
+use wasi_common::sync::WasiCtxBuilder;
+use wasmtime::{Caller, Engine, InstancePre, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::policy_evaluator_builder::EpochDeadlines;
+use crate::runtimes::wapc::errors::{Result, WapcRuntimeError, WapcTrap};
+
+/// Namespace of the host functions imported by waPC guests
+const HOST_NAMESPACE: &str = "wapc";
+
+/// Function exported by waPC guests, used by the host to invoke the
+/// functions registered by the guest
+const GUEST_CALL_FN: &str = "__guest_call";
+
+/// Functions exported by waPC guests to initialize themselves, they are
+/// invoked when found. TinyGo guests export `_start`, the other ones
+/// `wapc_init`
+const INIT_FNS: [&str; 2] = ["_start", "wapc_init"];
+
+type ProviderResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub(crate) struct Context {
+    wasi_ctx: wasi_common::WasiCtx,
+    /// State of the waPC host, set by [`EngineProvider::init`]
+    host: Option<Arc<ModuleState>>,
+    /// Enforced only when the `StoreConfig` has resource limits
+    resource_limits: ResourceLimits,
+}
+
+/// Outcome of the last invocation of a guest function. It's shared by the
+/// `EngineProvider`, which is owned by the `wapc::WapcHost`, and by the
+/// `WapcStack`: the `wapc::WapcHost` turns the errors of the engine into
+/// strings
+#[derive(Clone, Default)]
+pub(crate) struct CallOutcome(Arc<Mutex<LastCall>>);
+
+#[derive(Clone, Default)]
+struct LastCall {
+    fuel_consumed: Option<u64>,
+    trap: Option<WapcTrap>,
+}
+
+impl CallOutcome {
+    /// Returns `None` when fuel consumption is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        self.lock().fuel_consumed
+    }
+
+    /// Returns `None` when the guest has not trapped
+    pub(crate) fn trap(&self) -> Option<WapcTrap> {
+        self.lock().trap.clone()
+    }
+
+    fn set(&self, fuel_consumed: Option<u64>, trap: Option<WapcTrap>) {
+        *self.lock() = LastCall {
+            fuel_consumed,
+            trap,
+        };
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LastCall> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Guest functions invoked by the host
+#[derive(Clone, Copy)]
+enum Invocation {
+    /// The initialization functions of the guest, see `INIT_FNS`
+    Init,
+    /// The function registered by the guest for a waPC operation
+    Call,
+}
+
+/// Configuration of the `wasmtime::Store` of the guest: the hooks are invoked
+/// when the store is created and before each invocation of the guest
+#[derive(Clone, Copy, Default)]
+pub(crate) struct StoreConfig {
+    pub(crate) epoch_deadlines: Option<EpochDeadlines>,
+    pub(crate) resource_limits: Option<ResourceLimits>,
+    pub(crate) fuel_limits: Option<FuelBudget>,
+}
+
+impl StoreConfig {
+    fn on_create(&self, store: &mut Store<Context>) {
+        if self.resource_limits.is_some() {
+            store.limiter(|c| &mut c.resource_limits);
+        }
+    }
+
+    fn on_invoke(
+        &self,
+        store: &mut Store<Context>,
+        invocation: Invocation,
+    ) -> wasmtime::Result<()> {
+        if let Some(deadlines) = self.epoch_deadlines {
+            store.set_epoch_deadline(match invocation {
+                Invocation::Init => deadlines.wapc_init,
+                Invocation::Call => deadlines.wapc_func,
+            });
+        }
+        if let Some(fuel) = self.fuel_limits {
+            store.set_fuel(Self::fuel_budget(fuel, invocation))?;
+        }
+        Ok(())
+    }
+
+    fn fuel_consumed(&self, store: &Store<Context>, invocation: Invocation) -> Option<u64> {
+        self.fuel_limits.and_then(|fuel| {
+            store
+                .get_fuel()
+                .ok()
+                .map(|remaining| Self::fuel_budget(fuel, invocation).saturating_sub(remaining))
+        })
+    }
+
+    fn fuel_budget(fuel: FuelBudget, invocation: Invocation) -> u64 {
+        match invocation {
+            Invocation::Init => fuel.init,
+            Invocation::Call => fuel.call,
+        }
+    }
+}
+
+/// The waPC engine provider used by all the waPC policies, built on top of
+/// wasmtime. Unlike the one provided by `wasmtime_provider`, it owns the
+/// `wasmtime::Store` of the guest, which is required to enforce resource
+/// limits and fuel budgets, and it reports the traps of the guest.
+#[derive(Clone)]
+pub(crate) struct EngineProviderPre {
+    engine: Engine,
+    instance_pre: InstancePre<Context>,
+    store_config: StoreConfig,
+}
+
+impl EngineProviderPre {
+    pub(crate) fn new(engine: Engine, module: Module, store_config: StoreConfig) -> Result<Self> {
+        let mut linker = Linker::<Context>::new(&engine);
+        // TinyGo guests require WASI
+        wasi_common::sync::add_to_linker(&mut linker, |c: &mut Context| &mut c.wasi_ctx)
+            .map_err(WapcRuntimeError::WasmLinker)?;
+        add_host_functions_to_linker(&mut linker).map_err(WapcRuntimeError::WasmLinker)?;
+
+        let instance_pre = linker
+            .instantiate_pre(&module)
+            .map_err(WapcRuntimeError::WasmInstantiate)?;
+        Ok(Self {
+            engine,
+            instance_pre,
+            store_config,
+        })
+    }
+
+    /// Create a new `EngineProvider` backed by a brand new `wasmtime::Store`.
+    /// The module is instantiated when the provider is given to a
+    /// `wapc::WapcHost`.
+    ///
+    /// The outcome of each invocation of a guest function is reported to
+    /// `call_outcome`
+    pub(crate) fn rehydrate(&self, call_outcome: CallOutcome) -> EngineProvider {
+        // the guest has no access to the stdio of the host, policies log via
+        // the waPC host callbacks
+        let ctx = Context {
+            wasi_ctx: WasiCtxBuilder::new().build(),
+            host: None,
+            resource_limits: self.store_config.resource_limits.unwrap_or_default(),
+        };
+        let mut store = Store::new(&self.engine, ctx);
+        self.store_config.on_create(&mut store);
+
+        EngineProvider {
+            store,
+            instance_pre: self.instance_pre.clone(),
+            store_config: self.store_config,
+            call_outcome,
+            guest_call_fn: None,
+        }
+    }
+}
+
+pub(crate) struct EngineProvider {
+    store: Store<Context>,
+    instance_pre: InstancePre<Context>,
+    store_config: StoreConfig,
+    call_outcome: CallOutcome,
+    /// Set by [`EngineProvider::init`]
+    guest_call_fn: Option<TypedFunc<(i32, i32), i32>>,
+}
+
+impl EngineProvider {
+    fn instantiate(&mut self) -> wasmtime::Result<TypedFunc<(i32, i32), i32>> {
+        self.store_config
+            .on_invoke(&mut self.store, Invocation::Init)?;
+        let instance = self.instance_pre.instantiate(&mut self.store)?;
+        for name in INIT_FNS {
+            let Some(init_fn) = instance.get_func(&mut self.store, name) else {
+                continue;
+            };
+            match init_fn
+                .typed::<(), ()>(&self.store)?
+                .call(&mut self.store, ())
+            {
+                Ok(_) => {}
+                // TinyGo guests might exit once initialized
+                Err(err)
+                    if err
+                        .downcast_ref::<wasi_common::I32Exit>()
+                        .is_some_and(|exit| exit.0 == 0) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        instance.get_typed_func::<(i32, i32), i32>(&mut self.store, GUEST_CALL_FN)
+    }
+}
+
+impl WebAssemblyEngineProvider for EngineProvider {
+    fn init(&mut self, host: Arc<ModuleState>) -> ProviderResult<()> {
+        self.store.data_mut().host = Some(host);
+        // a failed initialization prevents the creation of the waPC host,
+        // there's no outcome to report
+        let guest_call_fn = self.instantiate().map_err(|err| WapcTrap::from(&err))?;
+        self.guest_call_fn = Some(guest_call_fn);
+        Ok(())
+    }
+
+    fn call(&mut self, op_length: i32, msg_length: i32) -> ProviderResult<i32> {
+        let guest_call_fn = self
+            .guest_call_fn
+            .clone()
+            .ok_or("the waPC guest has not been initialized")?;
+        let result = self
+            .store_config
+            .on_invoke(&mut self.store, Invocation::Call)
+            .and_then(|_| guest_call_fn.call(&mut self.store, (op_length, msg_length)));
+
+        // the errors of the guest are stringified by the `wapc::WapcHost`,
+        // the trap is kept to be inspected by the `WapcStack`
+        let fuel_consumed = self
+            .store_config
+            .fuel_consumed(&self.store, Invocation::Call);
+        match result {
+            Ok(value) => {
+                self.call_outcome.set(fuel_consumed, None);
+                Ok(value)
+            }
+            Err(err) => {
+                let trap = WapcTrap::from(&err);
+                self.call_outcome.set(fuel_consumed, Some(trap.clone()));
+                Err(trap.into())
+            }
+        }
+    }
+
+    fn replace(&mut self, _module: &[u8]) -> ProviderResult<()> {
+        Err("replacing the module of a policy is not supported".into())
+    }
+}
+
+impl From<&wasmtime::Error> for WapcTrap {
+    fn from(error: &wasmtime::Error) -> Self {
+        match error.downcast_ref::<wasmtime::Trap>() {
+            Some(wasmtime::Trap::Interrupt) => return WapcTrap::DeadlineExceeded,
+            Some(wasmtime::Trap::OutOfFuel) => return WapcTrap::OutOfFuel,
+            _ => {}
+        }
+        if let Some(limit_error @ BurregoError::ResourceLimitExceeded(_)) =
+            error.downcast_ref::<BurregoError>()
+        {
+            return WapcTrap::ResourceLimitExceeded(limit_error.to_string());
+        }
+        WapcTrap::Other(format!("{error:#}"))
+    }
+}
+
+fn add_host_functions_to_linker(linker: &mut Linker<Context>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__guest_request",
+        |mut caller: Caller<'_, Context>, op_ptr: i32, ptr: i32| -> wasmtime::Result<()> {
+            let host = host(&caller)?;
+            if let Some(invocation) = host.get_guest_request() {
+                let memory = guest_memory(&mut caller)?;
+                memory.write(&mut caller, to_offset(ptr), &invocation.msg)?;
+                memory.write(
+                    &mut caller,
+                    to_offset(op_ptr),
+                    invocation.operation.as_bytes(),
+                )?;
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__console_log",
+        |mut caller: Caller<'_, Context>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let msg = read_string(&mut caller, ptr, len)?;
+            host(&caller)?.do_console_log(&msg);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__host_call",
+        |mut caller: Caller<'_, Context>,
+         bd_ptr: i32,
+         bd_len: i32,
+         ns_ptr: i32,
+         ns_len: i32,
+         op_ptr: i32,
+         op_len: i32,
+         ptr: i32,
+         len: i32|
+         -> wasmtime::Result<i32> {
+            let binding = read_string(&mut caller, bd_ptr, bd_len)?;
+            let namespace = read_string(&mut caller, ns_ptr, ns_len)?;
+            let operation = read_string(&mut caller, op_ptr, op_len)?;
+            let payload = read_bytes(&mut caller, ptr, len)?;
+
+            // the outcome of the host callback is kept by the waPC host, 0
+            // tells the guest to look for the error
+            Ok(host(&caller)?
+                .do_host_call(&binding, &namespace, &operation, &payload)
+                .unwrap_or(0))
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__host_response",
+        |mut caller: Caller<'_, Context>, ptr: i32| -> wasmtime::Result<()> {
+            if let Some(response) = host(&caller)?.get_host_response() {
+                let memory = guest_memory(&mut caller)?;
+                memory.write(&mut caller, to_offset(ptr), &response)?;
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__host_response_len",
+        |caller: Caller<'_, Context>| -> wasmtime::Result<i32> {
+            Ok(host(&caller)?
+                .get_host_response()
+                .map_or(0, |response| response.len() as i32))
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__guest_response",
+        |mut caller: Caller<'_, Context>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let response = read_bytes(&mut caller, ptr, len)?;
+            host(&caller)?.set_guest_response(response);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__guest_error",
+        |mut caller: Caller<'_, Context>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let error = read_string(&mut caller, ptr, len)?;
+            host(&caller)?.set_guest_error(error);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__host_error",
+        |mut caller: Caller<'_, Context>, ptr: i32| -> wasmtime::Result<()> {
+            if let Some(error) = host(&caller)?.get_host_error() {
+                let memory = guest_memory(&mut caller)?;
+                memory.write(&mut caller, to_offset(ptr), error.as_bytes())?;
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_NAMESPACE,
+        "__host_error_len",
+        |caller: Caller<'_, Context>| -> wasmtime::Result<i32> {
+            Ok(host(&caller)?
+                .get_host_error()
+                .map_or(0, |error| error.len() as i32))
+        },
+    )?;
+
+    Ok(())
+}
+
+fn host(caller: &Caller<'_, Context>) -> wasmtime::Result<Arc<ModuleState>> {
+    caller
+        .data()
+        .host
+        .clone()
+        .ok_or_else(|| wasmtime::Error::msg("the waPC host has not been initialized"))
+}
+
+fn guest_memory(caller: &mut Caller<'_, Context>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("cannot find the 'memory' export of the guest"))
+}
+
+/// Guest pointers are unsigned 32 bit integers
+fn to_offset(ptr: i32) -> usize {
+    ptr as u32 as usize
+}
+
+fn read_bytes(caller: &mut Caller<'_, Context>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let mut buffer = vec![0; to_offset(len)];
+    memory.read(&caller, to_offset(ptr), &mut buffer)?;
+    Ok(buffer)
+}
+
+fn read_string(caller: &mut Caller<'_, Context>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+    Ok(String::from_utf8(read_bytes(caller, ptr, len)?)?)
+}
@@ -20,9 +20,44 @@ pub enum WapcRuntimeError {
     #[error("cannot invoke 'protocol_version' waPC function : {0}")]
     InvokeProtocolVersion(#[source] wapc::errors::Error),
 
+    #[deprecated(
+        since = "0.19.0",
+        note = "the waPC policies are not run by `wasmtime_provider`, this error is never returned"
+    )]
     #[error("cannot build Wasmtime engine: {0}")]
     WasmtimeEngineBuilder(#[source] wasmtime_provider::errors::Error),
 
     #[error("cannot build Wapc host: {0}")]
     WapcHostBuilder(#[source] wapc::errors::Error),
+
+    #[error("cannot add to linker: {0}")]
+    WasmLinker(#[source] wasmtime::Error),
+
+    #[error("cannot instantiate module: {0}")]
+    WasmInstantiate(#[source] wasmtime::Error),
+}
+
+/// The reasons why the execution of a waPC guest has been aborted. Unlike
+/// the errors returned by the guest, they leave the guest in an unknown state
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WapcTrap {
+    #[error("guest code interrupted, execution deadline exceeded")]
+    DeadlineExceeded,
+
+    #[error("guest code interrupted, fuel exhausted")]
+    OutOfFuel,
+
+    #[error("{0}")]
+    ResourceLimitExceeded(String),
+
+    #[error("guest code trapped: {0}")]
+    Other(String),
+}
+
+impl WapcTrap {
+    /// Returns true when the guest has been interrupted by the host because
+    /// it exceeded its execution deadline, fuel budget or resource limits
+    pub fn is_interruption(&self) -> bool {
+        !matches!(self, WapcTrap::Other(_))
+    }
 }
@@ -1,4 +1,5 @@
 mod callback;
+mod engine_provider;
 pub mod errors;
 mod runtime;
 mod stack;
@@ -1,4 +1,4 @@
-use crate::runtimes::wapc::errors::{Result, WapcRuntimeError};
+use crate::runtimes::wapc::errors::{Result, WapcRuntimeError, WapcTrap};
 use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
@@ -12,13 +12,6 @@ use crate::runtimes::wapc::WapcStack;
 
 pub(crate) struct Runtime<'a>(pub(crate) &'a mut WapcStack);
 
-/// Error message returned by wasmtime_provider when the guest execution
-/// is interrupted because of epoch deadline is exceeded.
-///
-/// Unfortunately, wasmtime_provider doesn't return a typed error, hence we have
-/// to look for this text
-const WAPC_EPOCH_INTERRUPTION_ERR_MSG: &str = "guest code interrupted, execution deadline exceeded";
-
 impl<'a> Runtime<'a> {
     pub fn validate(
         &mut self,
@@ -83,15 +76,15 @@ impl<'a> Runtime<'a> {
             }
             Err(e) => {
                 error!(error = e.to_string().as_str(), "waPC communication error");
-                if e.to_string()
-                    .as_str()
-                    .contains(WAPC_EPOCH_INTERRUPTION_ERR_MSG)
-                {
+                let trap = self.0.last_trap();
+                // Exceeding the resource limits or the fuel budget interrupts
+                // the guest the same way, leaving it in an unknown state
+                if trap.as_ref().is_some_and(WapcTrap::is_interruption) {
                     // TL;DR: after code execution is interrupted because of an
                     // epoch deadline being reached, we have to reset the waPC host
                     // to ensure further invocations of the policy work as expected.
                     //
-                    // The waPC host is using the wasmtime_provider, which internally
+                    // The waPC host is using a wasmtime engine provider, which internally
                     // uses a wasmtime::Engine and a wasmtime::Store.
                     // The Store keeps track of the stateful data of the policy. When an
                     // epoch deadline is reached, wasmtime::Engine stops the execution of
@@ -123,7 +116,8 @@ impl<'a> Runtime<'a> {
                         info!("wapc_host reset performed after timeout protection was triggered");
                     }
                 }
-                AdmissionResponse::reject_internal_server_error(uid.to_string(), e.to_string())
+                let message = trap.map_or_else(|| e.to_string(), |trap| trap.to_string());
+                AdmissionResponse::reject_internal_server_error(uid.to_string(), message)
             }
         }
     }
@@ -147,7 +141,7 @@ impl<'a> Runtime<'a> {
         }
     }
 
-    pub fn protocol_version(&self) -> Result<ProtocolVersion> {
+    pub fn protocol_version(&mut self) -> Result<ProtocolVersion> {
         match self.0.call("protocol_version", &[0; 0]) {
             Ok(res) => ProtocolVersion::try_from(res.clone())
                 .map_err(|e| WapcRuntimeError::CreateProtocolVersion { res, error: e }),
@@ -160,22 +154,20 @@ impl<'a> Runtime<'a> {
 mod tests {
     use super::*;
     use crate::{
-        evaluation_context::EvaluationContext, runtimes::wapc::callback::new_host_callback,
-    };
-    use std::{
-        sync::{self, Arc},
-        thread, time,
+        evaluation_context::EvaluationContext, policy_evaluator_builder::EpochDeadlines,
+        runtimes::wapc::StackPre,
     };
+    use std::{sync, thread, time};
 
     #[test]
-    fn wapc_epoch_interrutpion_error_msg() {
-        // This unit test makes sure that waPC host error raised when a wasmtime
-        // epoch_interruption happens contains the WAPC_EPOCH_INTERRUPTION_ERR_MSG
-        // string
+    fn wapc_epoch_interruption_is_reported() {
+        // This unit test makes sure that the trap raised when a wasmtime
+        // epoch_interruption happens is reported by the waPC stack, and that
+        // the stack is reset afterwards
         //
-        // The unit test is a bit "low-level", meaning the target are the
-        // wapc libraries we consume, not the "high" level code we expose
-        // as part of policy-evaluator.
+        // The unit test is a bit "low-level", meaning the target is the waPC
+        // stack, not the "high" level code we expose as part of
+        // policy-evaluator.
         // This is done to make the whole testing process simple:
         // * No need to download a wasm module from a registry/commit a ~3Mb
         //   binary blob to this git repository
@@ -188,29 +180,26 @@ mod tests {
         let wat = include_bytes!("../../../tests/data/endless_wasm/wapc_endless_loop.wat");
         let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
 
-        // Create the wapc engine, the code will be interrupted after 10 ticks
-        // happen. We produce 1 tick every 10 milliseconds, see below
-        let wapc_engine_builder = wasmtime_provider::WasmtimeEngineProviderBuilder::new()
-            .engine(engine.clone())
-            .module(module)
-            .enable_epoch_interruptions(10, 10);
+        // The code will be interrupted after 10 ticks happen. We produce
+        // 1 tick every 10 milliseconds, see below
+        let stack_pre = StackPre::new(
+            engine.clone(),
+            module,
+            Some(EpochDeadlines {
+                wapc_init: 10,
+                wapc_func: 10,
+            }),
+            None,
+            None,
+        )
+        .expect("cannot create waPC stack pre");
 
         let eval_ctx = EvaluationContext {
             policy_id: "wapc_endless_loop".to_string(),
-            callback_channel: None,
-            ctx_aware_resources_allow_list: Default::default(),
+            ..Default::default()
         };
-
-        let eval_ctx = Arc::new(eval_ctx);
-
-        let wapc_engine = wapc_engine_builder
-            .build()
-            .expect("error creating wasmtime engine provider");
-        let host = wapc::WapcHost::new(
-            Box::new(wapc_engine),
-            Some(Box::new(new_host_callback(eval_ctx))),
-        )
-        .expect("cannot create waPC host");
+        let mut stack =
+            WapcStack::new_from_pre(&stack_pre, &eval_ctx).expect("cannot create waPC stack");
 
         // Create a lock to break the endless loop of the ticker thread
         let timer_lock = sync::Arc::new(sync::RwLock::new(false));
@@ -218,7 +207,7 @@ mod tests {
 
         // Start a thread that ticks the epoch timer of the wasmtime
         // engine. 1 tick equals 10 milliseconds
-        thread::spawn(move || {
+        let ticker = thread::spawn(move || {
             let interval = time::Duration::from_millis(10);
             loop {
                 thread::sleep(interval);
@@ -232,20 +221,23 @@ mod tests {
         // This triggers an endless loop inside of wasm
         // If the epoch_interruption doesn't work, this unit test
         // will never complete
-        let res = host.call("run", "".as_bytes());
+        let response = Runtime(&mut stack).validate(
+            &PolicySettings::default(),
+            &ValidateRequest::Raw(json!({"uid": "test"})),
+        );
 
         // Tell the ticker thread to quit
         {
             let mut w = timer_lock.write().unwrap();
             *w = true;
         }
-
-        // Ensure we got back an error from waPC, the error must
-        // contain the WAPC_EPOCH_INTERRUPTION_ERR_MSG string
-        let err = res.unwrap_err();
-        assert!(err
-            .to_string()
-            .as_str()
-            .contains(WAPC_EPOCH_INTERRUPTION_ERR_MSG));
+        ticker.join().unwrap();
+
+        assert!(!response.allowed);
+        assert_eq!(
+            Some(WapcTrap::DeadlineExceeded.to_string()),
+            response.status.and_then(|status| status.message)
+        );
+        assert!(stack.was_reset());
     }
 }
@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
 use crate::evaluation_context::EvaluationContext;
+use crate::runtimes::callback::HostCallbackRecorder;
 use crate::runtimes::wapc::{
     callback::new_host_callback,
-    errors::{Result, WapcRuntimeError},
+    engine_provider::CallOutcome,
+    errors::{Result, WapcRuntimeError, WapcTrap},
 };
 
 use super::StackPre;
@@ -12,17 +14,32 @@ pub(crate) struct WapcStack {
     wapc_host: wapc::WapcHost,
     stack_pre: StackPre,
     eval_ctx: Arc<EvaluationContext>,
+    callback_recorder: HostCallbackRecorder,
+    call_outcome: CallOutcome,
+    trapped: bool,
+    was_reset: bool,
 }
 
 impl WapcStack {
     pub(crate) fn new_from_pre(stack_pre: &StackPre, eval_ctx: &EvaluationContext) -> Result<Self> {
         let eval_ctx = Arc::new(eval_ctx.to_owned());
-        let wapc_host = Self::wapc_host_from_pre(stack_pre, eval_ctx.clone())?;
+        let callback_recorder = HostCallbackRecorder::default();
+        let call_outcome = CallOutcome::default();
+        let wapc_host = Self::wapc_host_from_pre(
+            stack_pre,
+            eval_ctx.clone(),
+            callback_recorder.clone(),
+            &call_outcome,
+        )?;
 
         Ok(Self {
             wapc_host,
             stack_pre: stack_pre.to_owned(),
             eval_ctx: eval_ctx.to_owned(),
+            callback_recorder,
+            call_outcome,
+            trapped: false,
+            was_reset: false,
         })
     }
 
@@ -34,32 +51,75 @@ impl WapcStack {
     /// variable.
     pub(crate) fn reset(&mut self) -> Result<()> {
         // Create a new wapc_host
-        let new_wapc_host = Self::wapc_host_from_pre(&self.stack_pre, self.eval_ctx.clone())?;
+        let new_wapc_host = Self::wapc_host_from_pre(
+            &self.stack_pre,
+            self.eval_ctx.clone(),
+            self.callback_recorder.clone(),
+            &self.call_outcome,
+        )?;
 
         self.wapc_host = new_wapc_host;
+        self.was_reset = true;
 
         Ok(())
     }
 
     /// Invokes the given waPC function using the provided payload
     pub(crate) fn call(
-        &self,
+        &mut self,
         op: &str,
         payload: &[u8],
     ) -> std::result::Result<Vec<u8>, wapc::errors::Error> {
-        self.wapc_host.call(op, payload)
+        self.was_reset = false;
+        let res = self.wapc_host.call(op, payload);
+        // The errors returned by the guest leave it in a consistent state,
+        // unlike the traps reported by the engine provider
+        self.trapped = res.is_err() && self.last_trap().is_some();
+        res
+    }
+
+    /// Returns true if the last invocation of a waPC function trapped
+    pub(crate) fn trapped(&self) -> bool {
+        self.trapped
+    }
+
+    /// Returns true if the waPC host has been reset after the last invocation
+    /// of a waPC function
+    pub(crate) fn was_reset(&self) -> bool {
+        self.was_reset
+    }
+
+    /// Amount of fuel consumed by the last invocation of a waPC function.
+    /// Returns `None` when fuel consumption is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        self.call_outcome.fuel_consumed()
+    }
+
+    /// The trap that aborted the last invocation of a waPC function, `None`
+    /// when the function completed or returned an error
+    pub(crate) fn last_trap(&self) -> Option<WapcTrap> {
+        self.call_outcome.trap()
+    }
+
+    /// The recorder tracking the host callbacks made by the policy
+    pub(crate) fn callback_recorder(&self) -> &HostCallbackRecorder {
+        &self.callback_recorder
     }
 
     /// Create a new `WapcHost` by rehydrating the `StackPre`. This is faster than creating the
-    /// `WasmtimeEngineProvider` from scratch
+    /// `EngineProvider` from scratch
     fn wapc_host_from_pre(
         pre: &StackPre,
         eval_ctx: Arc<EvaluationContext>,
+        callback_recorder: HostCallbackRecorder,
+        call_outcome: &CallOutcome,
     ) -> Result<wapc::WapcHost> {
-        let engine_provider = pre.rehydrate()?;
-        let wapc_host =
-            wapc::WapcHost::new(Box::new(engine_provider), Some(new_host_callback(eval_ctx)))
-                .map_err(WapcRuntimeError::WapcHostBuilder)?;
+        let engine_provider = pre.rehydrate(call_outcome);
+        let wapc_host = wapc::WapcHost::new(
+            Box::new(engine_provider),
+            Some(new_host_callback(eval_ctx, callback_recorder)),
+        )
+        .map_err(WapcRuntimeError::WapcHostBuilder)?;
         Ok(wapc_host)
     }
 }
@@ -1,12 +1,15 @@
 use wasmtime_provider::wasmtime;
 
 use crate::policy_evaluator_builder::EpochDeadlines;
-use crate::runtimes::wapc::errors::{Result, WapcRuntimeError};
+use crate::runtimes::wapc::engine_provider::{
+    CallOutcome, EngineProvider, EngineProviderPre, StoreConfig,
+};
+use crate::runtimes::wapc::errors::Result;
 
-/// Reduce allocation time of new `WasmtimeProviderEngine`, see the `rehydrate` method
+/// Reduce allocation time of new `EngineProvider`, see the `rehydrate` method
 #[derive(Clone)]
 pub(crate) struct StackPre {
-    engine_provider_pre: wasmtime_provider::WasmtimeEngineProviderPre,
+    engine_provider_pre: EngineProviderPre,
 }
 
 impl StackPre {
@@ -14,28 +17,27 @@ impl StackPre {
         engine: wasmtime::Engine,
         module: wasmtime::Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        resource_limits: Option<burrego::ResourceLimits>,
+        fuel_limits: Option<burrego::FuelBudget>,
     ) -> Result<Self> {
-        let mut builder = wasmtime_provider::WasmtimeEngineProviderBuilder::new()
-            .engine(engine)
-            .module(module);
-        if let Some(deadlines) = epoch_deadlines {
-            builder = builder.enable_epoch_interruptions(deadlines.wapc_init, deadlines.wapc_func);
-        }
-
-        let engine_provider_pre = builder
-            .build_pre()
-            .map_err(WapcRuntimeError::WasmtimeEngineBuilder)?;
+        let engine_provider_pre = EngineProviderPre::new(
+            engine,
+            module,
+            StoreConfig {
+                epoch_deadlines,
+                resource_limits,
+                fuel_limits,
+            },
+        )?;
         Ok(Self {
             engine_provider_pre,
         })
     }
 
-    /// Allocate a new `WasmtimeEngineProvider` instance by using a pre-allocated instance
-    pub(crate) fn rehydrate(&self) -> Result<wasmtime_provider::WasmtimeEngineProvider> {
-        let engine = self
-            .engine_provider_pre
-            .rehydrate()
-            .map_err(WapcRuntimeError::WasmtimeEngineBuilder)?;
-        Ok(engine)
+    /// Allocate a new waPC engine provider by using a pre-allocated instance.
+    /// The outcome of each invocation of a guest function, like the fuel it
+    /// consumed or the trap that aborted it, is reported to `call_outcome`
+    pub(crate) fn rehydrate(&self, call_outcome: &CallOutcome) -> EngineProvider {
+        self.engine_provider_pre.rehydrate(call_outcome.clone())
     }
 }
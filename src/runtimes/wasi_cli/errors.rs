@@ -11,12 +11,37 @@ pub enum WasiRuntimeError {
         error: wasmtime::Error,
     },
 
+    #[error("guest code interrupted, fuel exhausted")]
+    OutOfFuel,
+
+    #[error("cannot set fuel: {0}")]
+    Fuel(#[source] wasmtime::Error),
+
+    #[error("resource limit exceeded: {0}")]
+    ResourceLimitExceeded(String),
+
     #[error("cannot define host function '{name}': {error}")]
     WasmHostFuncDefinitionError { name: String, error: String },
 
+    #[error("the policy has been built with async support, it cannot be run synchronously from within a tokio current_thread runtime")]
+    SyncRunInsideCurrentThreadRuntime,
+
+    #[error("cannot build a tokio runtime to run the program: {0}")]
+    AsyncRuntime(#[source] std::io::Error),
+
     #[error("cannot find `_start` function inside of module: {0}")]
     WasmMissingStartFn(#[source] wasmtime::Error),
 
+    #[error("cannot create ProtocolVersion object from {stdout:?}: {error}")]
+    ProtocolVersion {
+        stdout: String,
+        #[source]
+        error: wasmtime::Error,
+    },
+
+    #[error("{name} exceeded the limit of {limit} bytes")]
+    OutputLimitExceeded { name: String, limit: usize },
+
     #[error("{name} pipe conversion error: {error}")]
     PipeConversion { name: String, error: String },
 
@@ -35,6 +60,19 @@ pub enum WasiRuntimeError {
     #[error("cannot build WasiCtxBuilder: {0}")]
     WasiCtxBuilder(#[source] wasi_common::StringArrayError),
 
+    #[error("cannot build WasiCtx: {0}")]
+    WasiCtx(#[source] wasi_common::Error),
+
+    #[error("invalid environment variable '{0}'")]
+    InvalidEnv(String),
+
+    #[error("cannot open preopened directory {host_path:?}: {error}")]
+    PreopenedDir {
+        host_path: std::path::PathBuf,
+        #[source]
+        error: std::io::Error,
+    },
+
     #[error("host_call: cannot convert bd to UTF8: {0}")]
     WasiMemOpToUtF8(#[source] std::str::Utf8Error),
 
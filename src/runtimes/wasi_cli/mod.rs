@@ -1,7 +1,9 @@
 pub mod errors;
+mod output;
 mod runtime;
 mod stack;
 mod stack_pre;
+mod wasi_ctx;
 mod wasi_pipe;
 
 pub(crate) use runtime::Runtime;
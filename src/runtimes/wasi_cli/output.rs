@@ -0,0 +1,274 @@
+use serde_json::json;
+use std::any::Any;
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+use wasi_common::file::{FdFlags, FileType};
+use wasi_common::WasiFile;
+
+use crate::evaluation_context::EvaluationContext;
+use crate::runtimes::callback::{log_policy_entry, HostCallbackRecorder};
+
+/// Forwards the lines written by the WASI program to the policy tracing
+/// subsystem, like the log entries produced via the `tracing` host callback
+pub(crate) struct PolicyLogSink {
+    pub(crate) eval_ctx: Arc<EvaluationContext>,
+    pub(crate) callback_recorder: HostCallbackRecorder,
+}
+
+impl PolicyLogSink {
+    fn log(&self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            return;
+        }
+
+        let entry = json!({
+            "level": "WARNING",
+            "message": line,
+            "stream": "stderr",
+        });
+        if let Ok(payload) = serde_json::to_vec(&entry) {
+            log_policy_entry(&payload, &self.eval_ctx, &self.callback_recorder);
+        }
+    }
+}
+
+/// Number of bytes of the lines already streamed to the [`PolicyLogSink`]
+/// that are kept by the [`OutputBuffer`], to give some context when the
+/// evaluation fails
+const STREAMED_EXCERPT_BYTES: usize = 4096;
+
+/// In-memory buffer holding the output of a WASI program.
+///
+/// Writes exceeding the size limit of the buffer fail. The caller is then
+/// expected to discard the outcome of the evaluation, see
+/// [`OutputBuffer::limit_exceeded`].
+///
+/// When the lines are streamed to a [`PolicyLogSink`], the buffer keeps only
+/// the line being written plus an excerpt of the last lines streamed.
+pub(crate) struct OutputBuffer {
+    contents: Vec<u8>,
+    limit: Option<usize>,
+    limit_exceeded: bool,
+    /// Number of bytes written by the program, including the ones that have
+    /// been dropped from `contents`
+    written: usize,
+    /// Number of bytes of `contents` that have already been streamed
+    streamed: usize,
+    log_sink: Option<PolicyLogSink>,
+}
+
+impl OutputBuffer {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            contents: Vec::new(),
+            limit,
+            limit_exceeded: false,
+            written: 0,
+            streamed: 0,
+            log_sink: None,
+        }
+    }
+
+    /// Stream each line written to the buffer to the given sink, as soon as
+    /// it's complete
+    pub(crate) fn stream_lines(mut self, log_sink: PolicyLogSink) -> Self {
+        self.log_sink = Some(log_sink);
+        self
+    }
+
+    /// Returns the size limit when it has been exceeded by the program
+    pub(crate) fn limit_exceeded(&self) -> Option<usize> {
+        self.limit.filter(|_| self.limit_exceeded)
+    }
+
+    /// Stream the last line, even if it's not complete, and return the
+    /// contents of the buffer. When the lines have been streamed, only the
+    /// last ones are returned
+    pub(crate) fn finish(self) -> Vec<u8> {
+        if let Some(sink) = &self.log_sink {
+            if self.streamed < self.contents.len() {
+                sink.log(&self.contents[self.streamed..]);
+            }
+        }
+
+        self.contents
+    }
+
+    fn stream_complete_lines(&mut self) {
+        let Some(sink) = &self.log_sink else {
+            return;
+        };
+
+        while let Some(end) = self.contents[self.streamed..]
+            .iter()
+            .position(|b| *b == b'\n')
+        {
+            sink.log(&self.contents[self.streamed..self.streamed + end]);
+            self.streamed += end + 1;
+        }
+
+        // drop the streamed lines, keeping only an excerpt of the last ones
+        if self.streamed > STREAMED_EXCERPT_BYTES {
+            let dropped = self.streamed - STREAMED_EXCERPT_BYTES;
+            self.contents.drain(..dropped);
+            self.streamed -= dropped;
+        }
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(limit) = self.limit {
+            if self.written + buf.len() > limit {
+                self.limit_exceeded = true;
+                return Err(io::Error::other(format!(
+                    "output size limit of {limit} bytes exceeded"
+                )));
+            }
+        }
+
+        self.written += buf.len();
+        self.contents.extend_from_slice(buf);
+        self.stream_complete_lines();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The write end of a pipe backed by an [`OutputBuffer`], used as stdout or
+/// stderr of a WASI program.
+///
+/// Exceeding the size limit of the buffer traps the program: a failed write
+/// could be ignored, or retried forever, by the program
+#[derive(Clone)]
+pub(crate) struct OutputPipe(Arc<RwLock<OutputBuffer>>);
+
+impl OutputPipe {
+    pub(crate) fn new(buffer: OutputBuffer) -> Self {
+        Self(Arc::new(RwLock::new(buffer)))
+    }
+
+    /// Take back the buffer. Fails with `Err(self)` when the pipe is still
+    /// referenced elsewhere, like by the `WasiCtx` of the program
+    pub(crate) fn try_into_inner(self) -> Result<OutputBuffer, Self> {
+        Arc::try_unwrap(self.0)
+            .map(|buffer| buffer.into_inner().unwrap_or_else(|e| e.into_inner()))
+            .map_err(Self)
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for OutputPipe {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, wasi_common::Error> {
+        Ok(FileType::Pipe)
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, wasi_common::Error> {
+        Ok(FdFlags::APPEND)
+    }
+
+    async fn write_vectored<'a>(
+        &self,
+        bufs: &[io::IoSlice<'a>],
+    ) -> Result<u64, wasi_common::Error> {
+        let mut buffer = self.0.write().unwrap_or_else(|e| e.into_inner());
+        let mut written: u64 = 0;
+        for buf in bufs {
+            buffer
+                .write_all(buf)
+                .map_err(|e| wasi_common::Error::trap(e.into()))?;
+            written += buf.len() as u64;
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::evaluation_context::PolicyLogCapture;
+
+    #[test]
+    fn output_buffer_enforces_limit() {
+        let mut buffer = OutputBuffer::new(Some(8));
+
+        assert!(buffer.write_all(b"hello").is_ok());
+        assert!(buffer.limit_exceeded().is_none());
+        assert!(buffer.write_all(b" world").is_err());
+        assert_eq!(Some(8), buffer.limit_exceeded());
+        assert_eq!(b"hello".to_vec(), buffer.finish());
+    }
+
+    #[test]
+    fn output_buffer_streams_lines() {
+        let eval_ctx = EvaluationContext {
+            policy_log_capture: Some(PolicyLogCapture {
+                max_entries: 10,
+                max_bytes: 1024,
+            }),
+            ..Default::default()
+        };
+        let callback_recorder = HostCallbackRecorder::default();
+        let mut buffer = OutputBuffer::new(None).stream_lines(PolicyLogSink {
+            eval_ctx: Arc::new(eval_ctx),
+            callback_recorder: callback_recorder.clone(),
+        });
+
+        buffer.write_all(b"first line\nsecond").unwrap();
+        assert_eq!(1, callback_recorder.policy_logs().entries.len());
+        buffer.write_all(b" line\r\n\nthird").unwrap();
+        assert_eq!(2, callback_recorder.policy_logs().entries.len());
+        assert_eq!(
+            b"first line\nsecond line\r\n\nthird".to_vec(),
+            buffer.finish()
+        );
+
+        assert_eq!(
+            vec![Some("first line"), Some("second line"), Some("third")],
+            callback_recorder
+                .policy_logs()
+                .entries
+                .iter()
+                .map(|e| e.message.as_deref())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn output_buffer_drops_streamed_lines() {
+        let eval_ctx = EvaluationContext::default();
+        let mut buffer =
+            OutputBuffer::new(Some(STREAMED_EXCERPT_BYTES * 4)).stream_lines(PolicyLogSink {
+                eval_ctx: Arc::new(eval_ctx),
+                callback_recorder: HostCallbackRecorder::default(),
+            });
+
+        let line = format!("{}\n", "a".repeat(99));
+        for _ in 0..(STREAMED_EXCERPT_BYTES * 3 / line.len()) {
+            buffer.write_all(line.as_bytes()).unwrap();
+        }
+        buffer.write_all(b"last").unwrap();
+
+        // the size limit applies to all the bytes written
+        assert!(buffer
+            .write_all("b".repeat(STREAMED_EXCERPT_BYTES * 2).as_bytes())
+            .is_err());
+        assert!(buffer.limit_exceeded().is_some());
+
+        let contents = buffer.finish();
+        assert!(contents.len() <= STREAMED_EXCERPT_BYTES + b"last".len());
+        assert!(contents.ends_with(format!("{line}last").as_bytes()));
+    }
+}
@@ -1,101 +1,156 @@
+use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
 use serde_json::json;
-use tracing::{error, warn};
+use tracing::error;
 
 use crate::admission_response::AdmissionResponse;
 use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::runtimes::wasi_cli::errors::WasiRuntimeError;
 use crate::runtimes::wasi_cli::stack::{RunResult, Stack};
 
-pub(crate) struct Runtime<'a>(pub(crate) &'a Stack);
+pub(crate) struct Runtime<'a>(pub(crate) &'a mut Stack);
+
+const VALIDATE_ARGS: [&str; 2] = ["policy.wasm", "validate"];
+const VALIDATE_SETTINGS_ARGS: [&str; 2] = ["policy.wasm", "validate-settings"];
+const PROTOCOL_VERSION_ARGS: [&str; 2] = ["policy.wasm", "protocol-version"];
 
 impl<'a> Runtime<'a> {
     pub fn validate(
-        &self,
+        &mut self,
         settings: &PolicySettings,
         request: &ValidateRequest,
     ) -> AdmissionResponse {
-        let validate_params = json!({
-            "request": request,
-            "settings": settings,
-        });
-
-        let input = match serde_json::to_vec(&validate_params) {
-            Ok(s) => s,
-            Err(e) => {
-                error!(
-                    error = e.to_string().as_str(),
-                    "cannot serialize validation params"
-                );
-                return AdmissionResponse::reject_internal_server_error(
-                    request.uid().to_string(),
-                    e.to_string(),
-                );
-            }
+        let input = match build_validate_input(settings, request) {
+            Ok(input) => input,
+            Err(response) => return response,
+        };
+
+        build_admission_response(request, self.0.run(&input, &VALIDATE_ARGS))
+    }
+
+    /// Asynchronous version of [`Runtime::validate`]
+    pub async fn validate_async(
+        &mut self,
+        settings: &PolicySettings,
+        request: &ValidateRequest,
+    ) -> AdmissionResponse {
+        let input = match build_validate_input(settings, request) {
+            Ok(input) => input,
+            Err(response) => return response,
         };
-        let args = ["policy.wasm", "validate"];
-
-        match self.0.run(&input, &args) {
-            Ok(RunResult { stdout, stderr }) => {
-                if !stderr.is_empty() {
-                    warn!(
-                        request = request.uid().to_string(),
-                        operation = "validate",
-                        "stderr: {:?}",
-                        stderr
+
+        build_admission_response(request, self.0.run_async(&input, &VALIDATE_ARGS).await)
+    }
+
+    pub fn validate_settings(&mut self, settings: String) -> SettingsValidationResponse {
+        build_settings_validation_response(self.0.run(settings.as_bytes(), &VALIDATE_SETTINGS_ARGS))
+    }
+
+    /// Run the `protocol-version` command of the policy. The version must be
+    /// written to stdout, either as a JSON string (`"v1"`) or as plain text
+    /// (`v1`)
+    pub fn protocol_version(&mut self) -> Result<ProtocolVersion, WasiRuntimeError> {
+        let RunResult { stdout } = self.0.run(&[], &PROTOCOL_VERSION_ARGS)?;
+
+        let version = stdout.trim();
+        let res = if version.starts_with('"') {
+            version.as_bytes().to_vec()
+        } else {
+            serde_json::to_vec(version).unwrap_or_default()
+        };
+        ProtocolVersion::try_from(res).map_err(|error| WasiRuntimeError::ProtocolVersion {
+            stdout: stdout.clone(),
+            error,
+        })
+    }
+
+    /// Asynchronous version of [`Runtime::validate_settings`]
+    pub async fn validate_settings_async(
+        &mut self,
+        settings: String,
+    ) -> SettingsValidationResponse {
+        build_settings_validation_response(
+            self.0
+                .run_async(settings.as_bytes(), &VALIDATE_SETTINGS_ARGS)
+                .await,
+        )
+    }
+}
+
+/// Build the input given to the `validate` command of the policy. An error
+/// response is returned when the input cannot be serialized
+fn build_validate_input(
+    settings: &PolicySettings,
+    request: &ValidateRequest,
+) -> Result<Vec<u8>, AdmissionResponse> {
+    let validate_params = json!({
+        "request": request,
+        "settings": settings,
+    });
+
+    serde_json::to_vec(&validate_params).map_err(|e| {
+        error!(
+            error = e.to_string().as_str(),
+            "cannot serialize validation params"
+        );
+        AdmissionResponse::reject_internal_server_error(request.uid().to_string(), e.to_string())
+    })
+}
+
+fn build_admission_response(
+    request: &ValidateRequest,
+    run_result: Result<RunResult, WasiRuntimeError>,
+) -> AdmissionResponse {
+    match run_result {
+        Ok(RunResult { stdout }) => {
+            match serde_json::from_slice::<PolicyValidationResponse>(stdout.as_bytes()) {
+                Ok(pvr) => {
+                    let req_json_value = serde_json::to_value(request)
+                        .expect("cannot convert request to json value");
+                    let req_obj = match request {
+                        ValidateRequest::Raw(_) => Some(&req_json_value),
+                        ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
+                    };
+
+                    AdmissionResponse::from_policy_validation_response(
+                        request.uid().to_string(),
+                        req_obj,
+                        &pvr,
                     )
                 }
-                match serde_json::from_slice::<PolicyValidationResponse>(stdout.as_bytes()) {
-                    Ok(pvr) => {
-                        let req_json_value = serde_json::to_value(request)
-                            .expect("cannot convert request to json value");
-                        let req_obj = match request {
-                            ValidateRequest::Raw(_) => Some(&req_json_value),
-                            ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
-                        };
-
-                        AdmissionResponse::from_policy_validation_response(
-                            request.uid().to_string(),
-                            req_obj,
-                            &pvr,
-                        )
-                    }
-                    .unwrap_or_else(|e| {
-                        AdmissionResponse::reject_internal_server_error(
-                            request.uid().to_string(),
-                            format!("Cannot convert policy validation response: {e}"),
-                        )
-                    }),
-                    Err(e) => AdmissionResponse::reject_internal_server_error(
+                .unwrap_or_else(|e| {
+                    AdmissionResponse::reject_internal_server_error(
                         request.uid().to_string(),
-                        format!("Cannot deserialize policy validation response: {e}"),
-                    ),
-                }
+                        format!("Cannot convert policy validation response: {e}"),
+                    )
+                }),
+                Err(e) => AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_string(),
+                    format!("Cannot deserialize policy validation response: {e}"),
+                ),
             }
-            Err(e) => AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500),
         }
+        Err(e) => AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500),
     }
+}
 
-    pub fn validate_settings(&self, settings: String) -> SettingsValidationResponse {
-        let args = ["policy.wasm", "validate-settings"];
-
-        match self.0.run(settings.as_bytes(), &args) {
-            Ok(RunResult { stdout, stderr }) => {
-                if !stderr.is_empty() {
-                    warn!(operation = "validate-settings", "stderr: {:?}", stderr)
-                }
-                serde_json::from_slice::<SettingsValidationResponse>(stdout.as_bytes())
-                    .unwrap_or_else(|e| SettingsValidationResponse {
-                        valid: false,
-                        message: Some(format!(
-                            "Cannot deserialize settings validation response: {e}"
-                        )),
-                    })
-            }
-            Err(e) => SettingsValidationResponse {
-                valid: false,
-                message: Some(e.to_string()),
-            },
-        }
+fn build_settings_validation_response(
+    run_result: Result<RunResult, WasiRuntimeError>,
+) -> SettingsValidationResponse {
+    match run_result {
+        Ok(RunResult { stdout }) => serde_json::from_slice::<SettingsValidationResponse>(
+            stdout.as_bytes(),
+        )
+        .unwrap_or_else(|e| SettingsValidationResponse {
+            valid: false,
+            message: Some(format!(
+                "Cannot deserialize settings validation response: {e}"
+            )),
+        }),
+        Err(e) => SettingsValidationResponse {
+            valid: false,
+            message: Some(e.to_string()),
+        },
     }
 }
@@ -1,13 +1,18 @@
-use std::io::Cursor;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tracing::debug;
-use wasi_common::pipe::{ReadPipe, WritePipe};
-use wasi_common::sync::WasiCtxBuilder;
+use wasi_common::pipe::ReadPipe;
 use wasi_common::WasiCtx;
 
+use burrego::{errors::BurregoError, ResourceLimits};
+
 use crate::evaluation_context::EvaluationContext;
+use crate::runtimes::callback::{block_on, HostCallbackRecorder};
 use crate::runtimes::wasi_cli::{
-    errors::WasiRuntimeError, stack_pre::StackPre, wasi_pipe::WasiPipe,
+    errors::WasiRuntimeError,
+    output::{OutputBuffer, OutputPipe, PolicyLogSink},
+    stack_pre::{is_instantiate_trap, StackPre},
+    wasi_pipe::WasiPipe,
 };
 
 const EXIT_SUCCESS: i32 = 0;
@@ -16,16 +21,22 @@ pub(crate) struct Context {
     pub(crate) wasi_ctx: WasiCtx,
     pub(crate) stdin_pipe: Arc<RwLock<WasiPipe>>,
     pub(crate) eval_ctx: Arc<EvaluationContext>,
+    pub(crate) callback_recorder: HostCallbackRecorder,
+    /// Enforced only when the `StackPre` has been created with resource limits
+    pub(crate) resource_limits: ResourceLimits,
 }
 
 pub(crate) struct Stack {
     stack_pre: StackPre,
     eval_ctx: Arc<EvaluationContext>,
+    callback_recorder: HostCallbackRecorder,
+    trapped: bool,
+    fuel_consumed: Option<u64>,
+    instantiation_time: Duration,
 }
 
 pub(crate) struct RunResult {
     pub stdout: String,
-    pub stderr: String,
 }
 
 impl Stack {
@@ -33,79 +44,261 @@ impl Stack {
         Self {
             stack_pre: stack_pre.to_owned(),
             eval_ctx: Arc::new(eval_ctx.to_owned()),
+            callback_recorder: HostCallbackRecorder::default(),
+            trapped: false,
+            fuel_consumed: None,
+            instantiation_time: Duration::ZERO,
         }
     }
 
+    /// Returns true when the WASI program can be run in an asynchronous fashion
+    pub(crate) fn async_support(&self) -> bool {
+        self.stack_pre.async_support()
+    }
+
+    /// Returns true if the last run of the WASI program has been aborted by a
+    /// trap. Exiting with a non-zero exit code is not considered a trap
+    pub(crate) fn trapped(&self) -> bool {
+        self.trapped
+    }
+
+    /// Time spent instantiating the WASI program during the last run
+    pub(crate) fn instantiation_time(&self) -> Duration {
+        self.instantiation_time
+    }
+
+    /// The recorder tracking the host callbacks made by the WASI program
+    pub(crate) fn callback_recorder(&self) -> &HostCallbackRecorder {
+        &self.callback_recorder
+    }
+
+    /// Amount of fuel consumed by the last run of the WASI program. Returns
+    /// `None` when fuel consumption is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        self.fuel_consumed
+    }
+
     /// Run a WASI program with the given input and args
+    ///
+    /// When the stack has been created with async support, the current thread
+    /// is blocked until the asynchronous evaluation is done. Inside of a tokio
+    /// multi-threaded runtime this is done using
+    /// [`tokio::task::block_in_place`], so that the other tasks of the worker,
+    /// including the ones serving the host callbacks of the program, keep
+    /// running. Blocking the only worker of a `current_thread` runtime would
+    /// stall them, hence the run is refused: [`Stack::run_async`] must be
+    /// used instead.
     pub(crate) fn run(
-        &self,
+        &mut self,
         input: &[u8],
         args: &[&str],
     ) -> std::result::Result<RunResult, WasiRuntimeError> {
-        let stdout_pipe = WritePipe::new_in_memory();
-        let stderr_pipe = WritePipe::new_in_memory();
-        let stdin_pipe: Arc<RwLock<WasiPipe>> = Arc::new(RwLock::new(WasiPipe::new(input)));
+        if self.stack_pre.async_support() {
+            return match tokio::runtime::Handle::try_current() {
+                Ok(handle) => match handle.runtime_flavor() {
+                    tokio::runtime::RuntimeFlavor::MultiThread => {
+                        tokio::task::block_in_place(|| block_on(self.run_async(input, args)))
+                            .map_err(WasiRuntimeError::AsyncRuntime)?
+                    }
+                    _ => Err(WasiRuntimeError::SyncRunInsideCurrentThreadRuntime),
+                },
+                Err(_) => {
+                    block_on(self.run_async(input, args)).map_err(WasiRuntimeError::AsyncRuntime)?
+                }
+            };
+        }
 
-        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        self.trapped = false;
+        self.fuel_consumed = None;
+        let (ctx, output_pipes) = self.build_context(input, args)?;
 
-        let wasi_ctx = WasiCtxBuilder::new()
-            .args(&args)
-            .map_err(WasiRuntimeError::WasiCtxBuilder)?
-            .stdin(Box::new(ReadPipe::from_shared(stdin_pipe.clone())))
-            .stdout(Box::new(stdout_pipe.clone()))
-            .stderr(Box::new(stderr_pipe.clone()))
-            .build();
-        let ctx = Context {
-            wasi_ctx,
-            stdin_pipe,
-            eval_ctx: self.eval_ctx.clone(),
+        let started_at = Instant::now();
+        let mut store = self.stack_pre.build_store(ctx)?;
+        let instance = match self.stack_pre.rehydrate(&mut store) {
+            Ok(instance) => instance,
+            Err(err) => return Err(self.instantiation_failed(&store, err)),
         };
-
-        let mut store = self.stack_pre.build_store(ctx);
-        let instance = self.stack_pre.rehydrate(&mut store)?;
+        self.instantiation_time = started_at.elapsed();
+        self.stack_pre.set_call_fuel(&mut store)?;
         let start_fn = instance
             .get_typed_func::<(), ()>(&mut store, "_start")
             .map_err(WasiRuntimeError::WasmMissingStartFn)?;
         let evaluation_result = start_fn.call(&mut store, ());
 
+        self.fuel_consumed = self.stack_pre.fuel_consumed(&store);
+
         // Dropping the store, this is no longer needed, plus it's keeping
-        // references to the WritePipe(s) that we need exclusive access to.
+        // references to the OutputPipe(s) that we need exclusive access to.
         drop(store);
 
-        let stderr = pipe_to_string("stderr", stderr_pipe)?.trim().to_string();
+        self.trapped = is_trap(&evaluation_result);
+        build_run_result(evaluation_result, output_pipes)
+    }
+
+    /// Run a WASI program with the given input and args. The program yields
+    /// while waiting for the outcome of its host callbacks.
+    ///
+    /// Requires the stack to be created with async support.
+    pub(crate) async fn run_async(
+        &mut self,
+        input: &[u8],
+        args: &[&str],
+    ) -> std::result::Result<RunResult, WasiRuntimeError> {
+        self.trapped = false;
+        self.fuel_consumed = None;
+        let (ctx, output_pipes) = self.build_context(input, args)?;
+
+        let started_at = Instant::now();
+        let mut store = self.stack_pre.build_store(ctx)?;
+        let instance = match self.stack_pre.rehydrate_async(&mut store).await {
+            Ok(instance) => instance,
+            Err(err) => return Err(self.instantiation_failed(&store, err)),
+        };
+        self.instantiation_time = started_at.elapsed();
+        self.stack_pre.set_call_fuel(&mut store)?;
+        let start_fn = instance
+            .get_typed_func::<(), ()>(&mut store, "_start")
+            .map_err(WasiRuntimeError::WasmMissingStartFn)?;
+        let evaluation_result = start_fn.call_async(&mut store, ()).await;
 
-        if let Err(err) = evaluation_result {
-            if let Some(exit_error) = err.downcast_ref::<wasi_common::I32Exit>() {
-                if exit_error.0 == EXIT_SUCCESS {
-                    let stdout = pipe_to_string("stdout", stdout_pipe)?;
-                    return Ok(RunResult { stdout, stderr });
-                } else {
-                    debug!(
-                        "WASI program exited with error code: {}, error: {}",
-                        exit_error.0, stderr
-                    );
+        self.fuel_consumed = self.stack_pre.fuel_consumed(&store);
 
-                    return Err(WasiRuntimeError::WasiEvaluation { stderr, error: err });
-                }
+        // Dropping the store, this is no longer needed, plus it's keeping
+        // references to the OutputPipe(s) that we need exclusive access to.
+        drop(store);
+
+        self.trapped = is_trap(&evaluation_result);
+        build_run_result(evaluation_result, output_pipes)
+    }
+
+    /// Record the outcome of a failed instantiation of the WASI program
+    fn instantiation_failed(
+        &mut self,
+        store: &wasmtime::Store<Context>,
+        error: WasiRuntimeError,
+    ) -> WasiRuntimeError {
+        self.trapped = is_instantiate_trap(&error);
+        self.fuel_consumed = self.stack_pre.init_fuel_consumed(store);
+        error
+    }
+
+    /// Build the `Context` to be used by the WASI program. The returned
+    /// pipes hold the stdout and stderr of the program
+    fn build_context(
+        &self,
+        input: &[u8],
+        args: &[&str],
+    ) -> std::result::Result<(Context, OutputPipes), WasiRuntimeError> {
+        let wasi_resources = self.stack_pre.wasi_resources();
+        let stdout_pipe = OutputPipe::new(OutputBuffer::new(wasi_resources.max_stdout_bytes()));
+        // stderr is streamed line by line to the policy tracing subsystem
+        let stderr_pipe = OutputPipe::new(
+            OutputBuffer::new(wasi_resources.max_stderr_bytes()).stream_lines(PolicyLogSink {
+                eval_ctx: self.eval_ctx.clone(),
+                callback_recorder: self.callback_recorder.clone(),
+            }),
+        );
+        let stdin_pipe: Arc<RwLock<WasiPipe>> = Arc::new(RwLock::new(WasiPipe::new(input)));
+
+        let wasi_ctx = self.stack_pre.build_wasi_ctx(args)?;
+        wasi_ctx.set_stdin(Box::new(ReadPipe::from_shared(stdin_pipe.clone())));
+        wasi_ctx.set_stdout(Box::new(stdout_pipe.clone()));
+        wasi_ctx.set_stderr(Box::new(stderr_pipe.clone()));
+        let ctx = Context {
+            wasi_ctx,
+            stdin_pipe,
+            eval_ctx: self.eval_ctx.clone(),
+            callback_recorder: self.callback_recorder.clone(),
+            resource_limits: ResourceLimits::default(),
+        };
+
+        Ok((
+            ctx,
+            OutputPipes {
+                stdout: stdout_pipe,
+                stderr: stderr_pipe,
+            },
+        ))
+    }
+}
+
+/// The pipes used to capture the output of a WASI program
+struct OutputPipes {
+    stdout: OutputPipe,
+    stderr: OutputPipe,
+}
+
+/// Returns true when the `_start` function of a WASI program has been
+/// aborted without invoking `proc_exit`
+fn is_trap(evaluation_result: &wasmtime::Result<()>) -> bool {
+    match evaluation_result {
+        Ok(_) => false,
+        Err(err) => err.downcast_ref::<wasi_common::I32Exit>().is_none(),
+    }
+}
+
+/// Process the outcome of the `_start` function of a WASI program.
+///
+/// Exceeding the size limit of stdout or stderr makes the evaluation fail,
+/// regardless of how the program terminated
+fn build_run_result(
+    evaluation_result: wasmtime::Result<()>,
+    output_pipes: OutputPipes,
+) -> std::result::Result<RunResult, WasiRuntimeError> {
+    let stderr = pipe_to_string("stderr", output_pipes.stderr)?
+        .trim()
+        .to_string();
+    let stdout = pipe_to_string("stdout", output_pipes.stdout)?;
+
+    if let Err(err) = evaluation_result {
+        if let Some(exit_error) = err.downcast_ref::<wasi_common::I32Exit>() {
+            if exit_error.0 == EXIT_SUCCESS {
+                return Ok(RunResult { stdout });
+            } else {
+                debug!(
+                    "WASI program exited with error code: {}, error: {}",
+                    exit_error.0, stderr
+                );
+
+                return Err(WasiRuntimeError::WasiEvaluation { stderr, error: err });
             }
+        }
 
-            debug!("WASI program exited with error: {}", stderr);
-            return Err(WasiRuntimeError::WasiEvaluation { stderr, error: err });
+        if matches!(
+            err.downcast_ref::<wasmtime::Trap>(),
+            Some(wasmtime::Trap::OutOfFuel)
+        ) {
+            debug!("WASI program exhausted its fuel: {}", stderr);
+            return Err(WasiRuntimeError::OutOfFuel);
         }
 
-        let stdout = pipe_to_string("stdout", stdout_pipe)?;
-        Ok(RunResult { stdout, stderr })
+        if let Some(BurregoError::ResourceLimitExceeded(reason)) =
+            err.downcast_ref::<BurregoError>()
+        {
+            debug!("WASI program exceeded its resource limits: {}", reason);
+            return Err(WasiRuntimeError::ResourceLimitExceeded(reason.to_owned()));
+        }
+
+        debug!("WASI program exited with error: {}", stderr);
+        return Err(WasiRuntimeError::WasiEvaluation { stderr, error: err });
     }
+
+    Ok(RunResult { stdout })
 }
 
-fn pipe_to_string(
-    name: &str,
-    pipe: WritePipe<Cursor<Vec<u8>>>,
-) -> std::result::Result<String, WasiRuntimeError> {
+/// Take back the buffer of the pipe, ensuring its size limit has not been
+/// exceeded
+fn pipe_to_string(name: &str, pipe: OutputPipe) -> std::result::Result<String, WasiRuntimeError> {
     match pipe.try_into_inner() {
-        Ok(cursor) => {
-            let buf = cursor.into_inner();
-            String::from_utf8(buf).map_err(|e| WasiRuntimeError::PipeConversion {
+        Ok(buffer) => {
+            if let Some(limit) = buffer.limit_exceeded() {
+                debug!("WASI program exceeded the size limit of {}", name);
+                return Err(WasiRuntimeError::OutputLimitExceeded {
+                    name: name.to_string(),
+                    limit,
+                });
+            }
+            String::from_utf8(buffer.finish()).map_err(|e| WasiRuntimeError::PipeConversion {
                 name: name.to_string(),
                 error: format!("Cannot convert buffer to UTF8 string: {e}"),
             })
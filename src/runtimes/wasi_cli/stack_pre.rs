@@ -1,11 +1,16 @@
 use std::io::Write;
+use std::sync::Arc;
 
+use burrego::{errors::BurregoError, FuelBudget, ResourceLimits};
 use wasmtime::{AsContext, Engine, InstancePre, Linker, Memory, Module, StoreContext};
 
 use crate::runtimes::wasi_cli::errors::{Result, WasiRuntimeError};
 
-use crate::policy_evaluator_builder::EpochDeadlines;
-use crate::runtimes::{callback::host_callback, wasi_cli::stack::Context};
+use crate::policy_evaluator_builder::{EpochDeadlines, WasiConfig};
+use crate::runtimes::{
+    callback::{host_callback, host_callback_async},
+    wasi_cli::{stack::Context, wasi_ctx::WasiResources},
+};
 
 /// Reduce the allocation time of a Wasi Stack. This is done by leveraging `wasmtime::InstancePre`.
 #[derive(Clone)]
@@ -13,18 +18,46 @@ pub(crate) struct StackPre {
     engine: Engine,
     instance_pre: InstancePre<Context>,
     epoch_deadlines: Option<EpochDeadlines>,
+    async_support: bool,
+    resource_limits: Option<ResourceLimits>,
+    fuel_limits: Option<FuelBudget>,
+    wasi_resources: Arc<WasiResources>,
 }
 
 impl StackPre {
+    /// Create a new `StackPre`.
+    ///
+    /// When `async_support` is set, the given `wasmtime::Engine` must have been
+    /// created with the [`async_support`](wasmtime::Config::async_support)
+    /// feature enabled. In this case the host callbacks do not block the current
+    /// thread while waiting for the response.
+    ///
+    /// When `resource_limits` are given, they are enforced on each store
+    /// created by [`StackPre::build_store`]. The same applies to the
+    /// `fuel_limits`, which require the `wasmtime::Engine` to be created with
+    /// the [`consume_fuel`](wasmtime::Config::consume_fuel) feature enabled.
+    ///
+    /// The `wasi_config` is validated straight away, the host directories
+    /// are opened once and shared by all the stacks.
     pub(crate) fn new(
         engine: Engine,
         module: Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        async_support: bool,
+        resource_limits: Option<ResourceLimits>,
+        fuel_limits: Option<FuelBudget>,
+        wasi_config: WasiConfig,
     ) -> Result<Self> {
+        let wasi_resources = Arc::new(WasiResources::new(wasi_config)?);
+
         let mut linker = Linker::<Context>::new(&engine);
         wasi_common::sync::add_to_linker(&mut linker, |c: &mut Context| &mut c.wasi_ctx)
             .map_err(WasiRuntimeError::WasmLinkerError)?;
-        add_host_call_to_linker(&mut linker)?;
+        if async_support {
+            add_async_host_call_to_linker(&mut linker)?;
+        } else {
+            add_host_call_to_linker(&mut linker)?;
+        }
 
         let instance_pre = linker
             .instantiate_pre(&module)
@@ -33,17 +66,81 @@ impl StackPre {
             engine,
             instance_pre,
             epoch_deadlines,
+            async_support,
+            resource_limits,
+            fuel_limits,
+            wasi_resources,
         })
     }
 
-    /// Create a brand new `wasmtime::Store` to be used during an evaluation
-    pub(crate) fn build_store(&self, ctx: Context) -> wasmtime::Store<Context> {
+    /// Create the `WasiCtx` of a new run of the WASI program. Only the stdio
+    /// of the program has to be set
+    pub(crate) fn build_wasi_ctx(&self, args: &[&str]) -> Result<wasi_common::WasiCtx> {
+        self.wasi_resources.build_ctx(args)
+    }
+
+    /// The WASI resources shared by all the runs of the WASI program
+    pub(crate) fn wasi_resources(&self) -> &WasiResources {
+        &self.wasi_resources
+    }
+
+    /// Returns true when the stack has to be used in an asynchronous fashion
+    pub(crate) fn async_support(&self) -> bool {
+        self.async_support
+    }
+
+    /// Create a brand new `wasmtime::Store` to be used during an evaluation.
+    ///
+    /// When fuel consumption is enabled, the store is given the fuel
+    /// available to the instantiation of the module
+    pub(crate) fn build_store(&self, mut ctx: Context) -> Result<wasmtime::Store<Context>> {
+        if let Some(limits) = self.resource_limits {
+            ctx.resource_limits = limits;
+        }
+
         let mut store = wasmtime::Store::new(&self.engine, ctx);
         if let Some(deadline) = self.epoch_deadlines {
             store.set_epoch_deadline(deadline.wapc_func);
         }
+        if self.resource_limits.is_some() {
+            store.limiter(|c| &mut c.resource_limits);
+        }
+        if let Some(fuel) = self.fuel_limits {
+            store.set_fuel(fuel.init).map_err(WasiRuntimeError::Fuel)?;
+        }
+
+        Ok(store)
+    }
+
+    /// Refill the store with the fuel available to the invocation of the
+    /// `_start` function. This is a no-op when fuel consumption is not enabled
+    pub(crate) fn set_call_fuel(&self, store: &mut wasmtime::Store<Context>) -> Result<()> {
+        if let Some(fuel) = self.fuel_limits {
+            store.set_fuel(fuel.call).map_err(WasiRuntimeError::Fuel)?;
+        }
+        Ok(())
+    }
+
+    /// Amount of fuel consumed by the invocation of the `_start` function.
+    /// Returns `None` when fuel consumption is not enabled
+    pub(crate) fn fuel_consumed(&self, store: &wasmtime::Store<Context>) -> Option<u64> {
+        self.fuel_limits.and_then(|fuel| {
+            store
+                .get_fuel()
+                .ok()
+                .map(|remaining| fuel.call.saturating_sub(remaining))
+        })
+    }
 
-        store
+    /// Amount of fuel consumed by the instantiation. Returns `None` when fuel
+    /// consumption is not enabled
+    pub(crate) fn init_fuel_consumed(&self, store: &wasmtime::Store<Context>) -> Option<u64> {
+        self.fuel_limits.and_then(|fuel| {
+            store
+                .get_fuel()
+                .ok()
+                .map(|remaining| fuel.init.saturating_sub(remaining))
+        })
     }
 
     /// Allocate a new `wasmtime::Instance` that is bound to the given `wasmtime::Store`.
@@ -55,8 +152,57 @@ impl StackPre {
     ) -> Result<wasmtime::Instance> {
         self.instance_pre
             .instantiate(store)
-            .map_err(WasiRuntimeError::WasmInstantiate)
+            .map_err(map_instantiate_error)
+    }
+
+    /// Asynchronous version of [`StackPre::rehydrate`], must be used when
+    /// async support is enabled
+    pub(crate) async fn rehydrate_async(
+        &self,
+        store: &mut wasmtime::Store<Context>,
+    ) -> Result<wasmtime::Instance> {
+        self.instance_pre
+            .instantiate_async(store)
+            .await
+            .map_err(map_instantiate_error)
+    }
+}
+
+/// Returns true when the instantiation has been aborted by a trap, including
+/// the ones raised when the fuel or the resource limits are exhausted
+pub(crate) fn is_instantiate_trap(error: &WasiRuntimeError) -> bool {
+    matches!(
+        error,
+        WasiRuntimeError::OutOfFuel
+            | WasiRuntimeError::ResourceLimitExceeded(_)
+            | WasiRuntimeError::WasmInstantiate(_)
+    )
+}
+
+/// The instantiation of the module runs its `start` function, which can
+/// exhaust the fuel given to the initialization of the policy. The memories
+/// and tables created by the instantiation count towards the resource limits
+fn map_instantiate_error(error: wasmtime::Error) -> WasiRuntimeError {
+    if let Some(BurregoError::ResourceLimitExceeded(reason)) = error.downcast_ref::<BurregoError>()
+    {
+        return WasiRuntimeError::ResourceLimitExceeded(reason.to_owned());
     }
+    match error.downcast_ref::<wasmtime::Trap>() {
+        Some(wasmtime::Trap::OutOfFuel) => WasiRuntimeError::OutOfFuel,
+        _ => WasiRuntimeError::WasmInstantiate(error),
+    }
+}
+
+/// The pointers and lengths given by the guest to the `host.call` function:
+/// binding, namespace, operation and payload
+type HostCallPtrs = (i32, i32, i32, i32, i32, i32, i32, i32);
+
+/// The parameters of a `host.call` invocation, read from the guest memory
+struct HostCallParams {
+    binding: String,
+    namespace: String,
+    operation: String,
+    payload: Vec<u8>,
 }
 
 fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()> {
@@ -73,41 +219,61 @@ fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()>
              op_len: i32,
              ptr: i32,
              len: i32| {
-                let memory_export = caller
-                    .get_export("memory")
-                    .ok_or_else(|| WasiRuntimeError::WasiMemExport)?;
-                let memory = memory_export
-                    .into_memory()
-                    .ok_or_else(|| WasiRuntimeError::WasiMemExportCannotConvert)?;
-
-                let stdin = caller.data().stdin_pipe.as_ref();
-
-                let vec = get_vec_from_memory(caller.as_context(), memory, ptr, len);
-                let bd_vec = get_vec_from_memory(caller.as_context(), memory, bd_ptr, bd_len);
-                let bd = std::str::from_utf8(&bd_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
-                let ns_vec = get_vec_from_memory(caller.as_context(), memory, ns_ptr, ns_len);
-                let ns = std::str::from_utf8(&ns_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
-                let op_vec = get_vec_from_memory(caller.as_context(), memory, op_ptr, op_len);
-                let op = std::str::from_utf8(&op_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
-
-                let host_callback_response =
-                    host_callback(bd, ns, op, &vec, &caller.data().eval_ctx);
-
-                // return 1 if the host callback failed, 0 otherwise
-                let func_return_value = host_callback_response.is_err() as i32;
-
-                let response_msg = match host_callback_response {
-                    Ok(r) => r,
-                    Err(e) => e.to_string().as_bytes().to_owned(),
-                };
-
-                let mut stdin_pipe = stdin
-                    .write()
-                    .map_err(|_| WasiRuntimeError::WasiWriteAccessStdin())?;
-                let _ = stdin_pipe
-                    .write(&response_msg)
-                    .map_err(|_| WasiRuntimeError::WasiCannotWriteStdin())?;
-                Ok(func_return_value)
+                let params = read_host_call_params(
+                    &mut caller,
+                    (bd_ptr, bd_len, ns_ptr, ns_len, op_ptr, op_len, ptr, len),
+                )?;
+
+                let host_callback_response = host_callback(
+                    &params.binding,
+                    &params.namespace,
+                    &params.operation,
+                    &params.payload,
+                    &caller.data().eval_ctx,
+                    &caller.data().callback_recorder,
+                );
+
+                Ok(write_host_call_response(
+                    caller.data(),
+                    host_callback_response,
+                )?)
+            },
+        )
+        .map_err(|e| WasiRuntimeError::WasmHostFuncDefinitionError {
+            name: "host.call".to_string(),
+            error: e.to_string(),
+        })?;
+    Ok(())
+}
+
+/// Same as `add_host_call_to_linker`, but the host function yields while
+/// waiting for the response of the host callback
+fn add_async_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()> {
+    linker
+        .func_wrap_async(
+            "host",
+            "call",
+            |mut caller: wasmtime::Caller<'_, Context>, ptrs: HostCallPtrs| {
+                Box::new(async move {
+                    let params = read_host_call_params(&mut caller, ptrs)?;
+                    let eval_ctx = caller.data().eval_ctx.clone();
+                    let callback_recorder = caller.data().callback_recorder.clone();
+
+                    let host_callback_response = host_callback_async(
+                        &params.binding,
+                        &params.namespace,
+                        &params.operation,
+                        &params.payload,
+                        &eval_ctx,
+                        &callback_recorder,
+                    )
+                    .await;
+
+                    Ok::<_, wasmtime::Error>(write_host_call_response(
+                        caller.data(),
+                        host_callback_response,
+                    )?)
+                })
             },
         )
         .map_err(|e| WasiRuntimeError::WasmHostFuncDefinitionError {
@@ -117,6 +283,58 @@ fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()>
     Ok(())
 }
 
+/// Read the parameters of the `host.call` function from the guest memory
+fn read_host_call_params(
+    caller: &mut wasmtime::Caller<'_, Context>,
+    (bd_ptr, bd_len, ns_ptr, ns_len, op_ptr, op_len, ptr, len): HostCallPtrs,
+) -> Result<HostCallParams> {
+    let memory_export = caller
+        .get_export("memory")
+        .ok_or_else(|| WasiRuntimeError::WasiMemExport)?;
+    let memory = memory_export
+        .into_memory()
+        .ok_or_else(|| WasiRuntimeError::WasiMemExportCannotConvert)?;
+
+    let payload = get_vec_from_memory(caller.as_context(), memory, ptr, len);
+    let bd_vec = get_vec_from_memory(caller.as_context(), memory, bd_ptr, bd_len);
+    let binding = std::str::from_utf8(&bd_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
+    let ns_vec = get_vec_from_memory(caller.as_context(), memory, ns_ptr, ns_len);
+    let namespace = std::str::from_utf8(&ns_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
+    let op_vec = get_vec_from_memory(caller.as_context(), memory, op_ptr, op_len);
+    let operation = std::str::from_utf8(&op_vec).map_err(WasiRuntimeError::WasiMemOpToUtF8)?;
+
+    Ok(HostCallParams {
+        binding: binding.to_owned(),
+        namespace: namespace.to_owned(),
+        operation: operation.to_owned(),
+        payload,
+    })
+}
+
+/// Write the outcome of the host callback to the STDIN of the guest.
+/// Returns the value to be given back to the guest: 1 if the host callback
+/// failed, 0 otherwise
+fn write_host_call_response(
+    ctx: &Context,
+    host_callback_response: std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<i32> {
+    let func_return_value = host_callback_response.is_err() as i32;
+
+    let response_msg = match host_callback_response {
+        Ok(r) => r,
+        Err(e) => e.to_string().as_bytes().to_owned(),
+    };
+
+    let mut stdin_pipe = ctx
+        .stdin_pipe
+        .write()
+        .map_err(|_| WasiRuntimeError::WasiWriteAccessStdin())?;
+    let _ = stdin_pipe
+        .write(&response_msg)
+        .map_err(|_| WasiRuntimeError::WasiCannotWriteStdin())?;
+    Ok(func_return_value)
+}
+
 fn get_vec_from_memory<'a, T: 'a>(
     store: impl Into<StoreContext<'a, T>>,
     mem: Memory,
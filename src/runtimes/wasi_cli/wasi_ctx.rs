@@ -0,0 +1,291 @@
+use std::any::Any;
+use std::path::PathBuf;
+
+use cap_std::ambient_authority;
+use cap_std::fs::Dir;
+use wasi_common::clocks::{WasiClocks, WasiMonotonicClock, WasiSystemClock};
+use wasi_common::dir::{OpenResult, ReaddirCursor, ReaddirEntity, WasiDir};
+use wasi_common::file::{FdFlags, Filestat, OFlags};
+use wasi_common::table::Table;
+use wasi_common::{Error, ErrorExt, WasiCtx};
+
+use crate::policy_evaluator_builder::{WasiClock, WasiConfig};
+use crate::runtimes::wasi_cli::errors::{Result, WasiRuntimeError};
+
+/// The host resources given to each run of a WASI program, built from the
+/// `WasiConfig` provided by the user
+pub(crate) struct WasiResources {
+    /// Host directories, opened once and cloned for each run
+    preopened_dirs: Vec<(String, Dir)>,
+    env: Vec<(String, String)>,
+    clock: WasiClock,
+    max_stdout_bytes: Option<usize>,
+    max_stderr_bytes: Option<usize>,
+}
+
+impl WasiResources {
+    /// Validate the configuration and open the host directories
+    pub(crate) fn new(config: WasiConfig) -> Result<Self> {
+        for (key, value) in &config.env {
+            if key.is_empty() || key.contains(['=', '\0']) || value.contains('\0') {
+                return Err(WasiRuntimeError::InvalidEnv(key.to_owned()));
+            }
+        }
+
+        let preopened_dirs = config
+            .preopened_dirs
+            .into_iter()
+            .map(|(guest_path, host_path)| {
+                if guest_path.is_empty() {
+                    return Err(WasiRuntimeError::PreopenedDir {
+                        host_path,
+                        error: std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            "the guest path cannot be empty",
+                        ),
+                    });
+                }
+                Dir::open_ambient_dir(&host_path, ambient_authority())
+                    .map(|dir| (guest_path, dir))
+                    .map_err(|error| WasiRuntimeError::PreopenedDir { host_path, error })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            preopened_dirs,
+            env: config.env.into_iter().collect(),
+            clock: config.clock,
+            max_stdout_bytes: config.max_stdout_bytes,
+            max_stderr_bytes: config.max_stderr_bytes,
+        })
+    }
+
+    /// Maximum number of bytes the program can write to stdout
+    pub(crate) fn max_stdout_bytes(&self) -> Option<usize> {
+        self.max_stdout_bytes
+    }
+
+    /// Maximum number of bytes the program can write to stderr
+    pub(crate) fn max_stderr_bytes(&self) -> Option<usize> {
+        self.max_stderr_bytes
+    }
+
+    /// Create a new `WasiCtx` with the given args, the environment variables,
+    /// the clocks and the read-only host directories. The stdio of the
+    /// program is not set
+    pub(crate) fn build_ctx(&self, args: &[&str]) -> Result<WasiCtx> {
+        let clocks = match self.clock {
+            WasiClock::Host => wasi_common::sync::clocks_ctx(),
+            WasiClock::Fixed(now) => {
+                let clock = FixedClock {
+                    system: cap_std::time::SystemTime::from_std(now),
+                    monotonic: cap_std::time::Instant::from_std(std::time::Instant::now()),
+                };
+                WasiClocks::new()
+                    .with_system(clock.clone())
+                    .with_monotonic(clock)
+            }
+        };
+
+        let mut wasi_ctx = WasiCtx::new(
+            wasi_common::sync::random_ctx(),
+            clocks,
+            wasi_common::sync::sched_ctx(),
+            Table::new(),
+        );
+        for arg in args {
+            wasi_ctx
+                .push_arg(arg)
+                .map_err(WasiRuntimeError::WasiCtxBuilder)?;
+        }
+        for (key, value) in &self.env {
+            wasi_ctx
+                .push_env(key, value)
+                .map_err(WasiRuntimeError::WasiCtxBuilder)?;
+        }
+        for (guest_path, dir) in &self.preopened_dirs {
+            let dir = dir
+                .try_clone()
+                .map_err(|error| WasiRuntimeError::PreopenedDir {
+                    host_path: PathBuf::from(guest_path),
+                    error,
+                })?;
+            wasi_ctx
+                .push_preopened_dir(
+                    Box::new(ReadOnlyDir(Box::new(
+                        wasi_common::sync::dir::Dir::from_cap_std(dir),
+                    ))),
+                    guest_path,
+                )
+                .map_err(WasiRuntimeError::WasiCtx)?;
+        }
+
+        Ok(wasi_ctx)
+    }
+}
+
+/// A clock that never advances
+#[derive(Clone)]
+struct FixedClock {
+    system: cap_std::time::SystemTime,
+    monotonic: cap_std::time::Instant,
+}
+
+impl WasiSystemClock for FixedClock {
+    fn resolution(&self) -> std::time::Duration {
+        std::time::Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: std::time::Duration) -> cap_std::time::SystemTime {
+        self.system
+    }
+}
+
+impl WasiMonotonicClock for FixedClock {
+    fn resolution(&self) -> std::time::Duration {
+        std::time::Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: std::time::Duration) -> cap_std::time::Instant {
+        self.monotonic
+    }
+}
+
+/// Wraps a directory, rejecting all the operations that would change its
+/// contents. The files inside of it can only be opened for reading.
+///
+/// The operations not implemented here are rejected by the default
+/// implementation of the `WasiDir` trait
+struct ReadOnlyDir(Box<dyn WasiDir>);
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> std::result::Result<OpenResult, Error> {
+        if write
+            || oflags.intersects(OFlags::CREATE | OFlags::EXCLUSIVE | OFlags::TRUNCATE)
+            || fdflags.contains(FdFlags::APPEND)
+        {
+            return Err(Error::perm());
+        }
+
+        match self
+            .0
+            .open_file(symlink_follow, path, oflags, read, write, fdflags)
+            .await?
+        {
+            OpenResult::Dir(dir) => Ok(OpenResult::Dir(Box::new(ReadOnlyDir(dir)))),
+            file => Ok(file),
+        }
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> std::result::Result<
+        Box<dyn Iterator<Item = std::result::Result<ReaddirEntity, Error>> + Send>,
+        Error,
+    > {
+        self.0.readdir(cursor).await
+    }
+
+    async fn read_link(&self, path: &str) -> std::result::Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> std::result::Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> std::result::Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::collections::BTreeMap;
+
+    #[rstest]
+    #[case::empty_key("", "value")]
+    #[case::key_with_equal("KEY=", "value")]
+    #[case::value_with_nul("KEY", "val\0ue")]
+    fn invalid_env_is_rejected(#[case] key: &str, #[case] value: &str) {
+        let config = WasiConfig {
+            env: BTreeMap::from([(key.to_owned(), value.to_owned())]),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            WasiResources::new(config),
+            Err(WasiRuntimeError::InvalidEnv(_))
+        ));
+    }
+
+    #[test]
+    fn missing_preopened_dir_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let config = WasiConfig {
+            preopened_dirs: BTreeMap::from([(
+                "/data".to_owned(),
+                root.path().join("does-not-exist"),
+            )]),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            WasiResources::new(config),
+            Err(WasiRuntimeError::PreopenedDir { .. })
+        ));
+    }
+
+    #[rstest]
+    #[case::read(OFlags::empty(), false, FdFlags::empty(), true)]
+    #[case::write(OFlags::empty(), true, FdFlags::empty(), false)]
+    #[case::create(OFlags::CREATE, false, FdFlags::empty(), false)]
+    #[case::truncate(OFlags::TRUNCATE, false, FdFlags::empty(), false)]
+    #[case::append(OFlags::empty(), false, FdFlags::APPEND, false)]
+    fn preopened_dir_is_read_only(
+        #[case] oflags: OFlags,
+        #[case] write: bool,
+        #[case] fdflags: FdFlags,
+        #[case] allowed: bool,
+    ) {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("data.json"), "{}").unwrap();
+        let dir = Dir::open_ambient_dir(root.path(), ambient_authority()).unwrap();
+        let dir = ReadOnlyDir(Box::new(wasi_common::sync::dir::Dir::from_cap_std(dir)));
+
+        let result = futures::executor::block_on(dir.open_file(
+            false,
+            "data.json",
+            oflags,
+            true,
+            write,
+            fdflags,
+        ));
+
+        assert_eq!(allowed, result.is_ok());
+        assert_eq!(
+            "{}",
+            std::fs::read_to_string(root.path().join("data.json")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, WasiComponentRuntimeError>;
+
+#[derive(Error, Debug)]
+pub enum WasiComponentRuntimeError {
+    #[error("cannot add to linker: {0}")]
+    WasmLinkerError(#[source] wasmtime::Error),
+
+    #[error("cannot instantiate component: {0}")]
+    WasmInstantiate(#[source] wasmtime::Error),
+
+    #[error("component does not implement the policy world: {0}")]
+    PolicyWorld(#[source] wasmtime::Error),
+
+    #[error("guest code interrupted, fuel exhausted")]
+    OutOfFuel,
+
+    #[error("cannot set fuel: {0}")]
+    Fuel(#[source] wasmtime::Error),
+
+    #[error("resource limit exceeded: {0}")]
+    ResourceLimitExceeded(String),
+
+    #[error("cannot create ProtocolVersion object from {version:?}: {error}")]
+    ProtocolVersion {
+        version: String,
+        #[source]
+        error: wasmtime::Error,
+    },
+
+    #[error("error invoking '{function}': {error}")]
+    Invocation {
+        function: String,
+        #[source]
+        error: wasmtime::Error,
+    },
+}
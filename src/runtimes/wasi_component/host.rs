@@ -0,0 +1,154 @@
+use kubewarden_policy_sdk::host_capabilities::{
+    kubernetes::{GetResourceRequest, ListAllResourcesRequest, ListResourcesByNamespaceRequest},
+    net::LookupResponse,
+    oci::ManifestDigestResponse,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+use crate::runtimes::callback::host_callback;
+use crate::runtimes::wasi_component::stack::{
+    kubewarden::policy::{host, types},
+    Context,
+};
+
+/// The binding used by all the typed host capabilities
+const BINDING: &str = "kubewarden";
+
+impl Context {
+    /// Perform the host callback identified by `namespace` and `operation`.
+    /// The typed imports are routed through the same code path of the
+    /// `host.call` function used by waPC and WASI policies, hence the same
+    /// allow-lists, quotas and rate limits are enforced
+    fn call(&self, namespace: &str, operation: &str, payload: &[u8]) -> Result<Vec<u8>, String> {
+        host_callback(
+            BINDING,
+            namespace,
+            operation,
+            payload,
+            &self.eval_ctx,
+            &self.callback_recorder,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Same as [`Context::call`], the request is serialized to JSON and the
+    /// response is deserialized from JSON
+    fn call_json<Req: Serialize, Res: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        operation: &str,
+        request: &Req,
+    ) -> Result<Res, String> {
+        let payload = serde_json::to_vec(request)
+            .map_err(|e| format!("cannot serialize {namespace}/{operation} request: {e}"))?;
+        let response = self.call(namespace, operation, &payload)?;
+        serde_json::from_slice(&response)
+            .map_err(|e| format!("cannot deserialize {namespace}/{operation} response: {e}"))
+    }
+
+    /// Same as [`Context::call`], the response is returned as a JSON string
+    fn call_raw(&self, namespace: &str, operation: &str, payload: &[u8]) -> Result<String, String> {
+        let response = self.call(namespace, operation, payload)?;
+        String::from_utf8(response)
+            .map_err(|e| format!("cannot convert {namespace}/{operation} response to UTF8: {e}"))
+    }
+}
+
+impl types::Host for Context {}
+
+impl host::Host for Context {
+    fn log(&mut self, level: host::LogLevel, message: String, fields: Option<String>) {
+        let level = match level {
+            host::LogLevel::Trace => "TRACE",
+            host::LogLevel::Debug => "DEBUG",
+            host::LogLevel::Info => "INFO",
+            host::LogLevel::Warning => "WARNING",
+            host::LogLevel::Error => "ERROR",
+        };
+        let mut entry = fields
+            .and_then(|fields| {
+                serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&fields).ok()
+            })
+            .unwrap_or_default();
+        entry.insert("level".to_string(), json!(level));
+        entry.insert("message".to_string(), json!(message));
+
+        // failures are already reported by the host callback
+        if let Ok(payload) = serde_json::to_vec(&entry) {
+            let _ = self.call("tracing", "log", &payload);
+        }
+    }
+
+    fn oci_manifest_digest(&mut self, image: String) -> Result<String, String> {
+        self.call_json::<_, ManifestDigestResponse>("oci", "v1/manifest_digest", &image)
+            .map(|response| response.digest)
+    }
+
+    fn oci_manifest(&mut self, image: String) -> Result<String, String> {
+        let payload = serde_json::to_vec(&image).map_err(|e| e.to_string())?;
+        self.call_raw("oci", "v1/oci_manifest", &payload)
+    }
+
+    fn oci_manifest_config(&mut self, image: String) -> Result<String, String> {
+        let payload = serde_json::to_vec(&image).map_err(|e| e.to_string())?;
+        self.call_raw("oci", "v1/oci_manifest_config", &payload)
+    }
+
+    fn sigstore_verify(&mut self, request: String) -> Result<String, String> {
+        self.call_raw("oci", "v2/verify", request.as_bytes())
+    }
+
+    fn is_certificate_trusted(&mut self, request: String) -> Result<String, String> {
+        self.call_raw("crypto", "v1/is_certificate_trusted", request.as_bytes())
+    }
+
+    fn dns_lookup_host(&mut self, host: String) -> Result<Vec<String>, String> {
+        self.call_json::<_, LookupResponse>("net", "v1/dns_lookup_host", &host)
+            .map(|response| response.ips)
+    }
+
+    fn kubernetes_get_resource(
+        &mut self,
+        request: host::GetResourceRequest,
+    ) -> Result<String, String> {
+        let request = GetResourceRequest {
+            api_version: request.api_version,
+            kind: request.kind,
+            name: request.name,
+            namespace: request.namespace,
+            disable_cache: request.disable_cache,
+        };
+        let payload = serde_json::to_vec(&request).map_err(|e| e.to_string())?;
+        self.call_raw("kubernetes", "get_resource", &payload)
+    }
+
+    fn kubernetes_list_resources(
+        &mut self,
+        request: host::ListResourcesRequest,
+    ) -> Result<String, String> {
+        let (operation, payload) = match request.namespace {
+            Some(namespace) => (
+                "list_resources_by_namespace",
+                serde_json::to_vec(&ListResourcesByNamespaceRequest {
+                    api_version: request.api_version,
+                    kind: request.kind,
+                    namespace,
+                    label_selector: request.label_selector,
+                    field_selector: request.field_selector,
+                }),
+            ),
+            None => (
+                "list_resources_all",
+                serde_json::to_vec(&ListAllResourcesRequest {
+                    api_version: request.api_version,
+                    kind: request.kind,
+                    label_selector: request.label_selector,
+                    field_selector: request.field_selector,
+                }),
+            ),
+        };
+        let payload = payload.map_err(|e| e.to_string())?;
+        self.call_raw("kubernetes", operation, &payload)
+    }
+}
@@ -0,0 +1,9 @@
+pub mod errors;
+mod host;
+mod runtime;
+mod stack;
+mod stack_pre;
+
+pub(crate) use runtime::Runtime;
+pub(crate) use stack::Stack;
+pub(crate) use stack_pre::StackPre;
@@ -0,0 +1,115 @@
+use kubewarden_policy_sdk::metadata::ProtocolVersion;
+use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
+use kubewarden_policy_sdk::settings::SettingsValidationResponse;
+use tracing::error;
+
+use crate::admission_response::AdmissionResponse;
+use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::runtimes::wasi_component::errors::{Result, WasiComponentRuntimeError};
+use crate::runtimes::wasi_component::stack::{kubewarden::policy::types, Stack};
+
+pub(crate) struct Runtime<'a>(pub(crate) &'a mut Stack);
+
+impl<'a> Runtime<'a> {
+    pub fn validate(
+        &mut self,
+        settings: &PolicySettings,
+        request: &ValidateRequest,
+    ) -> AdmissionResponse {
+        let (request_json, settings_json) = match (
+            serde_json::to_string(request),
+            serde_json::to_string(settings),
+        ) {
+            (Ok(request_json), Ok(settings_json)) => (request_json, settings_json),
+            (Err(e), _) | (_, Err(e)) => {
+                error!(
+                    error = e.to_string().as_str(),
+                    "cannot serialize validation params"
+                );
+                return AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_string(),
+                    e.to_string(),
+                );
+            }
+        };
+
+        let response = self.0.call("validate", |policy, store| {
+            policy.call_validate(store, &request_json, &settings_json)
+        });
+
+        match response {
+            Ok(response) => build_admission_response(request, response),
+            Err(e) => AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500),
+        }
+    }
+
+    pub fn validate_settings(&mut self, settings: String) -> SettingsValidationResponse {
+        let response = self.0.call("validate-settings", |policy, store| {
+            policy.call_validate_settings(store, &settings)
+        });
+
+        match response {
+            Ok(response) => SettingsValidationResponse {
+                valid: response.valid,
+                message: response.message,
+            },
+            Err(e) => SettingsValidationResponse {
+                valid: false,
+                message: Some(e.to_string()),
+            },
+        }
+    }
+
+    pub fn protocol_version(&mut self) -> Result<ProtocolVersion> {
+        let version = self.0.call("protocol-version", |policy, store| {
+            policy.call_protocol_version(store)
+        })?;
+
+        // the version is given as a plain string, while `ProtocolVersion`
+        // is built from its JSON representation
+        let res = serde_json::to_vec(&version).unwrap_or_default();
+        ProtocolVersion::try_from(res)
+            .map_err(|error| WasiComponentRuntimeError::ProtocolVersion { version, error })
+    }
+}
+
+fn build_admission_response(
+    request: &ValidateRequest,
+    response: types::ValidationResponse,
+) -> AdmissionResponse {
+    let mutated_object = match response.mutated_object.as_deref().map(serde_json::from_str) {
+        Some(Ok(mutated_object)) => Some(mutated_object),
+        Some(Err(e)) => {
+            return AdmissionResponse::reject_internal_server_error(
+                request.uid().to_string(),
+                format!("Cannot deserialize mutated object: {e}"),
+            )
+        }
+        None => None,
+    };
+    let pvr = PolicyValidationResponse {
+        accepted: response.accepted,
+        message: response.message,
+        code: response.code,
+        mutated_object,
+        audit_annotations: response
+            .audit_annotations
+            .map(|annotations| annotations.into_iter().collect()),
+        warnings: response.warnings,
+    };
+
+    let req_json_value =
+        serde_json::to_value(request).expect("cannot convert request to json value");
+    let req_obj = match request {
+        ValidateRequest::Raw(_) => Some(&req_json_value),
+        ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
+    };
+
+    AdmissionResponse::from_policy_validation_response(request.uid().to_string(), req_obj, &pvr)
+        .unwrap_or_else(|e| {
+            AdmissionResponse::reject_internal_server_error(
+                request.uid().to_string(),
+                format!("Cannot convert policy validation response: {e}"),
+            )
+        })
+}
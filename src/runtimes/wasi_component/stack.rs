@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime::component::ResourceTable;
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiView};
+
+use burrego::{errors::BurregoError, ResourceLimits};
+
+use crate::evaluation_context::EvaluationContext;
+use crate::runtimes::callback::HostCallbackRecorder;
+use crate::runtimes::wasi_component::{
+    errors::WasiComponentRuntimeError,
+    stack_pre::{is_instantiate_trap, StackPre},
+};
+
+wasmtime::component::bindgen!({
+    path: "src/runtimes/wasi_component/wit",
+    world: "policy",
+});
+
+pub(crate) struct Context {
+    pub(crate) wasi_ctx: WasiCtx,
+    pub(crate) table: ResourceTable,
+    pub(crate) eval_ctx: Arc<EvaluationContext>,
+    pub(crate) callback_recorder: HostCallbackRecorder,
+    /// Enforced only when the `StackPre` has been created with resource limits
+    pub(crate) resource_limits: ResourceLimits,
+}
+
+impl WasiView for Context {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi_ctx
+    }
+}
+
+pub(crate) struct Stack {
+    stack_pre: StackPre,
+    eval_ctx: Arc<EvaluationContext>,
+    callback_recorder: HostCallbackRecorder,
+    trapped: bool,
+    fuel_consumed: Option<u64>,
+    instantiation_time: Duration,
+}
+
+impl Stack {
+    pub(crate) fn new_from_pre(stack_pre: &StackPre, eval_ctx: &EvaluationContext) -> Self {
+        Self {
+            stack_pre: stack_pre.to_owned(),
+            eval_ctx: Arc::new(eval_ctx.to_owned()),
+            callback_recorder: HostCallbackRecorder::default(),
+            trapped: false,
+            fuel_consumed: None,
+            instantiation_time: Duration::ZERO,
+        }
+    }
+
+    /// Returns true if the last invocation of the component has been aborted
+    /// by a trap
+    pub(crate) fn trapped(&self) -> bool {
+        self.trapped
+    }
+
+    /// Time spent instantiating the component during the last invocation
+    pub(crate) fn instantiation_time(&self) -> Duration {
+        self.instantiation_time
+    }
+
+    /// The recorder tracking the host callbacks made by the component
+    pub(crate) fn callback_recorder(&self) -> &HostCallbackRecorder {
+        &self.callback_recorder
+    }
+
+    /// Amount of fuel consumed by the last invocation of the component.
+    /// Returns `None` when fuel consumption is not enabled
+    pub(crate) fn fuel_consumed(&self) -> Option<u64> {
+        self.fuel_consumed
+    }
+
+    /// Invoke the `function` export of the component. A brand new instance
+    /// of the component is created for each invocation
+    pub(crate) fn call<R>(
+        &mut self,
+        function: &str,
+        call: impl FnOnce(&Policy, &mut wasmtime::Store<Context>) -> wasmtime::Result<R>,
+    ) -> std::result::Result<R, WasiComponentRuntimeError> {
+        self.trapped = false;
+        self.fuel_consumed = None;
+
+        let wasi_ctx = WasiCtxBuilder::new().args(&["policy.wasm"]).build();
+        let ctx = Context {
+            wasi_ctx,
+            table: ResourceTable::new(),
+            eval_ctx: self.eval_ctx.clone(),
+            callback_recorder: self.callback_recorder.clone(),
+            resource_limits: ResourceLimits::default(),
+        };
+
+        let started_at = Instant::now();
+        let mut store = self.stack_pre.build_store(ctx)?;
+        let policy = match self.stack_pre.rehydrate(&mut store) {
+            Ok(policy) => policy,
+            Err(err) => {
+                self.trapped = is_instantiate_trap(&err);
+                self.fuel_consumed = self.stack_pre.init_fuel_consumed(&store);
+                return Err(err);
+            }
+        };
+        self.instantiation_time = started_at.elapsed();
+        self.stack_pre.set_call_fuel(&mut store)?;
+
+        let result = call(&policy, &mut store);
+
+        self.fuel_consumed = self.stack_pre.fuel_consumed(&store);
+        self.trapped = result.is_err();
+        result.map_err(|e| map_call_error(function, e))
+    }
+}
+
+/// Map the error returned by the invocation of a component, all of them are
+/// caused by a trap
+fn map_call_error(function: &str, error: wasmtime::Error) -> WasiComponentRuntimeError {
+    if matches!(
+        error.downcast_ref::<wasmtime::Trap>(),
+        Some(wasmtime::Trap::OutOfFuel)
+    ) {
+        return WasiComponentRuntimeError::OutOfFuel;
+    }
+    if let Some(BurregoError::ResourceLimitExceeded(reason)) = error.downcast_ref::<BurregoError>()
+    {
+        return WasiComponentRuntimeError::ResourceLimitExceeded(reason.to_owned());
+    }
+
+    WasiComponentRuntimeError::Invocation {
+        function: function.to_string(),
+        error,
+    }
+}
@@ -0,0 +1,153 @@
+use burrego::{errors::BurregoError, FuelBudget, ResourceLimits};
+use wasmtime::component::{Component, InstancePre, Linker};
+use wasmtime::Engine;
+
+use crate::policy_evaluator_builder::EpochDeadlines;
+use crate::runtimes::wasi_component::errors::{Result, WasiComponentRuntimeError};
+use crate::runtimes::wasi_component::stack::{Context, Policy};
+
+/// Reduce the allocation time of a Wasi component Stack. This is done by
+/// leveraging `wasmtime::component::InstancePre`.
+#[derive(Clone)]
+pub(crate) struct StackPre {
+    engine: Engine,
+    instance_pre: InstancePre<Context>,
+    epoch_deadlines: Option<EpochDeadlines>,
+    resource_limits: Option<ResourceLimits>,
+    fuel_limits: Option<FuelBudget>,
+}
+
+impl StackPre {
+    /// Create a new `StackPre`.
+    ///
+    /// The component is linked against the WASI preview 2 interfaces and the
+    /// host capabilities of the `policy` world. Components importing anything
+    /// else are rejected.
+    ///
+    /// When `resource_limits` are given, they are enforced on each store
+    /// created by [`StackPre::build_store`]. The same applies to the
+    /// `fuel_limits`, which require the `wasmtime::Engine` to be created with
+    /// the [`consume_fuel`](wasmtime::Config::consume_fuel) feature enabled.
+    pub(crate) fn new(
+        engine: Engine,
+        component: Component,
+        epoch_deadlines: Option<EpochDeadlines>,
+        resource_limits: Option<ResourceLimits>,
+        fuel_limits: Option<FuelBudget>,
+    ) -> Result<Self> {
+        let mut linker = Linker::<Context>::new(&engine);
+        wasmtime_wasi::add_to_linker_sync(&mut linker)
+            .map_err(WasiComponentRuntimeError::WasmLinkerError)?;
+        Policy::add_to_linker(&mut linker, |c: &mut Context| c)
+            .map_err(WasiComponentRuntimeError::WasmLinkerError)?;
+
+        let instance_pre = linker
+            .instantiate_pre(&component)
+            .map_err(WasiComponentRuntimeError::WasmInstantiate)?;
+        Ok(Self {
+            engine,
+            instance_pre,
+            epoch_deadlines,
+            resource_limits,
+            fuel_limits,
+        })
+    }
+
+    /// Create a brand new `wasmtime::Store` to be used during an evaluation.
+    ///
+    /// When fuel consumption is enabled, the store is given the fuel
+    /// available to the instantiation of the component
+    pub(crate) fn build_store(&self, mut ctx: Context) -> Result<wasmtime::Store<Context>> {
+        if let Some(limits) = self.resource_limits {
+            ctx.resource_limits = limits;
+        }
+
+        let mut store = wasmtime::Store::new(&self.engine, ctx);
+        if let Some(deadline) = self.epoch_deadlines {
+            store.set_epoch_deadline(deadline.wapc_func);
+        }
+        if self.resource_limits.is_some() {
+            store.limiter(|c| &mut c.resource_limits);
+        }
+        if let Some(fuel) = self.fuel_limits {
+            store
+                .set_fuel(fuel.init)
+                .map_err(WasiComponentRuntimeError::Fuel)?;
+        }
+
+        Ok(store)
+    }
+
+    /// Refill the store with the fuel available to the invocation of one of
+    /// the exports of the policy. This is a no-op when fuel consumption is not
+    /// enabled
+    pub(crate) fn set_call_fuel(&self, store: &mut wasmtime::Store<Context>) -> Result<()> {
+        if let Some(fuel) = self.fuel_limits {
+            store
+                .set_fuel(fuel.call)
+                .map_err(WasiComponentRuntimeError::Fuel)?;
+        }
+        Ok(())
+    }
+
+    /// Amount of fuel consumed by the invocation of the export. Returns
+    /// `None` when fuel consumption is not enabled
+    pub(crate) fn fuel_consumed(&self, store: &wasmtime::Store<Context>) -> Option<u64> {
+        self.fuel_limits.and_then(|fuel| {
+            store
+                .get_fuel()
+                .ok()
+                .map(|remaining| fuel.call.saturating_sub(remaining))
+        })
+    }
+
+    /// Amount of fuel consumed by the instantiation. Returns `None` when fuel
+    /// consumption is not enabled
+    pub(crate) fn init_fuel_consumed(&self, store: &wasmtime::Store<Context>) -> Option<u64> {
+        self.fuel_limits.and_then(|fuel| {
+            store
+                .get_fuel()
+                .ok()
+                .map(|remaining| fuel.init.saturating_sub(remaining))
+        })
+    }
+
+    /// Allocate a new instance of the component that is bound to the given
+    /// `wasmtime::Store`, and look up the exports of the `policy` world.
+    /// It's recommended to provide a brand new `wasmtime::Store` created by
+    /// the `build_store` method
+    pub(crate) fn rehydrate(&self, store: &mut wasmtime::Store<Context>) -> Result<Policy> {
+        let instance = self
+            .instance_pre
+            .instantiate(&mut *store)
+            .map_err(map_instantiate_error)?;
+
+        Policy::new(store, &instance).map_err(WasiComponentRuntimeError::PolicyWorld)
+    }
+}
+
+/// Returns true when the instantiation has been aborted by a trap, including
+/// the ones raised when the fuel or the resource limits are exhausted
+pub(crate) fn is_instantiate_trap(error: &WasiComponentRuntimeError) -> bool {
+    matches!(
+        error,
+        WasiComponentRuntimeError::OutOfFuel
+            | WasiComponentRuntimeError::ResourceLimitExceeded(_)
+            | WasiComponentRuntimeError::WasmInstantiate(_)
+    )
+}
+
+/// The instantiation of the component runs the start functions of its core
+/// modules, which can exhaust the fuel given to the initialization of the policy.
+/// The memories and tables created by the instantiation count towards the
+/// resource limits
+fn map_instantiate_error(error: wasmtime::Error) -> WasiComponentRuntimeError {
+    if let Some(BurregoError::ResourceLimitExceeded(reason)) = error.downcast_ref::<BurregoError>()
+    {
+        return WasiComponentRuntimeError::ResourceLimitExceeded(reason.to_owned());
+    }
+    match error.downcast_ref::<wasmtime::Trap>() {
+        Some(wasmtime::Trap::OutOfFuel) => WasiComponentRuntimeError::OutOfFuel,
+        _ => WasiComponentRuntimeError::WasmInstantiate(error),
+    }
+}
@@ -1,10 +1,16 @@
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
 use tempfile::TempDir;
 
 use policy_evaluator::{
     evaluation_context::EvaluationContext, policy_evaluator::PolicyEvaluator,
-    policy_evaluator::PolicyExecutionMode, policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_evaluator::PolicyEvaluatorPre, policy_evaluator::PolicyExecutionMode,
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
 };
 use policy_fetcher::{policy::Policy, PullDestination};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 pub(crate) async fn fetch_policy(policy_uri: &str, tempdir: TempDir) -> Policy {
     policy_evaluator::policy_fetcher::fetch_policy(
@@ -21,13 +27,46 @@ pub(crate) fn build_policy_evaluator(
     policy: &Policy,
     eval_ctx: &EvaluationContext,
 ) -> PolicyEvaluator {
-    let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+    rehydrate_policy_evaluator(policy_evaluator_builder(execution_mode, policy), eval_ctx)
+}
+
+/// Build a `PolicyEvaluator` that supports asynchronous evaluations
+pub(crate) fn build_async_policy_evaluator(
+    execution_mode: PolicyExecutionMode,
+    policy: &Policy,
+    eval_ctx: &EvaluationContext,
+) -> PolicyEvaluator {
+    rehydrate_policy_evaluator(
+        policy_evaluator_builder(execution_mode, policy).enable_async_support(),
+        eval_ctx,
+    )
+}
+
+pub(crate) fn build_policy_evaluator_pre(
+    execution_mode: PolicyExecutionMode,
+    policy: &Policy,
+) -> PolicyEvaluatorPre {
+    policy_evaluator_builder(execution_mode, policy)
+        .build_pre()
+        .expect("cannot build policy evaluator pre")
+}
+
+fn policy_evaluator_builder(
+    execution_mode: PolicyExecutionMode,
+    policy: &Policy,
+) -> PolicyEvaluatorBuilder {
+    PolicyEvaluatorBuilder::new()
         .execution_mode(execution_mode)
         .policy_file(&policy.local_path)
         .expect("cannot read policy file")
         .enable_wasmtime_cache()
-        .enable_epoch_interruptions(1, 2);
+        .enable_epoch_interruptions(1, 2)
+}
 
+fn rehydrate_policy_evaluator(
+    policy_evaluator_builder: PolicyEvaluatorBuilder,
+    eval_ctx: &EvaluationContext,
+) -> PolicyEvaluator {
     let policy_evaluator_pre = policy_evaluator_builder
         .build_pre()
         .expect("cannot build policy evaluator pre");
@@ -43,3 +82,132 @@ pub(crate) fn load_request_data(request_file_name: &str) -> Vec<u8> {
         .join(request_file_name);
     std::fs::read(request_file_path).expect("cannot read request file")
 }
+
+/// Serve the given policy over plain HTTP, acting as a stand-in of a remote
+/// server. Returns the address the server is listening on.
+pub(crate) async fn serve_policy(contents: Vec<u8>) -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("cannot bind listener");
+    let addr = listener.local_addr().expect("cannot get listener address");
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = [0u8; 4096];
+            let _ = socket.read(&mut request).await;
+
+            let headers = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/wasm\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                contents.len()
+            );
+            let _ = socket.write_all(headers.as_bytes()).await;
+            let _ = socket.write_all(&contents).await;
+            let _ = socket.shutdown().await;
+        }
+    });
+
+    addr
+}
+
+/// Serve the policy of `tests/data/signed_policy` through a minimal OCI
+/// registry, acting as a stand-in of a remote registry. The policy is
+/// published as `accept-all:v1`, together with a cosign signature of its
+/// manifest made of the given base64 encoded signature of the payload.
+/// Returns the address the registry is listening on.
+pub(crate) async fn serve_signed_policy(signature: &str) -> std::net::SocketAddr {
+    let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let read = |path: &str| std::fs::read(data_dir.join(path)).expect("cannot read policy data");
+    let digest = |contents: &[u8]| format!("sha256:{:x}", Sha256::digest(contents));
+
+    let config = b"{}".to_vec();
+    let manifest = read("signed_policy/manifest.json");
+    let payload = read("signed_policy/payload.json");
+    let signature_manifest = serde_json::to_vec(&json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": digest(&config),
+            "size": config.len(),
+        },
+        "layers": [{
+            "mediaType": "application/vnd.dev.cosign.simplesigning.v1+json",
+            "digest": digest(&payload),
+            "size": payload.len(),
+            "annotations": {
+                "dev.cosignproject.cosign/signature": signature,
+            },
+        }],
+    }))
+    .expect("cannot serialize signature manifest");
+
+    // cosign looks for the signatures of a manifest under the
+    // `sha256-<digest>.sig` tag
+    let signature_tag = format!("{}.sig", digest(&manifest).replace(':', "-"));
+    let manifests: HashMap<String, Vec<u8>> = HashMap::from([
+        ("v1".to_owned(), manifest.clone()),
+        (digest(&manifest), manifest),
+        (digest(&signature_manifest), signature_manifest.clone()),
+        (signature_tag, signature_manifest),
+    ]);
+    let blobs: HashMap<String, Vec<u8>> = [read("wasi_policy/accept_all.wat"), config, payload]
+        .into_iter()
+        .map(|blob| (digest(&blob), blob))
+        .collect();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("cannot bind listener");
+    let addr = listener.local_addr().expect("cannot get listener address");
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = [0u8; 4096];
+            let Ok(len) = socket.read(&mut request).await else {
+                continue;
+            };
+            let request = String::from_utf8_lossy(&request[..len]);
+            let mut request_line = request.split_whitespace();
+            let method = request_line.next().unwrap_or_default();
+            let path = request_line.next().unwrap_or_default();
+
+            let found = if path == "/v2/" {
+                Some(("application/json", b"{}".to_vec()))
+            } else if let Some(reference) = path.strip_prefix("/v2/accept-all/manifests/") {
+                manifests.get(reference).map(|manifest| {
+                    (
+                        "application/vnd.oci.image.manifest.v1+json",
+                        manifest.clone(),
+                    )
+                })
+            } else if let Some(blob_digest) = path.strip_prefix("/v2/accept-all/blobs/") {
+                blobs
+                    .get(blob_digest)
+                    .map(|blob| ("application/octet-stream", blob.clone()))
+            } else {
+                None
+            };
+
+            let (status, content_type, body) = match found {
+                Some((content_type, body)) => ("200 OK", content_type, body),
+                None => (
+                    "404 Not Found",
+                    "application/json",
+                    br#"{"errors":[{"code":"NAME_UNKNOWN","message":"not found"}]}"#.to_vec(),
+                ),
+            };
+            let headers = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nDocker-Content-Digest: {}\r\nConnection: close\r\n\r\n",
+                body.len(),
+                digest(&body),
+            );
+            let _ = socket.write_all(headers.as_bytes()).await;
+            if method != "HEAD" {
+                let _ = socket.write_all(&body).await;
+            }
+            let _ = socket.shutdown().await;
+        }
+    });
+
+    addr
+}
@@ -0,0 +1,126 @@
+;; A fake Rego policy implementing the subset of the OPA Wasm ABI used by
+;; burrego. JSON values are kept as null-terminated strings and are never
+;; parsed: each entrypoint evaluates to a canned result.
+;;
+;; Entrypoints:
+;; - `policy/main` (0): accepts every request
+;; - `opa/validate_settings` (1): OPA settings validation, the settings are
+;;   rejected when they contain the `forbidden` string
+;; - `gatekeeper/validate_settings` (2): Gatekeeper settings validation, the
+;;   settings are rejected when they contain the `forbidden` string
+;; - `gatekeeper/malformed` (3): evaluates to a malformed list of violations
+(module
+  (import "env" "memory" (memory 5))
+
+  (global $heap (mut i32) (i32.const 0x10000))
+  (global (export "opa_wasm_abi_version") i32 (i32.const 1))
+  (global (export "opa_wasm_abi_minor_version") i32 (i32.const 2))
+
+  ;; builtins
+  (data (i32.const 0x100) "{}\00")
+  ;; entrypoints
+  (data (i32.const 0x200) "{\"policy/main\":0,\"opa/validate_settings\":1,\"gatekeeper/validate_settings\":2,\"gatekeeper/malformed\":3}\00")
+  ;; forbidden
+  (data (i32.const 0x300) "forbidden\00")
+  ;; main_result
+  (data (i32.const 0x400) "[{\"result\":{\"response\":{\"uid\":\"\",\"allowed\":true}}}]\00")
+  ;; opa_valid
+  (data (i32.const 0x500) "[{\"result\":{\"valid\":true}}]\00")
+  ;; opa_invalid
+  (data (i32.const 0x600) "[{\"result\":{\"valid\":false,\"message\":\"forbidden setting\"}}]\00")
+  ;; gatekeeper_valid
+  (data (i32.const 0x700) "[{\"result\":[]}]\00")
+  ;; gatekeeper_invalid
+  (data (i32.const 0x800) "[{\"result\":[{\"msg\":\"forbidden setting\"}]}]\00")
+  ;; malformed
+  (data (i32.const 0x900) "[{\"result\":\"not a list of violations\"}]\00")
+
+  (func $malloc (export "opa_malloc") (param $size i32) (result i32)
+    (local $addr i32)
+    (local.set $addr (global.get $heap))
+    (global.set $heap (i32.add (global.get $heap) (local.get $size)))
+    (local.get $addr))
+
+  (func (export "opa_heap_ptr_get") (result i32)
+    (global.get $heap))
+
+  (func (export "opa_heap_ptr_set") (param $addr i32)
+    (global.set $heap (local.get $addr)))
+
+  ;; copy the raw JSON into a null-terminated string
+  (func (export "opa_json_parse") (param $addr i32) (param $len i32) (result i32)
+    (local $value i32)
+    (local.set $value (call $malloc (i32.add (local.get $len) (i32.const 1))))
+    (memory.copy (local.get $value) (local.get $addr) (local.get $len))
+    (i32.store8 (i32.add (local.get $value) (local.get $len)) (i32.const 0))
+    (local.get $value))
+
+  (func (export "opa_json_dump") (param $value i32) (result i32)
+    (local.get $value))
+
+  (func (export "builtins") (result i32)
+    (i32.const 0x100))
+
+  (func (export "entrypoints") (result i32)
+    (i32.const 0x200))
+
+  ;; the evaluation context is made of: input, data, entrypoint and result
+  (func (export "opa_eval_ctx_new") (result i32)
+    (call $malloc (i32.const 16)))
+
+  (func (export "opa_eval_ctx_set_input") (param $ctx i32) (param $input i32)
+    (i32.store (local.get $ctx) (local.get $input)))
+
+  (func (export "opa_eval_ctx_set_data") (param $ctx i32) (param $data i32)
+    (i32.store offset=4 (local.get $ctx) (local.get $data)))
+
+  (func (export "opa_eval_ctx_set_entrypoint") (param $ctx i32) (param $entrypoint i32)
+    (i32.store offset=8 (local.get $ctx) (local.get $entrypoint)))
+
+  (func (export "opa_eval_ctx_get_result") (param $ctx i32) (result i32)
+    (i32.load offset=12 (local.get $ctx)))
+
+  (func (export "eval") (param $ctx i32) (result i32)
+    (local $entrypoint i32)
+    (local $rejected i32)
+    (local $result i32)
+    (local.set $entrypoint (i32.load offset=8 (local.get $ctx)))
+    (local.set $rejected
+      (call $contains (i32.load (local.get $ctx)) (i32.const 0x300)))
+    (local.set $result (i32.const 0x900))
+    (if (i32.eqz (local.get $entrypoint))
+      (then (local.set $result (i32.const 0x400))))
+    (if (i32.eq (local.get $entrypoint) (i32.const 1))
+      (then
+        (local.set $result
+          (select (i32.const 0x600) (i32.const 0x500) (local.get $rejected)))))
+    (if (i32.eq (local.get $entrypoint) (i32.const 2))
+      (then
+        (local.set $result
+          (select (i32.const 0x800) (i32.const 0x700) (local.get $rejected)))))
+    (i32.store offset=12 (local.get $ctx) (local.get $result))
+    (i32.const 0))
+
+  ;; returns 1 when the null-terminated string $needle is found inside of
+  ;; the null-terminated string $haystack
+  (func $contains (param $haystack i32) (param $needle i32) (result i32)
+    (local $h i32)
+    (local $n i32)
+    (loop $next_start
+      (if (i32.eqz (i32.load8_u (local.get $haystack)))
+        (then (return (i32.const 0))))
+      (local.set $h (local.get $haystack))
+      (local.set $n (local.get $needle))
+      (block $mismatch
+        (loop $next_char
+          (if (i32.eqz (i32.load8_u (local.get $n)))
+            (then (return (i32.const 1))))
+          (br_if $mismatch
+            (i32.ne (i32.load8_u (local.get $h)) (i32.load8_u (local.get $n))))
+          (local.set $h (i32.add (local.get $h) (i32.const 1)))
+          (local.set $n (i32.add (local.get $n) (i32.const 1)))
+          (br $next_char)))
+      (local.set $haystack (i32.add (local.get $haystack) (i32.const 1)))
+      (br $next_start))
+    (i32.const 0))
+)
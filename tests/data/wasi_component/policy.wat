@@ -0,0 +1,194 @@
+;; This is a component implementing the `policy` world of the Kubewarden
+;; WASI component runtime.
+;;
+;; Strings are stored inside of the linear memory at fixed offsets, the values
+;; returned by the exports are written to fixed return areas.
+
+(component
+  (import "kubewarden:policy/host@0.1.0" (instance $host
+    (type $log-level' (enum "trace" "debug" "info" "warning" "error"))
+    (export "log-level" (type $log-level (eq $log-level')))
+    (type $log-fn (func
+      (param "level" $log-level)
+      (param "message" string)
+      (param "fields" (option string))))
+    (export "log" (func (type $log-fn)))
+  ))
+
+  ;; the memory is defined by its own module, it must exist before the
+  ;; `log` import can be lowered
+  (core module $libc
+    (memory (export "memory") 1)
+  )
+
+  (core module $policy
+    (import "host" "log" (func $log (param i32 i32 i32 i32 i32 i32)))
+    (import "env" "memory" (memory 1))
+
+    ;; first free address of the heap used by `cabi_realloc`
+    (global $heap (mut i32) (i32.const 4096))
+
+    (data (i32.const 0) "v1")
+    (data (i32.const 16) "validating request")
+    (data (i32.const 48) "reject")
+    (data (i32.const 64) "invalid")
+    (data (i32.const 80) "rejected by the settings")
+    (data (i32.const 112) "the settings are invalid")
+
+    ;; bump allocator used by the host to write the parameters of the exports
+    (func (export "cabi_realloc")
+      (param $old_ptr i32) (param $old_size i32) (param $align i32) (param $new_size i32)
+      (result i32)
+      (local $ptr i32)
+      (local.set $ptr
+        (i32.and
+          (i32.add (global.get $heap) (i32.sub (local.get $align) (i32.const 1)))
+          (i32.sub (i32.const 0) (local.get $align))))
+      (global.set $heap (i32.add (local.get $ptr) (local.get $new_size)))
+      (if (i32.gt_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536)))
+        (then
+          (if (i32.eq
+                (memory.grow
+                  (i32.add
+                    (i32.div_u
+                      (i32.sub (global.get $heap) (i32.mul (memory.size) (i32.const 65536)))
+                      (i32.const 65536))
+                    (i32.const 1)))
+                (i32.const -1))
+            (then unreachable))))
+      (if (local.get $old_ptr)
+        (then (memory.copy (local.get $ptr) (local.get $old_ptr) (local.get $old_size))))
+      (local.get $ptr)
+    )
+
+    ;; returns 1 when the `needle` string is part of the `haystack` string
+    (func $contains
+      (param $haystack i32) (param $haystack_len i32)
+      (param $needle i32) (param $needle_len i32)
+      (result i32)
+      (local $i i32)
+      (local $j i32)
+      (block $not_found
+        (loop $outer
+          (br_if $not_found
+            (i32.gt_u
+              (i32.add (local.get $i) (local.get $needle_len))
+              (local.get $haystack_len)))
+          (local.set $j (i32.const 0))
+          (block $mismatch
+            (loop $inner
+              (if (i32.ge_u (local.get $j) (local.get $needle_len))
+                (then (return (i32.const 1))))
+              (br_if $mismatch
+                (i32.ne
+                  (i32.load8_u
+                    (i32.add (local.get $haystack) (i32.add (local.get $i) (local.get $j))))
+                  (i32.load8_u (i32.add (local.get $needle) (local.get $j)))))
+              (local.set $j (i32.add (local.get $j) (i32.const 1)))
+              (br $inner)))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $outer)))
+      (i32.const 0)
+    )
+
+    ;; returns "v1", the string is described at offset 1024
+    (func (export "protocol-version") (result i32)
+      (i32.store (i32.const 1024) (i32.const 0))
+      (i32.store (i32.const 1028) (i32.const 2))
+      (i32.const 1024)
+    )
+
+    ;; the request is rejected when the settings contain "reject", the
+    ;; `validation-response` is written at offset 1040
+    (func (export "validate")
+      (param $request i32) (param $request_len i32)
+      (param $settings i32) (param $settings_len i32)
+      (result i32)
+      ;; log: info level, "validating request", no fields
+      (call $log
+        (i32.const 2)
+        (i32.const 16) (i32.const 18)
+        (i32.const 0) (i32.const 0) (i32.const 0))
+      (if (call $contains
+            (local.get $settings) (local.get $settings_len)
+            (i32.const 48) (i32.const 6))
+        (then
+          ;; accepted
+          (i32.store8 (i32.const 1040) (i32.const 0))
+          ;; message
+          (i32.store8 (i32.const 1044) (i32.const 1))
+          (i32.store (i32.const 1048) (i32.const 80))
+          (i32.store (i32.const 1052) (i32.const 24))
+          ;; code
+          (i32.store8 (i32.const 1056) (i32.const 1))
+          (i32.store16 (i32.const 1058) (i32.const 403)))
+        (else
+          (i32.store8 (i32.const 1040) (i32.const 1))
+          (i32.store8 (i32.const 1044) (i32.const 0))
+          (i32.store8 (i32.const 1056) (i32.const 0))))
+      ;; mutated-object, warnings and audit-annotations are not set
+      (i32.store8 (i32.const 1060) (i32.const 0))
+      (i32.store8 (i32.const 1072) (i32.const 0))
+      (i32.store8 (i32.const 1084) (i32.const 0))
+      (i32.const 1040)
+    )
+
+    ;; the settings are invalid when they contain "invalid", the
+    ;; `settings-validation-response` is written at offset 1104
+    (func (export "validate-settings")
+      (param $settings i32) (param $settings_len i32)
+      (result i32)
+      (if (call $contains
+            (local.get $settings) (local.get $settings_len)
+            (i32.const 64) (i32.const 7))
+        (then
+          (i32.store8 (i32.const 1104) (i32.const 0))
+          (i32.store8 (i32.const 1108) (i32.const 1))
+          (i32.store (i32.const 1112) (i32.const 112))
+          (i32.store (i32.const 1116) (i32.const 24)))
+        (else
+          (i32.store8 (i32.const 1104) (i32.const 1))
+          (i32.store8 (i32.const 1108) (i32.const 0))))
+      (i32.const 1104)
+    )
+  )
+
+  (core instance $libc (instantiate $libc))
+  (alias core export $libc "memory" (core memory $memory))
+
+  (alias export $host "log" (func $log))
+  (core func $log-lowered (canon lower (func $log) (memory $memory)))
+  (core instance $host-imports (export "log" (func $log-lowered)))
+
+  (core instance $policy (instantiate $policy
+    (with "host" (instance $host-imports))
+    (with "env" (instance $libc))))
+
+  (type $validation-response' (record
+    (field "accepted" bool)
+    (field "message" (option string))
+    (field "code" (option u16))
+    (field "mutated-object" (option string))
+    (field "warnings" (option (list string)))
+    (field "audit-annotations" (option (list (tuple string string))))))
+  (export $validation-response "validation-response" (type $validation-response'))
+
+  (type $settings-validation-response' (record
+    (field "valid" bool)
+    (field "message" (option string))))
+  (export $settings-validation-response "settings-validation-response"
+    (type $settings-validation-response'))
+
+  (func (export "protocol-version") (result string)
+    (canon lift (core func $policy "protocol-version") (memory $memory)))
+
+  (func (export "validate")
+    (param "request" string) (param "settings" string) (result $validation-response)
+    (canon lift (core func $policy "validate")
+      (memory $memory) (realloc (func $policy "cabi_realloc"))))
+
+  (func (export "validate-settings")
+    (param "settings" string) (result $settings-validation-response)
+    (canon lift (core func $policy "validate-settings")
+      (memory $memory) (realloc (func $policy "cabi_realloc"))))
+)
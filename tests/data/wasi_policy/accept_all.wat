@@ -0,0 +1,26 @@
+;; This is a module meant to be used as a Kubewarden WASI policy.
+;;
+;; The `_start` function writes a validation response accepting the request to
+;; stdout, regardless of the arguments given to the program and of its input.
+
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+  (memory (export "memory") 1)
+
+  ;; the iovec pointing to the response: offset 16, length 17
+  (data (i32.const 0) "\10\00\00\00\11\00\00\00")
+  (data (i32.const 16) "{\"accepted\":true}")
+
+  (func $main (export "_start")
+    ;; write the response to stdout, the number of bytes written is stored at
+    ;; offset 8
+    (drop
+      (call $fd_write
+        (i32.const 1)
+        (i32.const 0)
+        (i32.const 1)
+        (i32.const 8)))
+  )
+)
@@ -8,10 +8,13 @@ use hyper::{Request, Response};
 use kube::client::Body;
 use kube::Client;
 use policy_fetcher::oci_distribution::manifest::OciImageManifest;
+use policy_fetcher::sigstore::trust::ManualTrustRoot;
+use policy_fetcher::verify::config::{LatestVerificationConfig, Signature};
 use rstest::*;
 use serde_json::json;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::future::Future;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 use tower_test::mock::Handle;
@@ -23,13 +26,19 @@ use policy_evaluator::{
     admission_response::AdmissionResponseStatus,
     callback_handler::CallbackHandlerBuilder,
     callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse},
+    errors::{PolicyEvaluatorBuilderError, PolicyEvaluatorError},
     evaluation_context::EvaluationContext,
     policy_evaluator::PolicySettings,
     policy_evaluator::{PolicyExecutionMode, ValidateRequest},
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_group_evaluator::{PolicyGroupEvaluator, PolicyGroupMember},
     policy_metadata::ContextAwareResource,
 };
 
-use crate::common::{build_policy_evaluator, fetch_policy, load_request_data};
+use crate::common::{
+    build_async_policy_evaluator, build_policy_evaluator, build_policy_evaluator_pre, fetch_policy,
+    load_request_data, serve_policy, serve_signed_policy,
+};
 use crate::k8s_mock::{rego_scenario, wapc_and_wasi_scenario};
 
 async fn setup_callback_handler(
@@ -193,11 +202,7 @@ async fn test_policy_evaluator(
     let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
     let policy = fetch_policy(policy_uri, tempdir).await;
 
-    let eval_ctx = EvaluationContext {
-        policy_id: "test".to_owned(),
-        callback_channel: None,
-        ctx_aware_resources_allow_list: Default::default(),
-    };
+    let eval_ctx = EvaluationContext::new("test");
 
     let mut policy_evaluator = build_policy_evaluator(execution_mode, &policy, &eval_ctx);
 
@@ -285,10 +290,9 @@ async fn test_runtime_context_aware<F, Fut>(
     let (callback_handler_shutdown_channel_tx, callback_handler_channel) =
         setup_callback_handler(Some(client)).await;
 
-    let eval_ctx = EvaluationContext {
-        policy_id: "test".to_owned(),
-        callback_channel: Some(callback_handler_channel),
-        ctx_aware_resources_allow_list: BTreeSet::from([
+    let eval_ctx = EvaluationContext::new("test")
+        .callback_channel(callback_handler_channel)
+        .ctx_aware_resources_allow_list(BTreeSet::from([
             ContextAwareResource {
                 api_version: "v1".to_owned(),
                 kind: "Namespace".to_owned(),
@@ -301,8 +305,7 @@ async fn test_runtime_context_aware<F, Fut>(
                 api_version: "v1".to_owned(),
                 kind: "Service".to_owned(),
             },
-        ]),
-    };
+        ]));
 
     let request_data = load_request_data(request_file_path);
     let request: AdmissionRequest =
@@ -323,6 +326,166 @@ async fn test_runtime_context_aware<F, Fut>(
         .expect("cannot send shutdown signal");
 }
 
+#[test_log::test(rstest)]
+#[case::wasi(
+    PolicyExecutionMode::Wasi,
+    "ghcr.io/kubewarden/tests/go-wasi-context-aware-test-policy:latest",
+    "app_deployment.json",
+    wapc_and_wasi_scenario
+)]
+#[case::opa(
+    PolicyExecutionMode::Opa,
+    "ghcr.io/kubewarden/tests/context-aware-test-opa-policy:v0.1.0",
+    "app_deployment.json",
+    rego_scenario
+)]
+#[case::gatekeeper(
+    PolicyExecutionMode::OpaGatekeeper,
+    "ghcr.io/kubewarden/tests/context-aware-test-gatekeeper-policy:v0.1.0",
+    "app_deployment.json",
+    rego_scenario
+)]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_runtime_context_aware_async<F, Fut>(
+    #[case] execution_mode: PolicyExecutionMode,
+    #[case] policy_uri: &str,
+    #[case] request_file_path: &str,
+    #[case] scenario: F,
+) where
+    F: FnOnce(Handle<Request<Body>, Response<Body>>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    use kube::client::Body;
+
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy(policy_uri, tempdir).await;
+
+    let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+    let client = Client::new(mocksvc, "default");
+    scenario(handle).await;
+
+    let (callback_handler_shutdown_channel_tx, callback_handler_channel) =
+        setup_callback_handler(Some(client)).await;
+
+    let eval_ctx = EvaluationContext::new("test")
+        .callback_channel(callback_handler_channel)
+        .ctx_aware_resources_allow_list(BTreeSet::from([
+            ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Namespace".to_owned(),
+            },
+            ContextAwareResource {
+                api_version: "apps/v1".to_owned(),
+                kind: "Deployment".to_owned(),
+            },
+            ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Service".to_owned(),
+            },
+        ]));
+
+    let request_data = load_request_data(request_file_path);
+    let request: AdmissionRequest =
+        serde_json::from_slice(&request_data).expect("cannot deserialize request");
+
+    // no need to use `spawn_blocking`, the evaluation doesn't block the runtime
+    let mut policy_evaluator = build_async_policy_evaluator(execution_mode, &policy, &eval_ctx);
+    let admission_response = policy_evaluator
+        .validate_async(
+            ValidateRequest::AdmissionRequest(request),
+            &PolicySettings::default(),
+        )
+        .await
+        .expect("the policy can be evaluated asynchronously");
+
+    assert!(admission_response.allowed, "the admission request should have been accepted, it has been rejected with this details: {:?}", admission_response);
+
+    callback_handler_shutdown_channel_tx
+        .send(())
+        .expect("cannot send shutdown signal");
+}
+
+#[test_log::test(rstest)]
+#[case::wasi(
+    PolicyExecutionMode::Wasi,
+    "ghcr.io/kubewarden/tests/go-wasi-context-aware-test-policy:latest",
+    wapc_and_wasi_scenario
+)]
+#[case::wapc(
+    PolicyExecutionMode::KubewardenWapc,
+    "ghcr.io/kubewarden/tests/context-aware-test-policy:v0.1.0",
+    wapc_and_wasi_scenario
+)]
+#[case::opa(
+    PolicyExecutionMode::Opa,
+    "ghcr.io/kubewarden/tests/context-aware-test-opa-policy:v0.1.0",
+    rego_scenario
+)]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_evaluation_report<F, Fut>(
+    #[case] execution_mode: PolicyExecutionMode,
+    #[case] policy_uri: &str,
+    #[case] scenario: F,
+) where
+    F: FnOnce(Handle<Request<Body>, Response<Body>>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    use kube::client::Body;
+
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy(policy_uri, tempdir).await;
+
+    let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+    let client = Client::new(mocksvc, "default");
+    scenario(handle).await;
+
+    let (callback_handler_shutdown_channel_tx, callback_handler_channel) =
+        setup_callback_handler(Some(client)).await;
+
+    let eval_ctx = EvaluationContext::new("test")
+        .callback_channel(callback_handler_channel)
+        .ctx_aware_resources_allow_list(BTreeSet::from([
+            ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Namespace".to_owned(),
+            },
+            ContextAwareResource {
+                api_version: "apps/v1".to_owned(),
+                kind: "Deployment".to_owned(),
+            },
+            ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Service".to_owned(),
+            },
+        ]));
+
+    let request_data = load_request_data("app_deployment.json");
+    let request: AdmissionRequest =
+        serde_json::from_slice(&request_data).expect("cannot deserialize request");
+
+    tokio::task::spawn_blocking(move || {
+        let mut policy_evaluator = build_policy_evaluator(execution_mode, &policy, &eval_ctx);
+        let (admission_response, report) = policy_evaluator.validate_with_report(
+            ValidateRequest::AdmissionRequest(request),
+            &PolicySettings::default(),
+        );
+
+        assert!(admission_response.allowed, "the admission request should have been accepted, it has been rejected with this details: {:?}", admission_response);
+        assert!(!report.reset);
+        assert!(report
+            .host_callbacks
+            .iter()
+            .any(|cb| cb.binding == "kubewarden" && cb.namespace == "kubernetes" && cb.was_cached.is_some()));
+        assert!(report.host_callbacks_time() <= report.execution_time);
+    })
+    .await
+    .unwrap();
+
+    callback_handler_shutdown_channel_tx
+        .send(())
+        .expect("cannot send shutdown signal");
+}
+
 #[rstest]
 #[case::policy(
     "ghcr.io/kubewarden/tests/context-aware-test-policy:latest",
@@ -341,14 +504,12 @@ async fn test_oci_manifest_capability(
         request: CallbackRequestType::OciManifest {
             image: policy_uri.to_owned(),
         },
+        origin: None,
+        span: tracing::Span::current(),
         response_channel: tx,
     };
 
-    let eval_ctx = EvaluationContext {
-        policy_id: "test".to_owned(),
-        callback_channel: Some(callback_handler_channel),
-        ctx_aware_resources_allow_list: Default::default(),
-    };
+    let eval_ctx = EvaluationContext::new("test").callback_channel(callback_handler_channel);
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
         .callback_channel
@@ -430,14 +591,12 @@ async fn test_oci_manifest_and_config_capability(
         request: CallbackRequestType::OciManifestAndConfig {
             image: policy_uri.to_owned(),
         },
+        origin: None,
+        span: tracing::Span::current(),
         response_channel: tx,
     };
 
-    let eval_ctx = EvaluationContext {
-        policy_id: "test".to_owned(),
-        callback_channel: Some(callback_handler_channel),
-        ctx_aware_resources_allow_list: Default::default(),
-    };
+    let eval_ctx = EvaluationContext::new("test").callback_channel(callback_handler_channel);
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
         .callback_channel
@@ -482,3 +641,340 @@ async fn test_oci_manifest_and_config_capability(
         .send(())
         .expect("cannot send shutdown signal");
 }
+
+#[rstest]
+#[case::allowed("wapc && (wasi || opa)", true, None)]
+#[case::rejected("wapc && wasi", false, Some("wasi"))]
+#[case::not("!wasi", true, None)]
+#[tokio::test]
+async fn test_policy_group_evaluator(
+    #[case] expression: &str,
+    #[case] allowed: bool,
+    #[case] rejected_by: Option<&str>,
+) {
+    let members = [
+        (
+            "wapc",
+            PolicyExecutionMode::KubewardenWapc,
+            "ghcr.io/kubewarden/tests/raw-validation-policy:v0.1.0",
+            json!({
+                "validUsers": ["tonio", "wanda"],
+                "validActions": ["eats", "likes"],
+                "validResources": ["banana", "hay"],
+            }),
+        ),
+        (
+            "wasi",
+            PolicyExecutionMode::Wasi,
+            "ghcr.io/kubewarden/tests/raw-validation-wasi-policy:v0.1.0",
+            json!({
+                "validUsers": ["wanda"],
+                "validActions": ["eats", "likes"],
+                "validResources": ["banana", "hay"],
+            }),
+        ),
+        (
+            "opa",
+            PolicyExecutionMode::Opa,
+            "ghcr.io/kubewarden/tests/raw-validation-opa-policy:v0.1.0",
+            json!({}),
+        ),
+    ];
+
+    let mut group_members = HashMap::new();
+    for (name, execution_mode, policy_uri, settings) in members {
+        let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+        let policy = fetch_policy(policy_uri, tempdir).await;
+        let serde_json::Value::Object(settings) = settings else {
+            panic!("settings must be an object")
+        };
+
+        group_members.insert(
+            name.to_owned(),
+            PolicyGroupMember {
+                policy_evaluator_pre: build_policy_evaluator_pre(execution_mode, &policy),
+                settings,
+                ctx_aware_resources_allow_list: Default::default(),
+                host_capabilities_allow_list: None,
+            },
+        );
+    }
+
+    let eval_ctx = EvaluationContext::new("group");
+
+    let policy_group_evaluator = PolicyGroupEvaluator::new(expression, group_members, &eval_ctx, 1)
+        .expect("cannot build policy group evaluator");
+
+    let request_data = load_request_data("raw_validation.json");
+    let request_json = serde_json::from_slice(&request_data).expect("cannot deserialize request");
+
+    let admission_response = policy_group_evaluator.validate(&ValidateRequest::Raw(request_json));
+
+    assert_eq!(allowed, admission_response.allowed);
+    if allowed {
+        assert!(admission_response.status.is_none());
+    } else {
+        // the message of the rejecting member is prefixed with its name
+        let message = admission_response
+            .status
+            .and_then(|status| status.message)
+            .expect("missing rejection message");
+        assert!(message.starts_with(&format!("{}: ", rejected_by.unwrap())));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_policy_evaluator_from_policy_uri() {
+    let store_dir = tempfile::TempDir::new().expect("cannot create tempdir");
+
+    // the execution mode is read from the metadata of the policy
+    let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+        .policy_store_dir(store_dir.path())
+        .policy_uri(
+            "registry://ghcr.io/kubewarden/tests/raw-validation-policy:v0.1.0",
+            None,
+        )
+        .await
+        .expect("cannot fetch policy")
+        .build_pre()
+        .expect("cannot build policy evaluator pre");
+
+    // the policy has been saved inside of the content-addressed store
+    let stored: Vec<_> = std::fs::read_dir(store_dir.path().join("sha256"))
+        .expect("cannot read policy store")
+        .collect();
+    assert_eq!(1, stored.len());
+
+    let mut policy_evaluator = policy_evaluator_pre
+        .rehydrate(&EvaluationContext::default())
+        .expect("cannot rehydrate policy evaluator");
+
+    let request_data = load_request_data("raw_validation.json");
+    let request_json = serde_json::from_slice(&request_data).expect("cannot deserialize request");
+    let serde_json::Value::Object(settings) = json!({
+        "validUsers": ["tonio", "wanda"],
+        "validActions": ["eats", "likes"],
+        "validResources": ["banana", "hay"],
+    }) else {
+        panic!("settings must be an object")
+    };
+
+    let admission_response =
+        policy_evaluator.validate(ValidateRequest::Raw(request_json), &settings);
+    assert!(admission_response.allowed);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_policy_evaluator_from_http_policy_uri() {
+    let store_dir = tempfile::TempDir::new().expect("cannot create tempdir");
+
+    let contents = include_bytes!("data/wasi_policy/accept_all.wat");
+    let addr = serve_policy(contents.to_vec()).await;
+    let sources = insecure_sources(addr);
+
+    // the policy has no metadata, the execution mode must be given
+    let policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+        .execution_mode(PolicyExecutionMode::Wasi)
+        .policy_store_dir(store_dir.path())
+        .policy_uri(&format!("http://{addr}/policy.wasm"), Some(&sources))
+        .await
+        .expect("cannot fetch policy");
+    let policy_evaluator_pre = policy_evaluator_builder
+        .build_pre()
+        .expect("cannot build policy evaluator pre");
+
+    // the policy has been saved inside of the content-addressed store
+    let stored: Vec<_> = std::fs::read_dir(store_dir.path().join("sha256"))
+        .expect("cannot read policy store")
+        .map(|entry| entry.expect("cannot read policy store entry").path())
+        .collect();
+    assert_eq!(1, stored.len());
+
+    let mut policy_evaluator = policy_evaluator_pre
+        .rehydrate(&EvaluationContext::default())
+        .expect("cannot rehydrate policy evaluator");
+
+    let request_data = load_request_data("raw_validation.json");
+    let request_json = serde_json::from_slice(&request_data).expect("cannot deserialize request");
+    let admission_response = policy_evaluator.validate(
+        ValidateRequest::Raw(request_json),
+        &PolicySettings::default(),
+    );
+    assert!(admission_response.allowed);
+
+    // the stored policy is checked against its digest when it's loaded
+    std::fs::write(&stored[0], b"(module)").expect("cannot tamper with the stored policy");
+    assert!(matches!(
+        policy_evaluator_builder.build_pre(),
+        Err(PolicyEvaluatorBuilderError::ReadStoredPolicy(_))
+    ));
+}
+
+/// Require the policy to be signed with the key of `tests/data/signed_policy`
+fn signed_policy_verification_config() -> LatestVerificationConfig {
+    LatestVerificationConfig {
+        all_of: Some(vec![Signature::PubKey {
+            owner: None,
+            key: include_str!("data/signed_policy/cosign.pub").to_owned(),
+            annotations: None,
+        }]),
+        any_of: None,
+    }
+}
+
+fn insecure_sources(addr: std::net::SocketAddr) -> policy_fetcher::sources::Sources {
+    let sources_dir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let sources_file = sources_dir.path().join("sources.yaml");
+    std::fs::write(&sources_file, format!("insecure_sources: [\"{addr}\"]"))
+        .expect("cannot write sources file");
+    policy_fetcher::sources::read_sources_file(&sources_file).expect("cannot read sources file")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_policy_evaluator_from_signed_policy_uri() {
+    let store_dir = tempfile::TempDir::new().expect("cannot create tempdir");
+
+    let signature = include_str!("data/signed_policy/payload.sig");
+    let addr = serve_signed_policy(signature).await;
+    let sources = insecure_sources(addr);
+
+    // The policy is signed with a public key, the trust root doesn't need any
+    // Fulcio or Rekor data: nothing is fetched from the Sigstore infrastructure
+    let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+        .execution_mode(PolicyExecutionMode::Wasi)
+        .policy_store_dir(store_dir.path())
+        .verify_policy(
+            signed_policy_verification_config(),
+            Some(Arc::new(ManualTrustRoot::default())),
+        )
+        .policy_uri(&format!("registry://{addr}/accept-all:v1"), Some(&sources))
+        .await
+        .expect("cannot fetch and verify policy")
+        .build_pre()
+        .expect("cannot build policy evaluator pre");
+
+    let mut policy_evaluator = policy_evaluator_pre
+        .rehydrate(&EvaluationContext::default())
+        .expect("cannot rehydrate policy evaluator");
+
+    let request_data = load_request_data("raw_validation.json");
+    let request_json = serde_json::from_slice(&request_data).expect("cannot deserialize request");
+    let admission_response = policy_evaluator.validate(
+        ValidateRequest::Raw(request_json),
+        &PolicySettings::default(),
+    );
+    assert!(admission_response.allowed);
+}
+
+#[rstest]
+// base64 encoding of "not a signature"
+#[case::malformed_signature("bm90IGEgc2lnbmF0dXJl")]
+// a well formed signature, made by a different key over the same payload
+#[case::signature_made_by_another_key(
+    "MEUCIEHXrrKvtzUUYpjdufL5PUW9ZJqyUWMR0xpdVWs8uaLFAiEAmbYn4qt/pgPPOfPGEIGu50d6PIG4LCqHb5j1gQracKE="
+)]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_policy_uri_rejects_bad_signature(#[case] signature: &str) {
+    let store_dir = tempfile::TempDir::new().expect("cannot create tempdir");
+
+    let addr = serve_signed_policy(signature).await;
+    let sources = insecure_sources(addr);
+
+    let result = PolicyEvaluatorBuilder::new()
+        .execution_mode(PolicyExecutionMode::Wasi)
+        .policy_store_dir(store_dir.path())
+        .verify_policy(
+            signed_policy_verification_config(),
+            Some(Arc::new(ManualTrustRoot::default())),
+        )
+        .policy_uri(&format!("registry://{addr}/accept-all:v1"), Some(&sources))
+        .await;
+    assert!(matches!(
+        result,
+        Err(PolicyEvaluatorBuilderError::PolicyVerification(_))
+    ));
+
+    // the policy that failed the verification has not been stored
+    assert!(!store_dir.path().join("sha256").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_unknown_rego_entrypoint_is_rejected() {
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy(
+        "ghcr.io/kubewarden/tests/disallow-service-loadbalancer:v0.1.5",
+        tempdir,
+    )
+    .await;
+
+    let Err(error) = PolicyEvaluatorBuilder::new()
+        .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+        .policy_file(&policy.local_path)
+        .expect("cannot read policy file")
+        .rego_entrypoint("does/not/exist")
+        .build_pre()
+    else {
+        panic!("the unknown entrypoint should have been rejected")
+    };
+    assert!(error
+        .to_string()
+        .contains(r#"cannot find entrypoint "does/not/exist""#));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_blocking_policy_is_refused_by_validate_async() {
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy("ghcr.io/kubewarden/tests/pod-privileged:v0.2.1", tempdir).await;
+    let eval_ctx = EvaluationContext::new("test");
+    let mut policy_evaluator =
+        build_policy_evaluator(PolicyExecutionMode::KubewardenWapc, &policy, &eval_ctx);
+    assert!(!policy_evaluator.supports_async());
+
+    let request_data = load_request_data("pod_with_privileged_containers.json");
+    let request: AdmissionRequest =
+        serde_json::from_slice(&request_data).expect("cannot deserialize request");
+
+    // waPC policies cannot yield: the evaluation must be refused, not panic
+    assert!(matches!(
+        policy_evaluator
+            .validate_async(
+                ValidateRequest::AdmissionRequest(request),
+                &PolicySettings::default(),
+            )
+            .await,
+        Err(PolicyEvaluatorError::AsyncEvaluationNotSupported(_))
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_rego_policy_is_evaluated_asynchronously_by_current_thread_runtime() {
+    let tempdir = tempfile::TempDir::new().expect("cannot create tempdir");
+    let policy = fetch_policy(
+        "ghcr.io/kubewarden/tests/disallow-service-loadbalancer:v0.1.5",
+        tempdir,
+    )
+    .await;
+    let mut policy_evaluator = build_policy_evaluator(
+        PolicyExecutionMode::OpaGatekeeper,
+        &policy,
+        &EvaluationContext::default(),
+    );
+    assert!(policy_evaluator.supports_async());
+
+    let request_data = load_request_data("service_loadbalancer.json");
+    let request: AdmissionRequest =
+        serde_json::from_slice(&request_data).expect("cannot deserialize request");
+
+    let admission_response = policy_evaluator
+        .validate_async(
+            ValidateRequest::AdmissionRequest(request),
+            &PolicySettings::default(),
+        )
+        .await
+        .expect("Rego policies can be evaluated asynchronously");
+    assert!(!admission_response.allowed);
+    assert_eq!(
+        Some("Service of type LoadBalancer are not allowed".to_owned()),
+        admission_response.status.and_then(|status| status.message)
+    );
+}